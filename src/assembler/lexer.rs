@@ -0,0 +1,148 @@
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use assembler::ErrorKind;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Token {
+    Number(i64),
+    Ident(String),
+    Str(String),
+    Punct(char),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Ident(ref s) => write!(f, "{}", s),
+            Token::Str(ref s) => write!(f, "\"{}\"", s),
+            Token::Punct(c) => write!(f, "'{}'", c),
+        }
+    }
+}
+
+fn number<F>(chars: &mut Peekable<Chars>, radix: u32, accept: F) -> Result<i64, ErrorKind>
+    where F: Fn(char) -> bool
+{
+    let mut val: i64 = 0;
+    while let Some(c) = chars.peek().cloned().filter(|c| accept(*c)) {
+        chars.next();
+        val = val.checked_mul(radix as i64)
+                 .and_then(|v| v.checked_add(c.to_digit(radix).unwrap() as i64))
+                 .ok_or_else(|| ErrorKind::Syntax("Number too large".to_string()))?;
+    }
+
+    Ok(val)
+}
+
+/// Splits a line of source into tokens. Everything after a `;` or `\` that
+/// isn't inside a string is a comment.
+pub fn tokenize(line: &str) -> Result<Vec<Token>, ErrorKind> {
+    let mut tokens = vec![];
+    let mut chars = line.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            ';' | '\\' => break,
+            c if c.is_whitespace() => {
+                chars.next();
+            },
+            '0'..='9' => {
+                tokens.push(Token::Number(number(&mut chars, 10, |c| c.is_digit(10))?));
+            },
+            '&' | '$' => {
+                chars.next();
+                match chars.peek() {
+                    Some(c) if c.is_digit(16) =>
+                        tokens.push(Token::Number(number(&mut chars, 16, |c| c.is_digit(16))?)),
+                    _ => tokens.push(Token::Punct(c)),
+                }
+            },
+            '%' => {
+                chars.next();
+                match chars.peek() {
+                    Some(&'0') | Some(&'1') =>
+                        tokens.push(Token::Number(number(&mut chars, 2, |c| c == '0' || c == '1')?)),
+                    _ => tokens.push(Token::Punct(c)),
+                }
+            },
+            '"' => {
+                chars.next();
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => s.push(c),
+                        None => return Err(ErrorKind::Syntax("Unterminated string".to_string())),
+                    }
+                }
+                tokens.push(Token::Str(s));
+            },
+            '\'' => {
+                chars.next();
+                match (chars.next(), chars.next()) {
+                    (Some(c), Some('\'')) => tokens.push(Token::Number(c as i64)),
+                    _ => return Err(ErrorKind::Syntax("Invalid character literal".to_string())),
+                }
+            },
+            c if c.is_alphabetic() || c == '_' => {
+                let mut s = String::new();
+                while let Some(c) = chars.peek().cloned().filter(|c| c.is_alphanumeric() || *c == '_') {
+                    chars.next();
+                    s.push(c);
+                }
+                tokens.push(Token::Ident(s));
+            },
+            c => {
+                chars.next();
+                tokens.push(Token::Punct(c));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tokenize_should {
+    use super::*;
+
+    #[test]
+    fn read_numbers_in_each_base() {
+        assert_eq!(Ok(vec![
+            Token::Number(10),
+            Token::Number(0xff),
+            Token::Number(0x1234),
+            Token::Number(5),
+            Token::Number(0x41),
+        ]), tokenize("10 &FF $1234 %101 'A'"));
+    }
+
+    #[test]
+    fn stop_at_comments_outside_strings() {
+        assert_eq!(Ok(vec![
+            Token::Ident("EQUS".to_string()),
+            Token::Str("a;b".to_string()),
+        ]), tokenize("EQUS \"a;b\" ; comment"));
+        assert_eq!(Ok(vec![Token::Ident("RTS".to_string())]), tokenize("RTS \\ comment"));
+    }
+
+    #[test]
+    fn read_punctuation() {
+        assert_eq!(Ok(vec![
+            Token::Ident("LDA".to_string()),
+            Token::Punct('('),
+            Token::Ident("ptr".to_string()),
+            Token::Punct(')'),
+            Token::Punct(','),
+            Token::Ident("Y".to_string()),
+        ]), tokenize("LDA (ptr),Y"));
+    }
+
+    #[test]
+    fn reject_unterminated_strings() {
+        assert!(tokenize("EQUS \"abc").is_err());
+    }
+}
@@ -0,0 +1,748 @@
+mod lexer;
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+
+use cpu::{self, Addressing, CpuVariant, OpCode};
+use memory::MemoryMap;
+use self::lexer::Token;
+
+#[derive(Debug, PartialEq)]
+pub enum ErrorKind {
+    Syntax(String),
+    UnknownMnemonic(String),
+    UndefinedSymbol(String),
+    DuplicateSymbol(String),
+    InvalidAddressing(String),
+    ValueOutOfRange(i64),
+    BranchOutOfRange(i64),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct AssemblerError {
+    /// The 1-based line of the source the error was found on.
+    pub line: usize,
+    pub kind: ErrorKind,
+}
+
+impl Error for AssemblerError {
+    fn description(&self) -> &str {
+        match self.kind {
+            ErrorKind::Syntax(_) => "Syntax error",
+            ErrorKind::UnknownMnemonic(_) => "Unknown mnemonic",
+            ErrorKind::UndefinedSymbol(_) => "Undefined symbol",
+            ErrorKind::DuplicateSymbol(_) => "Duplicate symbol",
+            ErrorKind::InvalidAddressing(_) => "Invalid addressing mode",
+            ErrorKind::ValueOutOfRange(_) => "Value out of range",
+            ErrorKind::BranchOutOfRange(_) => "Branch out of range",
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ErrorKind::Syntax(ref s) => write!(f, "{}", s),
+            ErrorKind::UnknownMnemonic(ref s) => write!(f, "Unknown mnemonic '{}'", s),
+            ErrorKind::UndefinedSymbol(ref s) => write!(f, "Undefined symbol '{}'", s),
+            ErrorKind::DuplicateSymbol(ref s) => write!(f, "Symbol '{}' is already defined", s),
+            ErrorKind::InvalidAddressing(ref s) =>
+                write!(f, "Addressing mode isn't supported by {}", s),
+            ErrorKind::ValueOutOfRange(v) => write!(f, "Value {} is out of range", v),
+            ErrorKind::BranchOutOfRange(v) => write!(f, "Branch offset {} is out of range", v),
+        }
+    }
+}
+
+impl fmt::Display for AssemblerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Line {}: {}", self.line, self.kind)
+    }
+}
+
+/// A contiguous run of assembled bytes. A new segment is started whenever
+/// `ORG` moves the current address somewhere other than the end of the
+/// previous one.
+#[derive(Debug, PartialEq)]
+pub struct Segment {
+    pub origin: u16,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct Assembly {
+    segments: Vec<Segment>,
+    symbols: HashMap<String, i64>,
+}
+
+impl Assembly {
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// The value of a label or constant defined in the source.
+    pub fn symbol(&self, name: &str) -> Option<i64> {
+        self.symbols.get(name).cloned()
+    }
+
+    /// Writes every segment to `mem` at its origin.
+    pub fn load_into<M: MemoryMap>(&self, mut mem: M) {
+        for segment in &self.segments {
+            for (i, b) in segment.bytes.iter().enumerate() {
+                mem.write(segment.origin.wrapping_add(i as u16), *b);
+            }
+        }
+    }
+}
+
+/// Assembles `source` for the NMOS 6502. See `assemble_for`.
+pub fn assemble(source: &str) -> Result<Assembly, AssemblerError> {
+    assemble_for(CpuVariant::Nmos6502, source)
+}
+
+/// Assembles BBC BASIC / BeebAsm style source for `variant`. Supported are:
+///
+/// - `.label` definitions and `name = expr` constants
+/// - `ORG`, `EQUB`, `EQUW` and `EQUS` directives
+/// - `&`/`$` hex, `%` binary, decimal and `'c'` character literals
+/// - `+ - * /`, brackets, unary `-`, `<`/`LO()` and `>`/`HI()` in
+///   expressions, with `*` as the current address
+/// - several statements on a line separated by `:`, and `;` or `\`
+///   comments
+///
+/// Zero page addressing is used wherever the operand is known to fit in
+/// the first pass; operands that refer forwards always use the absolute
+/// form when the instruction has one.
+pub fn assemble_for(variant: CpuVariant, source: &str) -> Result<Assembly, AssemblerError> {
+    let mut asm = Assembler {
+        variant: variant,
+        final_pass: false,
+        pc: 0,
+        statement: 0,
+        symbols: HashMap::new(),
+        defined: HashSet::new(),
+        forward_refs: HashSet::new(),
+        segments: vec![],
+    };
+
+    asm.run(source)?;
+    asm.final_pass = true;
+    asm.run(source)?;
+
+    Ok(Assembly {
+        segments: asm.segments,
+        symbols: asm.symbols,
+    })
+}
+
+fn mnemonic(name: &str) -> Option<OpCode> {
+    use cpu::OpCode::*;
+
+    let op = match &*name.to_uppercase() {
+        "ADC" => Adc, "AND" => And, "ASL" => Asl, "BCC" => Bcc, "BCS" => Bcs,
+        "BEQ" => Beq, "BIT" => Bit, "BMI" => Bmi, "BNE" => Bne, "BPL" => Bpl,
+        "BRK" => Brk, "BVC" => Bvc, "BVS" => Bvs, "CLC" => Clc, "CLD" => Cld,
+        "CLI" => Cli, "CLV" => Clv, "CMP" => Cmp, "CPX" => Cpx, "CPY" => Cpy,
+        "DEC" => Dec, "DEX" => Dex, "DEY" => Dey, "EOR" => Eor, "INC" => Inc,
+        "INX" => Inx, "INY" => Iny, "JMP" => Jmp, "JSR" => Jsr, "LDA" => Lda,
+        "LDX" => Ldx, "LDY" => Ldy, "LSR" => Lsr, "NOP" => Nop, "ORA" => Ora,
+        "PHA" => Pha, "PHP" => Php, "PLA" => Pla, "PLP" => Plp, "ROL" => Rol,
+        "ROR" => Ror, "RTI" => Rti, "RTS" => Rts, "SBC" => Sbc, "SEC" => Sec,
+        "SED" => Sed, "SEI" => Sei, "STA" => Sta, "STX" => Stx, "STY" => Sty,
+        "TAX" => Tax, "TAY" => Tay, "TSX" => Tsx, "TXA" => Txa, "TXS" => Txs,
+        "TYA" => Tya,
+
+        "ALR" => Alr, "ANC" => Anc, "ARR" => Arr, "DCP" => Dcp, "ISC" => Isc,
+        "JAM" => Jam, "LAX" => Lax, "RLA" => Rla, "RRA" => Rra, "SAX" => Sax,
+        "SBX" => Sbx, "SLO" => Slo, "SRE" => Sre,
+
+        "BRA" => Bra, "PHX" => Phx, "PHY" => Phy, "PLX" => Plx, "PLY" => Ply,
+        "STZ" => Stz, "TRB" => Trb, "TSB" => Tsb,
+        _ => return None,
+    };
+
+    Some(op)
+}
+
+fn byte(v: i64) -> Result<u8, ErrorKind> {
+    if v < -0x80 || v > 0xff {
+        return Err(ErrorKind::ValueOutOfRange(v));
+    }
+    Ok(v as u8)
+}
+
+fn word(v: i64) -> Result<u16, ErrorKind> {
+    if v < -0x8000 || v > 0xffff {
+        return Err(ErrorKind::ValueOutOfRange(v));
+    }
+    Ok(v as u16)
+}
+
+fn zero_page(v: i64) -> Result<u8, ErrorKind> {
+    if v < 0 || v > 0xff {
+        return Err(ErrorKind::ValueOutOfRange(v));
+    }
+    Ok(v as u8)
+}
+
+/// The zero page and absolute forms of an operand, in the order they
+/// should be tried.
+fn zero_page_or<Z, A>(prefer_zero_page: bool, v: i64, zp: Z, abs: A)
+    -> Result<Vec<Addressing>, ErrorKind>
+    where Z: Fn(u8) -> Addressing,
+          A: Fn(u16) -> Addressing
+{
+    let abs = abs(word(v)?);
+    Ok(match zero_page(v) {
+        Ok(z) if prefer_zero_page => vec![zp(z), abs],
+        Ok(z) => vec![abs, zp(z)],
+        Err(_) => vec![abs],
+    })
+}
+
+fn unexpected(t: Option<&Token>) -> ErrorKind {
+    ErrorKind::Syntax(match t {
+        Some(t) => format!("Unexpected {}", t),
+        None => "Unexpected end of statement".to_string(),
+    })
+}
+
+enum Operand {
+    None,
+    Accumulator,
+    Immediate(i64),
+    Direct(i64),
+    IndexedX(i64),
+    IndexedY(i64),
+    Indirect(i64),
+    IndirectX(i64),
+    IndirectY(i64),
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_at(&self, n: usize) -> Option<&'a Token> {
+        self.tokens.get(self.pos + n)
+    }
+
+    fn next(&mut self) -> Option<&'a Token> {
+        let t = self.peek();
+        self.pos += 1;
+        t
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(&Token::Punct(c)) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ErrorKind> {
+        if !self.eat(c) {
+            return Err(unexpected(self.peek()));
+        }
+        Ok(())
+    }
+
+    fn is_register(&self, n: usize, name: &str) -> bool {
+        match self.peek_at(n) {
+            Some(&Token::Ident(ref s)) => s.eq_ignore_ascii_case(name),
+            _ => false,
+        }
+    }
+
+    fn expect_register(&mut self, name: &str) -> Result<(), ErrorKind> {
+        if !self.is_register(0, name) {
+            return Err(unexpected(self.peek()));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn ident(&mut self) -> Result<&'a str, ErrorKind> {
+        match self.next() {
+            Some(&Token::Ident(ref s)) => Ok(s),
+            t => Err(unexpected(t)),
+        }
+    }
+}
+
+struct Assembler {
+    variant: CpuVariant,
+    final_pass: bool,
+    pc: u16,
+    /// Counts statements so that the second pass can find out what the
+    /// first pass learned about each one.
+    statement: usize,
+    symbols: HashMap<String, i64>,
+    defined: HashSet<String>,
+    /// Statements whose operands referred to a symbol that wasn't yet
+    /// defined in the first pass. Their size was fixed without knowing the
+    /// value, so they mustn't shrink to zero page in the second pass.
+    forward_refs: HashSet<usize>,
+    segments: Vec<Segment>,
+}
+
+impl Assembler {
+    fn run(&mut self, source: &str) -> Result<(), AssemblerError> {
+        self.pc = 0;
+        self.statement = 0;
+        self.defined.clear();
+        self.segments.clear();
+
+        for (i, line) in source.lines().enumerate() {
+            self.line(line).map_err(|kind| AssemblerError { line: i + 1, kind: kind })?;
+        }
+
+        Ok(())
+    }
+
+    fn line(&mut self, line: &str) -> Result<(), ErrorKind> {
+        let tokens = lexer::tokenize(line)?;
+        for statement in tokens.split(|t| *t == Token::Punct(':')) {
+            self.statement(&mut Parser { tokens: statement, pos: 0 })?;
+            self.statement += 1;
+        }
+
+        Ok(())
+    }
+
+    fn statement(&mut self, p: &mut Parser) -> Result<(), ErrorKind> {
+        if p.eat('.') {
+            let name = p.ident()?;
+            let pc = self.pc as i64;
+            self.define(name, Some(pc))?;
+        }
+
+        if p.at_end() {
+            return Ok(());
+        }
+
+        if let (Some(&Token::Ident(ref name)), Some(&Token::Punct('='))) = (p.peek(), p.peek_at(1)) {
+            p.pos += 2;
+            let val = self.expr(p)?;
+            self.define(name, val)?;
+        }
+        else {
+            let name = p.ident()?;
+            let directive = name.to_uppercase();
+            match &*directive {
+                "ORG" => {
+                    let org = self.expr(p)?.ok_or_else(|| ErrorKind::Syntax(
+                        "ORG address must be defined before it is used".to_string()))?;
+                    self.pc = word(org)?;
+                },
+                "EQUB" | "EQUW" | "EQUS" => loop {
+                    match p.peek() {
+                        Some(&Token::Str(ref s)) if directive == "EQUS" => {
+                            p.pos += 1;
+                            self.emit(s.as_bytes());
+                        },
+                        _ => {
+                            let v = self.value(p)?;
+                            if directive == "EQUW" {
+                                let w = word(v)?;
+                                self.emit(&[w as u8, (w >> 8) as u8]);
+                            }
+                            else {
+                                self.emit(&[byte(v)?]);
+                            }
+                        }
+                    }
+
+                    if !p.eat(',') {
+                        break;
+                    }
+                },
+                _ => {
+                    let op = mnemonic(name)
+                        .ok_or_else(|| ErrorKind::UnknownMnemonic(name.to_string()))?;
+                    let operand = self.operand(p)?;
+                    let bytes = self.instruction(op, operand)
+                        .map_err(|e| match e {
+                            ErrorKind::InvalidAddressing(_) =>
+                                ErrorKind::InvalidAddressing(name.to_uppercase()),
+                            e => e,
+                        })?;
+                    self.emit(&bytes);
+                }
+            }
+        }
+
+        if !p.at_end() {
+            return Err(unexpected(p.peek()));
+        }
+
+        Ok(())
+    }
+
+    fn define(&mut self, name: &str, val: Option<i64>) -> Result<(), ErrorKind> {
+        if !self.defined.insert(name.to_string()) {
+            return Err(ErrorKind::DuplicateSymbol(name.to_string()));
+        }
+
+        if let Some(v) = val {
+            self.symbols.insert(name.to_string(), v);
+        }
+
+        Ok(())
+    }
+
+    fn emit(&mut self, bytes: &[u8]) {
+        let pc = self.pc;
+        let contiguous = self.segments.last()
+            .map_or(false, |s| s.origin as usize + s.bytes.len() == pc as usize);
+
+        if !contiguous {
+            self.segments.push(Segment { origin: pc, bytes: vec![] });
+        }
+
+        self.segments.last_mut().unwrap().bytes.extend_from_slice(bytes);
+        self.pc = self.pc.wrapping_add(bytes.len() as u16);
+    }
+
+    fn operand(&mut self, p: &mut Parser) -> Result<Operand, ErrorKind> {
+        if p.at_end() {
+            return Ok(Operand::None);
+        }
+
+        if p.is_register(0, "A") && p.peek_at(1).is_none() {
+            p.pos += 1;
+            return Ok(Operand::Accumulator);
+        }
+
+        if p.eat('#') {
+            return Ok(Operand::Immediate(self.value(p)?));
+        }
+
+        if p.peek() == Some(&Token::Punct('(')) {
+            let start = p.pos;
+            p.pos += 1;
+            let v = self.value(p)?;
+            if p.eat(',') {
+                p.expect_register("X")?;
+                p.expect(')')?;
+                return Ok(Operand::IndirectX(v));
+            }
+
+            p.expect(')')?;
+            if p.at_end() {
+                return Ok(Operand::Indirect(v));
+            }
+
+            if p.peek() == Some(&Token::Punct(',')) && p.is_register(1, "Y") {
+                p.pos += 2;
+                return Ok(Operand::IndirectY(v));
+            }
+
+            // The brackets were part of an expression
+            p.pos = start;
+        }
+
+        let v = self.value(p)?;
+        if p.eat(',') {
+            if p.is_register(0, "X") {
+                p.pos += 1;
+                return Ok(Operand::IndexedX(v));
+            }
+            p.expect_register("Y")?;
+            return Ok(Operand::IndexedY(v));
+        }
+
+        Ok(Operand::Direct(v))
+    }
+
+    fn instruction(&mut self, op: OpCode, operand: Operand) -> Result<Vec<u8>, ErrorKind> {
+        use cpu::Addressing::*;
+
+        let prefer_zero_page = !self.forward_refs.contains(&self.statement);
+        let is_branch = cpu::encode_instruction_for(self.variant, op, Relative(0)).is_ok();
+
+        let candidates = match operand {
+            Operand::None => vec![Implied, Accumulator],
+            Operand::Accumulator => vec![Accumulator],
+            Operand::Immediate(v) => vec![Immediate(byte(v)?)],
+            Operand::Direct(v) if is_branch => vec![Relative(self.branch_offset(v)?)],
+            Operand::Direct(v) => zero_page_or(prefer_zero_page, v, ZeroPage, Absolute)?,
+            Operand::IndexedX(v) => zero_page_or(prefer_zero_page, v, ZeroPageX, AbsoluteX)?,
+            Operand::IndexedY(v) => zero_page_or(prefer_zero_page, v, ZeroPageY, AbsoluteY)?,
+            Operand::Indirect(v) =>
+                zero_page_or(prefer_zero_page, v, ZeroPageIndirect, Indirect)?,
+            Operand::IndirectX(v) =>
+                zero_page_or(prefer_zero_page, v, IndirectX, AbsoluteIndexedIndirect)?,
+            Operand::IndirectY(v) => vec![IndirectY(zero_page(v)?)],
+        };
+
+        candidates.into_iter()
+            .filter_map(|addr| cpu::encode_instruction_for(self.variant, op, addr).ok())
+            .next()
+            .ok_or_else(|| ErrorKind::InvalidAddressing(format!("{:?}", op)))
+    }
+
+    fn branch_offset(&self, target: i64) -> Result<i8, ErrorKind> {
+        let offset = target - (self.pc as i64 + 2);
+        if offset < -0x80 || offset > 0x7f {
+            if self.final_pass {
+                return Err(ErrorKind::BranchOutOfRange(offset));
+            }
+            return Ok(0);
+        }
+
+        Ok(offset as i8)
+    }
+
+    /// Evaluates an expression, using 0 in place of anything that can't be
+    /// known until the second pass.
+    fn value(&mut self, p: &mut Parser) -> Result<i64, ErrorKind> {
+        Ok(self.expr(p)?.unwrap_or(0))
+    }
+
+    fn expr(&mut self, p: &mut Parser) -> Result<Option<i64>, ErrorKind> {
+        let mut lhs = self.term(p)?;
+        loop {
+            let op = match p.peek() {
+                Some(&Token::Punct(c)) if c == '+' || c == '-' => c,
+                _ => return Ok(lhs),
+            };
+            p.pos += 1;
+
+            let rhs = self.term(p)?;
+            lhs = match (lhs, rhs) {
+                (Some(l), Some(r)) if op == '+' => Some(l.wrapping_add(r)),
+                (Some(l), Some(r)) => Some(l.wrapping_sub(r)),
+                _ => None,
+            };
+        }
+    }
+
+    fn term(&mut self, p: &mut Parser) -> Result<Option<i64>, ErrorKind> {
+        let mut lhs = self.unary(p)?;
+        loop {
+            let op = match p.peek() {
+                Some(&Token::Punct(c)) if c == '*' || c == '/' => c,
+                _ => return Ok(lhs),
+            };
+            p.pos += 1;
+
+            let rhs = self.unary(p)?;
+            lhs = match (lhs, rhs) {
+                (Some(l), Some(r)) if op == '*' => Some(l.wrapping_mul(r)),
+                (Some(l), Some(r)) => Some(l.checked_div(r).ok_or_else(||
+                    ErrorKind::Syntax("Division by zero".to_string()))?),
+                _ => None,
+            };
+        }
+    }
+
+    fn unary(&mut self, p: &mut Parser) -> Result<Option<i64>, ErrorKind> {
+        if p.eat('-') {
+            return Ok(self.unary(p)?.map(|v| v.wrapping_neg()));
+        }
+        if p.eat('<') {
+            return Ok(self.unary(p)?.map(|v| v & 0xff));
+        }
+        if p.eat('>') {
+            return Ok(self.unary(p)?.map(|v| (v >> 8) & 0xff));
+        }
+
+        self.primary(p)
+    }
+
+    fn primary(&mut self, p: &mut Parser) -> Result<Option<i64>, ErrorKind> {
+        match p.next() {
+            Some(&Token::Number(n)) => Ok(Some(n)),
+            Some(&Token::Punct('*')) => Ok(Some(self.pc as i64)),
+            Some(&Token::Punct('(')) => {
+                let v = self.expr(p)?;
+                p.expect(')')?;
+                Ok(v)
+            },
+            Some(&Token::Ident(ref name)) if p.peek() == Some(&Token::Punct('(')) => {
+                let shift = match &*name.to_uppercase() {
+                    "LO" => 0,
+                    "HI" => 8,
+                    _ => return Err(ErrorKind::Syntax(format!("Unknown function '{}'", name))),
+                };
+                p.pos += 1;
+                let v = self.expr(p)?;
+                p.expect(')')?;
+                Ok(v.map(|v| (v >> shift) & 0xff))
+            },
+            Some(&Token::Ident(ref name)) => self.symbol(name),
+            t => Err(unexpected(t)),
+        }
+    }
+
+    fn symbol(&mut self, name: &str) -> Result<Option<i64>, ErrorKind> {
+        match self.symbols.get(name) {
+            Some(&v) => Ok(Some(v)),
+            None if self.final_pass => Err(ErrorKind::UndefinedSymbol(name.to_string())),
+            None => {
+                self.forward_refs.insert(self.statement);
+                Ok(None)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod assemble_should {
+    use super::*;
+    use cpu::decode_instruction_for;
+    use memory::Map;
+
+    fn bytes(source: &str) -> Vec<u8> {
+        let asm = assemble(source).unwrap();
+        assert_eq!(1, asm.segments().len());
+        asm.segments()[0].bytes.clone()
+    }
+
+    #[test]
+    fn assemble_each_addressing_mode() {
+        assert_eq!(vec![0xea], bytes("NOP"));
+        assert_eq!(vec![0x0a, 0x0a], bytes("ASL : asl A"));
+        assert_eq!(vec![0xa9, 0x41], bytes("LDA #'A'"));
+        assert_eq!(vec![0xa5, 0x70], bytes("LDA &70"));
+        assert_eq!(vec![0xb5, 0x70], bytes("LDA &70,X"));
+        assert_eq!(vec![0xb6, 0x70], bytes("LDX &70,Y"));
+        assert_eq!(vec![0xad, 0x00, 0x12], bytes("LDA $1200"));
+        assert_eq!(vec![0xbd, 0x00, 0x12], bytes("LDA $1200,x"));
+        assert_eq!(vec![0xb9, 0x00, 0x12], bytes("LDA $1200,Y"));
+        assert_eq!(vec![0xb9, 0x70, 0x00], bytes("LDA &70,Y"));
+        assert_eq!(vec![0xa1, 0x70], bytes("LDA (&70,X)"));
+        assert_eq!(vec![0xb1, 0x70], bytes("LDA (&70),Y"));
+        assert_eq!(vec![0x6c, 0x00, 0x02], bytes("JMP (&200)"));
+        assert_eq!(vec![0xa9, 0x07], bytes("LDA #(1+2)*2+1"));
+    }
+
+    #[test]
+    fn resolve_labels_in_both_directions() {
+        let asm = assemble("
+            ORG &2000
+            .start
+                LDX #0
+            .loop
+                LDA message,X
+                BEQ done
+                JSR &FFEE
+                INX
+                BNE loop
+            .done
+                RTS
+            .message
+                EQUS \"HI\", 13, 0
+        ").unwrap();
+
+        assert_eq!(&[Segment {
+            origin: 0x2000,
+            bytes: vec![
+                0xa2, 0x00,
+                0xbd, 0x0e, 0x20,
+                0xf0, 0x06,
+                0x20, 0xee, 0xff,
+                0xe8,
+                0xd0, 0xf5,
+                0x60,
+                b'H', b'I', 13, 0,
+            ],
+        }], asm.segments());
+        assert_eq!(Some(0x2002), asm.symbol("loop"));
+        assert_eq!(Some(0x200e), asm.symbol("message"));
+    }
+
+    #[test]
+    fn keep_forward_references_absolute() {
+        assert_eq!(vec![0xad, 0x03, 0x00, 0x60], bytes("LDA later : .later RTS"));
+        assert_eq!(vec![0x96, 0x70], bytes("STX ptr,Y : ptr = &70"));
+    }
+
+    #[test]
+    fn evaluate_expressions_and_constants() {
+        assert_eq!(vec![0x34, 0x12, 0xff, 0xfe, 0x34, 0x12, 0xfe, 0x2f], bytes("
+            value = &1234
+            ORG &2000
+            EQUW value
+            EQUB -1, <-2, LO(value), >value
+            EQUW *-4/2 + &1000 - 2 * 3
+        ").into_iter().take(8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn start_a_new_segment_for_each_org() {
+        let asm = assemble("ORG &70 : EQUB 1 : ORG &1900 : EQUB 2, 3").unwrap();
+        assert_eq!(&[
+            Segment { origin: 0x70, bytes: vec![1] },
+            Segment { origin: 0x1900, bytes: vec![2, 3] },
+        ], asm.segments());
+
+        let mut mem = Map::new();
+        asm.load_into(&mut mem);
+        assert_eq!(1, mem.read(0x70));
+        assert_eq!(3, mem.read(0x1901));
+    }
+
+    #[test]
+    fn assemble_cmos_instructions_only_for_the_cmos_variant() {
+        let asm = assemble_for(CpuVariant::Cmos65sc12, "STZ &70 : LDA (&70) : BRA *").unwrap();
+        assert_eq!(vec![0x64, 0x70, 0xb2, 0x70, 0x80, 0xfe], asm.segments()[0].bytes);
+
+        assert_eq!(ErrorKind::InvalidAddressing("STZ".to_string()),
+                   assemble("STZ &70").unwrap_err().kind);
+    }
+
+    #[test]
+    fn round_trip_through_the_decoder() {
+        let asm = assemble("
+            ORG &1000
+            .top
+                LDA (&80),Y : STA &1234,X : ROR A : BIT &20
+                INC &70,X : JMP (&FFFC) : BNE top : LAX &1234,Y
+        ").unwrap();
+
+        let bytes = &asm.segments()[0].bytes;
+        let mut disassembled = vec![];
+        let mut pos = 0;
+        while pos < bytes.len() {
+            let (len, ins) = decode_instruction_for(CpuVariant::Nmos6502, &bytes[pos..]).unwrap();
+            assert_eq!(Ok(bytes[pos..pos + len].to_vec()), ins.encode());
+            disassembled.push(format!("{}", ins));
+            pos += len;
+        }
+
+        assert_eq!(8, disassembled.len());
+        assert_eq!(Some(0x1000), asm.symbol("top"));
+    }
+
+    #[test]
+    fn report_errors_with_their_line() {
+        let err = assemble("NOP\nLDA missing\n").unwrap_err();
+        assert_eq!(AssemblerError {
+            line: 2,
+            kind: ErrorKind::UndefinedSymbol("missing".to_string())
+        }, err);
+        assert_eq!("Line 2: Undefined symbol 'missing'", format!("{}", err));
+
+        assert_eq!(ErrorKind::UnknownMnemonic("FOO".to_string()),
+                   assemble("FOO").unwrap_err().kind);
+        assert_eq!(ErrorKind::DuplicateSymbol("a".to_string()),
+                   assemble(".a\n.a").unwrap_err().kind);
+        assert_eq!(ErrorKind::ValueOutOfRange(256), assemble("LDA #256").unwrap_err().kind);
+        assert_eq!(ErrorKind::BranchOutOfRange(198),
+                   assemble("BEQ end : ORG 200 : .end").unwrap_err().kind);
+    }
+}
@@ -12,22 +12,27 @@ use std::thread;
 use std::cmp;
 use std::path::Path;
 
-use minifb::{Window, WindowOptions, Key};
+use minifb::{Window, WindowOptions, Key, KeyRepeat};
 
 use bbc_em::cpu::CpuError;
-use bbc_em::emulator::{StepResult, Emulator, BbcEmulator};
+use bbc_em::emulator::{StepResult, Emulator, BbcEmulator, ElectronEmulator, Machine, ResetKind};
 use bbc_em::debugger::{
     Backend, 
     FrontEnd,
     FrontEndError,
 };
-use bbc_em::memory::Map;
+use bbc_em::memory::{Map, Model};
 use bbc_em::debugger::Error;
+use bbc_em::state::StateError;
 use bbc_em::video::FrameBuffer;
 
 const NS_PER_CYCLE: u64 = 500;
 const MAX_FRAME_NS: u64 = 2_000_000;
 
+/// The host key that acts as BREAK. Hold CTRL or SHIFT with it for
+/// CTRL-BREAK and SHIFT-BREAK.
+const BREAK_KEY: Key = Key::F12;
+
 struct Timer(Instant);
 
 impl Timer {
@@ -48,6 +53,7 @@ enum ApplicationError {
     Emulator(CpuError),
     DebuggerFrontEnd(FrontEndError),
     DebuggerBackEnd(Error),
+    State(StateError),
 }
 
 impl From<io::Error> for ApplicationError {
@@ -68,6 +74,12 @@ impl From<FrontEndError> for ApplicationError {
     }
 }
 
+impl From<StateError> for ApplicationError {
+    fn from(e: StateError) -> ApplicationError {
+        ApplicationError::State(e)
+    }
+}
+
 impl From<Error> for ApplicationError {
     fn from(e: Error) -> ApplicationError {
         ApplicationError::DebuggerBackEnd(e)
@@ -78,16 +90,71 @@ fn load_rom_file<P: AsRef<Path>>(path: P) -> io::Result<Vec<u8>> {
     fs::File::open(path)?.bytes().collect::<io::Result<Vec<_>>>()
 }
 
-fn build_memory(args: &[String]) -> io::Result<Map> {
-    let mut map = Map::new().with_hw_range(0xfe00..0xff00);
-    for f in &args[2..] {
-        map.add_paged_rom(load_rom_file(f)?);
+/// The machines that can be picked with `--machine`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum MachineArg {
+    Bbc(Machine),
+    Electron,
+}
+
+impl MachineArg {
+    fn parse(name: &str) -> Option<MachineArg> {
+        match name {
+            "a" => Some(MachineArg::Bbc(Machine::ModelA)),
+            "b" => Some(MachineArg::Bbc(Machine::ModelB)),
+            "bplus" => Some(MachineArg::Bbc(Machine::BPlus)),
+            "master" => Some(MachineArg::Bbc(Machine::Master128)),
+            "electron" => Some(MachineArg::Electron),
+            _ => None,
+        }
+    }
+}
+
+fn build_memory(machine: MachineArg, args: &[String]) -> io::Result<Map> {
+    let model = match machine {
+        MachineArg::Bbc(m) => m.model(),
+        MachineArg::Electron => Model::Electron,
+    };
+    let mut map = Map::new()
+        .with_model(model)
+        .with_paging_latch()
+        .with_hw_range(0xfc00..0xff00)
+        .with_one_mhz_ranges(model.one_mhz_ranges().iter().cloned())
+        .with_rom_ranges(vec![0xc000..0xfc00, 0xff00..0x10000]);
+
+    match machine {
+        // MOS 3.20 comes as a single image holding the MOS and the
+        // sideways ROMs that go with it
+        MachineArg::Bbc(Machine::Master128) => {
+            if let Some(f) = args.get(1) {
+                map.load_master_mos(&load_rom_file(f)?);
+            }
+        },
+        // The first paged ROM is BASIC, which the Electron looks for in
+        // slots 10 and 11. Slots 8 and 9 are the keyboard.
+        MachineArg::Electron => {
+            if let Some(f) = args.get(2) {
+                let basic = load_rom_file(f)?;
+                map.sideways_mut().insert_rom(10, &basic);
+                map.sideways_mut().insert_rom(11, &basic);
+            }
+            for (slot, f) in (12..16).rev().zip(args.iter().skip(3)) {
+                map.sideways_mut().insert_rom(slot, &load_rom_file(f)?);
+            }
+        },
+        // The first paged ROM, normally BASIC, goes in slot 15 as it would
+        // on a real machine, with the rest in the slots below it
+        MachineArg::Bbc(_) => {
+            for (slot, f) in (0..16).rev().zip(args.iter().skip(2)) {
+                map.sideways_mut().insert_rom(slot, &load_rom_file(f)?);
+            }
+        },
     }
 
     Ok(map)
 }
 
-fn run_emulator<E>(mut emu: E, args: &[String]) -> Result<(), ApplicationError>
+fn load_os_rom<E>(emu: &mut E, args: &[String]) -> Result<(), ApplicationError>
     where E: Emulator,
           ApplicationError: From<E::Error>
 {
@@ -98,7 +165,43 @@ fn run_emulator<E>(mut emu: E, args: &[String]) -> Result<(), ApplicationError>
 
     emu.place_rom_at(0xc000, os_rom.as_slice());
     emu.initialize()?;
+    Ok(())
+}
+
+/// Builds the BBC, picking up from the BeebEm snapshot at `state` if
+/// there is one.
+fn build_bbc(machine: Machine, args: &[String], state: Option<&String>)
+    -> Result<BbcEmulator<Map>, ApplicationError>
+{
+    let mut emu = BbcEmulator::with_machine(machine, build_memory(MachineArg::Bbc(machine), args)?);
+    match machine {
+        // build_memory has already installed MOS 3.20 and its sideways
+        // ROMs, so there's no OS ROM to place
+        Machine::Master128 => {
+            if args.get(1).is_none() {
+                return Err(ApplicationError::MissingRom("No OS ROM file specified!"));
+            }
+            emu.initialize()?;
+        },
+        _ => load_os_rom(&mut emu, args)?,
+    }
+    if let Some(path) = state {
+        emu.import_uef_state(&load_rom_file(path)?)?;
+    }
 
+    Ok(emu)
+}
+
+fn build_electron(args: &[String]) -> Result<ElectronEmulator, ApplicationError> {
+    let mut emu = ElectronEmulator::with_memory(build_memory(MachineArg::Electron, args)?);
+    load_os_rom(&mut emu, args)?;
+    Ok(emu)
+}
+
+fn run_emulator<E>(mut emu: E) -> Result<(), ApplicationError>
+    where E: Emulator,
+          ApplicationError: From<E::Error>
+{
     let mut window = Window::new("Bbc-Em",
                                  640,
                                  480,
@@ -139,11 +242,15 @@ fn run_emulator<E>(mut emu: E, args: &[String]) -> Result<(), ApplicationError>
 
         emu.clear_keyboard_buffer();
         window.get_keys().map(|keys| {
-            for k in keys {
+            for k in keys.into_iter().filter(|k| *k != BREAK_KEY) {
                 emu.keydown(k as u32);
             }
         });
 
+        if window.is_key_pressed(BREAK_KEY, KeyRepeat::No) {
+            emu.reset(ResetKind::Break)?;
+        }
+
         thread::sleep(Duration::from_millis(3));
     }
 
@@ -163,6 +270,9 @@ fn main() {
             args.remove(i);
             debug = true;
         });
+    // The debugger runs the emulator as a child process with the same
+    // arguments
+    let debug_args = args.clone();
 
     args.iter()
         .position(|i| *i == "--attach")
@@ -171,19 +281,54 @@ fn main() {
             attach = true
         });
 
-    match (debug, attach) {
-        (true, false) => FrontEnd::with_args(&args).run().unwrap(),
-        (false, true) => {
-            let emu = BbcEmulator::with_memory(build_memory(&args).unwrap());
-            run_emulator(Backend::new(emu), &args).unwrap();
+    let mut machine = MachineArg::Bbc(Machine::ModelB);
+    if let Some(i) = args.iter().position(|i| *i == "--machine") {
+        args.remove(i);
+        match args.get(i).and_then(|name| MachineArg::parse(name)) {
+            Some(m) => {
+                args.remove(i);
+                machine = m;
+            },
+            None => {
+                eprintln!("--machine must be followed by a, b, bplus, master or electron");
+                return;
+            },
+        }
+    }
+
+    let mut uef_state = None;
+    if let Some(i) = args.iter().position(|i| *i == "--uef-state") {
+        args.remove(i);
+        if i >= args.len() {
+            eprintln!("--uef-state must be followed by a file");
+            return;
+        }
+        uef_state = Some(args.remove(i));
+    }
+
+    // BeebEm snapshots hold a BBC, so there's nothing to load them into
+    if uef_state.is_some() && machine == MachineArg::Electron {
+        eprintln!("--uef-state can't be used with --machine electron");
+        return;
+    }
+
+    match (debug, attach, machine) {
+        (true, false, _) => FrontEnd::with_args(&debug_args).run().unwrap(),
+        (false, true, MachineArg::Bbc(m)) => {
+            let emu = build_bbc(m, &args, uef_state.as_ref()).unwrap();
+            run_emulator(Backend::new(emu)).unwrap();
+        }
+        (false, true, MachineArg::Electron) => {
+            run_emulator(Backend::new(build_electron(&args).unwrap())).unwrap();
         }
-        (false, false) => {
-            let emu = BbcEmulator::with_memory(build_memory(&args).unwrap());
-            run_emulator(emu, &args).unwrap();
+        (false, false, MachineArg::Bbc(m)) => {
+            run_emulator(build_bbc(m, &args, uef_state.as_ref()).unwrap()).unwrap();
+        }
+        (false, false, MachineArg::Electron) => {
+            run_emulator(build_electron(&args).unwrap()).unwrap();
         }
         _ => {
             eprintln!("--debug and --attach flags cannot be used together");
         }
     }
 }
-
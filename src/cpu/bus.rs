@@ -0,0 +1,56 @@
+use memory::{MemoryMap, BusCycle, BusOperation};
+
+/// Wraps a `MemoryMap`, recording every read and write the CPU makes
+/// through it.
+pub(super) struct Bus<'a, M> {
+    mem: M,
+    cycles: &'a mut Vec<BusCycle>,
+}
+
+impl<'a, M: MemoryMap> Bus<'a, M> {
+    pub(super) fn new(mem: M, cycles: &'a mut Vec<BusCycle>) -> Bus<'a, M> {
+        Bus {
+            mem: mem,
+            cycles: cycles,
+        }
+    }
+
+    fn record(&mut self, address: u16, value: u8, operation: BusOperation) {
+        let cycle = self.cycles.len();
+        self.cycles.push(BusCycle {
+            cycle: cycle,
+            address: address,
+            value: value,
+            operation: operation,
+        });
+    }
+}
+
+impl<'a, M: MemoryMap> MemoryMap for Bus<'a, M> {
+    fn hw_accesses(&self) -> &[BusCycle] {
+        self.mem.hw_accesses()
+    }
+
+    fn write(&mut self, loc: u16, val: u8) {
+        self.mem.write(loc, val);
+        self.record(loc, val, BusOperation::Write);
+    }
+
+    fn read(&mut self, loc: u16) -> u8 {
+        let val = self.mem.read(loc);
+        self.record(loc, val, BusOperation::Read);
+        val
+    }
+
+    fn clear_last_hw_access(&mut self) {
+        self.mem.clear_last_hw_access();
+    }
+
+    fn sync(&mut self, pc: u16) {
+        self.mem.sync(pc);
+    }
+
+    fn is_one_mhz(&self, loc: u16) -> bool {
+        self.mem.is_one_mhz(loc)
+    }
+}
@@ -0,0 +1,307 @@
+use super::{
+    Instruction,
+    InstructionDecodeError,
+    decode_u8,
+    decode_i8,
+    decode_u16
+};
+
+/// The opcodes that are new or behave differently on the 65SC12. Anything
+/// not listed here is decoded using the documented NMOS table.
+///
+/// Every opcode that was undocumented on the NMOS part is a `NOP` of some
+/// length on the CMOS parts.
+pub(super) fn decode<'a, I>(opcode: u8, mut iter: I)
+    -> Result<Option<Instruction>, InstructionDecodeError>
+    where I: Iterator<Item=&'a u8>
+{
+    use super::Addressing::*;
+    use super::OpCode::*;
+
+    let ins = match opcode {
+        0x72 => Some(Instruction(Adc, ZeroPageIndirect(decode_u8(&mut iter)?), 5)),
+        0x32 => Some(Instruction(And, ZeroPageIndirect(decode_u8(&mut iter)?), 5)),
+        0xd2 => Some(Instruction(Cmp, ZeroPageIndirect(decode_u8(&mut iter)?), 5)),
+        0x52 => Some(Instruction(Eor, ZeroPageIndirect(decode_u8(&mut iter)?), 5)),
+        0xb2 => Some(Instruction(Lda, ZeroPageIndirect(decode_u8(&mut iter)?), 5)),
+        0x12 => Some(Instruction(Ora, ZeroPageIndirect(decode_u8(&mut iter)?), 5)),
+        0xf2 => Some(Instruction(Sbc, ZeroPageIndirect(decode_u8(&mut iter)?), 5)),
+        0x92 => Some(Instruction(Sta, ZeroPageIndirect(decode_u8(&mut iter)?), 5)),
+
+        0x89 => Some(Instruction(Bit, Immediate(decode_u8(&mut iter)?), 2)),
+        0x34 => Some(Instruction(Bit, ZeroPageX(decode_u8(&mut iter)?), 4)),
+        0x3c => Some(Instruction(Bit, AbsoluteX(decode_u16(&mut iter)?), 4)),
+
+        0x80 => Some(Instruction(Bra, Relative(decode_i8(&mut iter)?), 3)),
+
+        0x1a => Some(Instruction(Inc, Accumulator, 2)),
+        0x3a => Some(Instruction(Dec, Accumulator, 2)),
+
+        0x6c => Some(Instruction(Jmp, Indirect(decode_u16(&mut iter)?), 6)),
+        0x7c => Some(Instruction(Jmp, AbsoluteIndexedIndirect(decode_u16(&mut iter)?), 6)),
+
+        0xda => Some(Instruction(Phx, Implied, 3)),
+        0x5a => Some(Instruction(Phy, Implied, 3)),
+        0xfa => Some(Instruction(Plx, Implied, 4)),
+        0x7a => Some(Instruction(Ply, Implied, 4)),
+
+        0x64 => Some(Instruction(Stz, ZeroPage(decode_u8(&mut iter)?), 3)),
+        0x74 => Some(Instruction(Stz, ZeroPageX(decode_u8(&mut iter)?), 4)),
+        0x9c => Some(Instruction(Stz, Absolute(decode_u16(&mut iter)?), 4)),
+        0x9e => Some(Instruction(Stz, AbsoluteX(decode_u16(&mut iter)?), 5)),
+
+        0x14 => Some(Instruction(Trb, ZeroPage(decode_u8(&mut iter)?), 5)),
+        0x1c => Some(Instruction(Trb, Absolute(decode_u16(&mut iter)?), 6)),
+        0x04 => Some(Instruction(Tsb, ZeroPage(decode_u8(&mut iter)?), 5)),
+        0x0c => Some(Instruction(Tsb, Absolute(decode_u16(&mut iter)?), 6)),
+
+        // Shifts and rotates with `abs,X` addressing are a cycle faster.
+        0x1e => Some(Instruction(Asl, AbsoluteX(decode_u16(&mut iter)?), 6)),
+        0x5e => Some(Instruction(Lsr, AbsoluteX(decode_u16(&mut iter)?), 6)),
+        0x3e => Some(Instruction(Rol, AbsoluteX(decode_u16(&mut iter)?), 6)),
+        0x7e => Some(Instruction(Ror, AbsoluteX(decode_u16(&mut iter)?), 6)),
+
+        0x02 | 0x22 | 0x42 | 0x62 | 0x82 | 0xc2 | 0xe2 => Some(Instruction(Nop, Immediate(decode_u8(&mut iter)?), 2)),
+        0x44 => Some(Instruction(Nop, ZeroPage(decode_u8(&mut iter)?), 3)),
+        0x54 | 0xd4 | 0xf4 => Some(Instruction(Nop, ZeroPageX(decode_u8(&mut iter)?), 4)),
+        0xdc | 0xfc => Some(Instruction(Nop, Absolute(decode_u16(&mut iter)?), 4)),
+        0x5c => Some(Instruction(Nop, Absolute(decode_u16(&mut iter)?), 8)),
+        n if (n & 0x03) == 0x03 => Some(Instruction(Nop, Implied, 1)),
+
+        _ => None
+    };
+
+    Ok(ins)
+}
+
+#[cfg(test)]
+mod decode_should {
+    use cpu::*;
+
+    fn decode(mem: &[u8]) -> (usize, Instruction) {
+        decode_instruction_for(CpuVariant::Cmos65sc12, mem).unwrap()
+    }
+
+    #[test]
+    fn read_cmos_instructions() {
+        assert_eq!(
+            (2, Instruction(OpCode::Lda, Addressing::ZeroPageIndirect(0x70), 5)),
+            decode(&[0xb2, 0x70]));
+        assert_eq!(
+            (3, Instruction(OpCode::Stz, Addressing::AbsoluteX(0x1234), 5)),
+            decode(&[0x9e, 0x34, 0x12]));
+        assert_eq!(
+            (1, Instruction(OpCode::Phx, Addressing::Implied, 3)),
+            decode(&[0xda]));
+        assert_eq!(
+            (3, Instruction(OpCode::Jmp, Addressing::AbsoluteIndexedIndirect(0x8000), 6)),
+            decode(&[0x7c, 0x00, 0x80]));
+        assert_eq!(
+            (1, Instruction(OpCode::Inc, Addressing::Accumulator, 2)),
+            decode(&[0x1a]));
+    }
+
+    #[test]
+    fn read_documented_instructions_from_the_nmos_table() {
+        assert_eq!(
+            (3, Instruction(OpCode::Adc, Addressing::AbsoluteX(0x8000), 4)),
+            decode(&[0x7d, 0x00, 0x80]));
+    }
+
+    #[test]
+    fn read_nmos_undocumented_opcodes_as_nops() {
+        assert_eq!((1, Instruction(OpCode::Nop, Addressing::Implied, 1)), decode(&[0xa7, 0x70]));
+        assert_eq!((2, Instruction(OpCode::Nop, Addressing::Immediate(0x70), 2)), decode(&[0x02, 0x70]));
+        assert_eq!((3, Instruction(OpCode::Nop, Addressing::Absolute(0x1234), 8)), decode(&[0x5c, 0x34, 0x12]));
+    }
+
+    /// Base cycle counts for the 65SC02 core, from the WDC W65C02S data
+    /// sheet with the Rockwell bit instructions replaced by one cycle NOPs.
+    const CMOS_CYCLES: [usize; 256] = [
+    //  0  1  2  3  4  5  6  7  8  9  a  b  c  d  e  f
+        7, 6, 2, 1, 5, 3, 5, 1, 3, 2, 2, 1, 6, 4, 6, 1, // 0
+        2, 5, 5, 1, 5, 4, 6, 1, 2, 4, 2, 1, 6, 4, 6, 1, // 1
+        6, 6, 2, 1, 3, 3, 5, 1, 4, 2, 2, 1, 4, 4, 6, 1, // 2
+        2, 5, 5, 1, 4, 4, 6, 1, 2, 4, 2, 1, 4, 4, 6, 1, // 3
+        6, 6, 2, 1, 3, 3, 5, 1, 3, 2, 2, 1, 3, 4, 6, 1, // 4
+        2, 5, 5, 1, 4, 4, 6, 1, 2, 4, 3, 1, 8, 4, 6, 1, // 5
+        6, 6, 2, 1, 3, 3, 5, 1, 4, 2, 2, 1, 6, 4, 6, 1, // 6
+        2, 5, 5, 1, 4, 4, 6, 1, 2, 4, 4, 1, 6, 4, 6, 1, // 7
+        3, 6, 2, 1, 3, 3, 3, 1, 2, 2, 2, 1, 4, 4, 4, 1, // 8
+        2, 6, 5, 1, 4, 4, 4, 1, 2, 5, 2, 1, 4, 5, 5, 1, // 9
+        2, 6, 2, 1, 3, 3, 3, 1, 2, 2, 2, 1, 4, 4, 4, 1, // a
+        2, 5, 5, 1, 4, 4, 4, 1, 2, 4, 2, 1, 4, 4, 4, 1, // b
+        2, 6, 2, 1, 3, 3, 5, 1, 2, 2, 2, 1, 4, 4, 6, 1, // c
+        2, 5, 5, 1, 4, 4, 6, 1, 2, 4, 3, 1, 4, 4, 7, 1, // d
+        2, 6, 2, 1, 3, 3, 5, 1, 2, 2, 2, 1, 4, 4, 6, 1, // e
+        2, 5, 5, 1, 4, 4, 6, 1, 2, 4, 4, 1, 4, 4, 7, 1, // f
+    ];
+
+    #[test]
+    fn report_the_published_base_cycles_for_every_opcode() {
+        let wrong = (0x00..0x100_usize)
+            .filter_map(|opcode| {
+                let mem = [opcode as u8, 0x00, 0x00];
+                match decode(&mem) {
+                    (_, Instruction(_, _, cycles)) if cycles != CMOS_CYCLES[opcode] => 
+                        Some(format!("{:02x}: {} != {}", opcode, cycles, CMOS_CYCLES[opcode])),
+                    _ => None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        assert!(wrong.is_empty(), "Incorrect cycles for {:?}", wrong);
+    }
+
+    #[test]
+    fn decode_every_opcode() {
+        for opcode in 0x00..0x100_usize {
+            let mem = [opcode as u8, 0x00, 0x00];
+            assert!(
+                decode_instruction_for(CpuVariant::Cmos65sc12, &mem).is_ok(),
+                "Opcode {:02x}", opcode);
+        }
+    }
+}
+
+#[cfg(test)]
+mod execute_should {
+    use cpu::*;
+    use cpu::execute_instruction;
+    use memory::{Map, MemoryMap};
+
+    fn execute(ins: Instruction, mem: &mut Map, reg: &mut Registers) -> usize {
+        execute_instruction(ins, mem, reg, CpuVariant::Cmos65sc12).unwrap()
+    }
+
+    #[test]
+    fn set_flags_from_the_bcd_result_in_decimal_mode() {
+        let mut mem = Map::new();
+        let mut reg = Registers::new();
+        reg.status.decimal = true;
+        reg.acc = 0x99;
+
+        let cycles = execute(Instruction(OpCode::Adc, Addressing::Immediate(0x01), 2), &mut mem, &mut reg);
+
+        assert_eq!(3, cycles);
+        assert_eq!(0x00, reg.acc);
+        assert!(reg.status.carry);
+        assert!(reg.status.zero);
+        assert!(!reg.status.negative);
+
+        reg.acc = 0x00;
+        reg.status.carry = true;
+        execute(Instruction(OpCode::Sbc, Addressing::Immediate(0x01), 2), &mut mem, &mut reg);
+
+        assert_eq!(0x99, reg.acc);
+        assert!(!reg.status.carry);
+        assert!(reg.status.negative);
+        assert!(!reg.status.zero);
+    }
+
+    #[test]
+    fn not_wrap_jmp_indirect_within_the_page() {
+        let mut mem = Map::new();
+        let mut reg = Registers::new();
+        mem.write(0x10ff, 0x34);
+        mem.write(0x1000, 0x56);
+        mem.write(0x1100, 0x12);
+
+        execute(Instruction(OpCode::Jmp, Addressing::Indirect(0x10ff), 6), &mut mem, &mut reg);
+        assert_eq!(0x1234, reg.pc);
+
+        execute_instruction(
+            Instruction(OpCode::Jmp, Addressing::Indirect(0x10ff), 5),
+            &mut mem,
+            &mut reg,
+            CpuVariant::Nmos6502
+        ).unwrap();
+        assert_eq!(0x5634, reg.pc);
+    }
+
+    #[test]
+    fn jump_through_an_indexed_vector() {
+        let mut mem = Map::new();
+        let mut reg = Registers::new();
+        reg.x = 0x02;
+        mem.write(0x2002, 0x00);
+        mem.write(0x2003, 0x80);
+
+        execute(Instruction(OpCode::Jmp, Addressing::AbsoluteIndexedIndirect(0x2000), 6), &mut mem, &mut reg);
+        assert_eq!(0x8000, reg.pc);
+    }
+
+    #[test]
+    fn test_and_modify_bits() {
+        let mut mem = Map::new();
+        let mut reg = Registers::new();
+        mem.write(0x0010, 0x0f);
+        reg.acc = 0x11;
+
+        execute(Instruction(OpCode::Tsb, Addressing::ZeroPage(0x10), 5), &mut mem, &mut reg);
+        assert_eq!(0x1f, mem.read(0x0010));
+        assert!(!reg.status.zero);
+
+        reg.acc = 0x03;
+        execute(Instruction(OpCode::Trb, Addressing::ZeroPage(0x10), 5), &mut mem, &mut reg);
+        assert_eq!(0x1c, mem.read(0x0010));
+
+        reg.acc = 0x03;
+        execute(Instruction(OpCode::Trb, Addressing::ZeroPage(0x10), 5), &mut mem, &mut reg);
+        assert!(reg.status.zero);
+    }
+
+    #[test]
+    fn push_and_pull_index_registers() {
+        let mut mem = Map::new();
+        let mut reg = Registers::new();
+        reg.sp = 0xff;
+        reg.x = 0x80;
+
+        execute(Instruction(OpCode::Phx, Addressing::Implied, 3), &mut mem, &mut reg);
+        execute(Instruction(OpCode::Ply, Addressing::Implied, 4), &mut mem, &mut reg);
+
+        assert_eq!(0x80, reg.y);
+        assert_eq!(0xff, reg.sp);
+        assert!(reg.status.negative);
+    }
+
+    #[test]
+    fn use_zero_page_indirect_addressing() {
+        let mut mem = Map::new();
+        let mut reg = Registers::new();
+        mem.write(0x0080, 0x00);
+        mem.write(0x0081, 0x30);
+        mem.write(0x3000, 0xaa);
+
+        execute(Instruction(OpCode::Lda, Addressing::ZeroPageIndirect(0x80), 5), &mut mem, &mut reg);
+        assert_eq!(0xaa, reg.acc);
+
+        reg.acc = 0x55;
+        execute(Instruction(OpCode::Sta, Addressing::ZeroPageIndirect(0x80), 5), &mut mem, &mut reg);
+        assert_eq!(0x55, mem.read(0x3000));
+    }
+
+    #[test]
+    fn only_set_zero_for_bit_immediate() {
+        let mut mem = Map::new();
+        let mut reg = Registers::new();
+        reg.acc = 0x01;
+
+        execute(Instruction(OpCode::Bit, Addressing::Immediate(0xc0), 2), &mut mem, &mut reg);
+        assert!(reg.status.zero);
+        assert!(!reg.status.negative);
+        assert!(!reg.status.overflow);
+    }
+
+    #[test]
+    fn clear_decimal_on_brk() {
+        let mut mem = Map::new();
+        let mut reg = Registers::new();
+        reg.sp = 0xff;
+        reg.status.decimal = true;
+
+        execute(Instruction(OpCode::Brk, Addressing::Implied, 7), &mut mem, &mut reg);
+        assert!(!reg.status.decimal);
+    }
+}
@@ -0,0 +1,96 @@
+use state::{SaveState, StateReader, StateWriter, StateError};
+
+/// The devices that can drive the CPU's interrupt inputs.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum InterruptSource {
+    SystemVia = 0,
+    UserVia = 1,
+    Acia = 2,
+    DiscController = 3,
+    Econet = 4,
+    /// The Electron's ULA.
+    Ula = 5,
+}
+
+impl InterruptSource {
+    fn mask(self) -> u8 {
+        0x01 << self as u8
+    }
+}
+
+/// The IRQ input. Every source has an open collector output on the same
+/// wire, so the line is asserted for as long as any of them holds it.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub struct IrqLine {
+    asserted_by: u8,
+}
+
+impl IrqLine {
+    pub fn set(&mut self, source: InterruptSource, asserted: bool) {
+        if asserted {
+            self.asserted_by |= source.mask();
+        }
+        else {
+            self.asserted_by &= !source.mask();
+        }
+    }
+
+    pub fn is_asserted(&self) -> bool {
+        self.asserted_by != 0
+    }
+}
+
+/// The NMI input. Shared in the same way as `IrqLine`, but the CPU only
+/// responds to it going from released to asserted, so that edge is
+/// latched until the CPU takes it.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub struct NmiLine {
+    line: IrqLine,
+    edge: bool,
+}
+
+impl NmiLine {
+    pub fn set(&mut self, source: InterruptSource, asserted: bool) {
+        let was_asserted = self.line.is_asserted();
+        self.line.set(source, asserted);
+        if !was_asserted && self.line.is_asserted() {
+            self.edge = true;
+        }
+    }
+
+    pub fn is_asserted(&self) -> bool {
+        self.line.is_asserted()
+    }
+
+    /// Returns `true`, once, if the line has been asserted since the last
+    /// call.
+    pub fn take_edge(&mut self) -> bool {
+        let edge = self.edge;
+        self.edge = false;
+        edge
+    }
+}
+
+impl SaveState for IrqLine {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_u8(self.asserted_by);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.asserted_by = state.read_u8()?;
+        Ok(())
+    }
+}
+
+impl SaveState for NmiLine {
+    fn save(&self, state: &mut StateWriter) {
+        self.line.save(state);
+        state.write_bool(self.edge);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.line.load(state)?;
+        self.edge = state.read_bool()?;
+        Ok(())
+    }
+}
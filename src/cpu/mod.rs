@@ -1,9 +1,29 @@
 use std::fmt;
 use std::error::Error;
 
-use memory::{MemoryMap, AsMemoryRegion, AsMemoryRegionMut};
+use memory::{MemoryMap, AsMemoryRegion, AsMemoryRegionMut, BusCycle};
+use state::{SaveState, StateReader, StateWriter, StateError};
+use self::bus::Bus;
 
-#[derive(Debug, PartialEq)]
+mod bus;
+mod cmos;
+mod lines;
+mod observer;
+
+pub use self::lines::{InterruptSource, IrqLine, NmiLine};
+pub use self::observer::{CpuObserver, Interrupt};
+
+/// The flavour of 6502 being emulated.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CpuVariant {
+    /// The NMOS 6502 fitted to the Model A, Model B and B+.
+    Nmos6502,
+    /// The CMOS 65SC12 fitted to the Master series. This is a 65SC02 core,
+    /// so it lacks the Rockwell bit manipulation instructions.
+    Cmos65sc12,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Addressing {
     Implied,
     Accumulator,
@@ -18,6 +38,8 @@ pub enum Addressing {
     Indirect(u16),
     IndirectX(u8),
     IndirectY(u8),
+    ZeroPageIndirect(u8),
+    AbsoluteIndexedIndirect(u16),
 }
 
 impl fmt::Display for Addressing {
@@ -37,12 +59,14 @@ impl fmt::Display for Addressing {
             Indirect(ref a) => write!(f, "$({:04x})", *a),
             IndirectX(ref a) => write!(f, "$({:02x}, X)", *a),
             IndirectY(ref a) => write!(f, "$({:02x}), Y", *a),
+            ZeroPageIndirect(ref a) => write!(f, "$({:02x})", *a),
+            AbsoluteIndexedIndirect(ref a) => write!(f, "$({:04x}, X)", *a),
             _ => return Ok(())
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum OpCode {
     Adc,
     And,
@@ -100,9 +124,34 @@ pub enum OpCode {
     Txa,
     Txs,
     Tya,
+
+    // Undocumented NMOS opcodes
+    Alr,
+    Anc,
+    Arr,
+    Dcp,
+    Isc,
+    Jam,
+    Lax,
+    Rla,
+    Rra,
+    Sax,
+    Sbx,
+    Slo,
+    Sre,
+
+    // CMOS only opcodes
+    Bra,
+    Phx,
+    Phy,
+    Plx,
+    Ply,
+    Stz,
+    Trb,
+    Tsb,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub struct Instruction(OpCode, Addressing, usize);
 
 impl fmt::Display for Instruction {
@@ -114,6 +163,80 @@ impl fmt::Display for Instruction {
 #[derive(Debug, PartialEq)]
 pub struct InstructionDecodeError;
 
+#[derive(Debug, PartialEq)]
+pub struct InstructionEncodeError;
+
+impl Addressing {
+    /// The operand bytes that follow the opcode, in the order they appear
+    /// in memory.
+    pub fn operand_bytes(&self) -> Vec<u8> {
+        use self::Addressing::*;
+
+        match *self {
+            Implied | Accumulator => vec![],
+            Immediate(ref v) | ZeroPage(ref v) | ZeroPageX(ref v) | ZeroPageY(ref v) |
+            IndirectX(ref v) | IndirectY(ref v) | ZeroPageIndirect(ref v) => vec![*v],
+            Relative(ref v) => vec![*v as u8],
+            Absolute(ref v) | AbsoluteX(ref v) | AbsoluteY(ref v) | 
+            Indirect(ref v) | AbsoluteIndexedIndirect(ref v) => 
+                vec![(*v & 0x00ff) as u8, ((*v & 0xff00) >> 8) as u8],
+        }
+    }
+}
+
+impl Instruction {
+    /// Encodes the instruction for the NMOS 6502. This is the inverse of
+    /// `decode_instruction`.
+    pub fn encode(&self) -> Result<Vec<u8>, InstructionEncodeError> {
+        self.encode_for(CpuVariant::Nmos6502)
+    }
+
+    /// Encodes the instruction using the opcode table for `variant`. Where
+    /// several opcodes behave the same, the one with a matching cycle count
+    /// is used, so the result always decodes back to `self`.
+    pub fn encode_for(&self, variant: CpuVariant) -> Result<Vec<u8>, InstructionEncodeError> {
+        encode(variant, &self.1.operand_bytes(), |ins| ins == self)
+            .ok_or_else(|| InstructionEncodeError)
+    }
+}
+
+/// Encodes `opcode` with `addr` using the opcode table for `variant`. The
+/// documented opcodes are preferred over undocumented ones that do the same
+/// thing.
+pub fn encode_instruction_for(variant: CpuVariant, opcode: OpCode, addr: Addressing)
+    -> Result<Vec<u8>, InstructionEncodeError>
+{
+    encode(variant, &addr.operand_bytes(), |ins| ins.0 == opcode && ins.1 == addr)
+        .ok_or_else(|| InstructionEncodeError)
+}
+
+fn encode<F>(variant: CpuVariant, operands: &[u8], matches: F) -> Option<Vec<u8>>
+    where F: Fn(&Instruction) -> bool
+{
+    let is_documented = |op: &u8| {
+        match decode_documented(*op, operands.iter()) {
+            Ok(Some(_)) => true,
+            _ => false,
+        }
+    };
+
+    let opcodes = (0..256usize).map(|op| op as u8);
+    let candidates = opcodes.clone().filter(|op| is_documented(op))
+        .chain(opcodes.filter(|op| !is_documented(op)));
+
+    for op in candidates {
+        let mut bytes = vec![op];
+        bytes.extend_from_slice(operands);
+
+        match decode_instruction_for(variant, &bytes) {
+            Ok((len, ins)) if len == bytes.len() && matches(&ins) => return Some(bytes),
+            _ => {}
+        }
+    }
+
+    None
+}
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub struct StatusFlags {
     pub negative: bool,
@@ -215,221 +338,26 @@ fn decode_u16<'a, I>(iter: &mut I) -> Result<u16, InstructionDecodeError>
 }
 
 pub fn decode_instruction(mem: &[u8]) -> Result<(usize, Instruction), InstructionDecodeError> {
-    use self::Addressing::*;
-    use self::OpCode::*;
+    decode_instruction_for(CpuVariant::Nmos6502, mem)
+}
 
+/// Decodes the instruction at the start of `mem` using the opcode table for
+/// `variant`.
+pub fn decode_instruction_for(variant: CpuVariant, mem: &[u8]) 
+    -> Result<(usize, Instruction), InstructionDecodeError> 
+{
     let mut iter = mem.iter();
     let len = iter.as_slice().len();
     if let Some(opcode) = iter.next() {
-        let ins = match *opcode {
-            0x69 => Some(Instruction(Adc, Immediate(decode_u8(&mut iter)?), 2)),
-            0x65 => Some(Instruction(Adc, ZeroPage(decode_u8(&mut iter)?),  3)),
-            0x75 => Some(Instruction(Adc, ZeroPageX(decode_u8(&mut iter)?), 4)),
-            0x6d => Some(Instruction(Adc, Absolute(decode_u16(&mut iter)?),4)),
-            0x7d => Some(Instruction(Adc, AbsoluteX(decode_u16(&mut iter)?),4)),
-            0x79 => Some(Instruction(Adc, AbsoluteY(decode_u16(&mut iter)?),4)),
-            0x61 => Some(Instruction(Adc, IndirectX(decode_u8(&mut iter)?), 6)),
-            0x71 => Some(Instruction(Adc, IndirectY(decode_u8(&mut iter)?), 5)),
-
-            0x29 => Some(Instruction(And, Immediate(decode_u8(&mut iter)?), 2)),
-            0x25 => Some(Instruction(And, ZeroPage(decode_u8(&mut iter)?),  3)),
-            0x35 => Some(Instruction(And, ZeroPageX(decode_u8(&mut iter)?), 4)),
-            0x2d => Some(Instruction(And, Absolute(decode_u16(&mut iter)?),4)),
-            0x3d => Some(Instruction(And, AbsoluteX(decode_u16(&mut iter)?),4)),
-            0x39 => Some(Instruction(And, AbsoluteY(decode_u16(&mut iter)?),4)),
-            0x21 => Some(Instruction(And, IndirectX(decode_u8(&mut iter)?), 6)),
-            0x31 => Some(Instruction(And, IndirectY(decode_u8(&mut iter)?), 5)),
-
-            0x0a => Some(Instruction(Asl, Accumulator, 2)),
-            0x06 => Some(Instruction(Asl, ZeroPage(decode_u8(&mut iter)?), 5)),
-            0x16 => Some(Instruction(Asl, ZeroPageX(decode_u8(&mut iter)?), 6)),
-            0x0e => Some(Instruction(Asl, Absolute(decode_u16(&mut iter)?), 6)),
-            0x1e => Some(Instruction(Asl, AbsoluteX(decode_u16(&mut iter)?), 7)),
-
-            0x90 => Some(Instruction(Bcc, Relative(decode_i8(&mut iter)?), 2)),
-
-            0xb0 => Some(Instruction(Bcs, Relative(decode_i8(&mut iter)?), 2)),
-
-            0xf0 => Some(Instruction(Beq, Relative(decode_i8(&mut iter)?), 2)),
-
-            0x30 => Some(Instruction(Bmi, Relative(decode_i8(&mut iter)?), 2)),
-
-            0xd0 => Some(Instruction(Bne, Relative(decode_i8(&mut iter)?), 2)),
-
-            0x10 => Some(Instruction(Bpl, Relative(decode_i8(&mut iter)?), 2)),
-
-            0x50 => Some(Instruction(Bvc, Relative(decode_i8(&mut iter)?), 2)),
-
-            0x70 => Some(Instruction(Bvs, Relative(decode_i8(&mut iter)?), 2)),
-
-            0x24 => Some(Instruction(Bit, ZeroPage(decode_u8(&mut iter)?), 3)),
-            0x2c => Some(Instruction(Bit, Absolute(decode_u16(&mut iter)?), 4)),
-
-            0x00 => Some(Instruction(Brk, Implied, 7)),
-
-            0x18 => Some(Instruction(Clc, Implied, 2)),
-
-            0xd8 => Some(Instruction(Cld, Implied, 2)),
-
-            0x58 => Some(Instruction(Cli, Implied, 2)),
-
-            0xb8 => Some(Instruction(Clv, Implied, 2)),
-
-            0xc9 => Some(Instruction(Cmp, Immediate(decode_u8(&mut iter)?), 2)),
-            0xc5 => Some(Instruction(Cmp, ZeroPage(decode_u8(&mut iter)?), 2)),
-            0xd5 => Some(Instruction(Cmp, ZeroPageX(decode_u8(&mut iter)?), 4)),
-            0xcd => Some(Instruction(Cmp, Absolute(decode_u16(&mut iter)?), 4)),
-            0xdd => Some(Instruction(Cmp, AbsoluteX(decode_u16(&mut iter)?), 4)),
-            0xd9 => Some(Instruction(Cmp, AbsoluteY(decode_u16(&mut iter)?), 4)),
-            0xc1 => Some(Instruction(Cmp, IndirectX(decode_u8(&mut iter)?), 6)),
-            0xd1 => Some(Instruction(Cmp, IndirectY(decode_u8(&mut iter)?), 5)),
-            
-            0xe0 => Some(Instruction(Cpx, Immediate(decode_u8(&mut iter)?), 2)),
-            0xe4 => Some(Instruction(Cpx, ZeroPage(decode_u8(&mut iter)?), 3)),
-            0xec => Some(Instruction(Cpx, Absolute(decode_u16(&mut iter)?), 4)),
-            
-            0xc0 => Some(Instruction(Cpy, Immediate(decode_u8(&mut iter)?), 2)),
-            0xc4 => Some(Instruction(Cpy, ZeroPage(decode_u8(&mut iter)?), 3)),
-            0xcc => Some(Instruction(Cpy, Absolute(decode_u16(&mut iter)?), 4)),
-            
-            0xc6 => Some(Instruction(Dec, ZeroPage(decode_u8(&mut iter)?), 5)),
-            0xd6 => Some(Instruction(Dec, ZeroPageX(decode_u8(&mut iter)?), 6)),
-            0xce => Some(Instruction(Dec, Absolute(decode_u16(&mut iter)?), 3)),
-            0xde => Some(Instruction(Dec, AbsoluteX(decode_u16(&mut iter)?), 7)),
-            
-            0xca => Some(Instruction(Dex, Implied, 2)),
-
-            0x88 => Some(Instruction(Dey, Implied, 2)),
-
-            0x49 => Some(Instruction(Eor, Immediate(decode_u8(&mut iter)?), 2)),
-            0x45 => Some(Instruction(Eor, ZeroPage(decode_u8(&mut iter)?), 3)),
-            0x55 => Some(Instruction(Eor, ZeroPageX(decode_u8(&mut iter)?), 4)),
-            0x4d => Some(Instruction(Eor, Absolute(decode_u16(&mut iter)?), 4)),
-            0x5d => Some(Instruction(Eor, AbsoluteX(decode_u16(&mut iter)?), 4)),
-            0x59 => Some(Instruction(Eor, AbsoluteY(decode_u16(&mut iter)?), 4)),
-            0x41 => Some(Instruction(Eor, IndirectX(decode_u8(&mut iter)?), 6)),
-            0x51 => Some(Instruction(Eor, IndirectY(decode_u8(&mut iter)?), 5)),
-            
-            0xe6 => Some(Instruction(Inc, ZeroPage(decode_u8(&mut iter)?), 5)),
-            0xf6 => Some(Instruction(Inc, ZeroPageX(decode_u8(&mut iter)?), 6)),
-            0xee => Some(Instruction(Inc, Absolute(decode_u16(&mut iter)?), 6)),
-            0xfe => Some(Instruction(Inc, AbsoluteX(decode_u16(&mut iter)?), 7)),
-            
-            0xe8 => Some(Instruction(Inx, Implied, 2)),
-
-            0xc8 => Some(Instruction(Iny, Implied, 2)),
-
-            0x4c => Some(Instruction(Jmp, Absolute(decode_u16(&mut iter)?), 3)),
-            0x6c => Some(Instruction(Jmp, Indirect(decode_u16(&mut iter)?), 5)),
-
-            0x20 => Some(Instruction(Jsr, Absolute(decode_u16(&mut iter)?), 6)),
-
-            0xa9 => Some(Instruction(Lda, Immediate(decode_u8(&mut iter)?), 2)),
-            0xa5 => Some(Instruction(Lda, ZeroPage(decode_u8(&mut iter)?), 3)),
-            0xb5 => Some(Instruction(Lda, ZeroPageX(decode_u8(&mut iter)?), 4)),
-            0xad => Some(Instruction(Lda, Absolute(decode_u16(&mut iter)?), 4)),
-            0xbd => Some(Instruction(Lda, AbsoluteX(decode_u16(&mut iter)?), 4)),
-            0xb9 => Some(Instruction(Lda, AbsoluteY(decode_u16(&mut iter)?), 4)),
-            0xa1 => Some(Instruction(Lda, IndirectX(decode_u8(&mut iter)?), 6)),
-            0xb1 => Some(Instruction(Lda, IndirectY(decode_u8(&mut iter)?), 5)),
-            
-            0xa2 => Some(Instruction(Ldx, Immediate(decode_u8(&mut iter)?), 2)),
-            0xa6 => Some(Instruction(Ldx, ZeroPage(decode_u8(&mut iter)?), 3)),
-            0xb6 => Some(Instruction(Ldx, ZeroPageY(decode_u8(&mut iter)?), 4)),
-            0xae => Some(Instruction(Ldx, Absolute(decode_u16(&mut iter)?), 4)),
-            0xbe => Some(Instruction(Ldx, AbsoluteY(decode_u16(&mut iter)?), 4)),
-            
-            0xa0 => Some(Instruction(Ldy, Immediate(decode_u8(&mut iter)?), 2)),
-            0xa4 => Some(Instruction(Ldy, ZeroPage(decode_u8(&mut iter)?), 3)),
-            0xb4 => Some(Instruction(Ldy, ZeroPageX(decode_u8(&mut iter)?), 4)),
-            0xac => Some(Instruction(Ldy, Absolute(decode_u16(&mut iter)?), 4)),
-            0xbc => Some(Instruction(Ldy, AbsoluteX(decode_u16(&mut iter)?), 4)),
-            
-            0x4a => Some(Instruction(Lsr, Accumulator, 2)),
-            0x46 => Some(Instruction(Lsr, ZeroPage(decode_u8(&mut iter)?), 5)),
-            0x56 => Some(Instruction(Lsr, ZeroPageX(decode_u8(&mut iter)?), 6)),
-            0x4e => Some(Instruction(Lsr, Absolute(decode_u16(&mut iter)?), 6)),
-            0x5e => Some(Instruction(Lsr, AbsoluteX(decode_u16(&mut iter)?), 7)),
-
-            0xea => Some(Instruction(Nop, Implied, 2)),
-
-            0x09 => Some(Instruction(Ora, Immediate(decode_u8(&mut iter)?), 2)),
-            0x05 => Some(Instruction(Ora, ZeroPage(decode_u8(&mut iter)?), 3)),
-            0x15 => Some(Instruction(Ora, ZeroPageX(decode_u8(&mut iter)?), 4)),
-            0x0d => Some(Instruction(Ora, Absolute(decode_u16(&mut iter)?), 4)),
-            0x1d => Some(Instruction(Ora, AbsoluteX(decode_u16(&mut iter)?), 4)),
-            0x19 => Some(Instruction(Ora, AbsoluteY(decode_u16(&mut iter)?), 4)),
-            0x01 => Some(Instruction(Ora, IndirectX(decode_u8(&mut iter)?), 6)),
-            0x11 => Some(Instruction(Ora, IndirectY(decode_u8(&mut iter)?), 5)),
-            
-            0x48 => Some(Instruction(Pha, Implied, 3)),
-
-            0x08 => Some(Instruction(Php, Implied, 3)),
-
-            0x68 => Some(Instruction(Pla, Implied, 4)),
-
-            0x28 => Some(Instruction(Plp, Implied, 4)),
-
-            0x2a => Some(Instruction(Rol, Accumulator, 2)),
-            0x26 => Some(Instruction(Rol, ZeroPage(decode_u8(&mut iter)?), 5)),
-            0x36 => Some(Instruction(Rol, ZeroPageX(decode_u8(&mut iter)?), 6)),
-            0x2e => Some(Instruction(Rol, Absolute(decode_u16(&mut iter)?), 6)),
-            0x3e => Some(Instruction(Rol, AbsoluteX(decode_u16(&mut iter)?), 7)),
-
-            0x6a => Some(Instruction(Ror, Accumulator, 2)),
-            0x66 => Some(Instruction(Ror, ZeroPage(decode_u8(&mut iter)?), 5)),
-            0x76 => Some(Instruction(Ror, ZeroPageX(decode_u8(&mut iter)?), 6)),
-            0x6e => Some(Instruction(Ror, Absolute(decode_u16(&mut iter)?), 6)),
-            0x7e => Some(Instruction(Ror, AbsoluteX(decode_u16(&mut iter)?), 7)),
-
-            0x40 => Some(Instruction(Rti, Implied, 6)),
-
-            0x60 => Some(Instruction(Rts, Implied, 6)),
-
-            0xe9 => Some(Instruction(Sbc, Immediate(decode_u8(&mut iter)?), 2)),
-            0xe5 => Some(Instruction(Sbc, ZeroPage(decode_u8(&mut iter)?), 3)),
-            0xf5 => Some(Instruction(Sbc, ZeroPageX(decode_u8(&mut iter)?), 4)),
-            0xed => Some(Instruction(Sbc, Absolute(decode_u16(&mut iter)?), 4)),
-            0xfd => Some(Instruction(Sbc, AbsoluteX(decode_u16(&mut iter)?), 4)),
-            0xf9 => Some(Instruction(Sbc, AbsoluteY(decode_u16(&mut iter)?), 4)),
-            0xe1 => Some(Instruction(Sbc, IndirectX(decode_u8(&mut iter)?), 6)),
-            0xf1 => Some(Instruction(Sbc, IndirectY(decode_u8(&mut iter)?), 5)),
-            
-            0x38 => Some(Instruction(Sec, Implied, 2)),
-
-            0xf8 => Some(Instruction(Sed, Implied, 2)),
-
-            0x78 => Some(Instruction(Sei, Implied, 2)),
-
-            0x85 => Some(Instruction(Sta, ZeroPage(decode_u8(&mut iter)?), 3)),
-            0x95 => Some(Instruction(Sta, ZeroPageX(decode_u8(&mut iter)?), 4)),
-            0x8d => Some(Instruction(Sta, Absolute(decode_u16(&mut iter)?), 4)),
-            0x9d => Some(Instruction(Sta, AbsoluteX(decode_u16(&mut iter)?), 5)),
-            0x99 => Some(Instruction(Sta, AbsoluteY(decode_u16(&mut iter)?), 5)),
-            0x81 => Some(Instruction(Sta, IndirectX(decode_u8(&mut iter)?), 6)),
-            0x91 => Some(Instruction(Sta, IndirectY(decode_u8(&mut iter)?), 6)),
-            
-            0x86 => Some(Instruction(Stx, ZeroPage(decode_u8(&mut iter)?), 3)),
-            0x96 => Some(Instruction(Stx, ZeroPageY(decode_u8(&mut iter)?), 4)),
-            0x8e => Some(Instruction(Stx, Absolute(decode_u16(&mut iter)?), 4)),
-
-            0x84 => Some(Instruction(Sty, ZeroPage(decode_u8(&mut iter)?), 3)),
-            0x94 => Some(Instruction(Sty, ZeroPageX(decode_u8(&mut iter)?), 4)),
-            0x8c => Some(Instruction(Sty, Absolute(decode_u16(&mut iter)?), 4)),
-
-            0xaa => Some(Instruction(Tax, Implied, 2)),
-
-            0xa8 => Some(Instruction(Tay, Implied, 2)),
-
-            0xba => Some(Instruction(Tsx, Implied, 2)),
-
-            0x8a => Some(Instruction(Txa, Implied, 2)),
-
-            0x9a => Some(Instruction(Txs, Implied, 2)),
-
-            0x98 => Some(Instruction(Tya, Implied, 2)),
-
-            _ => None
+        let ins = match variant {
+            CpuVariant::Nmos6502 => match decode_documented(*opcode, &mut iter)? {
+                None => decode_undocumented(*opcode, &mut iter)?,
+                ins => ins,
+            },
+            CpuVariant::Cmos65sc12 => match cmos::decode(*opcode, &mut iter)? {
+                None => decode_documented(*opcode, &mut iter)?,
+                ins => ins,
+            },
         };
 
         if let Some(ins) = ins {
@@ -440,6 +368,323 @@ pub fn decode_instruction(mem: &[u8]) -> Result<(usize, Instruction), Instructio
     Err(InstructionDecodeError)
 }
 
+/// The opcodes shared by every 6502 variant.
+fn decode_documented<'a, I>(opcode: u8, mut iter: I) 
+    -> Result<Option<Instruction>, InstructionDecodeError>
+    where I: Iterator<Item=&'a u8>
+{
+    use self::Addressing::*;
+    use self::OpCode::*;
+
+    let ins = match opcode {
+        0x69 => Some(Instruction(Adc, Immediate(decode_u8(&mut iter)?), 2)),
+        0x65 => Some(Instruction(Adc, ZeroPage(decode_u8(&mut iter)?),  3)),
+        0x75 => Some(Instruction(Adc, ZeroPageX(decode_u8(&mut iter)?), 4)),
+        0x6d => Some(Instruction(Adc, Absolute(decode_u16(&mut iter)?),4)),
+        0x7d => Some(Instruction(Adc, AbsoluteX(decode_u16(&mut iter)?),4)),
+        0x79 => Some(Instruction(Adc, AbsoluteY(decode_u16(&mut iter)?),4)),
+        0x61 => Some(Instruction(Adc, IndirectX(decode_u8(&mut iter)?), 6)),
+        0x71 => Some(Instruction(Adc, IndirectY(decode_u8(&mut iter)?), 5)),
+
+        0x29 => Some(Instruction(And, Immediate(decode_u8(&mut iter)?), 2)),
+        0x25 => Some(Instruction(And, ZeroPage(decode_u8(&mut iter)?),  3)),
+        0x35 => Some(Instruction(And, ZeroPageX(decode_u8(&mut iter)?), 4)),
+        0x2d => Some(Instruction(And, Absolute(decode_u16(&mut iter)?),4)),
+        0x3d => Some(Instruction(And, AbsoluteX(decode_u16(&mut iter)?),4)),
+        0x39 => Some(Instruction(And, AbsoluteY(decode_u16(&mut iter)?),4)),
+        0x21 => Some(Instruction(And, IndirectX(decode_u8(&mut iter)?), 6)),
+        0x31 => Some(Instruction(And, IndirectY(decode_u8(&mut iter)?), 5)),
+
+        0x0a => Some(Instruction(Asl, Accumulator, 2)),
+        0x06 => Some(Instruction(Asl, ZeroPage(decode_u8(&mut iter)?), 5)),
+        0x16 => Some(Instruction(Asl, ZeroPageX(decode_u8(&mut iter)?), 6)),
+        0x0e => Some(Instruction(Asl, Absolute(decode_u16(&mut iter)?), 6)),
+        0x1e => Some(Instruction(Asl, AbsoluteX(decode_u16(&mut iter)?), 7)),
+
+        0x90 => Some(Instruction(Bcc, Relative(decode_i8(&mut iter)?), 2)),
+
+        0xb0 => Some(Instruction(Bcs, Relative(decode_i8(&mut iter)?), 2)),
+
+        0xf0 => Some(Instruction(Beq, Relative(decode_i8(&mut iter)?), 2)),
+
+        0x30 => Some(Instruction(Bmi, Relative(decode_i8(&mut iter)?), 2)),
+
+        0xd0 => Some(Instruction(Bne, Relative(decode_i8(&mut iter)?), 2)),
+
+        0x10 => Some(Instruction(Bpl, Relative(decode_i8(&mut iter)?), 2)),
+
+        0x50 => Some(Instruction(Bvc, Relative(decode_i8(&mut iter)?), 2)),
+
+        0x70 => Some(Instruction(Bvs, Relative(decode_i8(&mut iter)?), 2)),
+
+        0x24 => Some(Instruction(Bit, ZeroPage(decode_u8(&mut iter)?), 3)),
+        0x2c => Some(Instruction(Bit, Absolute(decode_u16(&mut iter)?), 4)),
+
+        0x00 => Some(Instruction(Brk, Implied, 7)),
+
+        0x18 => Some(Instruction(Clc, Implied, 2)),
+
+        0xd8 => Some(Instruction(Cld, Implied, 2)),
+
+        0x58 => Some(Instruction(Cli, Implied, 2)),
+
+        0xb8 => Some(Instruction(Clv, Implied, 2)),
+
+        0xc9 => Some(Instruction(Cmp, Immediate(decode_u8(&mut iter)?), 2)),
+        0xc5 => Some(Instruction(Cmp, ZeroPage(decode_u8(&mut iter)?), 3)),
+        0xd5 => Some(Instruction(Cmp, ZeroPageX(decode_u8(&mut iter)?), 4)),
+        0xcd => Some(Instruction(Cmp, Absolute(decode_u16(&mut iter)?), 4)),
+        0xdd => Some(Instruction(Cmp, AbsoluteX(decode_u16(&mut iter)?), 4)),
+        0xd9 => Some(Instruction(Cmp, AbsoluteY(decode_u16(&mut iter)?), 4)),
+        0xc1 => Some(Instruction(Cmp, IndirectX(decode_u8(&mut iter)?), 6)),
+        0xd1 => Some(Instruction(Cmp, IndirectY(decode_u8(&mut iter)?), 5)),
+        
+        0xe0 => Some(Instruction(Cpx, Immediate(decode_u8(&mut iter)?), 2)),
+        0xe4 => Some(Instruction(Cpx, ZeroPage(decode_u8(&mut iter)?), 3)),
+        0xec => Some(Instruction(Cpx, Absolute(decode_u16(&mut iter)?), 4)),
+        
+        0xc0 => Some(Instruction(Cpy, Immediate(decode_u8(&mut iter)?), 2)),
+        0xc4 => Some(Instruction(Cpy, ZeroPage(decode_u8(&mut iter)?), 3)),
+        0xcc => Some(Instruction(Cpy, Absolute(decode_u16(&mut iter)?), 4)),
+        
+        0xc6 => Some(Instruction(Dec, ZeroPage(decode_u8(&mut iter)?), 5)),
+        0xd6 => Some(Instruction(Dec, ZeroPageX(decode_u8(&mut iter)?), 6)),
+        0xce => Some(Instruction(Dec, Absolute(decode_u16(&mut iter)?), 6)),
+        0xde => Some(Instruction(Dec, AbsoluteX(decode_u16(&mut iter)?), 7)),
+        
+        0xca => Some(Instruction(Dex, Implied, 2)),
+
+        0x88 => Some(Instruction(Dey, Implied, 2)),
+
+        0x49 => Some(Instruction(Eor, Immediate(decode_u8(&mut iter)?), 2)),
+        0x45 => Some(Instruction(Eor, ZeroPage(decode_u8(&mut iter)?), 3)),
+        0x55 => Some(Instruction(Eor, ZeroPageX(decode_u8(&mut iter)?), 4)),
+        0x4d => Some(Instruction(Eor, Absolute(decode_u16(&mut iter)?), 4)),
+        0x5d => Some(Instruction(Eor, AbsoluteX(decode_u16(&mut iter)?), 4)),
+        0x59 => Some(Instruction(Eor, AbsoluteY(decode_u16(&mut iter)?), 4)),
+        0x41 => Some(Instruction(Eor, IndirectX(decode_u8(&mut iter)?), 6)),
+        0x51 => Some(Instruction(Eor, IndirectY(decode_u8(&mut iter)?), 5)),
+        
+        0xe6 => Some(Instruction(Inc, ZeroPage(decode_u8(&mut iter)?), 5)),
+        0xf6 => Some(Instruction(Inc, ZeroPageX(decode_u8(&mut iter)?), 6)),
+        0xee => Some(Instruction(Inc, Absolute(decode_u16(&mut iter)?), 6)),
+        0xfe => Some(Instruction(Inc, AbsoluteX(decode_u16(&mut iter)?), 7)),
+        
+        0xe8 => Some(Instruction(Inx, Implied, 2)),
+
+        0xc8 => Some(Instruction(Iny, Implied, 2)),
+
+        0x4c => Some(Instruction(Jmp, Absolute(decode_u16(&mut iter)?), 3)),
+        0x6c => Some(Instruction(Jmp, Indirect(decode_u16(&mut iter)?), 5)),
+
+        0x20 => Some(Instruction(Jsr, Absolute(decode_u16(&mut iter)?), 6)),
+
+        0xa9 => Some(Instruction(Lda, Immediate(decode_u8(&mut iter)?), 2)),
+        0xa5 => Some(Instruction(Lda, ZeroPage(decode_u8(&mut iter)?), 3)),
+        0xb5 => Some(Instruction(Lda, ZeroPageX(decode_u8(&mut iter)?), 4)),
+        0xad => Some(Instruction(Lda, Absolute(decode_u16(&mut iter)?), 4)),
+        0xbd => Some(Instruction(Lda, AbsoluteX(decode_u16(&mut iter)?), 4)),
+        0xb9 => Some(Instruction(Lda, AbsoluteY(decode_u16(&mut iter)?), 4)),
+        0xa1 => Some(Instruction(Lda, IndirectX(decode_u8(&mut iter)?), 6)),
+        0xb1 => Some(Instruction(Lda, IndirectY(decode_u8(&mut iter)?), 5)),
+        
+        0xa2 => Some(Instruction(Ldx, Immediate(decode_u8(&mut iter)?), 2)),
+        0xa6 => Some(Instruction(Ldx, ZeroPage(decode_u8(&mut iter)?), 3)),
+        0xb6 => Some(Instruction(Ldx, ZeroPageY(decode_u8(&mut iter)?), 4)),
+        0xae => Some(Instruction(Ldx, Absolute(decode_u16(&mut iter)?), 4)),
+        0xbe => Some(Instruction(Ldx, AbsoluteY(decode_u16(&mut iter)?), 4)),
+        
+        0xa0 => Some(Instruction(Ldy, Immediate(decode_u8(&mut iter)?), 2)),
+        0xa4 => Some(Instruction(Ldy, ZeroPage(decode_u8(&mut iter)?), 3)),
+        0xb4 => Some(Instruction(Ldy, ZeroPageX(decode_u8(&mut iter)?), 4)),
+        0xac => Some(Instruction(Ldy, Absolute(decode_u16(&mut iter)?), 4)),
+        0xbc => Some(Instruction(Ldy, AbsoluteX(decode_u16(&mut iter)?), 4)),
+        
+        0x4a => Some(Instruction(Lsr, Accumulator, 2)),
+        0x46 => Some(Instruction(Lsr, ZeroPage(decode_u8(&mut iter)?), 5)),
+        0x56 => Some(Instruction(Lsr, ZeroPageX(decode_u8(&mut iter)?), 6)),
+        0x4e => Some(Instruction(Lsr, Absolute(decode_u16(&mut iter)?), 6)),
+        0x5e => Some(Instruction(Lsr, AbsoluteX(decode_u16(&mut iter)?), 7)),
+
+        0xea => Some(Instruction(Nop, Implied, 2)),
+
+        0x09 => Some(Instruction(Ora, Immediate(decode_u8(&mut iter)?), 2)),
+        0x05 => Some(Instruction(Ora, ZeroPage(decode_u8(&mut iter)?), 3)),
+        0x15 => Some(Instruction(Ora, ZeroPageX(decode_u8(&mut iter)?), 4)),
+        0x0d => Some(Instruction(Ora, Absolute(decode_u16(&mut iter)?), 4)),
+        0x1d => Some(Instruction(Ora, AbsoluteX(decode_u16(&mut iter)?), 4)),
+        0x19 => Some(Instruction(Ora, AbsoluteY(decode_u16(&mut iter)?), 4)),
+        0x01 => Some(Instruction(Ora, IndirectX(decode_u8(&mut iter)?), 6)),
+        0x11 => Some(Instruction(Ora, IndirectY(decode_u8(&mut iter)?), 5)),
+        
+        0x48 => Some(Instruction(Pha, Implied, 3)),
+
+        0x08 => Some(Instruction(Php, Implied, 3)),
+
+        0x68 => Some(Instruction(Pla, Implied, 4)),
+
+        0x28 => Some(Instruction(Plp, Implied, 4)),
+
+        0x2a => Some(Instruction(Rol, Accumulator, 2)),
+        0x26 => Some(Instruction(Rol, ZeroPage(decode_u8(&mut iter)?), 5)),
+        0x36 => Some(Instruction(Rol, ZeroPageX(decode_u8(&mut iter)?), 6)),
+        0x2e => Some(Instruction(Rol, Absolute(decode_u16(&mut iter)?), 6)),
+        0x3e => Some(Instruction(Rol, AbsoluteX(decode_u16(&mut iter)?), 7)),
+
+        0x6a => Some(Instruction(Ror, Accumulator, 2)),
+        0x66 => Some(Instruction(Ror, ZeroPage(decode_u8(&mut iter)?), 5)),
+        0x76 => Some(Instruction(Ror, ZeroPageX(decode_u8(&mut iter)?), 6)),
+        0x6e => Some(Instruction(Ror, Absolute(decode_u16(&mut iter)?), 6)),
+        0x7e => Some(Instruction(Ror, AbsoluteX(decode_u16(&mut iter)?), 7)),
+
+        0x40 => Some(Instruction(Rti, Implied, 6)),
+
+        0x60 => Some(Instruction(Rts, Implied, 6)),
+
+        0xe9 => Some(Instruction(Sbc, Immediate(decode_u8(&mut iter)?), 2)),
+        0xe5 => Some(Instruction(Sbc, ZeroPage(decode_u8(&mut iter)?), 3)),
+        0xf5 => Some(Instruction(Sbc, ZeroPageX(decode_u8(&mut iter)?), 4)),
+        0xed => Some(Instruction(Sbc, Absolute(decode_u16(&mut iter)?), 4)),
+        0xfd => Some(Instruction(Sbc, AbsoluteX(decode_u16(&mut iter)?), 4)),
+        0xf9 => Some(Instruction(Sbc, AbsoluteY(decode_u16(&mut iter)?), 4)),
+        0xe1 => Some(Instruction(Sbc, IndirectX(decode_u8(&mut iter)?), 6)),
+        0xf1 => Some(Instruction(Sbc, IndirectY(decode_u8(&mut iter)?), 5)),
+        
+        0x38 => Some(Instruction(Sec, Implied, 2)),
+
+        0xf8 => Some(Instruction(Sed, Implied, 2)),
+
+        0x78 => Some(Instruction(Sei, Implied, 2)),
+
+        0x85 => Some(Instruction(Sta, ZeroPage(decode_u8(&mut iter)?), 3)),
+        0x95 => Some(Instruction(Sta, ZeroPageX(decode_u8(&mut iter)?), 4)),
+        0x8d => Some(Instruction(Sta, Absolute(decode_u16(&mut iter)?), 4)),
+        0x9d => Some(Instruction(Sta, AbsoluteX(decode_u16(&mut iter)?), 5)),
+        0x99 => Some(Instruction(Sta, AbsoluteY(decode_u16(&mut iter)?), 5)),
+        0x81 => Some(Instruction(Sta, IndirectX(decode_u8(&mut iter)?), 6)),
+        0x91 => Some(Instruction(Sta, IndirectY(decode_u8(&mut iter)?), 6)),
+        
+        0x86 => Some(Instruction(Stx, ZeroPage(decode_u8(&mut iter)?), 3)),
+        0x96 => Some(Instruction(Stx, ZeroPageY(decode_u8(&mut iter)?), 4)),
+        0x8e => Some(Instruction(Stx, Absolute(decode_u16(&mut iter)?), 4)),
+
+        0x84 => Some(Instruction(Sty, ZeroPage(decode_u8(&mut iter)?), 3)),
+        0x94 => Some(Instruction(Sty, ZeroPageX(decode_u8(&mut iter)?), 4)),
+        0x8c => Some(Instruction(Sty, Absolute(decode_u16(&mut iter)?), 4)),
+
+        0xaa => Some(Instruction(Tax, Implied, 2)),
+
+        0xa8 => Some(Instruction(Tay, Implied, 2)),
+
+        0xba => Some(Instruction(Tsx, Implied, 2)),
+
+        0x8a => Some(Instruction(Txa, Implied, 2)),
+
+        0x9a => Some(Instruction(Txs, Implied, 2)),
+
+        0x98 => Some(Instruction(Tya, Implied, 2)),
+
+        _ => None
+    };
+
+    Ok(ins)
+}
+
+/// The undocumented NMOS opcodes.
+fn decode_undocumented<'a, I>(opcode: u8, mut iter: I) 
+    -> Result<Option<Instruction>, InstructionDecodeError>
+    where I: Iterator<Item=&'a u8>
+{
+    use self::Addressing::*;
+    use self::OpCode::*;
+
+    let ins = match opcode {
+        0x07 => Some(Instruction(Slo, ZeroPage(decode_u8(&mut iter)?), 5)),
+        0x17 => Some(Instruction(Slo, ZeroPageX(decode_u8(&mut iter)?), 6)),
+        0x03 => Some(Instruction(Slo, IndirectX(decode_u8(&mut iter)?), 8)),
+        0x13 => Some(Instruction(Slo, IndirectY(decode_u8(&mut iter)?), 8)),
+        0x0f => Some(Instruction(Slo, Absolute(decode_u16(&mut iter)?), 6)),
+        0x1f => Some(Instruction(Slo, AbsoluteX(decode_u16(&mut iter)?), 7)),
+        0x1b => Some(Instruction(Slo, AbsoluteY(decode_u16(&mut iter)?), 7)),
+
+        0x27 => Some(Instruction(Rla, ZeroPage(decode_u8(&mut iter)?), 5)),
+        0x37 => Some(Instruction(Rla, ZeroPageX(decode_u8(&mut iter)?), 6)),
+        0x23 => Some(Instruction(Rla, IndirectX(decode_u8(&mut iter)?), 8)),
+        0x33 => Some(Instruction(Rla, IndirectY(decode_u8(&mut iter)?), 8)),
+        0x2f => Some(Instruction(Rla, Absolute(decode_u16(&mut iter)?), 6)),
+        0x3f => Some(Instruction(Rla, AbsoluteX(decode_u16(&mut iter)?), 7)),
+        0x3b => Some(Instruction(Rla, AbsoluteY(decode_u16(&mut iter)?), 7)),
+
+        0x47 => Some(Instruction(Sre, ZeroPage(decode_u8(&mut iter)?), 5)),
+        0x57 => Some(Instruction(Sre, ZeroPageX(decode_u8(&mut iter)?), 6)),
+        0x43 => Some(Instruction(Sre, IndirectX(decode_u8(&mut iter)?), 8)),
+        0x53 => Some(Instruction(Sre, IndirectY(decode_u8(&mut iter)?), 8)),
+        0x4f => Some(Instruction(Sre, Absolute(decode_u16(&mut iter)?), 6)),
+        0x5f => Some(Instruction(Sre, AbsoluteX(decode_u16(&mut iter)?), 7)),
+        0x5b => Some(Instruction(Sre, AbsoluteY(decode_u16(&mut iter)?), 7)),
+
+        0x67 => Some(Instruction(Rra, ZeroPage(decode_u8(&mut iter)?), 5)),
+        0x77 => Some(Instruction(Rra, ZeroPageX(decode_u8(&mut iter)?), 6)),
+        0x63 => Some(Instruction(Rra, IndirectX(decode_u8(&mut iter)?), 8)),
+        0x73 => Some(Instruction(Rra, IndirectY(decode_u8(&mut iter)?), 8)),
+        0x6f => Some(Instruction(Rra, Absolute(decode_u16(&mut iter)?), 6)),
+        0x7f => Some(Instruction(Rra, AbsoluteX(decode_u16(&mut iter)?), 7)),
+        0x7b => Some(Instruction(Rra, AbsoluteY(decode_u16(&mut iter)?), 7)),
+
+        0xc7 => Some(Instruction(Dcp, ZeroPage(decode_u8(&mut iter)?), 5)),
+        0xd7 => Some(Instruction(Dcp, ZeroPageX(decode_u8(&mut iter)?), 6)),
+        0xc3 => Some(Instruction(Dcp, IndirectX(decode_u8(&mut iter)?), 8)),
+        0xd3 => Some(Instruction(Dcp, IndirectY(decode_u8(&mut iter)?), 8)),
+        0xcf => Some(Instruction(Dcp, Absolute(decode_u16(&mut iter)?), 6)),
+        0xdf => Some(Instruction(Dcp, AbsoluteX(decode_u16(&mut iter)?), 7)),
+        0xdb => Some(Instruction(Dcp, AbsoluteY(decode_u16(&mut iter)?), 7)),
+
+        0xe7 => Some(Instruction(Isc, ZeroPage(decode_u8(&mut iter)?), 5)),
+        0xf7 => Some(Instruction(Isc, ZeroPageX(decode_u8(&mut iter)?), 6)),
+        0xe3 => Some(Instruction(Isc, IndirectX(decode_u8(&mut iter)?), 8)),
+        0xf3 => Some(Instruction(Isc, IndirectY(decode_u8(&mut iter)?), 8)),
+        0xef => Some(Instruction(Isc, Absolute(decode_u16(&mut iter)?), 6)),
+        0xff => Some(Instruction(Isc, AbsoluteX(decode_u16(&mut iter)?), 7)),
+        0xfb => Some(Instruction(Isc, AbsoluteY(decode_u16(&mut iter)?), 7)),
+
+        0x87 => Some(Instruction(Sax, ZeroPage(decode_u8(&mut iter)?), 3)),
+        0x97 => Some(Instruction(Sax, ZeroPageY(decode_u8(&mut iter)?), 4)),
+        0x83 => Some(Instruction(Sax, IndirectX(decode_u8(&mut iter)?), 6)),
+        0x8f => Some(Instruction(Sax, Absolute(decode_u16(&mut iter)?), 4)),
+
+        0xa7 => Some(Instruction(Lax, ZeroPage(decode_u8(&mut iter)?), 3)),
+        0xb7 => Some(Instruction(Lax, ZeroPageY(decode_u8(&mut iter)?), 4)),
+        0xa3 => Some(Instruction(Lax, IndirectX(decode_u8(&mut iter)?), 6)),
+        0xb3 => Some(Instruction(Lax, IndirectY(decode_u8(&mut iter)?), 5)),
+        0xaf => Some(Instruction(Lax, Absolute(decode_u16(&mut iter)?), 4)),
+        0xbf => Some(Instruction(Lax, AbsoluteY(decode_u16(&mut iter)?), 4)),
+
+        0x0b | 0x2b => Some(Instruction(Anc, Immediate(decode_u8(&mut iter)?), 2)),
+
+        0x4b => Some(Instruction(Alr, Immediate(decode_u8(&mut iter)?), 2)),
+
+        0x6b => Some(Instruction(Arr, Immediate(decode_u8(&mut iter)?), 2)),
+
+        0xcb => Some(Instruction(Sbx, Immediate(decode_u8(&mut iter)?), 2)),
+
+        0xeb => Some(Instruction(Sbc, Immediate(decode_u8(&mut iter)?), 2)),
+
+        0x1a | 0x3a | 0x5a | 0x7a | 0xda | 0xfa => Some(Instruction(Nop, Implied, 2)),
+        0x80 | 0x82 | 0x89 | 0xc2 | 0xe2 => Some(Instruction(Nop, Immediate(decode_u8(&mut iter)?), 2)),
+        0x04 | 0x44 | 0x64 => Some(Instruction(Nop, ZeroPage(decode_u8(&mut iter)?), 3)),
+        0x14 | 0x34 | 0x54 | 0x74 | 0xd4 | 0xf4 => Some(Instruction(Nop, ZeroPageX(decode_u8(&mut iter)?), 4)),
+        0x0c => Some(Instruction(Nop, Absolute(decode_u16(&mut iter)?), 4)),
+        0x1c | 0x3c | 0x5c | 0x7c | 0xdc | 0xfc => Some(Instruction(Nop, AbsoluteX(decode_u16(&mut iter)?), 4)),
+
+        0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 |
+        0x62 | 0x72 | 0x92 | 0xb2 | 0xd2 | 0xf2 => Some(Instruction(Jam, Implied, 2)),
+
+        _ => None
+    };
+
+    Ok(ins)
+}
+
 #[derive(Debug)]
 pub struct MemoryAccessError;
 
@@ -454,7 +699,7 @@ pub enum StackError {
 pub enum CpuError {
     Memory(MemoryAccessError),
     Stack(StackError),
-    InvalidInstruction,
+    InvalidInstruction { pc: u16, opcode: u8 },
     Paused,
 }
 
@@ -480,7 +725,7 @@ impl Error for CpuError {
             CpuError::Memory(ref e) => e.description(),
             CpuError::Stack(ref e) => e.description(),
             CpuError::Paused => "CPU paused",
-            CpuError::InvalidInstruction => "Invalid instruction",
+            CpuError::InvalidInstruction { .. } => "Invalid instruction",
         }
     }
 }
@@ -515,143 +760,352 @@ impl From<MemoryAccessError> for StackError {
     }
 }
 
-impl From<InstructionDecodeError> for CpuError {
-    fn from(_: InstructionDecodeError) -> CpuError {
-        CpuError::InvalidInstruction
-    }
-}
-
 impl fmt::Display for CpuError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", <Self as Error>::description(self))
+        match *self {
+            CpuError::InvalidInstruction { pc, opcode } => 
+                write!(f, "Invalid instruction {:02x} at {:04x}", opcode, pc),
+            _ => write!(f, "{}", <Self as Error>::description(self)),
+        }
     }
 }
 
 const STACK_BOTTOM: u16 = 0x0100;
 
+/// The stack pointer wraps around page one in both directions, as it does
+/// on the real part.
 fn push_stack<M: MemoryMap>(val: u8, mut mem: M, reg: &mut Registers) -> Result<(), StackError> {
     mem.write(STACK_BOTTOM + reg.sp as u16, val);
-    reg.sp.checked_sub(1)
-        .map(|r| reg.sp = r)
-        .ok_or_else(|| StackError::Overflow)?;
-
+    reg.sp = reg.sp.wrapping_sub(1);
     Ok(())
 }
 
-fn pop_stack<M: MemoryMap>(mut mem: M, reg: &mut Registers) -> Result<u8, StackError> {
-    reg.sp.checked_add(1)
-        .map(|r| reg.sp = r)
-        .ok_or_else(|| StackError::Underflow)?;
+/// Pulling from the stack costs an extra cycle, during which the CPU reads
+/// from the current top of the stack.
+fn pull_delay<M: MemoryMap>(mut mem: M, reg: &Registers) {
+    mem.read(STACK_BOTTOM + reg.sp as u16);
+}
 
+fn pop_stack<M: MemoryMap>(mut mem: M, reg: &mut Registers) -> Result<u8, StackError> {
+    reg.sp = reg.sp.wrapping_add(1);
     let val = mem.read(STACK_BOTTOM + reg.sp as u16);
     Ok(val)
 }
 
-fn write_mem<M: MemoryMap>(val: u8, 
-                           addr: &Addressing, 
-                           mut mem: M, 
-                           reg: &mut Registers) -> Result<(), MemoryAccessError> 
+/// Whether the CPU always spends a cycle carrying into the high byte of an
+/// indexed address, or only when indexing crosses a page.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Fixup {
+    OnPageCross,
+    Always,
+}
+
+fn page_crossed(from: u16, to: u16) -> bool {
+    (from & 0xff00) != (to & 0xff00)
+}
+
+/// The 6502 reads the byte after the opcode on cycles where it has nothing
+/// else to do.
+fn idle<M: MemoryMap>(mut mem: M, reg: &Registers) {
+    mem.read(reg.pc);
+}
+
+fn read_zero_page_pointer<M: MemoryMap>(loc: u8, mut mem: M) -> u16 {
+    mem.read(loc as _) as u16 | (mem.read(loc.wrapping_add(1) as _) as u16) << 8
+}
+
+fn indexed<M: MemoryMap>(base: u16, 
+                         index: u8, 
+                         fixup: Fixup, 
+                         mut mem: M, 
+                         reg: &Registers,
+                         variant: CpuVariant) -> (u16, bool)
+{
+    let target = base.wrapping_add(index as u16);
+    let crossed = page_crossed(base, target);
+
+    // While the high byte is fixed up the NMOS part reads from the
+    // partially formed address. The CMOS parts re-read the last operand
+    // byte instead.
+    if crossed || fixup == Fixup::Always {
+        let dummy = match variant {
+            CpuVariant::Nmos6502 => (base & 0xff00) | (target & 0x00ff),
+            CpuVariant::Cmos65sc12 if crossed => reg.pc.wrapping_sub(1),
+            CpuVariant::Cmos65sc12 => target,
+        };
+        mem.read(dummy);
+    }
+
+    (target, crossed)
+}
+
+/// Performs the bus cycles needed to find the effective address for
+/// `addr`, including any dummy reads. The returned flag is `true` when
+/// indexing moved the effective address into a different page from the
+/// base address.
+fn effective_address<M: MemoryMap>(addr: &Addressing, 
+                                   fixup: Fixup, 
+                                   mut mem: M, 
+                                   reg: &Registers,
+                                   variant: CpuVariant) -> (u16, bool)
 {
     use self::Addressing::*;
 
     match *addr {
-        Accumulator => reg.acc = val,
-        Absolute(ref loc) => mem.write(*loc, val),
-        AbsoluteX(ref loc) => mem.write(*loc + reg.x as u16, val),
-        AbsoluteY(ref loc) => mem.write(*loc + reg.y as u16, val),
-        ZeroPage(ref loc) => mem.write(*loc as _, val),
-        ZeroPageX(ref loc) => mem.write(loc.wrapping_add(reg.x) as _, val),
+        Absolute(ref loc) => (*loc, false),
+        AbsoluteX(ref loc) => indexed(*loc, reg.x, fixup, mem, reg, variant),
+        AbsoluteY(ref loc) => indexed(*loc, reg.y, fixup, mem, reg, variant),
+        ZeroPage(ref loc) => (*loc as _, false),
+        ZeroPageX(ref loc) => {
+            mem.read(*loc as _);
+            (loc.wrapping_add(reg.x) as _, false)
+        },
+        ZeroPageY(ref loc) => {
+            mem.read(*loc as _);
+            (loc.wrapping_add(reg.y) as _, false)
+        },
         IndirectX(ref loc) => {
-            let loc = *loc as u16 + reg.x as u16;
-            let target = mem.read(loc) as u16 | (mem.read(loc + 1) as u16) << 8;
-//            let target_lo = mem.read(loc as _) as u16;
-//            let target_hi = mem.read(loc as u16 + 1) as u16;
-//            let target = target_lo as u16 | (target_hi as u16) << 8;
-            mem.write(target as _, val);
+            mem.read(*loc as _);
+            (read_zero_page_pointer(loc.wrapping_add(reg.x), mem), false)
         },
         IndirectY(ref loc) => {
-            let target = mem.read(*loc as _) as u16 | (mem.read(*loc as u16 + 1) as u16) << 8;
-//            let target_lo = mem.read(*loc as _);
-//            let target_hi = mem.read(*loc as u16 + 1);
-//            let target = target_lo as u16 | (target_hi as u16) << 8;
-            mem.write(target.wrapping_add(reg.y as u16) as _, val);
+            let target = read_zero_page_pointer(*loc, &mut mem);
+            indexed(target, reg.y, fixup, mem, reg, variant)
         },
-        _ => unreachable!()
+        ZeroPageIndirect(ref loc) => (read_zero_page_pointer(*loc, mem), false),
+        _ => panic!("{:?} doesn't address memory", addr)
     }
-
-    Ok(())
 }
 
-fn page_crossed(from: u16, to: u16) -> bool {
-    (from & 0xff00) != (to & 0xff00)
+fn write_mem<M: MemoryMap>(val: u8, 
+                           addr: &Addressing, 
+                           mut mem: M, 
+                           reg: &mut Registers,
+                           variant: CpuVariant) -> Result<(), MemoryAccessError> 
+{
+    match *addr {
+        Addressing::Accumulator => reg.acc = val,
+        _ => {
+            let (target, _) = effective_address(addr, Fixup::Always, &mut mem, reg, variant);
+            mem.write(target, val);
+        }
+    }
+
+    Ok(())
 }
 
+/// Reads the operand for `addr`. The returned flag is `true` when indexing
+/// moved the effective address into a different page from the base
+/// address, which costs an extra cycle for most read instructions.
 fn read_mem<M: MemoryMap>(addr: &Addressing, 
                           mut mem: M, 
-                          reg: &Registers) -> Result<(u8, bool), MemoryAccessError> 
+                          reg: &Registers,
+                          variant: CpuVariant) -> Result<(u8, bool), MemoryAccessError> 
 {
     use self::Addressing::*;
 
     match *addr {
         Accumulator => Ok((reg.acc, false)),
         Immediate(ref v) => Ok((*v, false)),
-        Absolute(ref loc) => Ok((mem.read(*loc), false)),
-        AbsoluteX(ref loc) => Ok((mem.read(*loc + reg.x as u16), page_crossed(reg.pc, *loc + reg.x as u16))),
-        AbsoluteY(ref loc) => Ok((mem.read(*loc + reg.y as u16), page_crossed(reg.pc, *loc + reg.y as u16))),
-        Indirect(ref loc) => {
-            let target = mem.read(*loc as _) as u16 | (mem.read(*loc as u16 + 1) as u16) << 8;
-            Ok((mem.read(target), false))
-        },
-        IndirectX(ref loc) => {
-            let loc = *loc as u16 + reg.x as u16;
-            let target = mem.read(loc) as u16 | (mem.read(loc + 1) as u16) << 8;
-//            let target_lo = mem.read(loc as _) as u16;
-//            let target_hi = mem.read(loc as u16 + 1) as u16;
-//            let target = target_lo as u16 | (target_hi as u16) << 8;
-            Ok((mem.read(target as _), false))
+        // These never supply an operand, so a read through one is a bug
+        // in the decoder rather than something the program did
+        Relative(_) | Implied | Indirect(_) | AbsoluteIndexedIndirect(_) => {
+            log_cpu!("Attempting to read mem for {:?}", addr);
+            Err(MemoryAccessError)
         },
-        IndirectY(ref loc) => {
-            let target = mem.read(*loc as _) as u16 | (mem.read(*loc as u16 + 1) as u16) << 8;
-//            let target_lo = mem.read(*loc as _);
-//            let target_hi = mem.read(*loc as u16 + 1);
-//            let target = target_lo as u16 | (target_hi as u16) << 8;
-            Ok((mem.read(target.wrapping_add(reg.y as u16)), page_crossed(reg.pc, target.wrapping_add(reg.y as u16))))
+        _ => {
+            let (target, cross_page) = 
+                effective_address(addr, Fixup::OnPageCross, &mut mem, reg, variant);
+            Ok((mem.read(target), cross_page))
+        }
+    }
+}
+
+/// Applies `op` to the operand for `addr` and stores the result, making
+/// the same bus accesses as the real part. The NMOS 6502 writes the
+/// unmodified value back while it works out the result (which peripherals
+/// see as a second write); the CMOS parts read it again instead.
+fn read_modify_write<M, F>(addr: &Addressing,
+                           fixup: Fixup,
+                           mut mem: M,
+                           reg: &mut Registers,
+                           variant: CpuVariant,
+                           op: F) -> Result<(u8, bool), MemoryAccessError>
+    where M: MemoryMap,
+          F: FnOnce(u8, &mut Registers) -> u8
+{
+    if let Addressing::Accumulator = *addr {
+        idle(&mut mem, reg);
+        let val = reg.acc;
+        reg.acc = op(val, reg);
+        return Ok((reg.acc, false));
+    }
+
+    let (target, cross_page) = effective_address(addr, fixup, &mut mem, reg, variant);
+    let val = mem.read(target);
+    match variant {
+        CpuVariant::Nmos6502 => mem.write(target, val),
+        CpuVariant::Cmos65sc12 => { mem.read(target); },
+    }
+
+    let result = op(val, reg);
+    mem.write(target, result);
+
+    Ok((result, cross_page))
+}
+
+/// Moves the program counter to the branch target, making the extra bus
+/// accesses a taken branch costs. Returns the extra cycle spent when the
+/// target is in a different page.
+fn take_branch<M: MemoryMap>(addr: &Addressing, mut mem: M, reg: &mut Registers) -> usize {
+    match *addr {
+        Addressing::Relative(ref offset) => {
+            let old_pos = reg.pc;
+            mem.read(old_pos);
+            reg.pc = reg.pc.wrapping_add(*offset as i16 as u16);
+            if page_crossed(old_pos, reg.pc) {
+                mem.read((old_pos & 0xff00) | (reg.pc & 0x00ff));
+                1
+            }
+            else {
+                0
+            }
         },
-        ZeroPage(ref loc) => Ok((mem.read(*loc as _), false)),
-        ZeroPageX(ref loc) => Ok((mem.read(loc.wrapping_add(reg.x) as _), false)),
-        ZeroPageY(ref loc) => Ok((mem.read(loc.wrapping_add(reg.y) as _), false)),
-        Relative(_) | Implied => panic!(format!("Attempting to read mem for {:?}", addr))
+        _ => unreachable!()
+    }
+}
+
+/// Adds `val` and the carry flag to the accumulator.
+///
+/// When the decimal flag is set this follows the NMOS 6502 behaviour: the
+/// accumulator and carry hold the BCD result, `Z` is taken from the binary
+/// sum and `N`/`V` are taken from the intermediate result *before* the high
+/// nibble is adjusted. Invalid BCD operands produce the same (undocumented)
+/// results as the real chip. The CMOS parts instead set `N` and `Z` from the
+/// BCD result.
+fn add_with_carry(val: u8, reg: &mut Registers, variant: CpuVariant) {
+    let carry = reg.status.carry as u16;
+    let binary = reg.acc as u16 + val as u16 + carry;
+
+    reg.status.zero = 0 == (binary & 0xff);
+
+    if !reg.status.decimal {
+        reg.status.carry = binary > 0xff;
+        reg.status.overflow = 0 != (!(reg.acc ^ val) & (reg.acc ^ binary as u8) & 0x80);
+        reg.status.negative = 0 != (binary & 0x80);
+        reg.acc = binary as u8;
+        return;
+    }
+
+    let mut low = (reg.acc & 0x0f) as u16 + (val & 0x0f) as u16 + carry;
+    if low >= 0x0a {
+        low = ((low + 0x06) & 0x0f) + 0x10;
     }
+
+    let mut result = (reg.acc & 0xf0) as u16 + (val & 0xf0) as u16 + low;
+    let signed = (reg.acc & 0xf0) as i8 as i16 + (val & 0xf0) as i8 as i16 + low as i16;
+
+    reg.status.negative = 0 != (result & 0x80);
+    reg.status.overflow = signed < -128 || signed > 127;
+
+    if result >= 0xa0 {
+        result += 0x60;
+    }
+
+    reg.status.carry = result > 0xff;
+    reg.acc = result as u8;
+
+    if variant == CpuVariant::Cmos65sc12 {
+        reg.status.zero = reg.acc == 0;
+        reg.status.negative = bit_is_set!(reg.acc, 7);
+    }
+}
+
+/// Subtracts `val` and the inverted carry flag from the accumulator.
+///
+/// On the NMOS 6502 all of the flags are set from the binary subtraction,
+/// even in decimal mode. Only the value left in the accumulator differs. The
+/// CMOS parts correct the result differently and set `N` and `Z` from it.
+fn subtract_with_borrow(val: u8, reg: &mut Registers, variant: CpuVariant) {
+    let borrow = !reg.status.carry as i16;
+    let binary = reg.acc as i16 - val as i16 - borrow;
+
+    reg.status.carry = binary >= 0;
+    reg.status.zero = 0 == (binary & 0xff);
+    reg.status.overflow = 0 != ((reg.acc ^ val) & (reg.acc ^ binary as u8) & 0x80);
+    reg.status.negative = 0 != (binary & 0x80);
+
+    if !reg.status.decimal {
+        reg.acc = binary as u8;
+        return;
+    }
+
+    let mut low = (reg.acc & 0x0f) as i16 - (val & 0x0f) as i16 - borrow;
+
+    if variant == CpuVariant::Cmos65sc12 {
+        let mut result = binary;
+        if result < 0 {
+            result -= 0x60;
+        }
+        if low < 0 {
+            result -= 0x06;
+        }
+
+        reg.acc = result as u8;
+        reg.status.zero = reg.acc == 0;
+        reg.status.negative = bit_is_set!(reg.acc, 7);
+        return;
+    }
+
+    if low < 0 {
+        low = ((low - 0x06) & 0x0f) - 0x10;
+    }
+
+    let mut result = (reg.acc & 0xf0) as i16 - (val & 0xf0) as i16 + low;
+    if result < 0 {
+        result -= 0x60;
+    }
+
+    reg.acc = result as u8;
+}
+
+fn compare(lhs: u8, val: u8, reg: &mut Registers) {
+    let result = lhs.wrapping_sub(val);
+    reg.status.carry = lhs >= val;
+    reg.status.zero = result == 0;
+    reg.status.negative = bit_is_set!(result, 7);
 }
 
 fn execute_instruction<M: MemoryMap>(ins: Instruction, 
                                      mut mem: M, 
-                                     reg: &mut Registers) -> Result<usize, CpuError>
+                                     reg: &mut Registers,
+                                     variant: CpuVariant) -> Result<usize, CpuError>
 {
     use self::OpCode::*;
 
-    match ins.0 {
-        Adc => {
-            assert!(!reg.status.decimal);
+    // The CMOS parts take an extra cycle to correct ADC/SBC in decimal mode
+    let decimal_cycles = 
+        if variant == CpuVariant::Cmos65sc12 && reg.status.decimal { 1 } else { 0 };
 
-            let orig = reg.acc;
-            let (val, cross_page) = read_mem(&ins.1, mem, reg)?;
-            let (v, o) = reg.acc.overflowing_add(val);
-            reg.acc = v;
+    // The CMOS parts only spend the extra cycle on indexed shifts and
+    // rotates when indexing crosses a page
+    let shift_fixup = match variant {
+        CpuVariant::Nmos6502 => Fixup::Always,
+        CpuVariant::Cmos65sc12 => Fixup::OnPageCross,
+    };
 
-            // Carry?
-            reg.status.carry = o;
-            // Zero?
-            reg.status.zero = reg.acc == 0;
-            // Overflow?
-            reg.status.overflow = 0 != (orig & 0x80) ^ (reg.acc & 0x80);
-            // Negative?
-            reg.status.negative = bit_is_set!(reg.acc, 7);
+    match ins.0 {
+        Adc => {
+            let (val, cross_page) = read_mem(&ins.1, &mut mem, reg, variant)?;
+            if decimal_cycles > 0 {
+                idle(&mut mem, reg);
+            }
+            add_with_carry(val, reg, variant);
 
-            Ok(ins.2 + if cross_page { 1 } else { 0 })
+            Ok(ins.2 + decimal_cycles + if cross_page { 1 } else { 0 })
         },
         And => {
-            let (val, cross_page) = read_mem(&ins.1, mem, reg)?;
+            let (val, cross_page) = read_mem(&ins.1, mem, reg, variant)?;
             reg.acc = val & reg.acc;
 
             reg.status.zero = reg.acc == 0;
@@ -660,26 +1114,19 @@ fn execute_instruction<M: MemoryMap>(ins: Instruction,
             Ok(ins.2 + if cross_page { 1 } else { 0 })
         },
         Asl => {
-            let (val, _) = read_mem(&ins.1, &mut mem, reg)?;
-            let (result, overflow) = val.overflowing_shl(1);
-            reg.status.carry = overflow;
-            reg.status.zero = result == 0;
-            reg.status.negative = bit_is_set!(result, 7);
-            write_mem(result, &ins.1, mem, reg)?;
-
-            Ok(ins.2)
+            let (_, cross_page) = read_modify_write(&ins.1, shift_fixup, mem, reg, variant, |val, reg| {
+                let result = val << 1;
+                reg.status.carry = bit_is_set!(val, 7);
+                reg.status.zero = result == 0;
+                reg.status.negative = bit_is_set!(result, 7);
+                result
+            })?;
+
+            Ok(ins.2 + if cross_page && variant == CpuVariant::Cmos65sc12 { 1 } else { 0 })
         },
         Bcc => {
             if !reg.status.carry {
-                match ins.1 {
-                    Addressing::Relative(ref offset) => {
-                        let old_pos = reg.pc;
-                        reg.pc = (reg.pc as i16 + *offset as i16) as u16;
-                        let cross_page = (old_pos & 0xff00) != (reg.pc & 0xff00);
-                        Ok(ins.2 + 1 + if cross_page { 1 } else { 0 })
-                    },
-                    _ => unreachable!()
-                }
+                Ok(ins.2 + 1 + take_branch(&ins.1, mem, reg))
             }
             else {
                 Ok(ins.2)
@@ -687,15 +1134,7 @@ fn execute_instruction<M: MemoryMap>(ins: Instruction,
         },
         Bcs => {
             if reg.status.carry {
-                match ins.1 {
-                    Addressing::Relative(ref offset) => {
-                        let old_pos = reg.pc;
-                        reg.pc = (reg.pc as i16 + *offset as i16) as u16;
-                        let cross_page = (old_pos & 0xff00) != (reg.pc & 0xff00);
-                        Ok(ins.2 + 1 + if cross_page { 1 } else { 0 })
-                    },
-                    _ => unreachable!()
-                }
+                Ok(ins.2 + 1 + take_branch(&ins.1, mem, reg))
             }
             else {
                 Ok(ins.2)
@@ -703,39 +1142,29 @@ fn execute_instruction<M: MemoryMap>(ins: Instruction,
         },
         Beq => {
             if reg.status.zero {
-                match ins.1 {
-                    Addressing::Relative(ref offset) => {
-                        let old_pos = reg.pc;
-                        reg.pc = (reg.pc as i16 + *offset as i16) as u16;
-                        let cross_page = (old_pos & 0xff00) != (reg.pc & 0xff00);
-                        Ok(ins.2 + 1 + if cross_page { 1 } else { 0 })
-                    },
-                    _ => unreachable!()
-                }
+                Ok(ins.2 + 1 + take_branch(&ins.1, mem, reg))
             }
             else {
                 Ok(ins.2)
             }
         },
         Bit => {
-            let (val, _) = read_mem(&ins.1, mem, reg)?;
+            let (val, cross_page) = read_mem(&ins.1, mem, reg, variant)?;
             reg.status.zero = 0 == val & reg.acc;
+
+            // The CMOS `BIT #imm` only affects Z
+            if let Addressing::Immediate(_) = ins.1 {
+                return Ok(ins.2);
+            }
+
             reg.status.overflow = bit_is_set!(val, 6);
             reg.status.negative = bit_is_set!(val, 7);
 
-            Ok(ins.2)
+            Ok(ins.2 + if cross_page { 1 } else { 0 })
         },
         Bmi => {
             if reg.status.negative {
-                match ins.1 {
-                    Addressing::Relative(ref offset) => {
-                        let old_pos = reg.pc;
-                        reg.pc = (reg.pc as i16 + *offset as i16) as u16;
-                        let cross_page = (old_pos & 0xff00) != (reg.pc & 0xff00);
-                        Ok(ins.2 + 1 + if cross_page { 1 } else { 0 })
-                    },
-                    _ => unreachable!()
-                }
+                Ok(ins.2 + 1 + take_branch(&ins.1, mem, reg))
             }
             else {
                 Ok(ins.2)
@@ -743,15 +1172,7 @@ fn execute_instruction<M: MemoryMap>(ins: Instruction,
         },
         Bne => {
             if !reg.status.zero {
-                match ins.1 {
-                    Addressing::Relative(ref offset) => {
-                        let old_pos = reg.pc;
-                        reg.pc = (reg.pc as i16 + *offset as i16) as u16;
-                        let cross_page = (old_pos & 0xff00) != (reg.pc & 0xff00);
-                        Ok(ins.2 + 1 + if cross_page { 1 } else { 0 })
-                    },
-                    _ => unreachable!()
-                }
+                Ok(ins.2 + 1 + take_branch(&ins.1, mem, reg))
             }
             else {
                 Ok(ins.2)
@@ -759,42 +1180,32 @@ fn execute_instruction<M: MemoryMap>(ins: Instruction,
         },
         Bpl => {
             if !reg.status.negative {
-                match ins.1 {
-                    Addressing::Relative(ref offset) => {
-                        let old_pos = reg.pc;
-                        reg.pc = (reg.pc as i16 + *offset as i16) as u16;
-                        let cross_page = (old_pos & 0xff00) != (reg.pc & 0xff00);
-                        Ok(ins.2 + 1 + if cross_page { 1 } else { 0 })
-                    },
-                    _ => unreachable!()
-                }
+                Ok(ins.2 + 1 + take_branch(&ins.1, mem, reg))
             }
             else {
                 Ok(ins.2)
             }
         },
         Brk => {
-            reg.status.brk = true;
-            push_stack( ((reg.pc & 0xff00) >> 8) as u8, &mut mem, reg )?;
-            push_stack( (reg.pc & 0x00ff) as u8, &mut mem, reg )?;
-            push_stack( u8::from(&reg.status), &mut mem, reg )?;
+            // BRK skips a padding byte, so the return address is BRK + 2
+            idle(&mut mem, reg);
+            let ret = reg.pc.wrapping_add(1);
+            push_stack( ((ret & 0xff00) >> 8) as u8, &mut mem, reg )?;
+            push_stack( (ret & 0x00ff) as u8, &mut mem, reg )?;
+            push_stack( u8::from(&reg.status) | 0x10, &mut mem, reg )?;
             let lo = mem.read(0xfffe);
             let hi = mem.read(0xffff);
             reg.pc = (hi as u16) << 8 | lo as u16;
-            reg.status.brk = true;
+            reg.status.interrupt = true;
+            if variant == CpuVariant::Cmos65sc12 {
+                reg.status.decimal = false;
+            }
 
             Ok(7)
         },
         Bvc => {
             if !reg.status.overflow {
-                match ins.1 {
-                    Addressing::Relative(ref offset) => {
-                        let old_pos = reg.pc;
-                        reg.pc = (reg.pc as i16 + *offset as i16) as u16;
-                        Ok(ins.2 + 1 + if page_crossed(old_pos, reg.pc) { 1 } else { 0 })
-                    },
-                    _ => unreachable!()
-                }
+                Ok(ins.2 + 1 + take_branch(&ins.1, mem, reg))
             }
             else {
                 Ok(ins.2)
@@ -802,109 +1213,78 @@ fn execute_instruction<M: MemoryMap>(ins: Instruction,
         }
         Bvs => {
             if reg.status.overflow {
-                match ins.1 {
-                    Addressing::Relative(ref offset) => {
-                        let old_pos = reg.pc;
-                        reg.pc = (reg.pc as i16 + *offset as i16) as u16;
-                        Ok(ins.2 + 1 + if page_crossed(old_pos, reg.pc) { 1 } else { 0 })
-                    },
-                    _ => unreachable!()
-                }
+                Ok(ins.2 + 1 + take_branch(&ins.1, mem, reg))
             }
             else {
                 Ok(ins.2)
             }
         }
         Clc => {
+            idle(mem, reg);
             reg.status.carry = false;
             Ok(ins.2)
         },
         Cld => {
+            idle(mem, reg);
             reg.status.decimal = false;
             Ok(ins.2)
         },
         Cli => {
+            idle(mem, reg);
             reg.status.interrupt = false;
             Ok(ins.2)
         },
         Clv => {
+            idle(mem, reg);
             reg.status.overflow = false;
             Ok(ins.2)
         },
         Cmp => {
-            let (val, cross_page) = read_mem(&ins.1, mem, reg)?;
-            match reg.acc.overflowing_sub(val) {
-                (val, true) => {
-                    reg.status.carry = false;
-                    reg.status.zero = false;
-                    reg.status.negative = bit_is_set!(val, 7);
-                },
-                (val, false) => {
-                    reg.status.carry = true;
-                    reg.status.zero = val == 0;
-                    reg.status.negative = false;
-                }
-            }
+            let (val, cross_page) = read_mem(&ins.1, mem, reg, variant)?;
+            let lhs = reg.acc;
+            compare(lhs, val, reg);
 
             Ok(ins.2 + if cross_page { 1 } else { 0 })
         },
         Cpx => {
-            let (val, _) = read_mem(&ins.1, mem, reg)?;
-            match reg.x.overflowing_sub(val) {
-                (val, true) => {
-                    reg.status.carry = false;
-                    reg.status.zero = false;
-                    reg.status.negative = bit_is_set!(val, 7);
-                },
-                (val, false) => {
-                    reg.status.carry = true;
-                    reg.status.zero = val == 0;
-                    reg.status.negative = false;
-                }
-            }
+            let (val, _) = read_mem(&ins.1, mem, reg, variant)?;
+            let lhs = reg.x;
+            compare(lhs, val, reg);
 
             Ok(ins.2)
         },
         Cpy => {
-            let (val, _) = read_mem(&ins.1, mem, reg)?;
-            match reg.y.overflowing_sub(val) {
-                (val, true) => {
-                    reg.status.carry = false;
-                    reg.status.zero = false;
-                    reg.status.negative = bit_is_set!(val, 7);
-                },
-                (val, false) => {
-                    reg.status.carry = true;
-                    reg.status.zero = val == 0;
-                    reg.status.negative = false;
-                }
-            }
+            let (val, _) = read_mem(&ins.1, mem, reg, variant)?;
+            let lhs = reg.y;
+            compare(lhs, val, reg);
 
             Ok(ins.2)
         },
         Dec => {
-            let (mut val, _) = read_mem(&ins.1, &mut mem, reg)?;
-            val = val.wrapping_sub(1); 
-            write_mem(val, &ins.1, &mut mem, reg)?;
+            let (val, _) = read_modify_write(&ins.1, Fixup::Always, mem, reg, variant, |val, _| {
+                val.wrapping_sub(1)
+            })?;
             reg.status.zero = 0 == val;
             reg.status.negative = bit_is_set!(val, 7);
 
             Ok(ins.2)
         },
         Dex => {
+            idle(mem, reg);
             reg.x = reg.x.wrapping_sub(1);
             reg.status.zero = 0 == reg.x;
             reg.status.negative = bit_is_set!(reg.x, 7);
             Ok(ins.2)
         },
         Dey => {
+            idle(mem, reg);
             reg.y = reg.y.wrapping_sub(1);
             reg.status.zero = 0 == reg.y;
             reg.status.negative = bit_is_set!(reg.y, 7);
             Ok(ins.2)
         },
         Eor => {
-            let (val, cross_page) = read_mem(&ins.1, mem, reg)?;
+            let (val, cross_page) = read_mem(&ins.1, mem, reg, variant)?;
             reg.acc ^= val;
             reg.status.zero = reg.acc == 0;
             reg.status.negative = bit_is_set!(reg.acc, 7);
@@ -912,21 +1292,23 @@ fn execute_instruction<M: MemoryMap>(ins: Instruction,
             Ok(ins.2 + if cross_page { 1 } else { 0 })
         },
         Inc => {
-            let (mut val, _) = read_mem(&ins.1, &mut mem, reg)?;
-            val = val.wrapping_add(1); 
-            write_mem(val, &ins.1, &mut mem, reg)?;
+            let (val, _) = read_modify_write(&ins.1, Fixup::Always, mem, reg, variant, |val, _| {
+                val.wrapping_add(1)
+            })?;
             reg.status.zero = 0 == val;
             reg.status.negative = bit_is_set!(val, 7);
 
             Ok(ins.2)
         },
         Inx => {
+            idle(mem, reg);
             reg.x = reg.x.wrapping_add(1);
             reg.status.zero = 0 == reg.x;
             reg.status.negative = bit_is_set!(reg.x, 7);
             Ok(ins.2)
         },
         Iny => {
+            idle(mem, reg);
             reg.y = reg.y.wrapping_add(1);
             reg.status.zero = 0 == reg.y;
             reg.status.negative = bit_is_set!(reg.y, 7);
@@ -936,20 +1318,42 @@ fn execute_instruction<M: MemoryMap>(ins: Instruction,
             match ins.1 {
                 Addressing::Absolute(ref loc) => reg.pc = *loc,
                 Addressing::Indirect(ref vec) => {
+                    // The NMOS 6502 doesn't carry into the high byte of
+                    // the vector address, so `JMP ($xxff)` wraps within
+                    // the page.
+                    let hi_addr = match variant {
+                        CpuVariant::Nmos6502 => (*vec & 0xff00) | (vec.wrapping_add(1) & 0x00ff),
+                        CpuVariant::Cmos65sc12 => vec.wrapping_add(1),
+                    };
+                    if variant == CpuVariant::Cmos65sc12 {
+                        mem.read(reg.pc.wrapping_sub(1));
+                    }
                     let low = mem.read(*vec);
-                    let hi = mem.read(*vec + 1);
+                    let hi = mem.read(hi_addr);
 
                     reg.pc = (hi as u16) << 8 | (low as u16);
-                }
+                },
+                Addressing::AbsoluteIndexedIndirect(ref vec) => {
+                    mem.read(reg.pc.wrapping_sub(1));
+                    let vec = vec.wrapping_add(reg.x as u16);
+                    let low = mem.read(vec);
+                    let hi = mem.read(vec.wrapping_add(1));
+
+                    reg.pc = (hi as u16) << 8 | (low as u16);
+                },
                 _ => unreachable!()
             }
 
             Ok(ins.2)
         },
         Jsr => {
-            reg.pc -= 1;
+            // The high byte of the target isn't fetched until the return
+            // address has been pushed, which leaves the PC pointing at it
+            reg.pc = reg.pc.wrapping_sub(1);
+            pull_delay(&mut mem, reg);
             push_stack(((reg.pc & 0xff00) >> 8) as u8, &mut mem, reg)?;
             push_stack((reg.pc & 0x00ff) as u8, &mut mem, reg)?;
+            mem.read(reg.pc);
             if let Addressing::Absolute(ref loc) = ins.1 {
                 reg.pc = *loc;
             }
@@ -959,7 +1363,7 @@ fn execute_instruction<M: MemoryMap>(ins: Instruction,
             Ok(6)
         },
         Lda => {
-            let (val, cross_page) = read_mem(&ins.1, mem, reg)?;
+            let (val, cross_page) = read_mem(&ins.1, mem, reg, variant)?;
             reg.acc = val;
             reg.status.zero = reg.acc == 0;
             reg.status.negative = bit_is_set!(reg.acc, 7);
@@ -967,7 +1371,7 @@ fn execute_instruction<M: MemoryMap>(ins: Instruction,
             Ok(ins.2 + if cross_page { 1 } else { 0 })
         },
         Ldx => {
-            let (val, cross_page) = read_mem(&ins.1, mem, reg)?;
+            let (val, cross_page) = read_mem(&ins.1, mem, reg, variant)?;
             reg.x = val;
             reg.status.zero = reg.x == 0;
             reg.status.negative = bit_is_set!(reg.x, 7);
@@ -975,7 +1379,7 @@ fn execute_instruction<M: MemoryMap>(ins: Instruction,
             Ok(ins.2 + if cross_page { 1 } else { 0 })
         },
         Ldy => {
-            let (val, cross_page) = read_mem(&ins.1, mem, reg)?;
+            let (val, cross_page) = read_mem(&ins.1, mem, reg, variant)?;
             reg.y = val;
             reg.status.zero = reg.y == 0;
             reg.status.negative = bit_is_set!(reg.y, 7);
@@ -983,18 +1387,43 @@ fn execute_instruction<M: MemoryMap>(ins: Instruction,
             Ok(ins.2 + if cross_page { 1 } else { 0 })
         },
         Lsr => {
-            let (mut val, _) = read_mem(&ins.1, &mut mem, reg)?;
-            reg.status.carry = 0x01 == (0x01 & val);
-            val = val >> 1;
-            reg.status.zero = val == 0;
-            reg.status.negative = bit_is_set!(val, 7);
-            write_mem(val, &ins.1, mem, reg)?;
+            let (_, cross_page) = read_modify_write(&ins.1, shift_fixup, mem, reg, variant, |mut val, reg| {
+                reg.status.carry = 0x01 == (0x01 & val);
+                val = val >> 1;
+                reg.status.zero = val == 0;
+                reg.status.negative = bit_is_set!(val, 7);
+                val
+            })?;
+
+            Ok(ins.2 + if cross_page && variant == CpuVariant::Cmos65sc12 { 1 } else { 0 })
+        },
+        Nop => {
+            // The multi-byte NOPs still perform their (discarded) read.
+            let cross_page = match ins.1 {
+                Addressing::Implied => {
+                    // The single cycle CMOS NOPs don't get as far as the
+                    // idle read
+                    if ins.2 > 1 {
+                        idle(mem, reg);
+                    }
+                    false
+                },
+                Addressing::Absolute(_) if ins.2 > 4 => {
+                    // The eight cycle CMOS NOP. What it does with its extra
+                    // cycles isn't documented, so they're treated as idle.
+                    read_mem(&ins.1, &mut mem, reg, variant)?;
+                    for _ in 4..ins.2 {
+                        idle(&mut mem, reg);
+                    }
+                    false
+                },
+                _ => read_mem(&ins.1, mem, reg, variant)?.1,
+            };
 
-            Ok(ins.2)
+            Ok(ins.2 + if cross_page { 1 } else { 0 })
         },
-        Nop => Ok(2),
         Ora => {
-            let (val, cross_page) = read_mem(&ins.1, mem, reg)?;
+            let (val, cross_page) = read_mem(&ins.1, mem, reg, variant)?;
             reg.acc |= val;
             reg.status.zero = reg.acc == 0;
             reg.status.negative = bit_is_set!(reg.acc, 7);
@@ -1002,155 +1431,457 @@ fn execute_instruction<M: MemoryMap>(ins: Instruction,
             Ok(ins.2 + if cross_page { 1 } else { 0 })
         },
         Pha => {
+            idle(&mut mem, reg);
             push_stack(reg.acc, mem, reg)?;
             Ok(ins.2)
         },
         Php => {
-            push_stack(u8::from(&reg.status), mem, reg)?;
+            // The B flag only exists on the stack; PHP always pushes it set
+            idle(&mut mem, reg);
+            push_stack(u8::from(&reg.status) | 0x10, mem, reg)?;
             Ok(ins.2)
         },
         Pla => {
+            idle(&mut mem, reg);
+            pull_delay(&mut mem, reg);
             reg.acc = pop_stack(mem, reg)?;
             reg.status.zero = reg.acc == 0;
             reg.status.negative = bit_is_set!(reg.acc, 7);
             Ok(ins.2)
         },
         Plp => {
+            idle(&mut mem, reg);
+            pull_delay(&mut mem, reg);
             reg.status = StatusFlags::from(pop_stack(mem, reg)?);
             Ok(ins.2)
         },
         Rol => {
-            let (mut val, _) = read_mem(&ins.1, &mut mem, reg)?;
-            let old_carry = reg.status.carry as u8;
-            reg.status.zero = false;
-            reg.status.carry = bit_is_set!(val, 7);
-            val = (val & 0x7f) << 1;
-            val = val | (old_carry & 0x01);
-            reg.status.zero = val == 0;
-            reg.status.negative = bit_is_set!(val, 7);
-            write_mem(val, &ins.1, mem, reg)?;
-
-            Ok(ins.2)
+            let (_, cross_page) = read_modify_write(&ins.1, shift_fixup, mem, reg, variant, |mut val, reg| {
+                let old_carry = reg.status.carry as u8;
+                reg.status.carry = bit_is_set!(val, 7);
+                val = (val & 0x7f) << 1;
+                val = val | (old_carry & 0x01);
+                reg.status.zero = val == 0;
+                reg.status.negative = bit_is_set!(val, 7);
+                val
+            })?;
+
+            Ok(ins.2 + if cross_page && variant == CpuVariant::Cmos65sc12 { 1 } else { 0 })
         },
         Ror => {
-            let (mut val, _) = read_mem(&ins.1, &mut mem, reg)?;
-            let old_carry = reg.status.carry as u8;
-            reg.status.zero = false;
-            reg.status.carry = bit_is_set!(val, 0);
-            val = val >> 1;
-            val = val | ((old_carry & 0x01) << 7);
-            reg.status.negative = bit_is_set!(val, 7);
-            reg.status.zero = val == 0;
-            write_mem(val, &ins.1, mem, reg)?;
-
-            Ok(ins.2)
+            let (_, cross_page) = read_modify_write(&ins.1, shift_fixup, mem, reg, variant, |mut val, reg| {
+                let old_carry = reg.status.carry as u8;
+                reg.status.carry = bit_is_set!(val, 0);
+                val = val >> 1;
+                val = val | ((old_carry & 0x01) << 7);
+                reg.status.negative = bit_is_set!(val, 7);
+                reg.status.zero = val == 0;
+                val
+            })?;
+
+            Ok(ins.2 + if cross_page && variant == CpuVariant::Cmos65sc12 { 1 } else { 0 })
         },
         Rti => {
+            idle(&mut mem, reg);
+            pull_delay(&mut mem, reg);
             reg.status = StatusFlags::from(pop_stack(&mut mem, reg)?);
             reg.pc = (pop_stack(&mut mem, reg)? as u16) | ((pop_stack(&mut mem, reg)? as u16) << 8);
             Ok(6)
         },
         Rts => {
+            idle(&mut mem, reg);
+            pull_delay(&mut mem, reg);
             reg.pc = (pop_stack(&mut mem, reg)? as u16) | ((pop_stack(&mut mem, reg)? as u16) << 8);
-            reg.pc += 1;
+            mem.read(reg.pc);
+            reg.pc = reg.pc.wrapping_add(1);
             Ok(6)
         },
         Sbc => {
-            assert!(!reg.status.decimal);
-
-            let orig = reg.acc;
-            let (val, cross_page) = read_mem(&ins.1, mem, reg)?;
-            let (v, o) = reg.acc.overflowing_sub(val);
-            reg.acc = v;
-
-            // Carry?
-            reg.status.carry = !o;
-            // Zero?
-            reg.status.zero = reg.acc == 0;
-            // Overflow?
-            reg.status.overflow = 0 != (orig & 0x80) ^ (reg.acc & 0x80);
-            // Negative?
-            reg.status.negative = bit_is_set!(reg.acc, 7);
+            let (val, cross_page) = read_mem(&ins.1, &mut mem, reg, variant)?;
+            if decimal_cycles > 0 {
+                idle(&mut mem, reg);
+            }
+            subtract_with_borrow(val, reg, variant);
 
-            Ok(ins.2 + if cross_page { 1 } else { 0 })
+            Ok(ins.2 + decimal_cycles + if cross_page { 1 } else { 0 })
         },
         Sec => {
+            idle(mem, reg);
             reg.status.carry = true;
             Ok(ins.2)
         },
         Sed => {
+            idle(mem, reg);
             reg.status.decimal = true;
             Ok(ins.2)
         },
         Sei => {
+            idle(mem, reg);
             reg.status.interrupt = true;
             Ok(ins.2)
         },
         Sta => {
-            write_mem(reg.acc, &ins.1, mem, reg)?;
+            write_mem(reg.acc, &ins.1, mem, reg, variant)?;
             Ok(ins.2)
         },
         Stx => {
-            write_mem(reg.x, &ins.1, mem, reg)?;
+            write_mem(reg.x, &ins.1, mem, reg, variant)?;
             Ok(ins.2)
         },
         Sty => {
-            write_mem(reg.y, &ins.1, mem, reg)?;
+            write_mem(reg.y, &ins.1, mem, reg, variant)?;
             Ok(ins.2)
         },
         Tax => {
+            idle(mem, reg);
             reg.x = reg.acc;
+            reg.status.zero = reg.x == 0;
+            reg.status.negative = bit_is_set!(reg.x, 7);
             Ok(ins.2)
         },
         Tay => {
+            idle(mem, reg);
             reg.y = reg.acc;
+            reg.status.zero = reg.y == 0;
+            reg.status.negative = bit_is_set!(reg.y, 7);
             Ok(ins.2)
         },
         Tsx => {
+            idle(mem, reg);
             reg.x = reg.sp;
+            reg.status.zero = reg.x == 0;
+            reg.status.negative = bit_is_set!(reg.x, 7);
             Ok(ins.2)
         },
         Txa => {
+            idle(mem, reg);
             reg.acc = reg.x;
+            reg.status.zero = reg.acc == 0;
+            reg.status.negative = bit_is_set!(reg.acc, 7);
             Ok(ins.2)
         },
         Txs => {
+            idle(mem, reg);
             reg.sp = reg.x;
             Ok(ins.2)
         },
         Tya => {
+            idle(mem, reg);
             reg.acc = reg.y;
+            reg.status.zero = reg.acc == 0;
+            reg.status.negative = bit_is_set!(reg.acc, 7);
             Ok(ins.2)
         },
-    }
-}
+        Alr => {
+            let (val, _) = read_mem(&ins.1, mem, reg, variant)?;
+            let val = reg.acc & val;
+            reg.status.carry = bit_is_set!(val, 0);
+            reg.acc = val >> 1;
+            reg.status.zero = reg.acc == 0;
+            reg.status.negative = false;
 
-pub struct Cpu {
-    registers: Registers,
-}
+            Ok(ins.2)
+        },
+        Anc => {
+            let (val, _) = read_mem(&ins.1, mem, reg, variant)?;
+            reg.acc &= val;
+            reg.status.zero = reg.acc == 0;
+            reg.status.negative = bit_is_set!(reg.acc, 7);
+            reg.status.carry = reg.status.negative;
 
-fn push_cpu_state<M: MemoryMap>(cpu: &mut Cpu, mut mem: M) -> Result<(), CpuError> {
-    push_stack(((cpu.registers.pc & 0xff00) >> 8) as u8, &mut mem, &mut cpu.registers)?;
-    push_stack((cpu.registers.pc & 0x00ff) as u8, &mut mem, &mut cpu.registers)?;
-    push_stack(u8::from(&cpu.registers.status), &mut mem, &mut cpu.registers)?;
-    Ok(())
-}
+            Ok(ins.2)
+        },
+        Arr => {
+            let (val, _) = read_mem(&ins.1, mem, reg, variant)?;
+            let val = reg.acc & val;
+            let old_carry = reg.status.carry as u8;
+            reg.acc = (val >> 1) | (old_carry << 7);
+            reg.status.zero = reg.acc == 0;
+            reg.status.negative = bit_is_set!(reg.acc, 7);
 
-impl Cpu {
-    pub fn new() -> Cpu {
-        Cpu {
-            registers: Registers::new()
-        }
-    }
+            if !reg.status.decimal {
+                reg.status.carry = bit_is_set!(reg.acc, 6);
+                reg.status.overflow = bit_is_set!(reg.acc, 6) != bit_is_set!(reg.acc, 5);
+            }
+            else {
+                // In decimal mode the rotated result is BCD corrected using
+                // the nibbles of the value *before* the rotate.
+                reg.status.overflow = 0 != ((val ^ reg.acc) & 0x40);
+                let (hi, lo) = (val >> 4, val & 0x0f);
+                if lo + (lo & 0x01) > 0x05 {
+                    reg.acc = (reg.acc & 0xf0) | (reg.acc.wrapping_add(0x06) & 0x0f);
+                }
+                reg.status.carry = hi + (hi & 0x01) > 0x05;
+                if reg.status.carry {
+                    reg.acc = reg.acc.wrapping_add(0x60);
+                }
+            }
 
-    pub fn program_counter(&self) -> u16 {
-        self.registers.pc
+            Ok(ins.2)
+        },
+        Dcp => {
+            let (val, _) = read_modify_write(&ins.1, Fixup::Always, mem, reg, variant, |val, _| {
+                val.wrapping_sub(1)
+            })?;
+            let acc = reg.acc;
+            compare(acc, val, reg);
+
+            Ok(ins.2)
+        },
+        Isc => {
+            let (val, _) = read_modify_write(&ins.1, Fixup::Always, mem, reg, variant, |val, _| {
+                val.wrapping_add(1)
+            })?;
+            subtract_with_borrow(val, reg, variant);
+
+            Ok(ins.2)
+        },
+        Jam => {
+            // Halting the CPU is handled by `Cpu::step`.
+            Ok(ins.2)
+        },
+        Lax => {
+            let (val, cross_page) = read_mem(&ins.1, mem, reg, variant)?;
+            reg.acc = val;
+            reg.x = val;
+            reg.status.zero = val == 0;
+            reg.status.negative = bit_is_set!(val, 7);
+
+            Ok(ins.2 + if cross_page { 1 } else { 0 })
+        },
+        Rla => {
+            let (val, _) = read_modify_write(&ins.1, Fixup::Always, mem, reg, variant, |val, reg| {
+                let old_carry = reg.status.carry as u8;
+                reg.status.carry = bit_is_set!(val, 7);
+                (val << 1) | old_carry
+            })?;
+            reg.acc &= val;
+            reg.status.zero = reg.acc == 0;
+            reg.status.negative = bit_is_set!(reg.acc, 7);
+
+            Ok(ins.2)
+        },
+        Rra => {
+            let (val, _) = read_modify_write(&ins.1, Fixup::Always, mem, reg, variant, |val, reg| {
+                let old_carry = reg.status.carry as u8;
+                reg.status.carry = bit_is_set!(val, 0);
+                (val >> 1) | (old_carry << 7)
+            })?;
+            add_with_carry(val, reg, variant);
+
+            Ok(ins.2)
+        },
+        Sax => {
+            let val = reg.acc & reg.x;
+            write_mem(val, &ins.1, mem, reg, variant)?;
+            Ok(ins.2)
+        },
+        Sbx => {
+            let (val, _) = read_mem(&ins.1, mem, reg, variant)?;
+            let lhs = reg.acc & reg.x;
+            compare(lhs, val, reg);
+            reg.x = lhs.wrapping_sub(val);
+
+            Ok(ins.2)
+        },
+        Slo => {
+            let (val, _) = read_modify_write(&ins.1, Fixup::Always, mem, reg, variant, |val, reg| {
+                reg.status.carry = bit_is_set!(val, 7);
+                val << 1
+            })?;
+            reg.acc |= val;
+            reg.status.zero = reg.acc == 0;
+            reg.status.negative = bit_is_set!(reg.acc, 7);
+
+            Ok(ins.2)
+        },
+        Sre => {
+            let (val, _) = read_modify_write(&ins.1, Fixup::Always, mem, reg, variant, |val, reg| {
+                reg.status.carry = bit_is_set!(val, 0);
+                val >> 1
+            })?;
+            reg.acc ^= val;
+            reg.status.zero = reg.acc == 0;
+            reg.status.negative = bit_is_set!(reg.acc, 7);
+
+            Ok(ins.2)
+        },
+        Bra => {
+            Ok(ins.2 + take_branch(&ins.1, mem, reg))
+        },
+        Phx => {
+            idle(&mut mem, reg);
+            push_stack(reg.x, mem, reg)?;
+            Ok(ins.2)
+        },
+        Phy => {
+            idle(&mut mem, reg);
+            push_stack(reg.y, mem, reg)?;
+            Ok(ins.2)
+        },
+        Plx => {
+            idle(&mut mem, reg);
+            pull_delay(&mut mem, reg);
+            reg.x = pop_stack(mem, reg)?;
+            reg.status.zero = reg.x == 0;
+            reg.status.negative = bit_is_set!(reg.x, 7);
+            Ok(ins.2)
+        },
+        Ply => {
+            idle(&mut mem, reg);
+            pull_delay(&mut mem, reg);
+            reg.y = pop_stack(mem, reg)?;
+            reg.status.zero = reg.y == 0;
+            reg.status.negative = bit_is_set!(reg.y, 7);
+            Ok(ins.2)
+        },
+        Stz => {
+            write_mem(0x00, &ins.1, mem, reg, variant)?;
+            Ok(ins.2)
+        },
+        Trb => {
+            read_modify_write(&ins.1, Fixup::Always, mem, reg, variant, |val, reg| {
+                reg.status.zero = 0 == val & reg.acc;
+                val & !reg.acc
+            })?;
+            Ok(ins.2)
+        },
+        Tsb => {
+            read_modify_write(&ins.1, Fixup::Always, mem, reg, variant, |val, reg| {
+                reg.status.zero = 0 == val & reg.acc;
+                val | reg.acc
+            })?;
+            Ok(ins.2)
+        },
+    }
+}
+
+/// The number of cycles reported by each call to `Cpu::step` while the CPU
+/// is jammed.
+const HALTED_CYCLES: usize = 2;
+
+/// The number of cycles it takes the CPU to respond to an IRQ or NMI.
+const INTERRUPT_CYCLES: usize = 7;
+
+pub struct Cpu {
+    registers: Registers,
+    variant: CpuVariant,
+    halted: bool,
+    bus_cycles: Vec<BusCycle>,
+    observers: Vec<Box<dyn CpuObserver>>,
+    irq: IrqLine,
+    nmi: NmiLine,
+    /// CLI, SEI and PLP change `I` after the CPU has polled for interrupts,
+    /// so for one instruction the poll still sees the old value.
+    delayed_interrupt_flag: Option<bool>,
+}
+
+/// The registers, interrupt inputs and anything else that carries over
+/// from one instruction to the next. Observers aren't included.
+impl SaveState for Cpu {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_u8(self.variant as u8);
+        state.write_u16(self.registers.pc);
+        state.write_u8(self.registers.sp);
+        state.write_u8(self.registers.acc);
+        state.write_u8(self.registers.x);
+        state.write_u8(self.registers.y);
+        state.write_u8(u8::from(&self.registers.status));
+        state.write_bool(self.halted);
+        self.irq.save(state);
+        self.nmi.save(state);
+        state.write_u8(match self.delayed_interrupt_flag {
+            None => 0,
+            Some(false) => 1,
+            Some(true) => 2,
+        });
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        if state.read_u8()? != self.variant as u8 {
+            return Err(StateError::Mismatch("Saved from a different CPU"));
+        }
+        self.registers.pc = state.read_u16()?;
+        self.registers.sp = state.read_u8()?;
+        self.registers.acc = state.read_u8()?;
+        self.registers.x = state.read_u8()?;
+        self.registers.y = state.read_u8()?;
+        self.registers.status = StatusFlags::from(state.read_u8()?);
+        self.halted = state.read_bool()?;
+        self.irq.load(state)?;
+        self.nmi.load(state)?;
+        self.delayed_interrupt_flag = match state.read_u8()? {
+            0 => None,
+            1 => Some(false),
+            _ => Some(true),
+        };
+        self.bus_cycles.clear();
+        Ok(())
+    }
+}
+
+fn push_cpu_state<M: MemoryMap>(cpu: &mut Cpu, mut mem: M) -> Result<(), CpuError> {
+    idle(&mut mem, &cpu.registers);
+    idle(&mut mem, &cpu.registers);
+    push_stack(((cpu.registers.pc & 0xff00) >> 8) as u8, &mut mem, &mut cpu.registers)?;
+    push_stack((cpu.registers.pc & 0x00ff) as u8, &mut mem, &mut cpu.registers)?;
+    push_stack(u8::from(&cpu.registers.status) & !0x10, &mut mem, &mut cpu.registers)?;
+    Ok(())
+}
+
+impl Cpu {
+    pub fn new(variant: CpuVariant) -> Cpu {
+        Cpu {
+            registers: Registers::new(),
+            variant: variant,
+            halted: false,
+            bus_cycles: vec![],
+            observers: vec![],
+            irq: IrqLine::default(),
+            nmi: NmiLine::default(),
+            delayed_interrupt_flag: None,
+        }
+    }
+
+    /// Attaches an observer that is told about every instruction and
+    /// interrupt the CPU executes from now on.
+    pub fn add_observer(&mut self, observer: Box<dyn CpuObserver>) {
+        self.observers.push(observer);
+    }
+
+    pub fn clear_observers(&mut self) {
+        self.observers.clear();
+    }
+
+    pub fn variant(&self) -> CpuVariant {
+        self.variant
+    }
+
+    pub fn program_counter(&self) -> u16 {
+        self.registers.pc
     }
 
     pub fn registers(&self) -> &Registers {
         &self.registers
     }
 
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.registers
+    }
+
+    /// Returns `true` if the CPU has executed one of the `JAM` opcodes. A
+    /// jammed CPU stops fetching instructions and ignores interrupts until
+    /// it is reset.
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// The bus accesses made by the last call to `step`, one for each
+    /// cycle the instruction took.
+    pub fn bus_cycles(&self) -> &[BusCycle] {
+        &self.bus_cycles
+    }
+
     pub fn initialize<M>(&mut self, mut mem: M) -> Result<(), CpuError>
         where M: MemoryMap + AsMemoryRegionMut
     {
@@ -1163,53 +1894,243 @@ impl Cpu {
         let low = mem.read(0xfffc);
         let hi = mem.read(0xfffd);
         self.registers.pc = ((hi as u16) << 8) | low as u16;
+        self.halted = false;
+        self.delayed_interrupt_flag = None;
         Ok(())
     }
 
+    /// Runs the 6502's reset sequence. This goes through the motions of an
+    /// interrupt without writing to the stack, so `SP` drops by three, then
+    /// sets `I` and jumps through the reset vector. Unlike `initialize`, it
+    /// is meant for resetting a machine that is already running.
+    pub fn reset<M: MemoryMap>(&mut self, mut mem: M) {
+        log_cpu!("RESET");
+        self.registers.sp = self.registers.sp.wrapping_sub(3);
+        self.registers.status.interrupt = true;
+        if self.variant == CpuVariant::Cmos65sc12 {
+            self.registers.status.decimal = false;
+        }
+        let low = mem.read(0xfffc);
+        let hi = mem.read(0xfffd);
+        self.registers.pc = ((hi as u16) << 8) | low as u16;
+        self.halted = false;
+        self.delayed_interrupt_flag = None;
+        self.bus_cycles.clear();
+    }
+
     pub fn step<M>(&mut self, mut mem: M) -> Result<usize, CpuError> 
         where M: MemoryMap + AsMemoryRegion
     {
+        self.bus_cycles.clear();
+        if self.halted {
+            return Ok(HALTED_CYCLES);
+        }
+
         let (bytes, ins) = {
-            let instruction_region = mem.region(self.registers.pc as _..self.registers.pc as usize + 4)
+            let end = ::std::cmp::min(self.registers.pc as usize + 4, mem.len());
+            let instruction_region = mem.region(self.registers.pc as _..end)
                                         .unwrap_or_else(|e| e.0);
-            decode_instruction(&instruction_region).unwrap()
+            decode_instruction_for(self.variant, &instruction_region)
+                .map_err(|_| CpuError::InvalidInstruction {
+                    pc: self.registers.pc,
+                    opcode: instruction_region.first().cloned().unwrap_or(0x00),
+                })?
         };
 
 
         log_cpu!("{:04x}: {}", self.registers.pc, ins);
-        self.registers.pc += bytes as u16;
-        let result = execute_instruction(ins, &mut mem, &mut self.registers);
-        result
+
+        let before = self.registers;
+        self.delayed_interrupt_flag = match ins.0 {
+            OpCode::Cli | OpCode::Sei | OpCode::Plp => Some(before.status.interrupt),
+            _ => None,
+        };
+
+        let cycles = {
+            let mut bus = Bus::new(&mut mem, &mut self.bus_cycles);
+
+            // JSR fetches the last byte of its operand itself
+            let fetched = if ins.0 == OpCode::Jsr { bytes - 1 } else { bytes };
+            bus.sync(self.registers.pc);
+            for n in 0..fetched {
+                bus.read(self.registers.pc.wrapping_add(n as u16));
+            }
+
+            if ins.0 == OpCode::Jam {
+                log_cpu!("CPU jammed at {:04x}", self.registers.pc);
+                self.halted = true;
+                ins.2
+            }
+            else {
+                self.registers.pc = self.registers.pc.wrapping_add(bytes as u16);
+                execute_instruction(ins, &mut bus, &mut self.registers, self.variant)?
+            }
+        };
+
+        for observer in &mut self.observers {
+            observer.instruction(before.pc, &ins, &before, &self.registers, cycles);
+        }
+
+        Ok(cycles)
+    }
+
+    fn notify_interrupt(&mut self, interrupt: Interrupt, before: &Registers) {
+        for observer in &mut self.observers {
+            observer.interrupt(interrupt, before.pc, before, &self.registers, INTERRUPT_CYCLES);
+        }
     }
 
     pub fn non_maskable_interrupt<M: MemoryMap>(&mut self, mut mem: M) -> Result<(), CpuError> {
+        if self.halted {
+            return Ok(());
+        }
+
         log_cpu!("NMI");
+        let before = self.registers;
         push_cpu_state(self, &mut mem)?;
+        if self.variant == CpuVariant::Cmos65sc12 {
+            self.registers.status.decimal = false;
+        }
         let low = mem.read(0xfffa);
         let hi = mem.read(0xfffb);
         self.registers.pc = ((hi as u16) << 8) | low as u16;
+        self.registers.status.interrupt = true;
+        self.delayed_interrupt_flag = None;
+        self.notify_interrupt(Interrupt::Nmi, &before);
         Ok(())
     }
 
+    /// Asserts or releases `source`'s hold on the IRQ line. The line is
+    /// level triggered, so the interrupt is taken again after `RTI` for as
+    /// long as a source keeps it asserted.
+    pub fn set_irq(&mut self, source: InterruptSource, asserted: bool) {
+        self.irq.set(source, asserted);
+    }
+
+    /// Asserts or releases `source`'s hold on the NMI line. An NMI is taken
+    /// once each time the line goes from released to asserted.
+    pub fn set_nmi(&mut self, source: InterruptSource, asserted: bool) {
+        self.nmi.set(source, asserted);
+    }
+
+    pub fn irq_line(&self) -> &IrqLine {
+        &self.irq
+    }
+
+    pub fn nmi_line(&self) -> &NmiLine {
+        &self.nmi
+    }
+
+    /// Samples the interrupt lines between instructions, taking an NMI if
+    /// one is pending, or an IRQ if the line is asserted and `I` is clear.
+    /// Returns the number of cycles used, which is 0 if no interrupt was
+    /// taken. The interrupt sequence's accesses replace the last
+    /// instruction's in `bus_cycles`.
+    pub fn poll_interrupts<M: MemoryMap>(&mut self, mut mem: M) -> Result<usize, CpuError> {
+        if self.halted {
+            return Ok(0);
+        }
+
+        let masked = self.delayed_interrupt_flag
+                         .unwrap_or(self.registers.status.interrupt);
+        let nmi = self.nmi.take_edge();
+        if !nmi && !(self.irq.is_asserted() && !masked) {
+            return Ok(0);
+        }
+
+        let mut cycles = ::std::mem::replace(&mut self.bus_cycles, vec![]);
+        cycles.clear();
+        let result = {
+            let mut bus = Bus::new(&mut mem, &mut cycles);
+            if nmi {
+                self.non_maskable_interrupt(&mut bus)
+            }
+            else {
+                self.take_irq(&mut bus)
+            }
+        };
+        self.bus_cycles = cycles;
+        result.map(|_| INTERRUPT_CYCLES)
+    }
+
     pub fn interrupt_request<M>(&mut self, mut mem: M) -> Result<bool, CpuError>
         where M: MemoryMap
     {
-        if !self.registers.status.interrupt {
-
-            log_cpu!("IRQ");
-//            mem.write(0xfe4d, 0xe0);
-//            mem.write(0xfe4e, 0xe0);
-
-            push_cpu_state(self, &mut mem)?;
-            let low = mem.read(0xfffe);
-            let hi = mem.read(0xffff);
-            self.registers.pc = ((hi as u16) << 8) | low as u16;
-            self.registers.status.interrupt = true;
+        if !self.registers.status.interrupt && !self.halted {
+            self.take_irq(&mut mem)?;
             return Ok(true);
         }
 
         Ok(false)
     }
+
+    fn take_irq<M: MemoryMap>(&mut self, mut mem: M) -> Result<(), CpuError> {
+        log_cpu!("IRQ");
+//        mem.write(0xfe4d, 0xe0);
+//        mem.write(0xfe4e, 0xe0);
+
+        let before = self.registers;
+        push_cpu_state(self, &mut mem)?;
+        if self.variant == CpuVariant::Cmos65sc12 {
+            self.registers.status.decimal = false;
+        }
+        let low = mem.read(0xfffe);
+        let hi = mem.read(0xffff);
+        self.registers.pc = ((hi as u16) << 8) | low as u16;
+        self.registers.status.interrupt = true;
+        self.delayed_interrupt_flag = None;
+        self.notify_interrupt(Interrupt::Irq, &before);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod encode_should {
+    use super::*;
+
+    #[test]
+    fn round_trip_every_opcode() {
+        const OPERANDS: &'static [u8] = &[0x00, 0x01, 0x7f, 0x80, 0xfe, 0xff];
+        let mut failures = vec![];
+
+        for &variant in &[CpuVariant::Nmos6502, CpuVariant::Cmos65sc12] {
+            for opcode in 0..256usize {
+                for &lo in OPERANDS {
+                    for &hi in OPERANDS {
+                        let bytes = [opcode as u8, lo, hi];
+                        let (len, ins) = match decode_instruction_for(variant, &bytes) {
+                            Ok(decoded) => decoded,
+                            Err(_) => continue,
+                        };
+
+                        let decoded = ins.encode_for(variant)
+                            .map_err(|_| ())
+                            .and_then(|b| decode_instruction_for(variant, &b).map_err(|_| ()));
+                        if decoded != Ok((len, ins)) {
+                            failures.push((variant, bytes));
+                        }
+                    }
+                }
+            }
+        }
+
+        assert!(failures.is_empty(), "{:x?}", failures);
+    }
+
+    #[test]
+    fn prefer_documented_opcodes() {
+        assert_eq!(Ok(vec![0xea]), encode_instruction_for(CpuVariant::Nmos6502, OpCode::Nop, Addressing::Implied));
+        assert_eq!(Ok(vec![0xe9, 0x01]), encode_instruction_for(CpuVariant::Nmos6502, OpCode::Sbc, Addressing::Immediate(0x01)));
+        assert_eq!(Ok(vec![0x64, 0x70]), encode_instruction_for(CpuVariant::Cmos65sc12, OpCode::Stz, Addressing::ZeroPage(0x70)));
+    }
+
+    #[test]
+    fn fail_to_encode_unsupported_addressing() {
+        assert_eq!(Err(InstructionEncodeError), 
+                   encode_instruction_for(CpuVariant::Nmos6502, OpCode::Stz, Addressing::ZeroPage(0x70)));
+        assert_eq!(Err(InstructionEncodeError), 
+                   encode_instruction_for(CpuVariant::Nmos6502, OpCode::Lda, Addressing::ZeroPageY(0x70)));
+    }
 }
 
 #[cfg(test)]
@@ -1236,12 +2157,82 @@ mod decode_should {
         assert_eq!(second, Instruction(OpCode::Adc, Addressing::ZeroPage(1), 3));
         assert_eq!(third, Instruction(OpCode::Adc, Addressing::IndirectY(0b10000001), 5));
     }
+    #[test]
+    fn read_undocumented_instructions() {
+        assert_eq!(
+            (2, Instruction(OpCode::Lax, Addressing::ZeroPage(0x70), 3)),
+            decode_instruction(&[0xa7, 0x70]).unwrap());
+        assert_eq!(
+            (3, Instruction(OpCode::Dcp, Addressing::AbsoluteY(0x1234), 7)),
+            decode_instruction(&[0xdb, 0x34, 0x12]).unwrap());
+        assert_eq!(
+            (2, Instruction(OpCode::Isc, Addressing::IndirectY(0x80), 8)),
+            decode_instruction(&[0xf3, 0x80]).unwrap());
+        assert_eq!(
+            (3, Instruction(OpCode::Nop, Addressing::AbsoluteX(0x2000), 4)),
+            decode_instruction(&[0x1c, 0x00, 0x20]).unwrap());
+        assert_eq!(
+            (1, Instruction(OpCode::Jam, Addressing::Implied, 2)),
+            decode_instruction(&[0x02]).unwrap());
+    }
+
+    /// Base cycle counts for every NMOS opcode, as published in the
+    /// "6502 Instruction Set" reference at masswerk.at. Branches are listed 
+    /// as not taken and indexed reads without a page crossing.
+    const NMOS_CYCLES: [usize; 256] = [
+    //  0  1  2  3  4  5  6  7  8  9  a  b  c  d  e  f
+        7, 6, 2, 8, 3, 3, 5, 5, 3, 2, 2, 2, 4, 4, 6, 6, // 0
+        2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, // 1
+        6, 6, 2, 8, 3, 3, 5, 5, 4, 2, 2, 2, 4, 4, 6, 6, // 2
+        2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, // 3
+        6, 6, 2, 8, 3, 3, 5, 5, 3, 2, 2, 2, 3, 4, 6, 6, // 4
+        2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, // 5
+        6, 6, 2, 8, 3, 3, 5, 5, 4, 2, 2, 2, 5, 4, 6, 6, // 6
+        2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, // 7
+        2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4, // 8
+        2, 6, 2, 6, 4, 4, 4, 4, 2, 5, 2, 5, 5, 5, 5, 5, // 9
+        2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4, // a
+        2, 5, 2, 5, 4, 4, 4, 4, 2, 4, 2, 4, 4, 4, 4, 4, // b
+        2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6, // c
+        2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, // d
+        2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6, // e
+        2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, // f
+    ];
+
+    #[test]
+    fn report_the_published_base_cycles_for_every_opcode() {
+        let wrong = (0x00..0x100_usize)
+            .filter_map(|opcode| {
+                let mem = [opcode as u8, 0x00, 0x00];
+                match decode_instruction(&mem) {
+                    Ok((_, Instruction(_, _, cycles))) if cycles != NMOS_CYCLES[opcode] => 
+                        Some(format!("{:02x}: {} != {}", opcode, cycles, NMOS_CYCLES[opcode])),
+                    _ => None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        assert!(wrong.is_empty(), "Incorrect cycles for {:?}", wrong);
+    }
+
+    #[test]
+    fn decode_every_nmos_opcode_except_the_unstable_ones() {
+        const UNSTABLE: &'static [u8] = &[0x8b, 0x93, 0x9b, 0x9c, 0x9e, 0x9f, 0xab, 0xbb];
+
+        for opcode in 0x00..0x100_usize {
+            let mem = [opcode as u8, 0x00, 0x00];
+            assert_eq!(
+                !UNSTABLE.contains(&(opcode as u8)),
+                decode_instruction(&mem).is_ok(),
+                "Opcode {:02x}", opcode);
+        }
+    }
 }
 
 #[cfg(test)]
 mod execute_should {
     use super::*;
-    use memory::Map;
+    use memory::{Map, BusOperation};
 
     #[test]
     fn add_to_accumulator_with_correct_overflow() {
@@ -1252,7 +2243,8 @@ mod execute_should {
         execute_instruction(
             Instruction(OpCode::Adc, Addressing::Immediate(0x7f), 2),
             &mut mem,
-            &mut reg
+            &mut reg,
+            CpuVariant::Nmos6502
         );
 
         assert!(reg.status.overflow);
@@ -1262,9 +2254,889 @@ mod execute_should {
         execute_instruction(
             Instruction(OpCode::Adc, Addressing::Immediate(0x3f), 2),
             &mut mem,
-            &mut reg
+            &mut reg,
+            CpuVariant::Nmos6502
         );
 
         assert!(!reg.status.overflow);
     }
+
+    fn adc(acc: u8, val: u8, carry: bool, decimal: bool) -> Registers {
+        let mut mem = Map::new();
+        let mut reg = Registers::new();
+        reg.acc = acc;
+        reg.status.carry = carry;
+        reg.status.decimal = decimal;
+
+        execute_instruction(
+            Instruction(OpCode::Adc, Addressing::Immediate(val), 2),
+            &mut mem,
+            &mut reg,
+            CpuVariant::Nmos6502
+        ).unwrap();
+
+        reg
+    }
+
+    fn sbc(acc: u8, val: u8, carry: bool, decimal: bool) -> Registers {
+        let mut mem = Map::new();
+        let mut reg = Registers::new();
+        reg.acc = acc;
+        reg.status.carry = carry;
+        reg.status.decimal = decimal;
+
+        execute_instruction(
+            Instruction(OpCode::Sbc, Addressing::Immediate(val), 2),
+            &mut mem,
+            &mut reg,
+            CpuVariant::Nmos6502
+        ).unwrap();
+
+        reg
+    }
+
+    #[test]
+    fn add_with_carry_in_binary_mode() {
+        let reg = adc(0x01, 0x01, true, false);
+        assert_eq!(0x03, reg.acc);
+        assert!(!reg.status.carry);
+
+        let reg = adc(0xff, 0x01, false, false);
+        assert_eq!(0x00, reg.acc);
+        assert!(reg.status.carry);
+        assert!(reg.status.zero);
+        assert!(!reg.status.overflow);
+
+        let reg = adc(0x80, 0xff, false, false);
+        assert_eq!(0x7f, reg.acc);
+        assert!(reg.status.carry);
+        assert!(reg.status.overflow);
+    }
+
+    #[test]
+    fn subtract_with_borrow_in_binary_mode() {
+        let reg = sbc(0x05, 0x03, true, false);
+        assert_eq!(0x02, reg.acc);
+        assert!(reg.status.carry);
+
+        let reg = sbc(0x05, 0x03, false, false);
+        assert_eq!(0x01, reg.acc);
+
+        let reg = sbc(0x00, 0x01, true, false);
+        assert_eq!(0xff, reg.acc);
+        assert!(!reg.status.carry);
+        assert!(reg.status.negative);
+
+        let reg = sbc(0x80, 0x01, true, false);
+        assert_eq!(0x7f, reg.acc);
+        assert!(reg.status.overflow);
+    }
+
+    #[test]
+    fn add_valid_bcd_in_decimal_mode() {
+        let reg = adc(0x09, 0x01, false, true);
+        assert_eq!(0x10, reg.acc);
+        assert!(!reg.status.carry);
+
+        let reg = adc(0x58, 0x46, true, true);
+        assert_eq!(0x05, reg.acc);
+        assert!(reg.status.carry);
+
+        let reg = adc(0x12, 0x34, false, true);
+        assert_eq!(0x46, reg.acc);
+        assert!(!reg.status.carry);
+    }
+
+    #[test]
+    fn set_nmos_flags_from_intermediate_result_in_decimal_mode() {
+        // 99 + 01 = 00 (carry), but Z comes from the binary sum ($9a) and
+        // N from the intermediate result.
+        let reg = adc(0x99, 0x01, false, true);
+        assert_eq!(0x00, reg.acc);
+        assert!(reg.status.carry);
+        assert!(!reg.status.zero);
+        assert!(reg.status.negative);
+
+        let reg = adc(0x79, 0x00, true, true);
+        assert_eq!(0x80, reg.acc);
+        assert!(reg.status.negative);
+        assert!(reg.status.overflow);
+
+        let reg = adc(0x81, 0x92, false, true);
+        assert_eq!(0x73, reg.acc);
+        assert!(reg.status.carry);
+        assert!(reg.status.overflow);
+        assert!(!reg.status.negative);
+    }
+
+    #[test]
+    fn add_invalid_bcd_in_decimal_mode() {
+        assert_eq!(0x16, adc(0x0f, 0x01, false, true).acc);
+        assert_eq!(0x20, adc(0x1a, 0x00, false, true).acc);
+
+        let reg = adc(0xff, 0xff, true, true);
+        assert_eq!(0x55, reg.acc);
+        assert!(reg.status.carry);
+    }
+
+    #[test]
+    fn subtract_valid_bcd_in_decimal_mode() {
+        let reg = sbc(0x46, 0x12, true, true);
+        assert_eq!(0x34, reg.acc);
+        assert!(reg.status.carry);
+
+        assert_eq!(0x27, sbc(0x40, 0x13, true, true).acc);
+        assert_eq!(0x29, sbc(0x32, 0x02, false, true).acc);
+
+        let reg = sbc(0x12, 0x21, true, true);
+        assert_eq!(0x91, reg.acc);
+        assert!(!reg.status.carry);
+        assert!(reg.status.negative);
+    }
+
+    #[test]
+    fn load_accumulator_and_x_with_lax() {
+        let mut mem = Map::new();
+        let mut reg = Registers::new();
+        mem.write(0x0070, 0x80);
+
+        execute_instruction(
+            Instruction(OpCode::Lax, Addressing::ZeroPage(0x70), 3),
+            &mut mem,
+            &mut reg,
+            CpuVariant::Nmos6502
+        ).unwrap();
+
+        assert_eq!(0x80, reg.acc);
+        assert_eq!(0x80, reg.x);
+        assert!(reg.status.negative);
+    }
+
+    #[test]
+    fn store_accumulator_and_x_with_sax() {
+        let mut mem = Map::new();
+        let mut reg = Registers::new();
+        reg.acc = 0xf0;
+        reg.x = 0x3c;
+
+        execute_instruction(
+            Instruction(OpCode::Sax, Addressing::Absolute(0x2000), 4),
+            &mut mem,
+            &mut reg,
+            CpuVariant::Nmos6502
+        ).unwrap();
+
+        assert_eq!(0x30, mem.read(0x2000));
+    }
+
+    #[test]
+    fn decrement_then_compare_with_dcp() {
+        let mut mem = Map::new();
+        let mut reg = Registers::new();
+        mem.write(0x0010, 0x43);
+        reg.acc = 0x42;
+
+        execute_instruction(
+            Instruction(OpCode::Dcp, Addressing::ZeroPage(0x10), 5),
+            &mut mem,
+            &mut reg,
+            CpuVariant::Nmos6502
+        ).unwrap();
+
+        assert_eq!(0x42, mem.read(0x0010));
+        assert!(reg.status.zero);
+        assert!(reg.status.carry);
+    }
+
+    #[test]
+    fn increment_then_subtract_with_isc() {
+        let mut mem = Map::new();
+        let mut reg = Registers::new();
+        mem.write(0x0010, 0x0f);
+        reg.acc = 0x20;
+        reg.status.carry = true;
+
+        execute_instruction(
+            Instruction(OpCode::Isc, Addressing::ZeroPage(0x10), 5),
+            &mut mem,
+            &mut reg,
+            CpuVariant::Nmos6502
+        ).unwrap();
+
+        assert_eq!(0x10, mem.read(0x0010));
+        assert_eq!(0x10, reg.acc);
+        assert!(reg.status.carry);
+    }
+
+    #[test]
+    fn shift_then_combine_with_slo_and_sre() {
+        let mut mem = Map::new();
+        let mut reg = Registers::new();
+        mem.write(0x0010, 0x81);
+        reg.acc = 0x01;
+
+        execute_instruction(
+            Instruction(OpCode::Slo, Addressing::ZeroPage(0x10), 5),
+            &mut mem,
+            &mut reg,
+            CpuVariant::Nmos6502
+        ).unwrap();
+
+        assert_eq!(0x02, mem.read(0x0010));
+        assert_eq!(0x03, reg.acc);
+        assert!(reg.status.carry);
+
+        execute_instruction(
+            Instruction(OpCode::Sre, Addressing::ZeroPage(0x10), 5),
+            &mut mem,
+            &mut reg,
+            CpuVariant::Nmos6502
+        ).unwrap();
+
+        assert_eq!(0x01, mem.read(0x0010));
+        assert_eq!(0x02, reg.acc);
+        assert!(!reg.status.carry);
+    }
+
+    #[test]
+    fn rotate_then_combine_with_rla_and_rra() {
+        let mut mem = Map::new();
+        let mut reg = Registers::new();
+        mem.write(0x0010, 0x80);
+        reg.acc = 0xff;
+        reg.status.carry = true;
+
+        execute_instruction(
+            Instruction(OpCode::Rla, Addressing::ZeroPage(0x10), 5),
+            &mut mem,
+            &mut reg,
+            CpuVariant::Nmos6502
+        ).unwrap();
+
+        assert_eq!(0x01, mem.read(0x0010));
+        assert_eq!(0x01, reg.acc);
+        assert!(reg.status.carry);
+
+        execute_instruction(
+            Instruction(OpCode::Rra, Addressing::ZeroPage(0x10), 5),
+            &mut mem,
+            &mut reg,
+            CpuVariant::Nmos6502
+        ).unwrap();
+
+        // $01 rotated right with carry in is $80 (carry out set), then
+        // $01 + $80 + 1
+        assert_eq!(0x80, mem.read(0x0010));
+        assert_eq!(0x82, reg.acc);
+    }
+
+    #[test]
+    fn set_flags_for_immediate_undocumented_instructions() {
+        let mut mem = Map::new();
+        let mut reg = Registers::new();
+
+        reg.acc = 0xff;
+        execute_instruction(
+            Instruction(OpCode::Anc, Addressing::Immediate(0x80), 2),
+            &mut mem,
+            &mut reg,
+            CpuVariant::Nmos6502
+        ).unwrap();
+        assert_eq!(0x80, reg.acc);
+        assert!(reg.status.carry);
+
+        reg.acc = 0xff;
+        execute_instruction(
+            Instruction(OpCode::Alr, Addressing::Immediate(0x03), 2),
+            &mut mem,
+            &mut reg,
+            CpuVariant::Nmos6502
+        ).unwrap();
+        assert_eq!(0x01, reg.acc);
+        assert!(reg.status.carry);
+
+        reg.acc = 0xff;
+        reg.status.carry = true;
+        execute_instruction(
+            Instruction(OpCode::Arr, Addressing::Immediate(0x40), 2),
+            &mut mem,
+            &mut reg,
+            CpuVariant::Nmos6502
+        ).unwrap();
+        assert_eq!(0xa0, reg.acc);
+        assert!(!reg.status.carry);
+        assert!(reg.status.overflow);
+
+        reg.acc = 0x0f;
+        reg.x = 0xfc;
+        execute_instruction(
+            Instruction(OpCode::Sbx, Addressing::Immediate(0x02), 2),
+            &mut mem,
+            &mut reg,
+            CpuVariant::Nmos6502
+        ).unwrap();
+        assert_eq!(0x0a, reg.x);
+        assert!(reg.status.carry);
+    }
+
+    fn step_cycles(program: &[u8], setup: fn(&mut Registers)) -> usize {
+        let mut mem = Map::new();
+        for (n, b) in program.iter().enumerate() {
+            mem.write(0x1000 + n as u16, *b);
+        }
+
+        let mut cpu = Cpu::new(CpuVariant::Nmos6502);
+        cpu.registers.pc = 0x1000;
+        setup(&mut cpu.registers);
+        cpu.step(&mut mem).unwrap()
+    }
+
+    #[test]
+    fn add_a_cycle_when_indexed_reads_cross_a_page() {
+        assert_eq!(4, step_cycles(&[0xbd, 0x00, 0x20], |r| r.x = 0xff));
+        assert_eq!(5, step_cycles(&[0xbd, 0x01, 0x20], |r| r.x = 0xff));
+        assert_eq!(5, step_cycles(&[0xb9, 0xff, 0x20], |r| r.y = 0x01));
+        assert_eq!(5, step_cycles(&[0xbe, 0xff, 0x20], |r| r.y = 0x01));
+        assert_eq!(5, step_cycles(&[0x3c, 0xff, 0x20], |r| r.x = 0x01));
+
+        // The pointer at $00 is zero, so ($00),Y crosses from page $00
+        assert_eq!(5, step_cycles(&[0xb1, 0x00], |r| r.y = 0xff));
+    }
+
+    #[test]
+    fn not_add_a_cycle_for_stores_and_read_modify_write() {
+        assert_eq!(5, step_cycles(&[0x9d, 0xff, 0x20], |r| r.x = 0x01));
+        assert_eq!(5, step_cycles(&[0x9d, 0x00, 0x20], |r| r.x = 0x01));
+        assert_eq!(7, step_cycles(&[0xfe, 0xff, 0x20], |r| r.x = 0x01));
+        assert_eq!(7, step_cycles(&[0x1e, 0xff, 0x20], |r| r.x = 0x01));
+        assert_eq!(6, step_cycles(&[0x91, 0x00], |r| r.y = 0xff));
+    }
+
+    #[test]
+    fn add_cycles_for_taken_branches() {
+        assert_eq!(2, step_cycles(&[0xd0, 0x10], |r| r.status.zero = true));
+        assert_eq!(3, step_cycles(&[0xd0, 0x10], |r| r.status.zero = false));
+        assert_eq!(4, step_cycles(&[0xd0, 0xf0], |r| r.status.zero = false));
+    }
+
+    #[test]
+    fn access_the_bus_once_per_cycle() {
+        let mut mismatches = vec![];
+
+        for &variant in &[CpuVariant::Nmos6502, CpuVariant::Cmos65sc12] {
+            for opcode in 0..256usize {
+                // Cover page crossing, taken branches and decimal mode
+                for &(operand, index, status) in &[(0x00, 0x00, 0x00), (0xff, 0xff, 0xff), (0x80, 0x01, 0x00)] {
+                    let mut mem = Map::new();
+                    mem.write(0x1000, opcode as u8);
+                    mem.write(0x1001, operand);
+                    mem.write(0x1002, operand);
+
+                    let mut cpu = Cpu::new(variant);
+                    cpu.registers.pc = 0x1000;
+                    cpu.registers.sp = 0x80;
+                    cpu.registers.x = index;
+                    cpu.registers.y = index;
+                    cpu.registers.status = StatusFlags::from(status);
+
+                    if let Ok(cycles) = cpu.step(&mut mem) {
+                        if !cpu.is_halted() && cycles != cpu.bus_cycles().len() {
+                            mismatches.push((variant, opcode, operand, cycles, cpu.bus_cycles().len()));
+                        }
+                    }
+                }
+            }
+        }
+
+        assert!(mismatches.is_empty(), "(variant, opcode, operand, cycles, bus accesses): {:x?}", mismatches);
+    }
+
+    fn bus_trace(program: &[u8], setup: fn(&mut Registers, &mut Map)) -> Vec<(u16, u8, BusOperation)> {
+        let mut mem = Map::new();
+        for (n, b) in program.iter().enumerate() {
+            mem.write(0x1000 + n as u16, *b);
+        }
+
+        let mut cpu = Cpu::new(CpuVariant::Nmos6502);
+        cpu.registers.pc = 0x1000;
+        cpu.registers.sp = 0xff;
+        setup(&mut cpu.registers, &mut mem);
+        cpu.step(&mut mem).unwrap();
+        cpu.bus_cycles().iter().map(|c| (c.address, c.value, c.operation)).collect()
+    }
+
+    #[test]
+    fn write_back_the_unmodified_value_during_read_modify_write() {
+        use self::BusOperation::*;
+
+        assert_eq!(vec![
+            (0x1000, 0xee, Read), 
+            (0x1001, 0x00, Read), 
+            (0x1002, 0x20, Read),
+            (0x2000, 0x41, Read),
+            (0x2000, 0x41, Write),
+            (0x2000, 0x42, Write),
+        ], bus_trace(&[0xee, 0x00, 0x20], |_, m| m.write(0x2000, 0x41)));
+    }
+
+    #[test]
+    fn read_from_the_unfixed_address_when_indexing_crosses_a_page() {
+        use self::BusOperation::*;
+
+        assert_eq!(vec![
+            (0x1000, 0xbd, Read), 
+            (0x1001, 0xff, Read), 
+            (0x1002, 0x20, Read),
+            (0x2000, 0x00, Read),
+            (0x2100, 0x00, Read),
+        ], bus_trace(&[0xbd, 0xff, 0x20], |r, _| r.x = 0x01));
+
+        // Stores always spend the cycle, even without crossing a page
+        assert_eq!(vec![
+            (0x1000, 0x9d, Read), 
+            (0x1001, 0x00, Read), 
+            (0x1002, 0x20, Read),
+            (0x2001, 0x00, Read),
+            (0x2001, 0x00, Write),
+        ], bus_trace(&[0x9d, 0x00, 0x20], |r, _| r.x = 0x01));
+    }
+
+    #[test]
+    fn fetch_the_jsr_target_after_pushing_the_return_address() {
+        use self::BusOperation::*;
+
+        assert_eq!(vec![
+            (0x1000, 0x20, Read), 
+            (0x1001, 0x00, Read), 
+            (0x01ff, 0x00, Read),
+            (0x01ff, 0x10, Write),
+            (0x01fe, 0x02, Write),
+            (0x1002, 0x30, Read),
+        ], bus_trace(&[0x20, 0x00, 0x30], |_, _| ()));
+    }
+
+    #[test]
+    fn report_invalid_instructions_without_advancing() {
+        let mut mem = Map::new();
+        let mut cpu = Cpu::new(CpuVariant::Nmos6502);
+        cpu.registers.pc = 0x1000;
+        mem.write(0x1000, 0x8b);
+
+        match cpu.step(&mut mem) {
+            Err(CpuError::InvalidInstruction { pc, opcode }) => {
+                assert_eq!(0x1000, pc);
+                assert_eq!(0x8b, opcode);
+            },
+            r => panic!("Expected an invalid instruction, got {:?}", r),
+        }
+        assert_eq!(0x1000, cpu.program_counter());
+    }
+
+    #[test]
+    fn halt_the_cpu_on_jam() {
+        let mut mem = Map::new();
+        let mut cpu = Cpu::new(CpuVariant::Nmos6502);
+        mem.write(0x0000, 0xe8);
+        mem.write(0x0001, 0x02);
+
+        cpu.step(&mut mem).unwrap();
+        assert!(!cpu.is_halted());
+
+        cpu.step(&mut mem).unwrap();
+        assert!(cpu.is_halted());
+        assert_eq!(0x0001, cpu.program_counter());
+
+        cpu.step(&mut mem).unwrap();
+        assert_eq!(0x0001, cpu.program_counter());
+        assert_eq!(Ok(false), cpu.interrupt_request(&mut mem).map_err(|_| ()));
+    }
+
+    #[test]
+    fn subtract_invalid_bcd_in_decimal_mode() {
+        assert_eq!(0x10, sbc(0x20, 0x0a, true, true).acc);
+
+        // Flags come from the binary subtraction: $00 - $00 = $00.
+        let reg = sbc(0x00, 0x00, true, true);
+        assert_eq!(0x00, reg.acc);
+        assert!(reg.status.zero);
+        assert!(reg.status.carry);
+    }
+
+    fn execute(ins: Instruction, mem: &mut Map, setup: fn(&mut Registers)) -> Registers {
+        let mut reg = Registers::new();
+        setup(&mut reg);
+        execute_instruction(ins, mem, &mut reg, CpuVariant::Nmos6502).unwrap();
+        reg
+    }
+
+    #[test]
+    fn push_status_with_break_flag_on_php() {
+        let mut mem = Map::new();
+        let reg = execute(Instruction(OpCode::Php, Addressing::Implied, 3), &mut mem, |r| r.sp = 0xff);
+        assert_eq!(0xfe, reg.sp);
+        assert_eq!(0x30, mem.read(0x01ff));
+    }
+
+    #[test]
+    fn wrap_the_stack_pointer_around_page_one() {
+        let mut mem = Map::new();
+        let reg = execute(Instruction(OpCode::Pha, Addressing::Implied, 3), &mut mem, |r| {
+            r.sp = 0x00;
+            r.acc = 0x42;
+        });
+        assert_eq!(0xff, reg.sp);
+        assert_eq!(0x42, mem.read(0x0100));
+
+        mem.write(0x0100, 0x24);
+        let reg = execute(Instruction(OpCode::Pla, Addressing::Implied, 4), &mut mem, |r| r.sp = 0xff);
+        assert_eq!(0x00, reg.sp);
+        assert_eq!(0x24, reg.acc);
+    }
+
+    #[test]
+    fn disable_interrupts_on_nmi() {
+        let mut mem = Map::new();
+        let mut cpu = Cpu::new(CpuVariant::Nmos6502);
+        cpu.registers.sp = 0xff;
+
+        cpu.non_maskable_interrupt(&mut mem).unwrap();
+        assert!(cpu.registers.status.interrupt);
+    }
+
+    #[test]
+    fn wrap_the_program_counter_around_memory() {
+        let mut mem = Map::new();
+        let reg = execute(Instruction(OpCode::Bne, Addressing::Relative(0x20), 2), &mut mem, |r| r.pc = 0x7ff2);
+        assert_eq!(0x8012, reg.pc);
+
+        mem.write(0x01fe, 0xff);
+        mem.write(0x01ff, 0xff);
+        let reg = execute(Instruction(OpCode::Rts, Addressing::Implied, 6), &mut mem, |r| r.sp = 0xfd);
+        assert_eq!(0x0000, reg.pc);
+
+        // A NOP in the last byte of memory
+        mem.write(0xffff, 0xea);
+        let mut cpu = Cpu::new(CpuVariant::Nmos6502);
+        cpu.registers.pc = 0xffff;
+        cpu.step(&mut mem).unwrap();
+        assert_eq!(0x0000, cpu.program_counter());
+    }
+
+    #[test]
+    fn set_flags_on_register_transfers() {
+        let mut mem = Map::new();
+
+        let reg = execute(Instruction(OpCode::Tax, Addressing::Implied, 2), &mut mem, |r| r.acc = 0x80);
+        assert!(reg.status.negative);
+        assert!(!reg.status.zero);
+
+        let reg = execute(Instruction(OpCode::Tya, Addressing::Implied, 2), &mut mem, |r| r.acc = 0x80);
+        assert!(!reg.status.negative);
+        assert!(reg.status.zero);
+
+        // TXS is the only transfer that leaves the flags alone
+        let reg = execute(Instruction(OpCode::Txs, Addressing::Implied, 2), &mut mem, |_| ());
+        assert!(!reg.status.zero);
+    }
+
+    #[test]
+    fn shift_bit_seven_into_carry() {
+        let mut mem = Map::new();
+        let reg = execute(Instruction(OpCode::Asl, Addressing::Accumulator, 2), &mut mem, |r| r.acc = 0x81);
+        assert_eq!(0x02, reg.acc);
+        assert!(reg.status.carry);
+    }
+
+    #[test]
+    fn set_negative_from_the_compare_result() {
+        let mut mem = Map::new();
+        let reg = execute(Instruction(OpCode::Cmp, Addressing::Immediate(0x10), 2), &mut mem, |r| r.acc = 0x90);
+        assert!(reg.status.carry);
+        assert!(reg.status.negative);
+
+        let reg = execute(Instruction(OpCode::Cpx, Addressing::Immediate(0x01), 2), &mut mem, |r| r.x = 0x02);
+        assert!(reg.status.carry);
+        assert!(!reg.status.negative);
+        assert!(!reg.status.zero);
+    }
+
+    #[test]
+    fn wrap_indirect_pointers_within_zero_page() {
+        let mut mem = Map::new();
+        mem.write(0x00ff, 0x34);
+        mem.write(0x0000, 0x12);
+        mem.write(0x1234, 0x42);
+
+        let reg = execute(Instruction(OpCode::Lda, Addressing::IndirectY(0xff), 5), &mut mem, |_| ());
+        assert_eq!(0x42, reg.acc);
+
+        let reg = execute(Instruction(OpCode::Lda, Addressing::IndirectX(0x80), 6), &mut mem, |r| r.x = 0x7f);
+        assert_eq!(0x42, reg.acc);
+    }
+
+    #[test]
+    fn push_return_address_and_break_flag_on_brk() {
+        let mut mem = Map::new();
+        mem.write(0x1000, 0x00);
+        mem.write(0xfffe, 0x00);
+        mem.write(0xffff, 0x20);
+
+        let mut cpu = Cpu::new(CpuVariant::Nmos6502);
+        cpu.registers.pc = 0x1000;
+        cpu.registers.sp = 0xff;
+        cpu.step(&mut mem).unwrap();
+
+        assert_eq!(0x2000, cpu.program_counter());
+        assert!(cpu.registers.status.interrupt);
+        assert_eq!(0x10, mem.read(0x01ff));
+        assert_eq!(0x02, mem.read(0x01fe));
+        assert_eq!(0x30, mem.read(0x01fd));
+    }
+
+    #[test]
+    fn push_status_without_break_flag_on_irq() {
+        let mut mem = Map::new();
+        let mut cpu = Cpu::new(CpuVariant::Nmos6502);
+        cpu.registers.sp = 0xff;
+
+        assert_eq!(Ok(true), cpu.interrupt_request(&mut mem).map_err(|_| ()));
+        assert_eq!(0x20, mem.read(0x01fd));
+    }
+
+    #[test]
+    fn refuse_to_read_an_operand_for_addressing_without_one() {
+        let mut mem = Map::new();
+        let reg = Registers::new();
+        for addr in &[Addressing::Implied, Addressing::Relative(0x10), Addressing::Indirect(0x1000)] {
+            assert!(read_mem(addr, &mut mem, &reg, CpuVariant::Nmos6502).is_err());
+        }
+    }
+}
+
+#[cfg(test)]
+mod observer_should {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use memory::Map;
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Vec<(Option<Interrupt>, u16, u16, u16, usize)>,
+    }
+
+    impl CpuObserver for Recorder {
+        fn instruction(&mut self,
+                       pc: u16,
+                       _: &Instruction,
+                       before: &Registers,
+                       after: &Registers,
+                       cycles: usize)
+        {
+            self.events.push((None, pc, before.pc, after.pc, cycles));
+        }
+
+        fn interrupt(&mut self,
+                     interrupt: Interrupt,
+                     pc: u16,
+                     before: &Registers,
+                     after: &Registers,
+                     cycles: usize)
+        {
+            self.events.push((Some(interrupt), pc, before.pc, after.pc, cycles));
+        }
+    }
+
+    #[test]
+    fn be_told_about_instructions_and_interrupts() {
+        let mut mem = Map::new();
+        let mut cpu = Cpu::new(CpuVariant::Nmos6502);
+        let recorder = Rc::new(RefCell::new(Recorder::default()));
+        cpu.add_observer(Box::new(recorder.clone()));
+
+        mem.write(0x1000, 0x58); // CLI
+        mem.write(0xfffe, 0x00);
+        mem.write(0xffff, 0x20);
+        mem.write(0xfffa, 0x00);
+        mem.write(0xfffb, 0x30);
+        cpu.registers.pc = 0x1000;
+        cpu.registers.sp = 0xff;
+
+        cpu.step(&mut mem).unwrap();
+        cpu.interrupt_request(&mut mem).unwrap();
+        cpu.non_maskable_interrupt(&mut mem).unwrap();
+
+        assert_eq!(vec![
+            (None, 0x1000, 0x1000, 0x1001, 2),
+            (Some(Interrupt::Irq), 0x1001, 0x1001, 0x2000, 7),
+            (Some(Interrupt::Nmi), 0x2000, 0x2000, 0x3000, 7),
+        ], recorder.borrow().events);
+
+        cpu.clear_observers();
+        cpu.step(&mut mem).unwrap();
+        assert_eq!(3, recorder.borrow().events.len());
+    }
+}
+
+#[cfg(test)]
+mod interrupt_lines_should {
+    use super::*;
+    use memory::{Map, BusOperation};
+
+    fn setup(program: &[u8]) -> (Cpu, Map) {
+        let mut mem = Map::new();
+        let mut cpu = Cpu::new(CpuVariant::Nmos6502);
+        for (i, b) in program.iter().enumerate() {
+            mem.write(0x1000 + i as u16, *b);
+        }
+        mem.write(0xfffe, 0x00);
+        mem.write(0xffff, 0x20);
+        mem.write(0xfffa, 0x00);
+        mem.write(0xfffb, 0x30);
+        mem.write(0x2000, 0x40); // RTI
+        cpu.registers.pc = 0x1000;
+        cpu.registers.sp = 0xff;
+        (cpu, mem)
+    }
+
+    #[test]
+    fn keep_taking_an_irq_while_the_line_is_asserted() {
+        let (mut cpu, mut mem) = setup(&[0xea, 0xea]);
+        cpu.registers.status.interrupt = false;
+        cpu.set_irq(InterruptSource::SystemVia, true);
+
+        cpu.step(&mut mem).unwrap();
+        assert_eq!(INTERRUPT_CYCLES, cpu.poll_interrupts(&mut mem).unwrap());
+        assert_eq!(0, cpu.poll_interrupts(&mut mem).unwrap());
+
+        // RTI restores I immediately, so the still asserted line is taken
+        // again straight away
+        cpu.step(&mut mem).unwrap();
+        assert_eq!(INTERRUPT_CYCLES, cpu.poll_interrupts(&mut mem).unwrap());
+
+        cpu.set_irq(InterruptSource::SystemVia, false);
+        cpu.step(&mut mem).unwrap();
+        assert_eq!(0, cpu.poll_interrupts(&mut mem).unwrap());
+    }
+
+    #[test]
+    fn wait_one_instruction_after_cli() {
+        let (mut cpu, mut mem) = setup(&[0x58, 0xea, 0xea]);
+        cpu.registers.status.interrupt = true;
+        cpu.set_irq(InterruptSource::UserVia, true);
+
+        cpu.step(&mut mem).unwrap();
+        assert_eq!(0, cpu.poll_interrupts(&mut mem).unwrap());
+
+        cpu.step(&mut mem).unwrap();
+        assert_eq!(INTERRUPT_CYCLES, cpu.poll_interrupts(&mut mem).unwrap());
+        assert_eq!(0x10, mem.read(0x01ff));
+        assert_eq!(0x02, mem.read(0x01fe));
+    }
+
+    #[test]
+    fn still_take_an_irq_straight_after_sei() {
+        let (mut cpu, mut mem) = setup(&[0x78, 0xea]);
+        cpu.registers.status.interrupt = false;
+        cpu.set_irq(InterruptSource::Acia, true);
+
+        cpu.step(&mut mem).unwrap();
+        assert_eq!(INTERRUPT_CYCLES, cpu.poll_interrupts(&mut mem).unwrap());
+        assert_eq!(0x01, mem.read(0x01fe));
+        // The stacked status already has I set
+        assert_eq!(0x04, mem.read(0x01fd) & 0x04);
+    }
+
+    #[test]
+    fn hold_the_irq_line_while_any_source_asserts_it() {
+        let mut line = IrqLine::default();
+        line.set(InterruptSource::SystemVia, true);
+        line.set(InterruptSource::UserVia, true);
+        line.set(InterruptSource::SystemVia, false);
+        assert!(line.is_asserted());
+        line.set(InterruptSource::UserVia, false);
+        assert!(!line.is_asserted());
+    }
+
+    #[test]
+    fn take_an_nmi_once_per_edge() {
+        let (mut cpu, mut mem) = setup(&[0xea, 0xea, 0xea]);
+        cpu.registers.status.interrupt = true;
+        cpu.set_nmi(InterruptSource::DiscController, true);
+
+        assert_eq!(INTERRUPT_CYCLES, cpu.poll_interrupts(&mut mem).unwrap());
+        assert_eq!(0x3000, cpu.program_counter());
+        assert_eq!(0, cpu.poll_interrupts(&mut mem).unwrap());
+
+        // Another source joining an already asserted line isn't an edge
+        cpu.set_nmi(InterruptSource::Econet, true);
+        assert_eq!(0, cpu.poll_interrupts(&mut mem).unwrap());
+
+        cpu.set_nmi(InterruptSource::DiscController, false);
+        cpu.set_nmi(InterruptSource::Econet, false);
+        cpu.set_nmi(InterruptSource::DiscController, true);
+        cpu.registers.pc = 0x1000;
+        assert_eq!(INTERRUPT_CYCLES, cpu.poll_interrupts(&mut mem).unwrap());
+        assert_eq!(0x3000, cpu.program_counter());
+    }
+
+    #[test]
+    fn record_the_bus_cycles_of_an_interrupt() {
+        let (mut cpu, mut mem) = setup(&[0xea]);
+        cpu.registers.status.interrupt = false;
+        cpu.set_irq(InterruptSource::SystemVia, true);
+
+        cpu.poll_interrupts(&mut mem).unwrap();
+        let accesses: Vec<(u16, BusOperation)> = cpu.bus_cycles()
+                                                    .iter()
+                                                    .map(|c| (c.address, c.operation))
+                                                    .collect();
+        assert_eq!(vec![(0x1000, BusOperation::Read),
+                        (0x1000, BusOperation::Read),
+                        (0x01ff, BusOperation::Write),
+                        (0x01fe, BusOperation::Write),
+                        (0x01fd, BusOperation::Write),
+                        (0xfffe, BusOperation::Read),
+                        (0xffff, BusOperation::Read)],
+                   accesses);
+        assert_eq!(INTERRUPT_CYCLES, cpu.bus_cycles().len());
+    }
+}
+
+#[cfg(test)]
+mod reset_should {
+    use super::*;
+    use memory::Map;
+
+    #[test]
+    fn restart_from_the_reset_vector() {
+        let mut mem = Map::new();
+        let mut cpu = Cpu::new(CpuVariant::Nmos6502);
+        mem.write(0xfffc, 0x00);
+        mem.write(0xfffd, 0xd9);
+        mem.write(0x1000, 0x02);
+        cpu.registers.pc = 0x1000;
+        cpu.registers.sp = 0xf0;
+        cpu.registers.acc = 0x55;
+        cpu.step(&mut mem).unwrap();
+        assert!(cpu.is_halted());
+
+        cpu.reset(&mut mem);
+        assert_eq!(0xd900, cpu.program_counter());
+        assert_eq!(0xed, cpu.registers.sp);
+        assert_eq!(0x55, cpu.registers.acc);
+        assert!(cpu.registers.status.interrupt);
+        assert!(!cpu.is_halted());
+        assert_eq!(0x00, mem.read(0x01f0));
+    }
+
+    #[test]
+    fn clear_decimal_mode_on_the_65sc12() {
+        let mut mem = Map::new();
+        let mut cpu = Cpu::new(CpuVariant::Cmos65sc12);
+        cpu.registers.status.decimal = true;
+
+        cpu.reset(&mut mem);
+        assert!(!cpu.registers.status.decimal);
+    }
 }
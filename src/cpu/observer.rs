@@ -0,0 +1,62 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use cpu::{Instruction, Registers};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Interrupt {
+    Irq,
+    Nmi,
+}
+
+/// Hooks that are called as the CPU executes, so tracing, profiling and
+/// coverage tools can be attached to a `Cpu` at runtime with
+/// `Cpu::add_observer`. Every method has an empty default, so observers
+/// only need to implement the events they're interested in.
+pub trait CpuObserver {
+    /// Called after each instruction that `Cpu::step` executes. `pc` is the
+    /// address the instruction was fetched from.
+    fn instruction(&mut self,
+                   _pc: u16,
+                   _instruction: &Instruction,
+                   _before: &Registers,
+                   _after: &Registers,
+                   _cycles: usize)
+    {
+    }
+
+    /// Called after the CPU has taken an interrupt. `pc` is the address
+    /// the interrupted program will return to.
+    fn interrupt(&mut self,
+                 _interrupt: Interrupt,
+                 _pc: u16,
+                 _before: &Registers,
+                 _after: &Registers,
+                 _cycles: usize)
+    {
+    }
+}
+
+/// Allows an observer to be shared with the `Cpu`, so its results can be
+/// read back while it's still attached.
+impl<T: CpuObserver> CpuObserver for Rc<RefCell<T>> {
+    fn instruction(&mut self,
+                   pc: u16,
+                   instruction: &Instruction,
+                   before: &Registers,
+                   after: &Registers,
+                   cycles: usize)
+    {
+        self.borrow_mut().instruction(pc, instruction, before, after, cycles)
+    }
+
+    fn interrupt(&mut self,
+                 interrupt: Interrupt,
+                 pc: u16,
+                 before: &Registers,
+                 after: &Registers,
+                 cycles: usize)
+    {
+        self.borrow_mut().interrupt(interrupt, pc, before, after, cycles)
+    }
+}
@@ -4,7 +4,7 @@ use std::io::{self, Write};
 
 use super::*;
 use self::protocol::{DebuggerCmd, DebuggerResponse, IntoDebuggerMessage, FromDebuggerMessage};
-use emulator::{StepResult, Emulator};
+use emulator::{StepResult, Emulator, ResetKind};
 use cpu::{self, Cpu, CpuError};
 use self::error::*;
 use memory::AsMemoryRegion;
@@ -110,7 +110,7 @@ impl<T> Backend<T>
                         while num > 0 {
                             let mem = self.mem().region_from(pc + offset..)
                                                 .unwrap_or_else(|e| e.0);
-                            offset += match cpu::decode_instruction(&mem) {
+                            offset += match cpu::decode_instruction_for(self.cpu().variant(), &mem) {
                                 Ok((bytes, ins)) => {
                                     let msg = format!("{:04x}: {}", pc + offset, ins);
                                     self.outgoing.send(DebuggerResponse::Message(msg)).ok();
@@ -138,15 +138,35 @@ impl<T> Backend<T>
     }
 
     fn send_current_instruction(&mut self) -> Result<(), CpuError> {
-
+        let pc = self.cpu().program_counter();
         let instruction_region = 
             self.mem()
-                .region(self.cpu().program_counter() as _..self.cpu().program_counter() as usize + 4)
+                .region(pc as _..::std::cmp::min(pc as usize + 4, self.mem().len()))
                 .unwrap_or_else(|e| e.0);
-        let (_, ins) = cpu::decode_instruction(&instruction_region)?;
-        self.outgoing.send(DebuggerResponse::Instruction(self.cpu().program_counter(), ins)).ok();
+        let (_, ins) = cpu::decode_instruction_for(self.cpu().variant(), &instruction_region)
+            .map_err(|_| CpuError::InvalidInstruction {
+                pc: pc,
+                opcode: instruction_region.first().cloned().unwrap_or(0x00),
+            })?;
+        self.outgoing.send(DebuggerResponse::Instruction(pc, ins)).ok();
         Ok(())
     }
+
+    /// Runs a single emulator step. If the CPU hits an instruction it can't
+    /// decode, the debugger stops at the offending address instead of
+    /// passing the error on.
+    fn step_emulator<K: Fn(u8) -> bool>(&mut self, fb: &mut FrameBuffer, key_eval: K) 
+        -> Result<StepResult, DebuggerError> 
+    {
+        match self.emulator.step(fb, key_eval).map_err(DebuggerError::from) {
+            Err(DebuggerError::Cpu(e @ CpuError::InvalidInstruction { .. })) => {
+                self.state = DebuggerState::Stop;
+                self.outgoing.send(DebuggerResponse::Message(format!("{}", e))).ok();
+                Ok(StepResult::Paused)
+            },
+            result => result,
+        }
+    }
 }
 
 impl<T> Emulator for Backend<T> 
@@ -165,6 +185,12 @@ impl<T> Emulator for Backend<T>
         Ok(self.send_current_instruction()?)
     }
 
+    fn reset(&mut self, kind: ResetKind) -> Result<(), Self::Error> {
+        self.emulator.reset(kind)?;
+        self.active_breakpoint = None;
+        Ok(self.send_current_instruction()?)
+    }
+
     fn step<K: Fn(u8) -> bool>(&mut self, fb: &mut FrameBuffer, key_eval: K) -> Result<StepResult, Self::Error> {
 
         if self.process_debugger_queue().is_none() {
@@ -182,9 +208,14 @@ impl<T> Emulator for Backend<T>
                     return Ok(StepResult::Paused);
                 }
                 else {
-                    let result = self.emulator.step(fb, key_eval)?;
-                    self.send_current_instruction()?;
                     self.state = DebuggerState::Step(num - 1);
+                    let result = self.step_emulator(fb, key_eval)?;
+                    if let StepResult::Paused = result {
+                        self.outgoing.send(DebuggerResponse::StreamEnd).ok();
+                    }
+                    else {
+                        self.send_current_instruction()?;
+                    }
                     return Ok(result);
                 }
             },
@@ -195,7 +226,7 @@ impl<T> Emulator for Backend<T>
             },
         }
 
-        let result = self.emulator.step(fb, key_eval)?;
+        let result = self.step_emulator(fb, key_eval)?;
         if let Some(bp) = self.breakpoints.iter().find(|i| **i == self.cpu().program_counter()) {
             self.active_breakpoint = Some(*bp);
             self.state = DebuggerState::Stop;
@@ -269,7 +269,8 @@ impl<'a> FrontEnd<'a> {
         unsafe { signal::SetConsoleCtrlHandler(None, signal::TRUE) };
 
         let mut child = process::Command::new(&self.0[0])
-            .args(&["--attach", &self.0[1], &self.0[2]])
+            .arg("--attach")
+            .args(&self.0[1..])
             .stdout(process::Stdio::piped())
             .stdin(process::Stdio::piped())
             .stderr(process::Stdio::inherit())
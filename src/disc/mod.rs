@@ -0,0 +1,66 @@
+pub mod wd1770;
+pub use self::wd1770::Wd1770;
+
+use std::ops::Range;
+
+pub const TRACKS: usize = 80;
+pub const SECTORS_PER_TRACK: usize = 10;
+pub const SECTOR_SIZE: usize = 256;
+
+/// A raw sector dump of a DFS disc. Single sided `.ssd` images hold each
+/// track in turn; double sided `.dsd` images interleave the tracks of the
+/// two sides. Images can be shorter than a full disc, in which case the
+/// missing sectors can't be found.
+pub struct DiscImage {
+    bytes: Vec<u8>,
+    sides: usize,
+    write_protected: bool,
+}
+
+impl DiscImage {
+    pub fn ssd(bytes: Vec<u8>) -> DiscImage {
+        DiscImage {
+            bytes: bytes,
+            sides: 1,
+            write_protected: false,
+        }
+    }
+
+    pub fn dsd(bytes: Vec<u8>) -> DiscImage {
+        DiscImage {
+            bytes: bytes,
+            sides: 2,
+            write_protected: false,
+        }
+    }
+
+    pub fn write_protect(mut self) -> DiscImage {
+        self.write_protected = true;
+        self
+    }
+
+    pub fn is_write_protected(&self) -> bool {
+        self.write_protected
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Where sector `sector` of `track` on `side` is in the image, or `None`
+    /// if the disc doesn't have it.
+    fn sector(&self, track: u8, side: usize, sector: u8) -> Option<Range<usize>> {
+        let (track, sector) = (track as usize, sector as usize);
+        if side >= self.sides || track >= TRACKS || sector >= SECTORS_PER_TRACK {
+            return None;
+        }
+
+        let start = ((track * self.sides + side) * SECTORS_PER_TRACK + sector) * SECTOR_SIZE;
+        if start + SECTOR_SIZE <= self.bytes.len() {
+            Some(start..start + SECTOR_SIZE)
+        }
+        else {
+            None
+        }
+    }
+}
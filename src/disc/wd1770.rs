@@ -0,0 +1,638 @@
+use std::mem;
+
+use disc::{DiscImage, SECTOR_SIZE};
+use memory::BusDevice;
+use state::{SaveState, StateReader, StateWriter, StateError};
+
+const STATUS_BUSY: u8 = 0x01;
+const STATUS_DATA_REQUEST: u8 = 0x02;
+const STATUS_TRACK_0: u8 = 0x04;
+const STATUS_LOST_DATA: u8 = 0x04;
+const STATUS_RECORD_NOT_FOUND: u8 = 0x10;
+const STATUS_SPIN_UP: u8 = 0x20;
+const STATUS_WRITE_PROTECT: u8 = 0x40;
+const STATUS_MOTOR_ON: u8 = 0x80;
+
+/// Control register: the two drive selects, active high.
+const CONTROL_DRIVE_0: u8 = 0;
+const CONTROL_DRIVE_1: u8 = 1;
+/// Control register: resets the 1770 while it's low.
+const CONTROL_NOT_RESET: u8 = 2;
+const CONTROL_SIDE: u8 = 4;
+const CONTROL_SINGLE_DENSITY: u8 = 5;
+
+/// How long the head takes to step one track, at 6ms.
+const STEP_CYCLES: usize = 12_000;
+/// How long it takes to find a sector once a command starts.
+const SEARCH_CYCLES: usize = 2_000;
+/// How long each byte takes to pass under the head, in single density.
+const SINGLE_DENSITY_BYTE_CYCLES: usize = 128;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Direction {
+    In,
+    Out,
+}
+
+enum State {
+    Idle,
+    /// A command that completes with `status` after `cycles`.
+    Completing { cycles: usize, status: u8 },
+    /// Handing `bytes` to the CPU, one every byte time.
+    Reading { bytes: Vec<u8>, next: usize, cycles: usize, multiple: bool },
+    /// Taking a sector from the CPU, one byte every byte time.
+    Writing { bytes: Vec<u8>, cycles: usize, multiple: bool },
+}
+
+/// The WD1770 floppy disc controller and its drive control latch, as
+/// wired on the Master. The control latch is at offset 4 and the 1770's
+/// registers at offsets 8 to 11. The 1770 signals both INTRQ and DRQ on
+/// the NMI line.
+///
+/// Track formatting isn't supported, and read track and write track
+/// commands fail with record not found.
+pub struct Wd1770 {
+    drives: [Option<DiscImage>; 2],
+    head_track: [u8; 2],
+    control: u8,
+    status: u8,
+    track: u8,
+    sector: u8,
+    data: u8,
+    direction: Direction,
+    intrq: bool,
+    state: State,
+}
+
+impl Wd1770 {
+    pub fn new() -> Wd1770 {
+        Wd1770 {
+            drives: [None, None],
+            head_track: [0, 0],
+            control: 0x00,
+            status: 0x00,
+            track: 0,
+            sector: 0,
+            data: 0,
+            direction: Direction::In,
+            intrq: false,
+            state: State::Idle,
+        }
+    }
+
+    /// Puts `disc` in `drive`, returning the disc that was there.
+    ///
+    /// Panics if `drive` isn't 0 or 1.
+    pub fn insert_disc(&mut self, drive: usize, disc: DiscImage) -> Option<DiscImage> {
+        self.drives[drive].replace(disc)
+    }
+
+    pub fn eject_disc(&mut self, drive: usize) -> Option<DiscImage> {
+        self.drives[drive].take()
+    }
+
+    pub fn disc(&self, drive: usize) -> Option<&DiscImage> {
+        self.drives[drive].as_ref()
+    }
+
+    /// Whether the controller is asserting NMI.
+    pub fn nmi(&self) -> bool {
+        self.intrq || self.status & STATUS_DATA_REQUEST != 0
+    }
+
+    /// Resets the 1770, as the reset line or the control latch would. The
+    /// discs stay where they are.
+    pub fn reset(&mut self) {
+        self.status = 0x00;
+        self.track = 0;
+        self.sector = 0;
+        self.data = 0;
+        self.intrq = false;
+        self.state = State::Idle;
+    }
+
+    fn selected_drive(&self) -> Option<usize> {
+        if bit_is_set!(self.control, CONTROL_DRIVE_0) {
+            Some(0)
+        }
+        else if bit_is_set!(self.control, CONTROL_DRIVE_1) {
+            Some(1)
+        }
+        else {
+            None
+        }
+    }
+
+    fn side(&self) -> usize {
+        if bit_is_set!(self.control, CONTROL_SIDE) { 1 } else { 0 }
+    }
+
+    fn byte_cycles(&self) -> usize {
+        if bit_is_set!(self.control, CONTROL_SINGLE_DENSITY) {
+            SINGLE_DENSITY_BYTE_CYCLES
+        }
+        else {
+            SINGLE_DENSITY_BYTE_CYCLES / 2
+        }
+    }
+
+    fn write_control(&mut self, val: u8) {
+        log_disc!("Drive control set to {:08b}", val);
+        self.control = val;
+        if !bit_is_set!(val, CONTROL_NOT_RESET) {
+            self.reset();
+        }
+    }
+
+    fn is_write_protected(&self) -> bool {
+        self.selected_drive()
+            .and_then(|d| self.drives[d].as_ref())
+            .map(|d| d.is_write_protected())
+            .unwrap_or(false)
+    }
+
+    /// The status bits that type I commands report about the drive.
+    fn drive_status(&self) -> u8 {
+        let mut status = STATUS_MOTOR_ON | STATUS_SPIN_UP;
+        if let Some(drive) = self.selected_drive() {
+            if self.head_track[drive] == 0 {
+                status |= STATUS_TRACK_0;
+            }
+        }
+        if self.is_write_protected() {
+            status |= STATUS_WRITE_PROTECT;
+        }
+        status
+    }
+
+    fn write_command(&mut self, command: u8) {
+        log_disc!("Command {:02x}", command);
+
+        // Force interrupt is the only command a busy controller accepts
+        if command & 0xf0 == 0xd0 {
+            self.state = State::Idle;
+            self.status &= !(STATUS_BUSY | STATUS_DATA_REQUEST);
+            self.intrq = command & 0x0f != 0;
+            return;
+        }
+        if self.status & STATUS_BUSY != 0 {
+            return;
+        }
+
+        self.intrq = false;
+        self.status = STATUS_MOTOR_ON | STATUS_BUSY;
+        match command & 0xe0 {
+            0x00 | 0x20 | 0x40 | 0x60 => self.step_head(command),
+            0x80 => self.read_sector(bit_is_set!(command, 4)),
+            0xa0 => self.write_sector(bit_is_set!(command, 4)),
+            0xc0 if command & 0xf0 == 0xc0 => self.read_address(),
+            _ => self.fail(STATUS_RECORD_NOT_FOUND),
+        }
+    }
+
+    /// Restore, seek, step, step in and step out.
+    fn step_head(&mut self, command: u8) {
+        let drive = self.selected_drive();
+        let head = drive.map(|d| self.head_track[d]).unwrap_or(0);
+        let (target, update_track) = match command & 0xf0 {
+            0x00 => {
+                self.track = 0;
+                (0, false)
+            },
+            0x10 => {
+                let target = head as i16 + self.data as i16 - self.track as i16;
+                self.track = self.data;
+                (target, false)
+            },
+            _ => {
+                self.direction = match command & 0x60 {
+                    0x40 => Direction::In,
+                    0x60 => Direction::Out,
+                    _ => self.direction,
+                };
+                match self.direction {
+                    Direction::In => (head as i16 + 1, bit_is_set!(command, 4)),
+                    Direction::Out => (head as i16 - 1, bit_is_set!(command, 4)),
+                }
+            },
+        };
+
+        let target = ::std::cmp::max(0, ::std::cmp::min(target, 0xff)) as u8;
+        if update_track {
+            self.track = match self.direction {
+                Direction::In => self.track.wrapping_add(1),
+                Direction::Out => self.track.wrapping_sub(1),
+            };
+        }
+        if let Some(drive) = drive {
+            self.head_track[drive] = target;
+        }
+
+        let mut status = self.drive_status();
+        let verify = bit_is_set!(command, 2);
+        let disc_present = drive.and_then(|d| self.drives[d].as_ref()).is_some();
+        if verify && (!disc_present || self.track != target) {
+            status |= STATUS_RECORD_NOT_FOUND;
+        }
+
+        let tracks = (head as i16 - target as i16).abs() as usize;
+        self.state = State::Completing {
+            cycles: ::std::cmp::max(1, tracks) * STEP_CYCLES,
+            status: status,
+        };
+    }
+
+    fn fail(&mut self, status: u8) {
+        self.state = State::Completing {
+            cycles: SEARCH_CYCLES,
+            status: STATUS_MOTOR_ON | status,
+        };
+    }
+
+    /// The bytes of the sector under the head that matches the track and
+    /// sector registers.
+    fn find_sector(&self) -> Option<&[u8]> {
+        let drive = self.selected_drive()?;
+        let disc = self.drives[drive].as_ref()?;
+        if self.head_track[drive] != self.track {
+            return None;
+        }
+        disc.sector(self.track, self.side(), self.sector)
+            .map(|r| &disc.as_bytes()[r])
+    }
+
+    fn read_sector(&mut self, multiple: bool) {
+        match self.find_sector().map(|s| s.to_vec()) {
+            Some(bytes) => {
+                self.state = State::Reading {
+                    bytes: bytes,
+                    next: 0,
+                    cycles: SEARCH_CYCLES,
+                    multiple: multiple,
+                };
+            },
+            None => self.fail(STATUS_RECORD_NOT_FOUND),
+        }
+    }
+
+    fn write_sector(&mut self, multiple: bool) {
+        if self.is_write_protected() {
+            self.fail(STATUS_WRITE_PROTECT);
+        }
+        else if self.find_sector().is_none() {
+            self.fail(STATUS_RECORD_NOT_FOUND);
+        }
+        else {
+            // The first byte is asked for before the sector's found
+            self.status |= STATUS_DATA_REQUEST;
+            self.state = State::Writing {
+                bytes: Vec::with_capacity(SECTOR_SIZE),
+                cycles: SEARCH_CYCLES,
+                multiple: multiple,
+            };
+        }
+    }
+
+    fn read_address(&mut self) {
+        let drive = self.selected_drive();
+        let disc_present = drive.and_then(|d| self.drives[d].as_ref()).is_some();
+        match drive {
+            Some(drive) if disc_present => {
+                let track = self.head_track[drive];
+                // The track is also copied into the sector register
+                self.sector = track;
+                self.state = State::Reading {
+                    bytes: vec![track, self.side() as u8, 0, 0x01, 0x00, 0x00],
+                    next: 0,
+                    cycles: SEARCH_CYCLES,
+                    multiple: false,
+                };
+            },
+            _ => self.fail(STATUS_RECORD_NOT_FOUND),
+        }
+    }
+
+    fn complete(&mut self, status: u8) {
+        log_disc!("Command complete with status {:08b}", status);
+        self.status = status;
+        self.intrq = true;
+        self.state = State::Idle;
+    }
+
+    /// Stores a written sector back in the disc image.
+    fn commit_sector(&mut self, bytes: &[u8]) {
+        let (track, side, sector) = (self.track, self.side(), self.sector);
+        if let Some(drive) = self.selected_drive() {
+            if let Some(ref mut disc) = self.drives[drive] {
+                if let Some(range) = disc.sector(track, side, sector) {
+                    disc.bytes[range].copy_from_slice(bytes);
+                }
+            }
+        }
+    }
+
+    pub fn step(&mut self, cycles: usize) {
+        let mut remaining = cycles;
+        while remaining > 0 {
+            remaining = self.advance(remaining);
+        }
+    }
+
+    /// Runs the current command for up to `cycles`, returning any that are
+    /// left after the command changes state.
+    fn advance(&mut self, cycles: usize) -> usize {
+        let byte_cycles = self.byte_cycles();
+        match mem::replace(&mut self.state, State::Idle) {
+            State::Idle => 0,
+            State::Completing { cycles: left, status } => {
+                if cycles < left {
+                    self.state = State::Completing { cycles: left - cycles, status: status };
+                    return 0;
+                }
+                self.complete(status);
+                cycles - left
+            },
+            State::Reading { bytes, next, cycles: left, multiple } => {
+                if cycles < left {
+                    self.state = State::Reading {
+                        bytes: bytes,
+                        next: next,
+                        cycles: left - cycles,
+                        multiple: multiple,
+                    };
+                    return 0;
+                }
+
+                if next < bytes.len() {
+                    if self.status & STATUS_DATA_REQUEST != 0 {
+                        self.status |= STATUS_LOST_DATA;
+                    }
+                    self.data = bytes[next];
+                    self.status |= STATUS_DATA_REQUEST;
+                    self.state = State::Reading {
+                        bytes: bytes,
+                        next: next + 1,
+                        cycles: byte_cycles,
+                        multiple: multiple,
+                    };
+                }
+                else if multiple {
+                    // Carries on until there's no next sector, which ends
+                    // the command with record not found
+                    self.sector = self.sector.wrapping_add(1);
+                    self.read_sector(true);
+                }
+                else {
+                    let status = self.status & !(STATUS_BUSY | STATUS_DATA_REQUEST);
+                    self.complete(status);
+                }
+                cycles - left
+            },
+            State::Writing { mut bytes, cycles: left, multiple } => {
+                if cycles < left {
+                    self.state = State::Writing {
+                        bytes: bytes,
+                        cycles: left - cycles,
+                        multiple: multiple,
+                    };
+                    return 0;
+                }
+
+                if self.status & STATUS_DATA_REQUEST != 0 {
+                    self.status |= STATUS_LOST_DATA;
+                    bytes.push(0x00);
+                }
+                else {
+                    bytes.push(self.data);
+                }
+
+                if bytes.len() < SECTOR_SIZE {
+                    self.status |= STATUS_DATA_REQUEST;
+                    self.state = State::Writing {
+                        bytes: bytes,
+                        cycles: byte_cycles,
+                        multiple: multiple,
+                    };
+                }
+                else {
+                    self.commit_sector(&bytes);
+                    if multiple {
+                        self.sector = self.sector.wrapping_add(1);
+                        self.write_sector(true);
+                    }
+                    else {
+                        let status = self.status & !(STATUS_BUSY | STATUS_DATA_REQUEST);
+                        self.complete(status);
+                    }
+                }
+                cycles - left
+            },
+        }
+    }
+}
+
+/// The registers and any command in progress. Discs aren't part of the
+/// state, so the same ones need to be inserted before it's loaded.
+impl SaveState for Wd1770 {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_u8(self.head_track[0]);
+        state.write_u8(self.head_track[1]);
+        state.write_u8(self.control);
+        state.write_u8(self.status);
+        state.write_u8(self.track);
+        state.write_u8(self.sector);
+        state.write_u8(self.data);
+        state.write_bool(self.direction == Direction::In);
+        state.write_bool(self.intrq);
+
+        match self.state {
+            State::Idle => state.write_u8(0),
+            State::Completing { cycles, status } => {
+                state.write_u8(1);
+                state.write_u64(cycles as u64);
+                state.write_u8(status);
+            },
+            State::Reading { ref bytes, next, cycles, multiple } => {
+                state.write_u8(2);
+                state.write_bytes(bytes);
+                state.write_u64(next as u64);
+                state.write_u64(cycles as u64);
+                state.write_bool(multiple);
+            },
+            State::Writing { ref bytes, cycles, multiple } => {
+                state.write_u8(3);
+                state.write_bytes(bytes);
+                state.write_u64(cycles as u64);
+                state.write_bool(multiple);
+            },
+        }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.head_track = [state.read_u8()?, state.read_u8()?];
+        self.control = state.read_u8()?;
+        self.status = state.read_u8()?;
+        self.track = state.read_u8()?;
+        self.sector = state.read_u8()?;
+        self.data = state.read_u8()?;
+        self.direction = if state.read_bool()? { Direction::In } else { Direction::Out };
+        self.intrq = state.read_bool()?;
+
+        self.state = match state.read_u8()? {
+            0 => State::Idle,
+            1 => State::Completing {
+                cycles: state.read_u64()? as usize,
+                status: state.read_u8()?,
+            },
+            2 => State::Reading {
+                bytes: state.read_bytes()?.to_vec(),
+                next: state.read_u64()? as usize,
+                cycles: state.read_u64()? as usize,
+                multiple: state.read_bool()?,
+            },
+            _ => State::Writing {
+                bytes: state.read_bytes()?.to_vec(),
+                cycles: state.read_u64()? as usize,
+                multiple: state.read_bool()?,
+            },
+        };
+        Ok(())
+    }
+}
+
+impl BusDevice for Wd1770 {
+    fn read(&mut self, addr: u16) -> u8 {
+        let val = self.peek(addr);
+        match addr & 0x0f {
+            0x08 | 0x0c => self.intrq = false,
+            0x0b | 0x0f => self.status &= !STATUS_DATA_REQUEST,
+            _ => {},
+        }
+        val
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr & 0x0f {
+            0x04..=0x07 => self.write_control(val),
+            0x08 | 0x0c => self.write_command(val),
+            0x09 | 0x0d => self.track = val,
+            0x0a | 0x0e => self.sector = val,
+            0x0b | 0x0f => {
+                self.data = val;
+                self.status &= !STATUS_DATA_REQUEST;
+            },
+            _ => {},
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        match addr & 0x0f {
+            0x04..=0x07 => self.control,
+            0x08 | 0x0c => self.status,
+            0x09 | 0x0d => self.track,
+            0x0a | 0x0e => self.sector,
+            0x0b | 0x0f => self.data,
+            _ => 0xff,
+        }
+    }
+}
+
+#[cfg(test)]
+mod wd1770_should {
+    use super::*;
+    use disc::SECTORS_PER_TRACK;
+
+    const CONTROL: u16 = 0xfe24;
+    const COMMAND: u16 = 0xfe28;
+    const TRACK: u16 = 0xfe29;
+    const SECTOR: u16 = 0xfe2a;
+    const DATA: u16 = 0xfe2b;
+
+    fn controller() -> Wd1770 {
+        let image = (0..4 * SECTORS_PER_TRACK * SECTOR_SIZE)
+            .map(|i| (i / SECTOR_SIZE) as u8)
+            .collect();
+        let mut fdc = Wd1770::new();
+        fdc.insert_disc(0, DiscImage::ssd(image));
+        fdc.write(CONTROL, 0x25);
+        fdc
+    }
+
+    /// Runs the controller until it asserts NMI.
+    fn wait_for_nmi(fdc: &mut Wd1770) {
+        for _ in 0..100_000 {
+            if fdc.nmi() {
+                return;
+            }
+            fdc.step(16);
+        }
+        panic!("No NMI");
+    }
+
+    #[test]
+    fn seek_to_the_track_in_the_data_register() {
+        let mut fdc = controller();
+        fdc.write(DATA, 2);
+        fdc.write(COMMAND, 0x14);
+        assert!(fdc.peek(COMMAND) & STATUS_BUSY != 0);
+
+        wait_for_nmi(&mut fdc);
+        assert_eq!(0, fdc.read(COMMAND) & (STATUS_BUSY | STATUS_RECORD_NOT_FOUND));
+        assert!(!fdc.nmi());
+        assert_eq!(2, fdc.peek(TRACK));
+
+        fdc.write(COMMAND, 0x00);
+        wait_for_nmi(&mut fdc);
+        assert_eq!(STATUS_TRACK_0, fdc.read(COMMAND) & STATUS_TRACK_0);
+        assert_eq!(0, fdc.peek(TRACK));
+    }
+
+    #[test]
+    fn hand_over_a_sector_a_byte_at_a_time() {
+        let mut fdc = controller();
+        fdc.write(DATA, 1);
+        fdc.write(COMMAND, 0x10);
+        wait_for_nmi(&mut fdc);
+        fdc.read(COMMAND);
+
+        fdc.write(SECTOR, 3);
+        fdc.write(COMMAND, 0x80);
+        let mut bytes = vec![];
+        loop {
+            wait_for_nmi(&mut fdc);
+            if fdc.peek(COMMAND) & STATUS_DATA_REQUEST == 0 {
+                break;
+            }
+            bytes.push(fdc.read(DATA));
+        }
+
+        assert_eq!(vec![13; SECTOR_SIZE], bytes);
+        assert_eq!(0, fdc.read(COMMAND) & (STATUS_BUSY | STATUS_LOST_DATA));
+    }
+
+    #[test]
+    fn report_sectors_that_are_not_on_the_disc() {
+        let mut fdc = controller();
+        fdc.write(SECTOR, 10);
+        fdc.write(COMMAND, 0x80);
+        wait_for_nmi(&mut fdc);
+        assert_eq!(STATUS_RECORD_NOT_FOUND, fdc.read(COMMAND) & STATUS_RECORD_NOT_FOUND);
+    }
+
+    #[test]
+    fn store_written_sectors_in_the_image() {
+        let mut fdc = controller();
+        fdc.write(SECTOR, 9);
+        fdc.write(COMMAND, 0xa0);
+        for i in 0..SECTOR_SIZE {
+            wait_for_nmi(&mut fdc);
+            assert!(fdc.peek(COMMAND) & STATUS_DATA_REQUEST != 0);
+            fdc.write(DATA, i as u8);
+        }
+        wait_for_nmi(&mut fdc);
+        assert_eq!(0, fdc.read(COMMAND) & (STATUS_BUSY | STATUS_LOST_DATA));
+
+        let sector = &fdc.disc(0).unwrap().as_bytes()[9 * SECTOR_SIZE..10 * SECTOR_SIZE];
+        assert_eq!((0..SECTOR_SIZE).map(|i| i as u8).collect::<Vec<_>>(), sector);
+    }
+}
@@ -0,0 +1,126 @@
+use memory::sideways::SLOT_SIZE;
+
+/// Where each host key is in the matrix, as `(column, row)`. Each of the
+/// 14 columns is selected by holding one of address lines A0-A13 low, and
+/// the keys pressed in it read back as ones on data lines D0-D3.
+const KEY_MAP: &'static [(u32, (usize, usize))] = &[
+    (53, (0, 0)),  // Right
+    (68, (0, 1)),  // End, for COPY
+    (77, (0, 3)),  // Space
+    (52, (1, 0)),  // Left
+    (51, (1, 1)),  // Down
+    (69, (1, 2)),  // Return
+    (66, (1, 3)),  // Backspace, for DELETE
+    (61, (2, 0)),  // -
+    (54, (2, 1)),  // Up
+    (55, (2, 2)),  // ', for :
+    (0, (3, 0)),   // 0
+    (25, (3, 1)),  // P
+    (64, (3, 2)),  // ;
+    (65, (3, 3)),  // /
+    (9, (4, 0)),   // 9
+    (24, (4, 1)),  // O
+    (21, (4, 2)),  // L
+    (62, (4, 3)),  // .
+    (8, (5, 0)),   // 8
+    (18, (5, 1)),  // I
+    (20, (5, 2)),  // K
+    (58, (5, 3)),  // ,
+    (7, (6, 0)),   // 7
+    (30, (6, 1)),  // U
+    (19, (6, 2)),  // J
+    (22, (6, 3)),  // M
+    (6, (7, 0)),   // 6
+    (34, (7, 1)),  // Y
+    (17, (7, 2)),  // H
+    (23, (7, 3)),  // N
+    (5, (8, 0)),   // 5
+    (29, (8, 1)),  // T
+    (16, (8, 2)),  // G
+    (11, (8, 3)),  // B
+    (4, (9, 0)),   // 4
+    (27, (9, 1)),  // R
+    (15, (9, 2)),  // F
+    (31, (9, 3)),  // V
+    (3, (10, 0)),  // 3
+    (14, (10, 1)), // E
+    (13, (10, 2)), // D
+    (12, (10, 3)), // C
+    (2, (11, 0)),  // 2
+    (32, (11, 1)), // W
+    (28, (11, 2)), // S
+    (33, (11, 3)), // X
+    (1, (12, 0)),  // 1
+    (26, (12, 1)), // Q
+    (10, (12, 2)), // A
+    (35, (12, 3)), // Z
+    (78, (13, 0)), // Tab, for ESCAPE
+    (80, (13, 1)), // Caps Lock
+    (84, (13, 2)), // Left Ctrl
+    (85, (13, 2)), // Right Ctrl
+    (82, (13, 3)), // Left Shift
+    (83, (13, 3)), // Right Shift
+];
+
+const COLUMNS: usize = 14;
+
+/// The Electron's keyboard, which the ULA reads through sideways ROM
+/// slots 8 and 9.
+#[derive(Default)]
+pub struct Keyboard {
+    columns: [u8; COLUMNS],
+}
+
+impl Keyboard {
+    pub fn new() -> Keyboard {
+        Keyboard::default()
+    }
+
+    /// Presses host key `key`. Keys the Electron doesn't have are ignored.
+    pub fn key_down(&mut self, key: u32) {
+        for &(_, (column, row)) in KEY_MAP.iter().filter(|k| k.0 == key) {
+            self.columns[column] |= 0x01 << row;
+        }
+    }
+
+    pub fn key_up(&mut self, key: u32) {
+        for &(_, (column, row)) in KEY_MAP.iter().filter(|k| k.0 == key) {
+            self.columns[column] &= !(0x01 << row);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.columns = [0; COLUMNS];
+    }
+
+    /// What a read from `offset` into the keyboard's slot returns.
+    pub fn read(&self, offset: usize) -> u8 {
+        (0..COLUMNS).filter(|c| offset & (0x01 << c) == 0)
+                    .fold(0x00, |val, c| val | self.columns[c])
+    }
+
+    /// The keyboard as the contents of a sideways slot.
+    pub fn as_rom(&self) -> Vec<u8> {
+        (0..SLOT_SIZE).map(|offset| self.read(offset)).collect()
+    }
+}
+
+#[cfg(test)]
+mod keyboard_should {
+    use super::*;
+
+    #[test]
+    fn report_keys_in_the_columns_whose_address_lines_are_low() {
+        let mut kb = Keyboard::new();
+        kb.key_down(10);
+        kb.key_down(82);
+
+        assert_eq!(0x04, kb.read(0x3fff & !(0x01 << 12)));
+        assert_eq!(0x08, kb.read(0x3fff & !(0x01 << 13)));
+        assert_eq!(0x0c, kb.read(0x0000));
+        assert_eq!(0x00, kb.read(0x3fff));
+
+        kb.key_up(10);
+        assert_eq!(0x00, kb.read(0x3fff & !(0x01 << 12)));
+    }
+}
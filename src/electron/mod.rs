@@ -0,0 +1,5 @@
+pub mod keyboard;
+pub mod ula;
+
+pub use self::keyboard::Keyboard;
+pub use self::ula::Ula;
@@ -0,0 +1,367 @@
+use memory::{AsMemoryRegion, BusDevice};
+use video::FrameBuffer;
+
+/// The ULA runs the Electron in 2MHz cycles, 128 to a line and 312 lines
+/// to a frame.
+const LINE_CYCLES: u64 = 128;
+const FRAME_LINES: u64 = 312;
+pub const FRAME_CYCLES: u64 = LINE_CYCLES * FRAME_LINES;
+
+/// How much of each displayed line the ULA spends fetching screen memory.
+/// In modes 0-3 it needs the RAM for the whole of that time.
+pub const DISPLAY_LINE_CYCLES: u64 = LINE_CYCLES * 5 / 8;
+
+/// The line the real time clock interrupt is raised on, half way down
+/// the screen.
+const RTC_LINE: u64 = 100;
+
+/// A byte at 1200 baud, with its start and stop bits.
+const TAPE_BYTE_CYCLES: u64 = 2_000_000 * 10 / 1200;
+
+// The interrupt status and enable bits at $FE00.
+const MASTER_IRQ: u8 = 0x01;
+const POWER_ON_RESET: u8 = 0x02;
+const DISPLAY_END: u8 = 0x04;
+const RTC: u8 = 0x08;
+const RECEIVE_FULL: u8 = 0x10;
+const TRANSMIT_EMPTY: u8 = 0x20;
+const HIGH_TONE: u8 = 0x40;
+const INTERRUPTS: u8 = 0x7c;
+
+/// The comms mode in bits 1-2 of $FE07 that drives the speaker.
+const SOUND_MODE: u8 = 0x01;
+const CASSETTE_MOTOR: u8 = 6;
+const CAPS_LOCK_LED: u8 = 7;
+
+struct ScreenMode {
+    bits_per_pixel: usize,
+    /// Character cells across the screen, each a byte per scanline.
+    columns: usize,
+    rows: usize,
+    scanlines_per_row: usize,
+    /// Where the screen wraps back to once it runs past $7FFF.
+    base: usize,
+}
+
+impl ScreenMode {
+    fn lines(&self) -> u64 {
+        (self.rows * self.scanlines_per_row) as u64
+    }
+
+    /// Whether the ULA shuts the CPU out of RAM while it draws a line.
+    fn blocks_cpu(&self) -> bool {
+        self.columns == 80
+    }
+}
+
+/// Modes 0-6. Mode 7 doesn't exist on the Electron; selecting it gives
+/// mode 4's layout.
+const MODES: [ScreenMode; 8] = [
+    ScreenMode { bits_per_pixel: 1, columns: 80, rows: 32, scanlines_per_row: 8, base: 0x3000 },
+    ScreenMode { bits_per_pixel: 2, columns: 80, rows: 32, scanlines_per_row: 8, base: 0x3000 },
+    ScreenMode { bits_per_pixel: 4, columns: 80, rows: 32, scanlines_per_row: 8, base: 0x3000 },
+    ScreenMode { bits_per_pixel: 1, columns: 80, rows: 25, scanlines_per_row: 10, base: 0x4000 },
+    ScreenMode { bits_per_pixel: 1, columns: 40, rows: 32, scanlines_per_row: 8, base: 0x5800 },
+    ScreenMode { bits_per_pixel: 2, columns: 40, rows: 32, scanlines_per_row: 8, base: 0x5800 },
+    ScreenMode { bits_per_pixel: 1, columns: 40, rows: 25, scanlines_per_row: 10, base: 0x6000 },
+    ScreenMode { bits_per_pixel: 1, columns: 40, rows: 32, scanlines_per_row: 8, base: 0x5800 },
+];
+
+/// The colours the MOS sets up for 2, 4 and 16 colour modes. The palette
+/// registers at $FE08-$FE0F are latched but not yet applied, so programs
+/// that reprogram the palette are drawn in these.
+const TWO_COLOURS: [u32; 2] = [0xff000000, 0xffffffff];
+const FOUR_COLOURS: [u32; 4] = [0xff000000, 0xffff0000, 0xffffff00, 0xffffffff];
+const SIXTEEN_COLOURS: [u32; 16] = [
+    0xff000000, 0xffff0000, 0xff00ff00, 0xffffff00,
+    0xff0000ff, 0xffff00ff, 0xff00ffff, 0xffffffff,
+    0xff000000, 0xffff0000, 0xff00ff00, 0xffffff00,
+    0xff0000ff, 0xffff00ff, 0xff00ffff, 0xffffffff,
+];
+
+/// The Electron's ULA, which does the work of the BBC's video ULA, CRTC,
+/// system VIA, sound chip and cassette interface. It sits at $FE00-$FEFF,
+/// with its sixteen registers repeated throughout.
+pub struct Ula {
+    status: u8,
+    enabled: u8,
+    screen_start: u16,
+    selected_rom: u8,
+    counter: u8,
+    control: u8,
+    palette: [u8; 8],
+    tape_data: u8,
+    tape: Vec<u8>,
+    tape_position: usize,
+    tape_cycles: u64,
+    frame_cycle: u64,
+}
+
+impl Ula {
+    pub fn new() -> Ula {
+        Ula {
+            status: POWER_ON_RESET | TRANSMIT_EMPTY,
+            enabled: 0x00,
+            screen_start: 0x0000,
+            selected_rom: 0,
+            counter: 0,
+            control: 0,
+            palette: [0x00; 8],
+            tape_data: 0x00,
+            tape: vec![],
+            tape_position: 0,
+            tape_cycles: 0,
+            frame_cycle: 0,
+        }
+    }
+
+    /// Whether an enabled interrupt is pending.
+    pub fn irq(&self) -> bool {
+        self.status & self.enabled & INTERRUPTS != 0
+    }
+
+    /// The sideways slot paged in at $8000-$BFFF.
+    pub fn selected_rom(&self) -> usize {
+        self.selected_rom as usize
+    }
+
+    /// The pitch the speaker is sounding, in Hz, if it's on.
+    pub fn sound_frequency(&self) -> Option<u32> {
+        if (self.control >> 1) & 0x03 == SOUND_MODE {
+            Some(1_000_000 / (16 * (self.counter as u32 + 1)))
+        }
+        else {
+            None
+        }
+    }
+
+    pub fn caps_lock_led(&self) -> bool {
+        bit_is_set!(self.control, CAPS_LOCK_LED)
+    }
+
+    pub fn cassette_motor(&self) -> bool {
+        bit_is_set!(self.control, CASSETTE_MOTOR)
+    }
+
+    /// Loads a tape, as the bytes a `.uef` file's data chunks hold. They're
+    /// read in whenever the cassette motor is on.
+    pub fn insert_tape(&mut self, bytes: Vec<u8>) {
+        self.tape = bytes;
+        self.tape_position = 0;
+        self.tape_cycles = 0;
+    }
+
+    fn mode(&self) -> &'static ScreenMode {
+        &MODES[((self.control >> 3) & 0x07) as usize]
+    }
+
+    /// How many extra cycles a CPU access to RAM `offset` cycles from now
+    /// waits for. RAM runs at 1MHz, so an access first has to line up with
+    /// its clock, and in modes 0-3 it also has to wait for the ULA to
+    /// finish fetching the current line.
+    pub fn ram_access_delay(&self, offset: u64) -> usize {
+        let now = self.frame_cycle + offset;
+        let line = (now / LINE_CYCLES) % FRAME_LINES;
+        let column = now % LINE_CYCLES;
+        let mode = self.mode();
+
+        let blocked = if mode.blocks_cpu() && line < mode.lines() && column < DISPLAY_LINE_CYCLES {
+            DISPLAY_LINE_CYCLES - column
+        }
+        else {
+            0
+        };
+
+        blocked as usize + if (now + blocked) & 1 == 0 { 1 } else { 2 }
+    }
+
+    /// Moves the beam on by `cycles`, raising the real time clock and
+    /// display end interrupts as it passes them and drawing the screen
+    /// from `mem` once each frame is displayed.
+    pub fn step<M: AsMemoryRegion>(&mut self, cycles: usize, mem: M, fb: &mut FrameBuffer) {
+        let from = self.frame_cycle;
+        let to = from + cycles as u64;
+        let passes = |line: u64| {
+            let at = line * LINE_CYCLES;
+            (from < at && at <= to) || (from < at + FRAME_CYCLES && at + FRAME_CYCLES <= to)
+        };
+
+        if passes(RTC_LINE) {
+            self.status |= RTC;
+        }
+        if passes(self.mode().lines()) {
+            self.status |= DISPLAY_END;
+            self.render(mem, fb);
+        }
+        self.frame_cycle = to % FRAME_CYCLES;
+
+        if self.cassette_motor() && self.tape_position < self.tape.len() {
+            self.tape_cycles += cycles as u64;
+            if self.tape_cycles >= TAPE_BYTE_CYCLES {
+                self.tape_cycles -= TAPE_BYTE_CYCLES;
+                self.tape_data = self.tape[self.tape_position];
+                self.tape_position += 1;
+                self.status |= RECEIVE_FULL;
+            }
+        }
+    }
+
+    fn render<M: AsMemoryRegion>(&self, mem: M, fb: &mut FrameBuffer) {
+        let mode = self.mode();
+        let ram = &*mem.display_region(0..0x8000).unwrap_or_else(|e| e.0);
+        let colours: &[u32] = match mode.bits_per_pixel {
+            1 => &TWO_COLOURS,
+            2 => &FOUR_COLOURS,
+            _ => &SIXTEEN_COLOURS,
+        };
+
+        let pixels_per_byte = 8 / mode.bits_per_pixel;
+        let width = fb.width;
+        let scale = ::std::cmp::max(1, width / (mode.columns * pixels_per_byte));
+        let lines = ::std::cmp::min(mode.lines() as usize, fb.height);
+
+        for y in 0..lines {
+            let (row, scanline) = (y / mode.scanlines_per_row, y % mode.scanlines_per_row);
+            for column in 0..mode.columns {
+                let mut addr = self.screen_start as usize +
+                    (row * mode.columns + column) * 8 + scanline;
+                if addr >= 0x8000 {
+                    addr = mode.base + (addr - 0x8000) % (0x8000 - mode.base);
+                }
+                let byte = ram[addr];
+
+                for p in 0..pixels_per_byte {
+                    let colour = (0..mode.bits_per_pixel).fold(0, |c, k| {
+                        let bit = (byte >> (7 - p - k * pixels_per_byte)) & 0x01;
+                        c | (bit as usize) << (mode.bits_per_pixel - 1 - k)
+                    });
+                    let x = (column * pixels_per_byte + p) * scale;
+                    for n in x..::std::cmp::min(x + scale, width) {
+                        fb[y * width + n] = colours[colour];
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl BusDevice for Ula {
+    fn read(&mut self, addr: u16) -> u8 {
+        let val = self.peek(addr);
+        match addr & 0x0f {
+            0x00 => self.status &= !POWER_ON_RESET,
+            0x04 => self.status &= !RECEIVE_FULL,
+            _ => {}
+        }
+        val
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr & 0x0f {
+            0x00 => self.enabled = val & INTERRUPTS,
+            0x02 => {
+                self.screen_start = (self.screen_start & 0x7e00) | ((val & 0xe0) as u16) << 1;
+            },
+            0x03 => {
+                self.screen_start = (self.screen_start & 0x01c0) | ((val & 0x3f) as u16) << 9;
+            },
+            0x04 => {
+                self.tape_data = val;
+                self.status &= !TRANSMIT_EMPTY;
+            },
+            0x05 => {
+                if bit_is_set!(val, 4) { self.status &= !DISPLAY_END; }
+                if bit_is_set!(val, 5) { self.status &= !RTC; }
+                if bit_is_set!(val, 6) { self.status &= !HIGH_TONE; }
+
+                // Slots 8-11 hold the keyboard and BASIC. While one of them
+                // is paged in, slots 0-7 can't be selected directly.
+                if bit_is_set!(val, 3) {
+                    self.selected_rom = val & 0x0f;
+                }
+                else if self.selected_rom < 8 || self.selected_rom > 11 {
+                    self.selected_rom = val & 0x07;
+                }
+                log_video!("ULA: paged in slot {}", (self.selected_rom));
+            },
+            0x06 => self.counter = val,
+            0x07 => {
+                let motor_starting = !self.cassette_motor() && bit_is_set!(val, CASSETTE_MOTOR);
+                self.control = val;
+                if motor_starting && self.tape_position < self.tape.len() {
+                    self.status |= HIGH_TONE;
+                }
+            },
+            n => self.palette[n as usize - 0x08] = val,
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        match addr & 0x0f {
+            0x00 => {
+                let irq = if self.irq() { MASTER_IRQ } else { 0x00 };
+                0x80 | self.status | irq
+            },
+            0x04 => self.tape_data,
+            _ => 0x00,
+        }
+    }
+}
+
+#[cfg(test)]
+mod ula_should {
+    use super::*;
+    use memory::Map;
+
+    #[test]
+    fn only_page_in_slots_0_to_7_when_the_keyboard_and_basic_are_out() {
+        let mut ula = Ula::new();
+        ula.write(0xfe05, 0x0a);
+        assert_eq!(10, ula.selected_rom());
+
+        ula.write(0xfe05, 0x03);
+        assert_eq!(10, ula.selected_rom());
+
+        ula.write(0xfe05, 0x0c);
+        ula.write(0xfe05, 0x03);
+        assert_eq!(3, ula.selected_rom());
+    }
+
+    #[test]
+    fn raise_and_clear_the_rtc_and_display_end_interrupts() {
+        let mut ula = Ula::new();
+        let mut fb = FrameBuffer::new(640, 256);
+        let mem = Map::new();
+        ula.write(0xfe00, RTC | DISPLAY_END);
+        assert!(!ula.irq());
+        assert_eq!(POWER_ON_RESET, ula.read(0xfe00) & POWER_ON_RESET);
+
+        ula.step((RTC_LINE * LINE_CYCLES) as usize, &mem, &mut fb);
+        assert!(ula.irq());
+        assert_eq!(0x80 | RTC | MASTER_IRQ, ula.read(0xfe00) & !TRANSMIT_EMPTY);
+
+        ula.write(0xfe05, 0x20);
+        assert!(!ula.irq());
+
+        ula.step(((256 - RTC_LINE) * LINE_CYCLES) as usize, &mem, &mut fb);
+        assert_eq!(0x80 | DISPLAY_END | MASTER_IRQ, ula.read(0xfe00) & !TRANSMIT_EMPTY);
+    }
+
+    #[test]
+    fn decode_the_screen_start_address() {
+        let mut ula = Ula::new();
+        ula.write(0xfe02, 0xe0);
+        ula.write(0xfe03, 0x2c);
+        assert_eq!(0x59c0, ula.screen_start);
+    }
+
+    #[test]
+    fn keep_the_cpu_out_of_ram_while_modes_0_to_3_are_displayed() {
+        let mut ula = Ula::new();
+        assert_eq!(DISPLAY_LINE_CYCLES as usize + 1, ula.ram_access_delay(0));
+        assert_eq!(2, ula.ram_access_delay(DISPLAY_LINE_CYCLES + 1));
+
+        ula.write(0xfe07, 0x30);
+        assert_eq!(1, ula.ram_access_delay(0));
+    }
+}
@@ -0,0 +1,220 @@
+use cpu::*;
+use memory::*;
+use video::*;
+use electron::{Keyboard, Ula};
+use super::{copy_rom, Emulator, ResetKind, StepResult};
+use std::cell::RefCell;
+use std::ops::Range;
+use std::rc::Rc;
+
+const ULA_RANGE: Range<usize> = 0xfe00..0xff00;
+
+/// The slots the ULA reads the keyboard through.
+const KEYBOARD_SLOTS: [usize; 2] = [8, 9];
+
+/// Returns the extra 2MHz cycles `accesses` spend waiting for RAM, which
+/// the ULA shares with the CPU.
+fn contended_stretch(accesses: &[BusCycle], ula: &Ula) -> usize {
+    let mut stretch = 0;
+    for access in accesses.iter().filter(|a| a.address < 0x8000) {
+        stretch += ula.ram_access_delay(access.cycle as u64 + stretch as u64);
+    }
+
+    stretch
+}
+
+/// An Acorn Electron. The memory should have been built for
+/// `Model::Electron`, with BASIC in slots 10 and 11. The keyboard takes
+/// slots 8 and 9.
+pub struct ElectronEmulator {
+    cpu: Cpu,
+    mem: Map,
+    ula: Rc<RefCell<Ula>>,
+    keyboard: Keyboard,
+    keyboard_changed: bool,
+}
+
+impl ElectronEmulator {
+    pub fn with_memory(mut mem: Map) -> ElectronEmulator {
+        let ula = Rc::new(RefCell::new(Ula::new()));
+        mem.attach_device(ULA_RANGE, ula.clone());
+
+        ElectronEmulator {
+            cpu: Cpu::new(CpuVariant::Nmos6502),
+            mem: mem,
+            ula: ula,
+            keyboard: Keyboard::new(),
+            keyboard_changed: true,
+        }
+    }
+
+    /// Mutable access to the CPU, e.g. to attach a `CpuObserver`.
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+
+    /// The ULA, for inserting tapes.
+    pub fn ula(&self) -> &Rc<RefCell<Ula>> {
+        &self.ula
+    }
+
+    /// Brings the sideways slots in line with the ULA's ROM selection and
+    /// the keys held down.
+    fn sync_paging(&mut self) {
+        if self.keyboard_changed {
+            let rom = self.keyboard.as_rom();
+            for &slot in KEYBOARD_SLOTS.iter() {
+                self.mem.sideways_mut().insert_rom(slot, &rom);
+            }
+            self.keyboard_changed = false;
+        }
+
+        let selected = self.ula.borrow().selected_rom();
+        self.mem.sideways_mut().select(selected as u8);
+    }
+}
+
+impl Emulator for ElectronEmulator {
+    type Error = CpuError;
+    type Memory = Map;
+
+    fn place_rom_at(&mut self, location: u16, rom: &[u8]) {
+        copy_rom(&mut self.mem, location, rom);
+    }
+
+    fn initialize(&mut self) -> Result<(), CpuError> {
+        self.cpu.initialize(&mut self.mem)
+    }
+
+    fn reset(&mut self, kind: ResetKind) -> Result<(), CpuError> {
+        if kind == ResetKind::PowerOn {
+            *self.ula.borrow_mut() = Ula::new();
+        }
+
+        self.sync_paging();
+        self.mem.clear_last_hw_access();
+        self.cpu.set_irq(InterruptSource::Ula, self.ula.borrow().irq());
+        self.cpu.reset(&mut self.mem);
+        Ok(())
+    }
+
+    fn step<K: Fn(u8) -> bool>(&mut self, fb: &mut FrameBuffer, _key_eval: K) -> Result<StepResult, CpuError> {
+        self.sync_paging();
+        // A step either takes an interrupt or executes an instruction
+        let cycles = match self.cpu.poll_interrupts(&mut self.mem)? {
+            0 => self.cpu.step(&mut self.mem)?,
+            cycles => cycles,
+        };
+        let cycles = cycles + contended_stretch(self.cpu.bus_cycles(), &self.ula.borrow());
+
+        self.ula.borrow_mut().step(cycles, &self.mem, fb);
+        self.sync_paging();
+
+        self.cpu.set_irq(InterruptSource::Ula, self.ula.borrow().irq());
+
+        self.mem.clear_last_hw_access();
+        Ok(StepResult::Progressed(cycles))
+    }
+
+    fn keydown(&mut self, key: u32) {
+        self.keyboard.key_down(key);
+        self.keyboard_changed = true;
+    }
+
+    fn keyup(&mut self, key: u32) {
+        self.keyboard.key_up(key);
+        self.keyboard_changed = true;
+    }
+
+    fn clear_keyboard_buffer(&mut self) {
+        self.keyboard.clear();
+        self.keyboard_changed = true;
+    }
+
+    fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    fn mem(&self) -> &Map {
+        &self.mem
+    }
+}
+
+#[cfg(test)]
+mod electron_should {
+    use super::*;
+    use emulator::load_program;
+    use electron::ula::DISPLAY_LINE_CYCLES;
+
+    fn electron(source: &str) -> ElectronEmulator {
+        let mut mem = Map::new().with_model(Model::Electron).with_hw_range(0xfc00..0xff00);
+        load_program(&mut mem, CpuVariant::Nmos6502, source);
+
+        let mut emu = ElectronEmulator::with_memory(mem);
+        emu.reset(ResetKind::PowerOn).unwrap();
+        emu
+    }
+
+    #[test]
+    fn read_the_keyboard_through_slot_8() {
+        let mut emu = electron("
+            ORG &1000
+                LDA #&08 : STA &FE05
+                LDA &AFFF           ; &BFFF with A12 low
+        ");
+        let mut fb = FrameBuffer::new(640, 256);
+        emu.keydown(10);
+        for _ in 0..3 {
+            emu.step(&mut fb, |_| false).unwrap();
+        }
+
+        assert_eq!(8, emu.mem().sideways().selected());
+        assert_eq!(0x04, emu.cpu().registers().acc);
+    }
+
+    #[test]
+    fn slow_ram_accesses_down_while_mode_0_is_displayed() {
+        let mut emu = electron("ORG &1000 : LDA &3000");
+        let mut fb = FrameBuffer::new(640, 256);
+        let cycles = match emu.step(&mut fb, |_| false).unwrap() {
+            StepResult::Progressed(n) => n,
+            _ => panic!("Expected the CPU to progress"),
+        };
+
+        assert!(cycles > DISPLAY_LINE_CYCLES as usize);
+    }
+
+    #[test]
+    fn count_and_slow_down_the_cycles_of_a_taken_irq() {
+        let mut emu = electron("
+            ORG &1000
+                LDA #&08 : STA &FE00
+                CLI
+            .idle
+                JMP idle
+            ORG &2000
+            .irq
+                JMP irq
+            ORG &FFFE
+                EQUW irq
+        ");
+        let mut fb = FrameBuffer::new(640, 256);
+
+        let mut interrupt_cycles = None;
+        for _ in 0..20_000 {
+            let pc = emu.cpu().program_counter();
+            let cycles = match emu.step(&mut fb, |_| false).unwrap() {
+                StepResult::Progressed(n) => n,
+                _ => panic!("Expected the CPU to progress"),
+            };
+            if pc == 0x1006 && emu.cpu().program_counter() == 0x2000 {
+                interrupt_cycles = Some(cycles);
+                break;
+            }
+        }
+
+        // The two idle reads and three pushes all wait for RAM
+        let cycles = interrupt_cycles.expect("Expected the RTC interrupt to be taken");
+        assert!(cycles >= 7 + 5, "Took {} cycles", cycles);
+    }
+}
@@ -3,6 +3,90 @@ use timer::*;
 use memory::*;
 use video::*;
 use via;
+use disc::Wd1770;
+use state::{SaveState, StateReader, StateWriter, StateError, MAGIC, VERSION};
+use state::uef::{self, UefState};
+use memory::model::ACCCON_REGISTER;
+use std::cell::RefCell;
+use std::ops::Range;
+use std::rc::Rc;
+
+pub mod electron;
+pub use self::electron::ElectronEmulator;
+
+/// Returns the extra 2MHz cycles spent synchronising the accesses that
+/// `mem` puts on the 1MHz bus with its clock, given the number of cycles that had elapsed when the
+/// instruction started. An access has to wait for the start of the next
+/// 1MHz cycle and then lasts the whole of it, costing one or two extra
+/// cycles depending on the phase of the two clocks.
+fn one_mhz_stretch<M: MemoryMap>(mem: &M, start: u64) -> usize {
+    let mut stretch = 0;
+    for access in mem.hw_accesses() {
+        if mem.is_one_mhz(access.address) {
+            let now = start + access.cycle as u64 + stretch as u64;
+            stretch += if now & 1 == 0 { 1 } else { 2 };
+        }
+    }
+
+    stretch
+}
+
+/// Where ROM select writes go. See `BbcEmulator::reset`.
+const PAGED_ROM_SELECT: u16 = 0xfe30;
+
+const CRTC_RANGE: Range<usize> = 0xfe00..0xfe08;
+const VIDEO_ULA_RANGE: Range<usize> = 0xfe20..0xfe30;
+const SYSTEM_VIA_RANGE: Range<usize> = 0xfe40..0xfe60;
+
+/// The Master shares the Model B's video ULA addresses with its disc
+/// controller.
+const MASTER_VIDEO_ULA_RANGE: Range<usize> = 0xfe20..0xfe24;
+const MASTER_DISC_CONTROLLER_RANGE: Range<usize> = 0xfe24..0xfe30;
+
+/// The machines `BbcEmulator` can emulate. Save states record which one
+/// they came from by position, so new machines go at the end.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Machine {
+    ModelB,
+    /// The Master 128, with a 65SC12, CMOS RAM and a 1770 disc controller.
+    /// MOS 3.20 is loaded with `Map::load_master_mos`.
+    Master128,
+    /// The Model B with only 16K of RAM fitted.
+    ModelA,
+    /// The B+ 64K, a Model B with shadow and private RAM paged by ACCCON
+    /// and ROMSEL.
+    BPlus,
+}
+
+impl Machine {
+    /// The memory the machine's `Map` should be built with.
+    pub fn model(self) -> Model {
+        match self {
+            Machine::ModelB => Model::B,
+            Machine::Master128 => Model::Master128,
+            Machine::ModelA => Model::A,
+            Machine::BPlus => Model::BPlus,
+        }
+    }
+
+    pub fn cpu_variant(self) -> CpuVariant {
+        match self {
+            Machine::Master128 => CpuVariant::Cmos65sc12,
+            _ => CpuVariant::Nmos6502,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ResetKind {
+    /// Switching the machine on. The VIAs, CRTC and paged ROM selection
+    /// start from scratch, which the MOS detects as a cold start.
+    PowerOn,
+    /// Pressing BREAK, which only resets the CPU. Holding CTRL or SHIFT at
+    /// the same time gives CTRL-BREAK and SHIFT-BREAK, as the MOS reads the
+    /// keyboard while it restarts.
+    Break,
+}
 
 #[derive(Debug)]
 pub enum StepResult {
@@ -17,6 +101,7 @@ pub trait Emulator {
 
     fn place_rom_at(&mut self, location: u16, rom: &[u8]);
     fn initialize(&mut self) -> Result<(), Self::Error>;
+    fn reset(&mut self, kind: ResetKind) -> Result<(), Self::Error>;
     fn step<K>(&mut self, fb: &mut FrameBuffer, key_eval: K) -> Result<StepResult, Self::Error>
         where K: Fn(u8) -> bool;
     fn cpu(&self) -> &Cpu;
@@ -26,26 +111,232 @@ pub trait Emulator {
     fn clear_keyboard_buffer(&mut self) { }
 }
 
+/// Copies `rom` into `mem` at `location`, ignoring any write protection
+/// and truncating it at the end of memory.
+fn copy_rom<M: AsMemoryRegionMut>(mem: &mut M, location: u16, rom: &[u8]) {
+    use std::io::{self, Cursor};
+
+    let end = ::std::cmp::min(location as usize + rom.len(), mem.len());
+    let mut region = mem.region_mut(location as usize..end)
+                        .unwrap_or_else(|e| e.0);
+    io::copy(
+        &mut Cursor::new(rom), 
+        &mut Cursor::new(region.as_mut())
+    ).unwrap();
+}
+
+/// Copies as much of `src` into `dest` as fits. Other emulators don't
+/// always size each memory the same way.
+fn copy_ram(dest: &mut [u8], src: &[u8]) {
+    let len = ::std::cmp::min(dest.len(), src.len());
+    dest[..len].copy_from_slice(&src[..len]);
+}
+
 pub struct BbcEmulator<M> {
+    machine: Machine,
     cpu: Cpu,
     mem: M,
-    video: Crtc6845,
-    system_via: via::System,
+    video: Rc<RefCell<Crtc6845>>,
+    system_via: Rc<RefCell<via::System>>,
+    disc_controller: Option<Rc<RefCell<Wd1770>>>,
+    cycles: u64,
 }
 
-impl<M> BbcEmulator<M> {
+impl<M: AttachDevice> BbcEmulator<M> {
+    /// Emulates a Model B. See `with_machine`.
     pub fn with_memory(mem: M) -> BbcEmulator<M> {
-        use std::u16;
+        BbcEmulator::with_machine(Machine::ModelB, mem)
+    }
+
+    /// Attaches `machine`'s devices to `mem`, which should have been built
+    /// for `machine.model()`.
+    pub fn with_machine(machine: Machine, mut mem: M) -> BbcEmulator<M> {
+        let video = Rc::new(RefCell::new(Crtc6845::new()));
+        let system_via = match machine {
+            Machine::Master128 => via::System::new().with_cmos(),
+            _ => via::System::new(),
+        };
+        let system_via = Rc::new(RefCell::new(system_via));
+
+        mem.attach_device(CRTC_RANGE, video.clone());
+        mem.attach_device(SYSTEM_VIA_RANGE, system_via.clone());
+        let disc_controller = match machine {
+            Machine::Master128 => {
+                let fdc = Rc::new(RefCell::new(Wd1770::new()));
+                mem.attach_device(MASTER_VIDEO_ULA_RANGE, video.clone());
+                mem.attach_device(MASTER_DISC_CONTROLLER_RANGE, fdc.clone());
+                Some(fdc)
+            },
+            _ => {
+                mem.attach_device(VIDEO_ULA_RANGE, video.clone());
+                None
+            },
+        };
 
         BbcEmulator {
-            cpu: Cpu::new(),
+            machine: machine,
+            cpu: Cpu::new(machine.cpu_variant()),
             mem: mem,
-            video: Crtc6845::new(),
-            system_via: via::System::new(),
+            video: video,
+            system_via: system_via,
+            disc_controller: disc_controller,
+            cycles: 0,
         }
     }
 }
 
+impl<M> BbcEmulator<M> {
+
+    /// Mutable access to the CPU, e.g. to attach a `CpuObserver`.
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+
+    pub fn machine(&self) -> Machine {
+        self.machine
+    }
+
+    /// The 1770, for inserting discs, on machines that have one.
+    pub fn disc_controller(&self) -> Option<&Rc<RefCell<Wd1770>>> {
+        self.disc_controller.as_ref()
+    }
+}
+
+impl<M: SaveState> BbcEmulator<M> {
+    /// Saves everything needed to carry on from this point later. See
+    /// `load_state`.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        for &b in MAGIC.iter() {
+            state.write_u8(b);
+        }
+        state.write_u8(VERSION);
+        state.write_u8(self.machine as u8);
+        state.write_u64(self.cycles);
+
+        self.cpu.save(&mut state);
+        self.mem.save(&mut state);
+        self.video.borrow().save(&mut state);
+        self.system_via.borrow().save(&mut state);
+        if let Some(ref fdc) = self.disc_controller {
+            fdc.borrow().save(&mut state);
+        }
+
+        state.into_bytes()
+    }
+
+    /// Restores a state saved by `save_state`. The emulator has to be
+    /// built the same way as the one that saved it, with the same machine,
+    /// ROMs and discs, and it then carries on exactly as that one would
+    /// have. If loading fails part way through, reset the emulator before
+    /// running it again.
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), StateError> {
+        if !bytes.starts_with(MAGIC) {
+            return Err(StateError::NotASaveState);
+        }
+
+        let mut state = StateReader::new(&bytes[MAGIC.len()..]);
+        let version = state.read_u8()?;
+        if version != VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        if state.read_u8()? != self.machine as u8 {
+            return Err(StateError::Mismatch("Saved from a different machine"));
+        }
+        self.cycles = state.read_u64()?;
+
+        self.cpu.load(&mut state)?;
+        self.mem.load(&mut state)?;
+        self.video.borrow_mut().load(&mut state)?;
+        self.system_via.borrow_mut().load(&mut state)?;
+        if let Some(ref fdc) = self.disc_controller {
+            fdc.borrow_mut().load(&mut state)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl BbcEmulator<Map> {
+    /// BeebEm has no Model A, so it's saved as a Model B.
+    fn uef_machine(&self) -> u8 {
+        match self.machine {
+            Machine::ModelA | Machine::ModelB => uef::MODEL_B,
+            Machine::BPlus => uef::B_PLUS,
+            Machine::Master128 => uef::MASTER_128,
+        }
+    }
+
+    /// Loads a snapshot saved by BeebEm, as a `.uefstate` file. Only the
+    /// parts of the machine bbc_em emulates are restored, so the emulator
+    /// should have the same ROMs fitted as the one that saved it. B-Em
+    /// snapshots are refused with `StateError::BemSnapshot`.
+    pub fn import_uef_state(&mut self, bytes: &[u8]) -> Result<(), StateError> {
+        let state = UefState::parse(bytes)?;
+        if state.machine != self.uef_machine() {
+            return Err(StateError::Mismatch("Saved from a different machine"));
+        }
+
+        *self.cpu.registers_mut() = state.registers;
+        self.cycles = state.cycles as u64;
+
+        copy_ram(self.mem.main_ram_mut(), &state.main_ram);
+        copy_ram(self.mem.shadow_ram_mut(), &state.shadow_ram);
+        copy_ram(self.mem.private_ram_mut(), &state.private_ram);
+        copy_ram(self.mem.hazel_mut(), &state.filing_system_ram);
+        for &(slot, ref ram) in &state.sideways_ram {
+            let slot = (slot & 0x0f) as usize;
+            if self.mem.sideways().kind(slot) != Some(SlotKind::Ram) {
+                self.mem.sideways_mut().insert_ram(slot);
+            }
+            if let Some(dest) = self.mem.sideways_mut().contents_mut(slot) {
+                copy_ram(dest, ram);
+            }
+        }
+        self.mem.write(PAGED_ROM_SELECT, state.romsel);
+        if self.machine.model().has_shadow_ram() {
+            self.mem.write(ACCCON_REGISTER, state.acccon);
+        }
+        self.mem.clear_last_hw_access();
+
+        self.system_via.borrow_mut().restore(&state.system_via);
+        self.video.borrow_mut().restore(&state.crtc_registers,
+                                        state.video_ula_control,
+                                        &state.video_ula_palette);
+        Ok(())
+    }
+
+    /// Saves a snapshot in the format BeebEm reads.
+    pub fn export_uef_state(&self) -> Vec<u8> {
+        let sideways = self.mem.sideways();
+        let state = UefState {
+            machine: self.uef_machine(),
+            registers: *self.cpu.registers(),
+            // BeebEm only keeps 32 bits of the count, so it wraps
+            cycles: (self.cycles & 0xffff_ffff) as u32,
+            romsel: self.mem.romsel(),
+            acccon: self.mem.acccon(),
+            main_ram: self.mem.main_ram().to_vec(),
+            shadow_ram: self.mem.shadow_ram().to_vec(),
+            private_ram: self.mem.private_ram().to_vec(),
+            filing_system_ram: self.mem.hazel().to_vec(),
+            sideways_ram: (0..16).filter(|&s| sideways.kind(s) == Some(SlotKind::Ram))
+                                 .filter_map(|s| sideways.contents(s).map(|c| (s as u8, c.to_vec())))
+                                 .collect(),
+            system_via: self.system_via.borrow().snapshot(),
+            crtc_registers: {
+                let mut registers = [0; 18];
+                registers.copy_from_slice(self.video.borrow().registers());
+                registers
+            },
+            video_ula_control: self.video.borrow().video_control(),
+            video_ula_palette: *self.video.borrow().palette(),
+        };
+
+        state.to_bytes()
+    }
+}
+
 impl<M> Emulator for BbcEmulator<M> 
     where M: MemoryMap + AsMemoryRegionMut
 {
@@ -53,41 +344,63 @@ impl<M> Emulator for BbcEmulator<M>
     type Memory = M;
 
     fn place_rom_at(&mut self, location: u16, rom: &[u8]) {
-        use std::io::{self, Cursor};
-
-        let mut region = self.mem.region_from_mut(location as _..)
-                                 .unwrap_or_else(|e| e.0);
-        io::copy(
-            &mut Cursor::new(rom), 
-            &mut Cursor::new(region.as_mut())
-        ).unwrap();
+        copy_rom(&mut self.mem, location, rom);
     }
 
     fn initialize(&mut self) -> Result<(), CpuError> {
         self.cpu.initialize(&mut self.mem)
     }
 
-    fn step<K: Fn(u8) -> bool>(&mut self, fb: &mut FrameBuffer, key_eval: K) -> Result<StepResult, CpuError> {
-        let mut irq = false;
+    fn reset(&mut self, kind: ResetKind) -> Result<(), CpuError> {
+        if kind == ResetKind::PowerOn {
+            self.system_via.borrow_mut().reset();
+            *self.video.borrow_mut() = Crtc6845::new();
+            // The ROM select latch powers up in an undefined state, so
+            // start with bank 0. The MOS pages in a language ROM itself.
+            self.mem.write(PAGED_ROM_SELECT, 0x00);
+            if self.machine.model().has_shadow_ram() {
+                self.mem.write(ACCCON_REGISTER, 0x00);
+            }
+            if let Some(ref fdc) = self.disc_controller {
+                fdc.borrow_mut().reset();
+            }
+            self.cycles = 0;
+        }
 
-        let cycles = self.cpu.step(&mut self.mem)?;
-        self.system_via.step(cycles, &mut self.mem, || { irq = true }, key_eval);
-        self.video.step(cycles, &mut self.mem, fb);
+        self.mem.clear_last_hw_access();
+        self.cpu.set_irq(InterruptSource::SystemVia, self.system_via.borrow().irq());
+        self.cpu.reset(&mut self.mem);
+        Ok(())
+    }
+
+    fn step<K: Fn(u8) -> bool>(&mut self, fb: &mut FrameBuffer, _key_eval: K) -> Result<StepResult, CpuError> {
+        // A step either takes an interrupt or executes an instruction
+        let cycles = match self.cpu.poll_interrupts(&mut self.mem)? {
+            0 => self.cpu.step(&mut self.mem)?,
+            cycles => cycles,
+        };
+        let cycles = cycles + one_mhz_stretch(&self.mem, self.cycles);
+        self.cycles = self.cycles.wrapping_add(cycles as u64);
 
-        if irq {
-            self.cpu.interrupt_request(&mut self.mem);
+        self.system_via.borrow_mut().step(cycles);
+        self.video.borrow_mut().step(cycles, &mut self.mem, fb);
+        if let Some(ref fdc) = self.disc_controller {
+            fdc.borrow_mut().step(cycles);
+            self.cpu.set_nmi(InterruptSource::DiscController, fdc.borrow().nmi());
         }
 
+        self.cpu.set_irq(InterruptSource::SystemVia, self.system_via.borrow().irq());
+
         self.mem.clear_last_hw_access();
         Ok(StepResult::Progressed(cycles))
     }
 
     fn keydown(&mut self, key: u32) {
-        self.system_via.keydown(key);
+        self.system_via.borrow_mut().keydown(key);
     }
 
     fn clear_keyboard_buffer(&mut self) {
-        self.system_via.clear_keyboard_buffer();
+        self.system_via.borrow_mut().clear_keyboard_buffer();
     }
 
     fn cpu(&self) -> &Cpu {
@@ -99,3 +412,357 @@ impl<M> Emulator for BbcEmulator<M>
     }
 }
 
+/// Assembles `source` for `variant` into `mem` and points the reset vector
+/// at the start of it, for the emulator tests.
+#[cfg(test)]
+fn load_program<M: MemoryMap>(mut mem: M, variant: CpuVariant, source: &str) {
+    let asm = ::assembler::assemble_for(variant, source).unwrap();
+    asm.load_into(&mut mem);
+    let start = asm.segments()[0].origin;
+    mem.write(0xfffc, start as u8);
+    mem.write(0xfffd, (start >> 8) as u8);
+}
+
+#[cfg(test)]
+mod one_mhz_stretch_should {
+    use super::*;
+    use memory::model::ONE_MHZ_RANGES;
+
+    /// A BBC memory map that has been read from `addresses`, one per cycle.
+    fn after_reading(addresses: &[u16]) -> Map {
+        let mut mem = Map::new()
+            .with_hw_range(0xfc00..0xff00)
+            .with_one_mhz_ranges(ONE_MHZ_RANGES.iter().cloned());
+        for &loc in addresses {
+            mem.read(loc);
+        }
+        mem
+    }
+
+    #[test]
+    fn ignore_accesses_outside_the_1mhz_bus() {
+        let mem = after_reading(&[0x1000, 0x1001, 0x1002, 0xfe30, 0xfe21]);
+        assert_eq!(0, one_mhz_stretch(&mem, 0));
+    }
+
+    #[test]
+    fn only_stretch_accesses_the_map_puts_on_the_1mhz_bus() {
+        let mut mem = Map::new().with_hw_range(0xfc00..0xff00);
+        mem.read(0xfe4d);
+        assert_eq!(0, one_mhz_stretch(&mem, 0));
+    }
+
+    #[test]
+    fn stretch_by_one_or_two_cycles_depending_on_phase() {
+        let mem = after_reading(&[0x1000, 0x1001, 0x1002, 0xfe4d]);
+        assert_eq!(1, one_mhz_stretch(&mem, 1));
+        assert_eq!(2, one_mhz_stretch(&mem, 0));
+    }
+
+    #[test]
+    fn stretch_every_access_of_a_read_modify_write() {
+        // INC $FE4D: read, then write twice on cycles 3, 4 and 5. Once
+        // the first access has synchronised the clocks, the rest run at
+        // 1MHz.
+        let mem = after_reading(&[0x1000, 0x1001, 0x1002, 0xfe4d, 0xfe4d, 0xfe4d]);
+        assert_eq!(4, one_mhz_stretch(&mem, 0));
+        assert_eq!(3, one_mhz_stretch(&mem, 1));
+    }
+}
+
+#[cfg(test)]
+mod reset_should {
+    use super::*;
+
+    fn ier(emu: &BbcEmulator<Map>) -> u8 {
+        emu.mem().region(0xfe4e..0xfe4f).unwrap_or_else(|e| e.0)[0]
+    }
+
+    #[test]
+    fn only_clear_the_system_via_on_power_on() {
+        let mut mem = Map::new().with_hw_range(0xfc00..0xff00);
+        load_program(&mut mem, CpuVariant::Nmos6502, "
+            ORG &1000
+                LDA #&82 : STA &FE4E
+            .idle
+                JMP idle
+        ");
+
+        let mut emu = BbcEmulator::with_memory(mem);
+        let mut fb = FrameBuffer::new(640, 480);
+        emu.reset(ResetKind::PowerOn).unwrap();
+        for _ in 0..3 {
+            emu.step(&mut fb, |_| false).unwrap();
+        }
+        assert_eq!(0x82, ier(&emu));
+        assert_eq!(0x1005, emu.cpu().program_counter());
+
+        emu.reset(ResetKind::Break).unwrap();
+        assert_eq!(0x82, ier(&emu));
+        assert_eq!(0x1000, emu.cpu().program_counter());
+        assert!(emu.cpu().registers().status.interrupt);
+
+        emu.reset(ResetKind::PowerOn).unwrap();
+        assert_eq!(0x80, ier(&emu));
+        assert_eq!(0x1000, emu.cpu().program_counter());
+    }
+
+    #[test]
+    fn deselect_b_plus_shadow_ram_on_power_on() {
+        let mem = Map::new()
+            .with_model(Model::BPlus)
+            .with_paging_latch()
+            .with_hw_range(0xfc00..0xff00);
+        let mut emu = BbcEmulator::with_machine(Machine::BPlus, mem);
+        emu.mem.write(0x3000, 0x11);
+        emu.mem.write(0xfe30, 0x8f);
+        emu.mem.write(0xfe34, 0x80);
+        assert_eq!(0x00, emu.mem().display_region(0x3000..0x3001).unwrap()[0]);
+
+        emu.reset(ResetKind::PowerOn).unwrap();
+        assert_eq!(0x00, emu.mem().acccon());
+        assert_eq!(0x00, emu.mem().romsel());
+        assert_eq!(0x11, emu.mem().display_region(0x3000..0x3001).unwrap()[0]);
+    }
+}
+
+#[cfg(test)]
+mod interrupt_should {
+    use super::*;
+
+    #[test]
+    fn count_the_cycles_of_a_taken_irq() {
+        let mut mem = Map::new().with_hw_range(0xfc00..0xff00);
+        load_program(&mut mem, CpuVariant::Nmos6502, "
+            ORG &1000
+                LDA #&A0 : STA &FE4E
+                LDA #&20 : STA &FE48
+                LDA #&00 : STA &FE49
+                CLI
+            .idle
+                JMP idle
+            ORG &2000
+            .irq
+                JMP irq
+            ORG &FFFE
+                EQUW irq
+        ");
+
+        let mut emu = BbcEmulator::with_memory(mem);
+        let mut fb = FrameBuffer::new(640, 480);
+        emu.reset(ResetKind::PowerOn).unwrap();
+
+        let mut total = 0;
+        let mut interrupt_cycles = None;
+        for _ in 0..100 {
+            let pc = emu.cpu().program_counter();
+            let cycles = match emu.step(&mut fb, |_| false).unwrap() {
+                StepResult::Progressed(cycles) => cycles,
+                result => panic!("Unexpected {:?}", result),
+            };
+            total += cycles as u64;
+            if pc == 0x1010 && emu.cpu().program_counter() == 0x2000 {
+                interrupt_cycles = Some(cycles);
+            }
+        }
+
+        assert_eq!(Some(7), interrupt_cycles);
+        assert_eq!(total, emu.cycles);
+    }
+}
+
+#[cfg(test)]
+mod master_should {
+    use super::*;
+    use memory::Model;
+
+    fn master() -> BbcEmulator<Map> {
+        let mem = Map::new()
+            .with_model(Model::Master128)
+            .with_paging_latch()
+            .with_hw_range(0xfc00..0xff00);
+        BbcEmulator::with_machine(Machine::Master128, mem)
+    }
+
+    #[test]
+    fn run_65sc12_code_with_the_disc_controller_attached() {
+        let mut emu = master();
+        load_program(&mut emu.mem, CpuVariant::Cmos65sc12, "
+            ORG &1000
+                STZ &70
+                LDA #&25 : STA &FE24
+                LDA &FE28
+        ");
+        emu.mem.write(0x0070, 0xff);
+
+        let mut fb = FrameBuffer::new(640, 480);
+        emu.reset(ResetKind::PowerOn).unwrap();
+        for _ in 0..4 {
+            emu.step(&mut fb, |_| false).unwrap();
+        }
+
+        assert_eq!(0x00, emu.mem.read(0x0070));
+        assert_eq!(0x00, emu.cpu().registers().acc);
+        assert_eq!(0x25, emu.disc_controller().unwrap().borrow().peek(0xfe24));
+    }
+
+    #[test]
+    fn keep_cmos_ram_written_through_the_system_via_over_a_power_on() {
+        let mut emu = master();
+        let writes: &[(u16, u8)] = &[
+            (0xfe42, 0xcf), (0xfe43, 0xff),
+            // Latch address &20 with chip enable high
+            (0xfe4f, 0x20), (0xfe40, 0xc2), (0xfe40, 0x42),
+            // Strobe &42 in
+            (0xfe4f, 0x42), (0xfe40, 0x49), (0xfe40, 0x41), (0xfe40, 0x02),
+        ];
+        for &(addr, val) in writes {
+            emu.mem.write(addr, val);
+        }
+
+        emu.reset(ResetKind::PowerOn).unwrap();
+        assert_eq!(0x42, emu.system_via.borrow().cmos().unwrap().ram()[0x20]);
+
+        let reads: &[(u16, u8)] = &[
+            (0xfe42, 0xcf), (0xfe43, 0xff),
+            (0xfe4f, 0x20), (0xfe40, 0xc2), (0xfe40, 0x42),
+            // Switch to reading, then let the CMOS drive the bus
+            (0xfe40, 0x4a), (0xfe43, 0x00), (0xfe40, 0x49),
+        ];
+        for &(addr, val) in reads {
+            emu.mem.write(addr, val);
+        }
+        assert_eq!(0x42, emu.mem.read(0xfe4f));
+    }
+}
+
+#[cfg(test)]
+mod save_state_should {
+    use super::*;
+
+    /// Counts in a loop while the system VIA's timer 1 interrupts it every
+    /// 10ms, with the CRTC displaying a screen.
+    fn machine() -> BbcEmulator<Map> {
+        let mut mem = Map::new().with_paging_latch().with_hw_range(0xfc00..0xff00);
+        load_program(&mut mem, CpuVariant::Nmos6502, "
+            ORG &1000
+                LDA #&40 : STA &FE4B
+                LDA #&0E : STA &FE44
+                LDA #&27 : STA &FE45
+                LDA #&C0 : STA &FE4E
+                CLI
+            .count
+                INC &70
+                JMP count
+            .irq
+                INC &71
+                LDA #&40 : STA &FE4D
+                RTI
+            ORG &FFFE
+                EQUW irq
+        ");
+
+        let mut emu = BbcEmulator::with_memory(mem);
+        for &(reg, val) in &[(1, 40), (6, 25), (9, 7), (12, 0x06), (13, 0x00)] {
+            emu.mem.write(0xfe00, reg);
+            emu.mem.write(0xfe01, val);
+        }
+        emu
+    }
+
+    fn run(emu: &mut BbcEmulator<Map>, fb: &mut FrameBuffer, instructions: usize) {
+        for _ in 0..instructions {
+            emu.step(fb, |_| false).unwrap();
+        }
+    }
+
+    #[test]
+    fn resume_exactly_where_it_left_off() {
+        let mut fb = FrameBuffer::new(640, 480);
+        let mut original = machine();
+        original.reset(ResetKind::PowerOn).unwrap();
+        run(&mut original, &mut fb, 20_000);
+        let saved = original.save_state();
+        run(&mut original, &mut fb, 10_000);
+
+        let mut restored = machine();
+        restored.load_state(&saved).unwrap();
+        run(&mut restored, &mut fb, 10_000);
+
+        assert!(original.mem.read(0x71) > 0);
+        assert_eq!(original.cpu().registers(), restored.cpu().registers());
+        assert_eq!(original.save_state(), restored.save_state());
+    }
+
+    #[test]
+    fn refuse_states_from_other_versions_and_machines() {
+        let mut saved = machine().save_state();
+        let mem = Map::new()
+            .with_model(Model::Master128)
+            .with_paging_latch()
+            .with_hw_range(0xfc00..0xff00);
+        let mut master = BbcEmulator::with_machine(Machine::Master128, mem);
+        assert_eq!(Err(StateError::Mismatch("Saved from a different machine")),
+                   master.load_state(&saved));
+
+        saved[MAGIC.len()] = VERSION + 1;
+        assert_eq!(Err(StateError::UnsupportedVersion(VERSION + 1)),
+                   machine().load_state(&saved));
+        assert_eq!(Err(StateError::NotASaveState), machine().load_state(&[0x00; 8]));
+    }
+}
+
+#[cfg(test)]
+mod uef_state_should {
+    use super::*;
+
+    #[test]
+    fn carry_a_running_machine_across_to_a_new_one() {
+        let mut mem = Map::new().with_paging_latch().with_hw_range(0xfc00..0xff00);
+        mem.sideways_mut().insert_ram(4);
+        load_program(&mut mem, CpuVariant::Nmos6502, "
+            ORG &1000
+                LDA #&84 : STA &FE4E
+            .count
+                INC &70
+                JMP count
+        ");
+
+        let mut fb = FrameBuffer::new(640, 480);
+        let mut emu = BbcEmulator::with_memory(mem);
+        emu.reset(ResetKind::PowerOn).unwrap();
+        emu.mem.write(0xfe30, 0x04);
+        emu.mem.write(0x8000, 0x99);
+        emu.mem.write(0xfe00, 12);
+        emu.mem.write(0xfe01, 0x06);
+        emu.mem.write(0xfe21, 0x34);
+        for _ in 0..100 {
+            emu.step(&mut fb, |_| false).unwrap();
+        }
+
+        let mem = Map::new().with_paging_latch().with_hw_range(0xfc00..0xff00);
+        let mut other = BbcEmulator::with_memory(mem);
+        other.import_uef_state(&emu.export_uef_state()).unwrap();
+
+        assert_eq!(emu.cpu().registers(), other.cpu().registers());
+        assert_eq!(emu.mem.main_ram(), other.mem.main_ram());
+        assert_eq!(0x99, other.mem.read(0x8000));
+        assert_eq!(0x84, other.mem.read(0xfe4e));
+        assert_eq!(0x06, other.video.borrow().registers()[12]);
+        assert_eq!(0x03, other.video.borrow().palette()[3]);
+    }
+
+    #[test]
+    fn refuse_snapshots_from_other_machines() {
+        let mem = Map::new()
+            .with_model(Model::Master128)
+            .with_paging_latch()
+            .with_hw_range(0xfc00..0xff00);
+        let master = BbcEmulator::with_machine(Machine::Master128, mem);
+        let mem = Map::new().with_paging_latch().with_hw_range(0xfc00..0xff00);
+        let mut model_b = BbcEmulator::with_memory(mem);
+
+        assert_eq!(Err(StateError::Mismatch("Saved from a different machine")),
+                   model_b.import_uef_state(&master.export_uef_state()));
+    }
+}
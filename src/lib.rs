@@ -57,11 +57,26 @@ macro_rules! log_via {
     }};
 }
 
+macro_rules! log_disc {
+    ($fmt:expr, $($params:expr),+) => {{
+        #[cfg(feature="disc-logging")]
+        debug!("Disc: {}", format!($fmt, $($params),*));
+    }};
+    ($fmt:expr) => {{
+        #[cfg(feature="disc-logging")]
+        debug!("Disc: {}", format!($fmt));
+    }};
+}
+
+pub mod assembler;
 pub mod cpu;
+pub mod disc;
+pub mod electron;
 pub mod timer;
 pub mod emulator;
 pub mod debugger;
 pub mod memory;
+pub mod state;
 pub mod video;
 pub mod via;
 
@@ -0,0 +1,16 @@
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BusOperation {
+    Read,
+    Write,
+}
+
+/// A single access made on the CPU's address bus. The 6502 accesses the bus
+/// on every cycle, so `cycle` is also how many cycles into the instruction
+/// the access happened.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct BusCycle {
+    pub cycle: usize,
+    pub address: u16,
+    pub value: u8,
+    pub operation: BusOperation,
+}
@@ -0,0 +1,29 @@
+use std::cell::RefCell;
+use std::ops::Range;
+use std::rc::Rc;
+
+/// A peripheral with memory mapped registers. Once attached to a range of
+/// addresses, the device is told about each CPU access in that range as
+/// it happens.
+pub trait BusDevice {
+    /// Handles a CPU read, including any side effects reading has, such as
+    /// clearing interrupt flags.
+    fn read(&mut self, addr: u16) -> u8;
+
+    fn write(&mut self, addr: u16, val: u8);
+
+    /// Returns what `read` would, without any side effects. This is what
+    /// the debugger and raw memory regions see.
+    fn peek(&self, addr: u16) -> u8;
+}
+
+/// Devices are shared between the memory map, which forwards accesses to
+/// them, and the emulator, which steps them.
+pub type SharedDevice = Rc<RefCell<dyn BusDevice>>;
+
+/// Memory that devices can be attached to.
+pub trait AttachDevice {
+    /// Sends every access to `range` to `device` instead of memory. A
+    /// device can be attached to more than one range.
+    fn attach_device(&mut self, range: Range<usize>, device: SharedDevice);
+}
@@ -1,18 +1,50 @@
 use std::u16;
 use std::ops::{Range, RangeFrom, RangeTo};
-use std::io;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::rc::Rc;
 
 use memory::region::{Region, RegionMut};
+use memory::bus::{BusCycle, BusOperation};
+use memory::sideways::{SidewaysSlots, PAGED_MEMORY_RANGE, SLOT_COUNT, SLOT_SIZE};
+use memory::device::{AttachDevice, SharedDevice};
+use memory::paging::{PagingLatch, PAGING_RANGE};
+use state::{SaveState, StateReader, StateWriter, StateError};
+use memory::model::{
+    Model,
+    MIRROR_RANGE,
+    SHADOW_RANGE,
+    HAZEL_RANGE,
+};
 
 const MEM_SIZE: usize = u16::MAX as usize + 1;
 
 pub struct Map {
     bytes: Vec<u8>,
-    last_hw_write: Option<(u16, u8)>,
-    last_hw_read: Option<u16>,
+    hw_accesses: Vec<BusCycle>,
+    cycle: usize,
     hw_ranges: Vec<Range<usize>>,
-    paged_roms: Vec<Vec<u8>>,
-    current_paged_rom: Option<usize>,
+    one_mhz_ranges: Vec<Range<usize>>,
+    rom_ranges: Vec<Range<usize>>,
+    rom_write_hook: Option<Box<dyn FnMut(u16, u8)>>,
+    sideways: SidewaysSlots,
+    devices: Vec<(Range<usize>, SharedDevice)>,
+    model: Model,
+    paging: Option<Rc<RefCell<PagingLatch>>>,
+    shadow: Vec<u8>,
+    private: Vec<u8>,
+    hazel: Vec<u8>,
+    opcode_address: u16,
+}
+
+/// The memories that can be laid over main RAM.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Bank {
+    Main,
+    Paged,
+    Private,
+    Hazel,
+    Shadow,
 }
 
 #[derive(Debug)]
@@ -33,12 +65,30 @@ fn value_within_range<T>(val: T, range: &Range<T>) -> bool
     (val >= range.start && val <= range.end)
 }
 
+fn range_within<T>(section: &Range<T>, rhs: &Range<T>) -> bool
+    where T: PartialOrd
+{
+    section.start >= rhs.start && section.end <= rhs.end
+}
+
 pub trait MemoryMap {
-    fn last_hw_read(&self) -> Option<u16>;
-    fn last_hw_write(&self) -> Option<(u16, u8)>;
+    /// Every hardware access since the last call to `clear_last_hw_access`,
+    /// in the order they happened.
+    fn hw_accesses(&self) -> &[BusCycle];
     fn write(&mut self, loc: u16, val: u8);
     fn read(&mut self, loc: u16) -> u8;
     fn clear_last_hw_access(&mut self);
+
+    /// Called with the address of each opcode the CPU fetches, as signalled
+    /// by the 6502's SYNC output. Memory that decodes differently depending
+    /// on where code is running, like B+ shadow RAM, uses this.
+    fn sync(&mut self, _pc: u16) { }
+
+    /// Whether `loc` is on the 1MHz bus, so an access to it has to wait
+    /// for the slower clock. Nothing is by default.
+    fn is_one_mhz(&self, _loc: u16) -> bool {
+        false
+    }
 }
 
 pub trait AsMemoryRegionMut : AsMemoryRegion {
@@ -77,17 +127,22 @@ pub trait AsMemoryRegion {
     {
         self.region(0..range.end)
     }
+
+    /// The memory the CRTC displays from `range`. This is the same as
+    /// `region` unless the screen can be in separate memory to the one
+    /// the CPU is using.
+    fn display_region<'a>(&'a self, range: Range<usize>)
+        -> Result<Region<'a>, RawAccessToHardwareError<Region<'a>>>
+    {
+        self.region(range)
+    }
 }
 
 impl<'a, T> MemoryMap for &'a mut T
     where T: MemoryMap
 {    
-    fn last_hw_read(&self) -> Option<u16> {
-        T::last_hw_read(self)
-    }
-
-    fn last_hw_write(&self) -> Option<(u16, u8)> {
-        T::last_hw_write(self)
+    fn hw_accesses(&self) -> &[BusCycle] {
+        T::hw_accesses(self)
     }
 
     /// Panics if `loc` is greater than `u16::MAX + 1`
@@ -106,6 +161,14 @@ impl<'a, T> MemoryMap for &'a mut T
     fn clear_last_hw_access(&mut self) {
         T::clear_last_hw_access(self);
     }
+
+    fn sync(&mut self, pc: u16) {
+        T::sync(self, pc)
+    }
+
+    fn is_one_mhz(&self, loc: u16) -> bool {
+        T::is_one_mhz(self, loc)
+    }
 }
 
 impl<'a, T> AsMemoryRegion for &'a T
@@ -120,6 +183,12 @@ impl<'a, T> AsMemoryRegion for &'a T
     {
         T::region(self, range)
     }
+
+    fn display_region<'b>(&'b self, range: Range<usize>) 
+        -> Result<Region<'b>, RawAccessToHardwareError<Region<'b>>> 
+    {
+        T::display_region(self, range)
+    }
 }
 
 impl<'a, T> AsMemoryRegion for &'a mut T
@@ -134,6 +203,12 @@ impl<'a, T> AsMemoryRegion for &'a mut T
     {
         T::region(self, range)
     }
+
+    fn display_region<'b>(&'b self, range: Range<usize>) 
+        -> Result<Region<'b>, RawAccessToHardwareError<Region<'b>>> 
+    {
+        T::display_region(self, range)
+    }
 }
 
 impl<'a, T> AsMemoryRegionMut for &'a mut T
@@ -146,23 +221,154 @@ impl<'a, T> AsMemoryRegionMut for &'a mut T
     }
 }
 
-const PAGED_ROM_REGISTER: u16 = 0xfe30;
-const PAGED_ROM_MEMORY_RANGE: Range<usize> = 0x8000..0xc000;
-
 impl Map {
     pub fn new() -> Map {
         Map {
             bytes: vec![0; MEM_SIZE],
-            last_hw_write: None,
-            last_hw_read: None,
+            hw_accesses: vec![],
+            cycle: 0,
             hw_ranges: vec![],
-            paged_roms: vec![],
-            current_paged_rom: None,
+            one_mhz_ranges: vec![],
+            rom_ranges: vec![],
+            rom_write_hook: None,
+            sideways: SidewaysSlots::new(),
+            devices: vec![],
+            model: Model::B,
+            paging: None,
+            shadow: vec![],
+            private: vec![],
+            hazel: vec![],
+            opcode_address: 0,
+        }
+    }
+
+    /// Fits the memory for `model`. The default is a Model B.
+    pub fn with_model(mut self, model: Model) -> Map {
+        self.model = model;
+        self.shadow = if model.has_shadow_ram() {
+            vec![0; SHADOW_RANGE.end - SHADOW_RANGE.start]
+        }
+        else {
+            vec![]
+        };
+        self.private = model.private_ram_range()
+                            .map(|r| vec![0; r.end - r.start])
+                            .unwrap_or_else(Vec::new);
+        self.hazel = if model == Model::Master128 {
+            vec![0; HAZEL_RANGE.end - HAZEL_RANGE.start]
+        }
+        else {
+            vec![]
+        };
+        self
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    /// Attaches ROMSEL, and ACCCON on models that have it, at $FE30-$FE3F
+    /// and pages memory in as they're written. The latches are decoded for
+    /// the model given to `with_model`, so call that first. Does nothing on
+    /// the Electron, whose ULA does the paging.
+    pub fn with_paging_latch(mut self) -> Map {
+        if self.model.has_romsel() {
+            let latch = Rc::new(RefCell::new(PagingLatch::new(self.model)));
+            self.attach_device(PAGING_RANGE, latch.clone());
+            self.paging = Some(latch);
         }
+        self
     }
 
+    /// The last value written to ACCCON, or zero without one.
+    pub fn acccon(&self) -> u8 {
+        self.paging.as_ref().map_or(0, |l| l.borrow().acccon())
+    }
+
+    /// The last value written to ROMSEL or, without a paging latch, the
+    /// selected sideways slot.
+    pub fn romsel(&self) -> u8 {
+        match self.paging {
+            Some(ref latch) => latch.borrow().romsel(),
+            None => self.sideways.selected() as u8,
+        }
+    }
+
+    /// Whether bit 7 of ROMSEL has paged in the private RAM.
+    fn is_private_paged(&self) -> bool {
+        self.paging.is_some() && bit_is_set!(self.romsel(), 7)
+    }
+
+    /// Pages in the sideways slot that ROMSEL selects.
+    fn select_paged_rom(&mut self) {
+        if let Some(romsel) = self.paging.as_ref().map(|l| l.borrow().romsel()) {
+            self.sideways.select(romsel);
+        }
+    }
+
+    /// The bottom 32K of main memory, ignoring anything paged over it.
+    pub fn main_ram(&self) -> &[u8] {
+        &self.bytes[..0x8000]
+    }
+
+    pub fn main_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes[..0x8000]
+    }
+
+    /// The shadow screen memory. Empty on models without it.
+    pub fn shadow_ram(&self) -> &[u8] {
+        &self.shadow
+    }
+
+    pub fn shadow_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.shadow
+    }
+
+    /// The RAM paged in by bit 7 of ROMSEL. Empty on models without it.
+    pub fn private_ram(&self) -> &[u8] {
+        &self.private
+    }
+
+    pub fn private_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.private
+    }
+
+    /// The Master's filing system RAM. Empty on other models.
+    pub fn hazel(&self) -> &[u8] {
+        &self.hazel
+    }
+
+    pub fn hazel_mut(&mut self) -> &mut [u8] {
+        &mut self.hazel
+    }
+
+    /// Puts `rom` in the lowest numbered empty sideways slot. Use
+    /// `sideways_mut` to choose the slot.
+    ///
+    /// Panics if every slot is already full.
     pub fn add_paged_rom(&mut self, rom: Vec<u8>) {
-        self.paged_roms.push(rom)
+        let slot = self.sideways.first_free().expect("Every sideways slot is full");
+        self.sideways.insert_rom(slot, &rom);
+    }
+
+    /// Installs a Master MOS 3.20 image, which is the 16K MOS followed by
+    /// the internal ROMs for slots 9 to 15.
+    pub fn load_master_mos(&mut self, image: &[u8]) {
+        let mut banks = image.chunks(SLOT_SIZE);
+        if let Some(mos) = banks.next() {
+            self.bytes[0xc000..0xc000 + mos.len()].copy_from_slice(mos);
+        }
+        for (slot, rom) in (9..SLOT_COUNT).zip(banks) {
+            self.sideways.insert_rom(slot, rom);
+        }
+    }
+
+    pub fn sideways(&self) -> &SidewaysSlots {
+        &self.sideways
+    }
+
+    pub fn sideways_mut(&mut self) -> &mut SidewaysSlots {
+        &mut self.sideways
     }
 
     pub fn with_hw_range(mut self, range: Range<usize>) -> Map
@@ -178,50 +384,237 @@ impl Map {
         self
     }
 
-    fn switch_paged_rom_to(&mut self, num: usize) {
-        if let None =  self.paged_roms.get(num) {
-            return;
+    /// Puts `range` on the 1MHz bus, so the CPU is slowed down to reach it.
+    /// See `MemoryMap::is_one_mhz`.
+    pub fn with_one_mhz_range(mut self, range: Range<usize>) -> Map {
+        self.one_mhz_ranges.push(range);
+        self
+    }
+
+    pub fn with_one_mhz_ranges<R>(mut self, ranges: R) -> Map
+        where R: IntoIterator<Item=Range<usize>>
+    {
+        self.one_mhz_ranges.extend(ranges);
+        self
+    }
+
+    /// Makes `range` read-only to the CPU, so writes to it are ignored. ROM
+    /// images can still be installed through `region_mut`.
+    pub fn with_rom_range(mut self, range: Range<usize>) -> Map {
+        self.rom_ranges.push(range);
+        self
+    }
+
+    pub fn with_rom_ranges<R>(mut self, ranges: R) -> Map
+        where R: IntoIterator<Item=Range<usize>>
+    {
+        self.rom_ranges.extend(ranges);
+        self
+    }
+
+    /// Calls `hook` with the address and value of every write that's
+    /// ignored because it hit ROM, including ROM in a sideways slot.
+    pub fn on_rom_write<F>(&mut self, hook: F)
+        where F: FnMut(u16, u8) + 'static
+    {
+        self.rom_write_hook = Some(Box::new(hook));
+    }
+
+    fn is_write_protected(&self, loc: usize) -> bool {
+        match self.overlay_for(&(loc..loc + 1), false) {
+            Some((Bank::Paged, _)) => self.sideways.is_rom_paged(),
+            Some(_) => false,
+            None => self.rom_ranges.iter().any(|r| loc >= r.start && loc < r.end),
         }
+    }
 
-        self.current_paged_rom.map(|n|{
-            io::copy(
-                &mut &self.bytes[PAGED_ROM_MEMORY_RANGE], 
-                &mut &mut self.paged_roms[n][..]).unwrap();
-        });
+    /// Counts off a bus cycle, remembering it if `loc` is in one of the
+    /// hardware ranges.
+    fn record_access(&mut self, loc: u16, val: u8, operation: BusOperation) {
+        if self.hw_ranges.iter().any(|r| value_within_range(loc as usize, r)) {
+            self.hw_accesses.push(BusCycle {
+                cycle: self.cycle,
+                address: loc,
+                value: val,
+                operation: operation,
+            });
+        }
+        self.cycle += 1;
+    }
 
-        io::copy(
-            &mut &self.paged_roms[num][..], 
-            &mut &mut self.bytes[PAGED_ROM_MEMORY_RANGE]).unwrap();
+    fn device_at(&self, loc: usize) -> Option<SharedDevice> {
+        self.devices.iter()
+                    .find(|&&(ref r, _)| loc >= r.start && loc < r.end)
+                    .map(|&(_, ref d)| d.clone())
+    }
+
+    /// The memories laid over main RAM, highest priority first, as the CPU
+    /// sees them or, given `display`, the CRTC. Those that aren't paged in
+    /// have an empty window.
+    fn overlays(&self, display: bool) -> [(Bank, Range<usize>); 5] {
+        fn window(paged: bool, range: Range<usize>) -> Range<usize> {
+            if paged { range } else { 0..0 }
+        }
+
+        let private = match self.model.private_ram_range() {
+            Some(range) => window(self.is_private_paged(), range),
+            None => 0..0,
+        };
+        let acccon = self.acccon();
+        let shadowed = self.model.is_shadowed(acccon, display, self.opcode_address);
+
+        [
+            (Bank::Private, private),
+            (Bank::Paged, window(self.sideways.paged().is_some(), PAGED_MEMORY_RANGE)),
+            (Bank::Hazel, window(self.model.is_hazel_paged(acccon), HAZEL_RANGE)),
+            (Bank::Shadow, window(shadowed, SHADOW_RANGE)),
+            // Model A's mirror is main memory, offset by the mirror's start
+            (Bank::Main, window(self.model == Model::A, MIRROR_RANGE)),
+        ]
+    }
+
+    /// The highest priority overlay that `range` touches, with its window.
+    fn overlay_for(&self, range: &Range<usize>, display: bool) -> Option<(Bank, Range<usize>)> {
+        self.overlays(display)
+            .iter()
+            .find(|&&(_, ref w)| w.start < w.end && ranges_overlap(range.clone(), w))
+            .cloned()
+    }
+
+    fn bank(&self, bank: Bank) -> &[u8] {
+        match bank {
+            Bank::Main => &self.bytes,
+            Bank::Paged => self.sideways.paged().unwrap_or(&[]),
+            Bank::Private => &self.private,
+            Bank::Hazel => &self.hazel,
+            Bank::Shadow => &self.shadow,
+        }
+    }
+
+    fn bank_mut(&mut self, bank: Bank) -> &mut [u8] {
+        match bank {
+            Bank::Main => &mut self.bytes,
+            Bank::Paged => self.sideways.paged_mut().unwrap_or(&mut []),
+            Bank::Private => &mut self.private,
+            Bank::Hazel => &mut self.hazel,
+            Bank::Shadow => &mut self.shadow,
+        }
+    }
+
+    fn peek_as(&self, loc: usize, display: bool) -> u8 {
+        if let Some(device) = self.device_at(loc) {
+            return device.borrow().peek(loc as u16);
+        }
+
+        match self.overlay_for(&(loc..loc + 1), display) {
+            Some((bank, window)) => self.bank(bank)[loc - window.start],
+            None => self.bytes[loc],
+        }
+    }
+
+    fn peek(&self, loc: usize) -> u8 {
+        self.peek_as(loc, false)
+    }
+
+    /// `range` as one slice of whichever memory it's in, or `None` if
+    /// it's split between several.
+    fn slice_as(&self, range: &Range<usize>, display: bool) -> Option<&[u8]> {
+        match self.overlay_for(range, display) {
+            Some((bank, ref window)) if range_within(range, window) =>
+                Some(&self.bank(bank)[range.start - window.start..range.end - window.start]),
+            Some(_) => None,
+            None => Some(&self.bytes[range.clone()]),
+        }
+    }
+
+    fn region_as<'a>(&'a self, range: Range<usize>, display: bool)
+        -> Result<Region<'a>, RawAccessToHardwareError<Region<'a>>> 
+    {
+        let has_device = self.devices.iter()
+                                     .any(|&(ref r, _)| ranges_overlap(r.clone(), &range));
+        let region = match self.slice_as(&range, display) {
+            Some(slice) if !has_device => Region(Cow::Borrowed(slice)),
+            _ => Region(Cow::Owned(range.clone().map(|loc| self.peek_as(loc, display)).collect())),
+        };
+
+        if self.hw_ranges.iter().any(|r| ranges_overlap(r.clone(), &range)) {
+            Err(RawAccessToHardwareError(region))
+        }
+        else {
+            Ok(region)
+        }
     }
 }
 
-impl MemoryMap for Map {
-    fn last_hw_read(&self) -> Option<u16> {
-        self.last_hw_read
+impl AttachDevice for Map {
+    /// Also marks `range` as hardware.
+    fn attach_device(&mut self, range: Range<usize>, device: SharedDevice) {
+        self.hw_ranges.push(range.clone());
+        self.devices.push((range, device));
     }
+}
 
-    fn last_hw_write(&self) -> Option<(u16, u8)> {
-        self.last_hw_write
+/// All of main memory, any extra RAM the model has and what's paged in,
+/// including the paging latch. Other attached devices save their own state.
+impl SaveState for Map {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.bytes);
+        self.sideways.save(state);
+        state.write_bytes(&self.shadow);
+        state.write_bytes(&self.private);
+        state.write_bool(self.is_private_paged());
+        state.write_bytes(&self.hazel);
+        state.write_u8(self.acccon());
+        state.write_u16(self.opcode_address);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes_into(&mut self.bytes)?;
+        self.sideways.load(state)?;
+        state.read_bytes_into(&mut self.shadow)?;
+        state.read_bytes_into(&mut self.private)?;
+        let private_paged = state.read_bool()?;
+        state.read_bytes_into(&mut self.hazel)?;
+        let acccon = state.read_u8()?;
+        if let Some(ref latch) = self.paging {
+            let romsel = self.sideways.selected() as u8 | if private_paged { 0x80 } else { 0x00 };
+            latch.borrow_mut().restore(romsel, acccon);
+        }
+        self.opcode_address = state.read_u16()?;
+        self.hw_accesses.clear();
+        self.cycle = 0;
+        Ok(())
+    }
+}
+
+impl MemoryMap for Map {
+    fn hw_accesses(&self) -> &[BusCycle] {
+        &self.hw_accesses
     }
 
     /// Panics if `loc` is greater than `u16::MAX + 1`
     fn write(&mut self, loc: u16, val: u8) {
-        if loc == PAGED_ROM_REGISTER {
-            self.switch_paged_rom_to(val as usize);
+        if let Some(device) = self.device_at(loc as usize) {
+            log_mem!("HW Write {:02x} -> {:04x}", val, loc);
+            device.borrow_mut().write(loc, val);
+            self.select_paged_rom();
+        }
+        else if self.is_write_protected(loc as usize) {
+            log_mem!("Ignored write {:02x} -> {:04x} in ROM", val, loc);
+            if let Some(ref mut hook) = self.rom_write_hook {
+                hook(loc, val);
+            }
         }
-        self.bytes[loc as usize] = val;
-        self.last_hw_write = 
-            self.hw_ranges.iter()
-                          .find(|r| value_within_range(loc as usize, r))
-                          .map(|_| {
-                              log_mem!("HW Write {:02x} -> {:04x}", val, loc);
-                              (loc, val)
-                          })
-                          .or_else(|| {
-                              log_mem!("RAM Write {:02x} -> {:04x}", val, loc);
-                              None
-                          });
-                               
+        else {
+            log_mem!("Write {:02x} -> {:04x}", val, loc);
+            let loc = loc as usize;
+            match self.overlay_for(&(loc..loc + 1), false) {
+                Some((Bank::Paged, window)) => self.sideways.write(loc - window.start, val),
+                Some((bank, window)) => self.bank_mut(bank)[loc - window.start] = val,
+                None => self.bytes[loc] = val,
+            }
+        }
+        self.record_access(loc, val, BusOperation::Write);
     }
 
     /// Panics if `loc` is greater than `u16::MAX + 1`.
@@ -229,26 +622,28 @@ impl MemoryMap for Map {
     /// This function requires `&mut self` because reading can potentially
     /// have side effects, such as clearing hardware registers, etc.
     fn read(&mut self, loc: u16) -> u8 {
-        let val = self.bytes[loc as usize];
-        self.last_hw_read = 
-            self.hw_ranges.iter()
-                          .find(|r| value_within_range(loc as usize, r))
-                          .map(|_| {
-                              log_mem!("HW Read {:02x} <- {:04x}", val, loc);
-                              loc
-                          })
-                          .or_else(|| {
-                              log_mem!("RAM Read {:02x} <- {:04x}", val, loc);
-                              None
-                          });
+        let val = match self.device_at(loc as usize) {
+            Some(device) => device.borrow_mut().read(loc),
+            None => self.peek(loc as usize),
+        };
+        log_mem!("Read {:02x} <- {:04x}", val, loc);
+        self.record_access(loc, val, BusOperation::Read);
         val
     }
 
     fn clear_last_hw_access(&mut self) {
-        self.last_hw_read = None;
-        self.last_hw_write = None;
+        self.hw_accesses.clear();
+        self.cycle = 0;
+    }
+
+    fn sync(&mut self, pc: u16) {
+        self.opcode_address = pc;
     }
 
+    fn is_one_mhz(&self, loc: u16) -> bool {
+        let loc = loc as usize;
+        self.one_mhz_ranges.iter().any(|r| loc >= r.start && loc < r.end)
+    }
 }
 
 impl AsMemoryRegion for Map {
@@ -266,12 +661,15 @@ impl AsMemoryRegion for Map {
     fn region<'a>(&'a self, range: Range<usize>) 
         -> Result<Region<'a>, RawAccessToHardwareError<Region<'a>>> 
     {
-        if self.hw_ranges.iter().any(|r| ranges_overlap(r.clone(), &range)) {
-            Err(RawAccessToHardwareError(Region(&self.bytes[range])))
-        }
-        else {
-            Ok(Region(&self.bytes[range]))
-        }
+        self.region_as(range, false)
+    }
+
+    /// Shows B+ shadow RAM whenever it's selected, regardless of the code
+    /// that's running.
+    fn display_region<'a>(&'a self, range: Range<usize>) 
+        -> Result<Region<'a>, RawAccessToHardwareError<Region<'a>>> 
+    {
+        self.region_as(range, true)
     }
 }
 
@@ -286,11 +684,28 @@ impl AsMemoryRegionMut for Map {
     fn region_mut<'a>(&'a mut self, range: Range<usize>) 
         -> Result<RegionMut<'a>, RawAccessToHardwareError<RegionMut<'a>>>
     {
-        if self.hw_ranges.iter().any(|r| ranges_overlap(r.clone(), &range)) {
-            Err(RawAccessToHardwareError(RegionMut(&mut self.bytes[range])))
+        // A region straddling the edge of a paged in slot, shadow RAM or
+        // another overlay can't be borrowed as one slice, so it gets the
+        // memory underneath instead, which the CPU can't see.
+        let overlay = self.overlay_for(&range, false);
+        let hidden = match overlay {
+            Some((_, ref window)) => !range_within(&range, window),
+            None => false,
+        };
+        let is_hw = hidden ||
+                    self.hw_ranges.iter().any(|r| ranges_overlap(r.clone(), &range));
+
+        let region = match overlay {
+            Some((bank, window)) if !hidden =>
+                RegionMut(&mut self.bank_mut(bank)[range.start - window.start..range.end - window.start]),
+            _ => RegionMut(&mut self.bytes[range]),
+        };
+
+        if is_hw {
+            Err(RawAccessToHardwareError(region))
         }
         else {
-            Ok(RegionMut(&mut self.bytes[range]))
+            Ok(region)
         }
 
     }
@@ -300,6 +715,8 @@ impl AsMemoryRegionMut for Map {
 #[cfg(test)]
 mod map_should {
     use super::*;
+    use memory::device::BusDevice;
+    use memory::model::Model;
 
     #[test]
     fn return_err_when_accessing_hw_region() {
@@ -339,28 +756,231 @@ mod map_should {
         use_slice(&map.region_to(..0x0100).unwrap());
     }
 
+    struct Latch {
+        val: u8,
+        reads: usize,
+    }
+
+    impl BusDevice for Latch {
+        fn read(&mut self, _: u16) -> u8 {
+            self.reads += 1;
+            self.val
+        }
+
+        fn write(&mut self, addr: u16, val: u8) {
+            self.val = val ^ (addr as u8);
+        }
+
+        fn peek(&self, _: u16) -> u8 {
+            self.val
+        }
+    }
+
+    #[test]
+    fn send_accesses_to_attached_devices() {
+        use std::rc::Rc;
+        use std::cell::RefCell;
+
+        let latch = Rc::new(RefCell::new(Latch { val: 0, reads: 0 }));
+        let mut map = Map::new();
+        map.attach_device(0xfe40..0xfe60, latch.clone());
+
+        map.write(0xfe41, 0x10);
+        assert_eq!(0x51, latch.borrow().val);
+        assert_eq!(0x51, map.region(0xfe41..0xfe42).unwrap_or_else(|e| e.0)[0]);
+        assert_eq!(0, latch.borrow().reads);
+        assert_eq!(0x51, map.read(0xfe5f));
+        assert_eq!(1, latch.borrow().reads);
+
+        map.write(0xfe60, 0x22);
+        assert_eq!(0x22, map.read(0xfe60));
+        assert_eq!(0x51, latch.borrow().val);
+    }
+
     #[test]
-    fn should_report_last_hw_read() {
+    fn record_every_hw_access_with_its_cycle() {
         let mut map = Map::new()
             .with_hw_range(0xfe00 as usize..0xff00 as usize);
 
-        let _ = map.read(0xfe40);
-        assert_eq!(Some(0xfe40), map.last_hw_read());
-
         let _ = map.read(0x0100);
-        assert_eq!(None, map.last_hw_read());
+        let _ = map.read(0xfe4d);
+        map.write(0xfe4d, 0x7f);
+        map.write(0x0001, 0xde);
+        map.write(0xfe4d, 0x80);
+
+        assert_eq!(&[
+            BusCycle { cycle: 1, address: 0xfe4d, value: 0x00, operation: BusOperation::Read },
+            BusCycle { cycle: 2, address: 0xfe4d, value: 0x7f, operation: BusOperation::Write },
+            BusCycle { cycle: 4, address: 0xfe4d, value: 0x80, operation: BusOperation::Write },
+        ], map.hw_accesses());
+
+        map.clear_last_hw_access();
+        assert!(map.hw_accesses().is_empty());
     }
 
     #[test]
-    fn should_report_last_hw_write() {
-        let mut map = Map::new()
-            .with_hw_ranges(vec![0xfe00 as usize..0xff00 as usize]);
+    fn page_in_the_slot_selected_by_the_low_four_bits_of_romsel() {
+        let mut map = Map::new().with_paging_latch().with_hw_range(0xfe00..0xff00);
+        map.sideways_mut().insert_rom(1, &[0x11; 0x4000]);
+        map.sideways_mut().insert_rom(15, &[0xff; 0x4000]);
 
-        map.write(0xfe40, 0xde);
-        assert_eq!(Some((0xfe40, 0xde)), map.last_hw_write());
+        map.write(0xfe30, 0x01);
+        assert_eq!(0x11, map.read(0x8000));
+        map.write(0xfe30, 0xff);
+        assert_eq!(0xff, map.read(0xbfff));
+        assert_eq!(15, map.sideways().selected());
+        map.write(0xfe30, 0x21);
+        assert_eq!(0x11, map.read(0x9000));
+    }
 
-        map.write(0x0001, 0xde);
-        assert_eq!(None, map.last_hw_write());
+    #[test]
+    fn ignore_writes_to_rom_slots_but_keep_writes_to_sideways_ram() {
+        let mut map = Map::new().with_paging_latch();
+        map.sideways_mut().insert_rom(0, &[0xaa; 0x4000]);
+        map.sideways_mut().insert_ram(4);
+
+        map.write(0xfe30, 0x00);
+        map.write(0x8000, 0x12);
+        assert_eq!(0xaa, map.read(0x8000));
+
+        map.write(0xfe30, 0x04);
+        map.write(0x8000, 0x34);
+        map.write(0xfe30, 0x00);
+        map.write(0xfe30, 0x04);
+        assert_eq!(0x34, map.read(0x8000));
     }
-}
 
+    #[test]
+    fn leave_memory_visible_in_empty_slots() {
+        let mut map = Map::new().with_paging_latch();
+        map.write(0x8000, 0x56);
+        map.write(0xfe30, 0x07);
+        assert_eq!(0x56, map.read(0x8000));
+    }
+
+    #[test]
+    fn leave_fe30_as_memory_without_a_paging_latch() {
+        let mut map = Map::new();
+        map.sideways_mut().insert_rom(1, &[0xaa]);
+        map.write(0xfe30, 0x01);
+        assert_eq!(0x01, map.read(0xfe30));
+        assert_eq!(0x00, map.read(0x8000));
+    }
+
+    #[test]
+    fn mirror_small_roms_across_the_slot() {
+        let mut map = Map::new();
+        map.sideways_mut().insert_rom(0, &[0x01, 0x02]);
+        assert_eq!(0x01, map.read(0xa000));
+        assert_eq!(0x02, map.read(0xbfff));
+    }
+
+    #[test]
+    fn show_the_paged_slot_in_regions() {
+        let mut map = Map::new().with_paging_latch();
+        map.sideways_mut().insert_rom(2, &[0x22; 0x4000]);
+        map.write(0xfe30, 0x02);
+        map.write(0x7fff, 0x77);
+
+        assert_eq!(&[0x22, 0x22], &map.region(0x8000..0x8002).unwrap()[..]);
+        assert_eq!(&[0x77, 0x22], &map.region(0x7fff..0x8001).unwrap()[..]);
+        assert_eq!(0x22, map.region_mut(0x8000..0x8001).unwrap()[0]);
+        assert!(map.region_mut(0x7fff..0x8001).is_err());
+    }
+
+    #[test]
+    fn ignore_writes_to_rom_ranges_and_report_them() {
+        use std::rc::Rc;
+        use std::cell::RefCell;
+
+        let mut map = Map::new().with_rom_range(0xc000..0xfc00);
+        let writes = Rc::new(RefCell::new(vec![]));
+        let hook_writes = writes.clone();
+        map.on_rom_write(move |loc, val| hook_writes.borrow_mut().push((loc, val)));
+        map.region_mut(0xc000..0xc001).unwrap()[0] = 0x4c;
+        map.sideways_mut().insert_rom(0, &[0xaa]);
+
+        map.write(0xc000, 0x00);
+        map.write(0xfbff, 0x01);
+        map.write(0xfc00, 0x02);
+        map.write(0x8000, 0x03);
+
+        assert_eq!(0x4c, map.read(0xc000));
+        assert_eq!(0x00, map.read(0xfbff));
+        assert_eq!(0x02, map.read(0xfc00));
+        assert_eq!(0xaa, map.read(0x8000));
+        assert_eq!(vec![(0xc000, 0x00), (0xfbff, 0x01), (0x8000, 0x03)], *writes.borrow());
+    }
+
+    #[test]
+    fn mirror_model_a_ram_across_the_bottom_32k() {
+        let mut map = Map::new().with_model(Model::A);
+        map.write(0x0100, 0x11);
+        map.write(0x7fff, 0x22);
+
+        assert_eq!(0x11, map.read(0x4100));
+        assert_eq!(0x22, map.read(0x3fff));
+        assert_eq!(&[0x11], &map.region(0x4100..0x4101).unwrap()[..]);
+        assert_eq!(&[0x22, 0x00], &map.region(0x3fff..0x4001).unwrap()[..]);
+        assert!(map.region_mut(0x3fff..0x4001).is_err());
+    }
+
+    #[test]
+    fn give_the_crtc_and_vdu_drivers_shadow_ram_on_the_b_plus() {
+        let mut map = Map::new().with_model(Model::BPlus).with_paging_latch();
+        map.write(0x3000, 0x11);
+        map.write(0xfe34, 0x80);
+
+        map.sync(0x8000);
+        assert_eq!(0x11, map.read(0x3000));
+
+        map.sync(0xc123);
+        assert_eq!(0x00, map.read(0x3000));
+        map.write(0x3000, 0x22);
+        map.write(0x2fff, 0x33);
+        assert_eq!(0x22, map.display_region(0x3000..0x3001).unwrap()[0]);
+        assert_eq!(&[0x33, 0x22], &map.display_region(0x2fff..0x3001).unwrap()[..]);
+
+        map.sync(0xe000);
+        assert_eq!(0x11, map.read(0x3000));
+        assert_eq!(0x33, map.read(0x2fff));
+        assert_eq!(0x11, map.region(0x3000..0x3001).unwrap()[0]);
+
+        map.write(0xfe34, 0x00);
+        assert_eq!(0x11, map.display_region(0x3000..0x3001).unwrap()[0]);
+    }
+
+    #[test]
+    fn ignore_acccon_on_a_model_b() {
+        let mut map = Map::new().with_paging_latch();
+        map.write(0xfe34, 0x80);
+        map.sync(0xc000);
+        map.write(0x3000, 0x44);
+        assert_eq!(0x44, map.display_region(0x3000..0x3001).unwrap()[0]);
+    }
+
+    #[test]
+    fn page_andy_and_hazel_in_on_the_master() {
+        let mut map = Map::new()
+            .with_model(Model::Master128)
+            .with_paging_latch()
+            .with_rom_range(0xc000..0xfc00);
+        map.sideways_mut().insert_rom(15, &[0xbb]);
+        map.region_mut(0xc000..0xc001).unwrap()[0] = 0x4c;
+
+        map.write(0xfe30, 0x8f);
+        map.write(0x8000, 0x01);
+        assert_eq!(0x01, map.read(0x8000));
+        assert_eq!(0xbb, map.read(0x9000));
+        map.write(0xfe30, 0x0f);
+        assert_eq!(0xbb, map.read(0x8000));
+
+        map.write(0xc000, 0x02);
+        assert_eq!(0x4c, map.read(0xc000));
+        map.write(0xfe34, 0x08);
+        map.write(0xc000, 0x02);
+        assert_eq!(0x02, map.read(0xc000));
+        map.write(0xfe34, 0x00);
+        assert_eq!(0x4c, map.read(0xc000));
+    }
+}
@@ -1,5 +1,10 @@
+pub mod bus;
+pub mod device;
 pub mod map;
+pub mod model;
+pub mod paging;
 pub mod region;
+pub mod sideways;
 pub use self::map::{
     Map, 
     MemoryMap, 
@@ -8,4 +13,9 @@ pub use self::map::{
     RawAccessToHardwareError
 };
 pub use self::region::{Region, RegionMut};
+pub use self::bus::{BusCycle, BusOperation};
+pub use self::sideways::{SidewaysSlots, SlotKind};
+pub use self::device::{BusDevice, SharedDevice, AttachDevice};
+pub use self::model::Model;
+pub use self::paging::PagingLatch;
 
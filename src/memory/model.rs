@@ -0,0 +1,108 @@
+use std::ops::Range;
+
+/// The memory fitted to each model of BBC Micro.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Model {
+    /// 16K of RAM. Address line A14 isn't decoded, so it appears again at
+    /// $4000-$7FFF.
+    A,
+    /// 32K of RAM.
+    B,
+    /// 32K of RAM, 20K of shadow screen memory that can replace
+    /// $3000-$7FFF, and 12K that bit 7 of ROMSEL pages in at $8000-$AFFF.
+    BPlus,
+    /// 32K of RAM, 20K of shadow screen memory, 4K of ANDY that bit 7 of
+    /// ROMSEL pages in at $8000-$8FFF, and 8K of HAZEL that can replace the
+    /// MOS at $C000-$DFFF. The sideways RAM banks are fitted as slots.
+    Master128,
+    /// 32K of RAM. The ULA does the paging, so there's no ROMSEL latch.
+    Electron,
+}
+
+/// Where Model A's RAM appears a second time.
+pub const MIRROR_RANGE: Range<usize> = 0x4000..0x8000;
+
+/// The part of main memory that shadow RAM replaces.
+pub const SHADOW_RANGE: Range<usize> = 0x3000..0x8000;
+
+/// Code in the MOS VDU drivers, which can be given access to shadow RAM.
+pub const VDU_DRIVER_RANGE: Range<usize> = 0xc000..0xe000;
+
+/// Where HAZEL, the Master's filing system RAM, replaces the MOS.
+pub const HAZEL_RANGE: Range<usize> = 0xc000..0xe000;
+
+/// The parts of FRED, JIM and SHEILA that sit on the BBC's 1MHz bus. The
+/// video ULA, paged ROM select and Tube registers run at the full 2MHz.
+pub const ONE_MHZ_RANGES: &'static [Range<usize>] = &[
+    0xfc00..0xfe20,
+    0xfe40..0xfee0,
+];
+
+/// The access control register on the B+ and Master.
+pub const ACCCON_REGISTER: u16 = 0xfe34;
+
+/// B+ ACCCON: shadow RAM for the CRTC and the VDU drivers.
+const BPLUS_SHADOW: u8 = 7;
+
+/// Master ACCCON: HAZEL in place of the MOS.
+const MASTER_HAZEL: u8 = 3;
+/// Master ACCCON: shadow RAM for every CPU access.
+const MASTER_SHADOW_ALL: u8 = 2;
+/// Master ACCCON: shadow RAM for the VDU drivers.
+const MASTER_SHADOW_VDU: u8 = 1;
+/// Master ACCCON: shadow RAM for the CRTC.
+const MASTER_SHADOW_DISPLAY: u8 = 0;
+
+impl Model {
+    /// Whether writes to $FE30 select a sideways slot.
+    pub fn has_romsel(self) -> bool {
+        self != Model::Electron
+    }
+
+    /// Whether the model has shadow RAM, and the ACCCON latch that controls
+    /// it.
+    pub fn has_shadow_ram(self) -> bool {
+        self == Model::BPlus || self == Model::Master128
+    }
+
+    /// The hardware that the CPU has to slow down to 1MHz to reach. The
+    /// Electron's ULA does its own clock stretching, so it has none.
+    pub fn one_mhz_ranges(self) -> &'static [Range<usize>] {
+        match self {
+            Model::Electron => &[],
+            _ => ONE_MHZ_RANGES,
+        }
+    }
+
+    /// Where the RAM paged in by bit 7 of ROMSEL appears, on models that
+    /// have any.
+    pub fn private_ram_range(self) -> Option<Range<usize>> {
+        match self {
+            Model::BPlus => Some(0x8000..0xb000),
+            Model::Master128 => Some(0x8000..0x9000),
+            _ => None,
+        }
+    }
+
+    /// Whether `acccon` gives shadow RAM to the CRTC, given `display`, or
+    /// otherwise to the CPU while it runs code at `pc`.
+    pub fn is_shadowed(self, acccon: u8, display: bool, pc: u16) -> bool {
+        let in_vdu_drivers = pc as usize >= VDU_DRIVER_RANGE.start &&
+                             (pc as usize) < VDU_DRIVER_RANGE.end;
+        match self {
+            Model::BPlus =>
+                bit_is_set!(acccon, BPLUS_SHADOW) && (display || in_vdu_drivers),
+            Model::Master128 if display =>
+                bit_is_set!(acccon, MASTER_SHADOW_DISPLAY),
+            Model::Master128 =>
+                bit_is_set!(acccon, MASTER_SHADOW_ALL) ||
+                    (bit_is_set!(acccon, MASTER_SHADOW_VDU) && in_vdu_drivers),
+            _ => false,
+        }
+    }
+
+    /// Whether `acccon` pages HAZEL in over the MOS.
+    pub fn is_hazel_paged(self, acccon: u8) -> bool {
+        self == Model::Master128 && bit_is_set!(acccon, MASTER_HAZEL)
+    }
+}
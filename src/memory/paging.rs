@@ -0,0 +1,123 @@
+use std::ops::Range;
+
+use memory::device::BusDevice;
+use memory::model::Model;
+
+/// Where the paging latches are decoded.
+pub const PAGING_RANGE: Range<usize> = 0xfe30..0xfe40;
+
+/// The latches that control what's paged into the BBC's memory map: ROMSEL,
+/// and ACCCON on the models with shadow RAM. Only the bottom address lines
+/// are decoded, so on the Model A and B ROMSEL fills $FE30-$FE3F. The B+
+/// and Master put it at $FE30-$FE33 with ACCCON at $FE34-$FE37, leaving
+/// the rest of the range to other hardware.
+///
+/// `Map::with_paging_latch` attaches one and pages memory in and out as
+/// the latches change.
+pub struct PagingLatch {
+    model: Model,
+    romsel: u8,
+    acccon: u8,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Latch {
+    Romsel,
+    Acccon,
+}
+
+impl PagingLatch {
+    pub fn new(model: Model) -> PagingLatch {
+        PagingLatch {
+            model: model,
+            romsel: 0,
+            acccon: 0,
+        }
+    }
+
+    /// The last value written to ROMSEL. The low four bits select a
+    /// sideways slot, and bit 7 pages in the B+ and Master's private RAM.
+    pub fn romsel(&self) -> u8 {
+        self.romsel
+    }
+
+    /// The last value written to ACCCON, or zero on models without it.
+    pub fn acccon(&self) -> u8 {
+        self.acccon
+    }
+
+    /// Sets both latches at once, as when restoring a snapshot. ACCCON is
+    /// left alone on models without it.
+    pub fn restore(&mut self, romsel: u8, acccon: u8) {
+        self.romsel = romsel;
+        if self.model.has_shadow_ram() {
+            self.acccon = acccon;
+        }
+    }
+
+    fn decode(&self, addr: u16) -> Option<Latch> {
+        match (addr & 0x0f) >> 2 {
+            0 => Some(Latch::Romsel),
+            1 if self.model.has_shadow_ram() => Some(Latch::Acccon),
+            _ if self.model.has_shadow_ram() => None,
+            _ => Some(Latch::Romsel),
+        }
+    }
+}
+
+impl BusDevice for PagingLatch {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.peek(addr)
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        match self.decode(addr) {
+            Some(Latch::Romsel) => self.romsel = val,
+            Some(Latch::Acccon) => self.acccon = val,
+            None => {},
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        match self.decode(addr) {
+            Some(Latch::Romsel) => self.romsel,
+            Some(Latch::Acccon) => self.acccon,
+            None => 0xff,
+        }
+    }
+}
+
+#[cfg(test)]
+mod paging_latch_should {
+    use super::*;
+
+    #[test]
+    fn repeat_romsel_across_the_range_without_acccon() {
+        let mut latch = PagingLatch::new(Model::B);
+        latch.write(0xfe34, 0x8c);
+        assert_eq!(0x8c, latch.romsel());
+        assert_eq!(0x00, latch.acccon());
+        latch.write(0xfe3f, 0x05);
+        assert_eq!(0x05, latch.peek(0xfe30));
+    }
+
+    #[test]
+    fn put_acccon_at_fe34_on_models_with_shadow_ram() {
+        for &model in &[Model::BPlus, Model::Master128] {
+            let mut latch = PagingLatch::new(model);
+            latch.write(0xfe33, 0x0f);
+            latch.write(0xfe37, 0x80);
+            latch.write(0xfe38, 0x55);
+            assert_eq!(0x0f, latch.romsel());
+            assert_eq!(0x80, latch.acccon());
+            assert_eq!(0x80, latch.read(0xfe34));
+        }
+    }
+
+    #[test]
+    fn only_restore_acccon_on_models_with_it() {
+        let mut latch = PagingLatch::new(Model::B);
+        latch.restore(0x03, 0x80);
+        assert_eq!((0x03, 0x00), (latch.romsel(), latch.acccon()));
+    }
+}
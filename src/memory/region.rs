@@ -1,9 +1,10 @@
+use std::borrow::Cow;
 use std::ops::{Deref, DerefMut};
 use std::convert::{AsRef, AsMut};
 use std::slice::{Iter, IterMut};
 
 #[derive(Debug)]
-pub struct Region<'a>(pub(crate) &'a [u8]);
+pub struct Region<'a>(pub(crate) Cow<'a, [u8]>);
 #[derive(Debug)]
 pub struct RegionMut<'a>(pub(crate) &'a mut [u8]);
 
@@ -26,7 +27,7 @@ impl<'a> RegionMut<'a> {
 impl<'a> Deref for Region<'a> {
     type Target = [u8];
     fn deref(&self) -> &Self::Target {
-        self.0
+        &self.0
     }
 }
 
@@ -45,7 +46,7 @@ impl<'a> DerefMut for RegionMut<'a> {
 
 impl<'a> AsRef<[u8]> for Region<'a> {
     fn as_ref(&self) -> &[u8] {
-        self.0
+        &self.0
     }
 }
 
@@ -0,0 +1,146 @@
+use std::ops::Range;
+
+use state::{SaveState, StateReader, StateWriter, StateError};
+
+pub const SLOT_COUNT: usize = 16;
+pub const SLOT_SIZE: usize = 0x4000;
+
+/// Where the selected slot appears in the address space.
+pub const PAGED_MEMORY_RANGE: Range<usize> = 0x8000..0xc000;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SlotKind {
+    Rom,
+    Ram,
+}
+
+struct Slot {
+    kind: SlotKind,
+    bytes: Vec<u8>,
+}
+
+/// The sixteen sideways ROM/RAM slots that share $8000-$BFFF. Writing to
+/// ROMSEL at $FE30 pages one of them in, using the low four bits. Paging
+/// in an empty slot leaves whatever is in the underlying memory visible.
+pub struct SidewaysSlots {
+    slots: Vec<Option<Slot>>,
+    selected: usize,
+}
+
+impl SidewaysSlots {
+    pub fn new() -> SidewaysSlots {
+        SidewaysSlots {
+            slots: (0..SLOT_COUNT).map(|_| None).collect(),
+            selected: 0,
+        }
+    }
+
+    /// Puts a ROM image in `slot`, replacing whatever was there. Images
+    /// smaller than 16K are mirrored across the slot, as they would be by
+    /// the unconnected address lines; larger ones are truncated.
+    ///
+    /// Panics if `slot` isn't in `0..16`.
+    pub fn insert_rom(&mut self, slot: usize, rom: &[u8]) {
+        let bytes = if rom.is_empty() {
+            vec![0xff; SLOT_SIZE]
+        }
+        else {
+            rom.iter().cloned().cycle().take(SLOT_SIZE).collect()
+        };
+
+        self.slots[slot] = Some(Slot { kind: SlotKind::Rom, bytes: bytes });
+    }
+
+    /// Fits 16K of sideways RAM in `slot`, replacing whatever was there.
+    ///
+    /// Panics if `slot` isn't in `0..16`.
+    pub fn insert_ram(&mut self, slot: usize) {
+        self.slots[slot] = Some(Slot { kind: SlotKind::Ram, bytes: vec![0; SLOT_SIZE] });
+    }
+
+    pub fn remove(&mut self, slot: usize) {
+        self.slots[slot] = None;
+    }
+
+    pub fn kind(&self, slot: usize) -> Option<SlotKind> {
+        self.slots.get(slot).and_then(|s| s.as_ref()).map(|s| s.kind)
+    }
+
+    pub fn first_free(&self) -> Option<usize> {
+        self.slots.iter().position(|s| s.is_none())
+    }
+
+    /// The contents of `slot`, or `None` if it's empty.
+    pub fn contents(&self, slot: usize) -> Option<&[u8]> {
+        self.slots.get(slot).and_then(|s| s.as_ref()).map(|s| &s.bytes[..])
+    }
+
+    /// Raw access to the contents of `slot`, ignoring write protection.
+    pub fn contents_mut(&mut self, slot: usize) -> Option<&mut [u8]> {
+        self.slots.get_mut(slot).and_then(|s| s.as_mut()).map(|s| &mut s.bytes[..])
+    }
+
+    /// Handles a write to ROMSEL.
+    pub fn select(&mut self, val: u8) {
+        self.selected = (val & 0x0f) as usize;
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    /// The 16K currently paged in, or `None` if the selected slot is empty.
+    pub fn paged(&self) -> Option<&[u8]> {
+        self.slots[self.selected].as_ref().map(|s| &s.bytes[..])
+    }
+
+    /// Raw access to the 16K currently paged in, ignoring write protection.
+    pub fn paged_mut(&mut self) -> Option<&mut [u8]> {
+        self.slots[self.selected].as_mut().map(|s| &mut s.bytes[..])
+    }
+
+    /// Whether the slot paged in is ROM.
+    pub fn is_rom_paged(&self) -> bool {
+        self.kind(self.selected) == Some(SlotKind::Rom)
+    }
+
+    /// Writes to the paged slot if it's sideways RAM.
+    pub fn write(&mut self, offset: usize, val: u8) {
+        if let Some(Slot { kind: SlotKind::Ram, ref mut bytes }) = self.slots[self.selected] {
+            bytes[offset] = val;
+        }
+    }
+}
+
+/// The selected slot and the contents of the sideways RAM. ROM images
+/// aren't saved, so they have to be fitted before the state is loaded.
+impl SaveState for SidewaysSlots {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_u8(self.selected as u8);
+        for slot in &self.slots {
+            match *slot {
+                Some(Slot { kind: SlotKind::Ram, ref bytes }) => {
+                    state.write_bool(true);
+                    state.write_bytes(bytes);
+                },
+                _ => state.write_bool(false),
+            }
+        }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.select(state.read_u8()?);
+        for slot in 0..SLOT_COUNT {
+            if state.read_bool()? {
+                if self.kind(slot) != Some(SlotKind::Ram) {
+                    self.insert_ram(slot);
+                }
+                if let Some(Slot { ref mut bytes, .. }) = self.slots[slot] {
+                    state.read_bytes_into(bytes)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
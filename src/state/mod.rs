@@ -0,0 +1,184 @@
+pub mod uef;
+
+use std::error::Error;
+use std::fmt;
+
+/// The first bytes of every save state.
+pub const MAGIC: &'static [u8; 4] = b"BBCS";
+
+/// Bumped whenever the layout of a save state changes. States saved by
+/// other versions are refused rather than misread.
+pub const VERSION: u8 = 5;
+
+#[derive(Debug, PartialEq)]
+pub enum StateError {
+    /// The data doesn't start with `MAGIC`.
+    NotASaveState,
+    UnsupportedVersion(u8),
+    /// The data ended before the whole machine had been read.
+    Truncated,
+    /// The state was saved from a machine built differently to the one
+    /// it's being loaded into.
+    Mismatch(&'static str),
+    /// A gzipped UEF file, which has to be decompressed first.
+    Compressed,
+    /// A UEF snapshot without the chunk with this ID.
+    MissingChunk(u16),
+    /// A snapshot saved by B-Em, whose format isn't supported.
+    BemSnapshot,
+}
+
+impl Error for StateError {
+    fn description(&self) -> &str {
+        match *self {
+            StateError::NotASaveState => "Not a save state",
+            StateError::UnsupportedVersion(_) => "Unsupported save state version",
+            StateError::Truncated => "Save state is truncated",
+            StateError::Mismatch(what) => what,
+            StateError::Compressed => "UEF file is compressed",
+            StateError::MissingChunk(_) => "UEF snapshot is missing a chunk",
+            StateError::BemSnapshot => "B-Em snapshots aren't supported; save a .uefstate from BeebEm",
+        }
+    }
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", <Self as Error>::description(self))
+    }
+}
+
+/// Builds up a save state. Values are written little endian, in the
+/// order each part of the machine chooses.
+pub struct StateWriter {
+    bytes: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        StateWriter { bytes: vec![] }
+    }
+
+    pub fn write_u8(&mut self, val: u8) {
+        self.bytes.push(val);
+    }
+
+    pub fn write_bool(&mut self, val: bool) {
+        self.write_u8(val as u8);
+    }
+
+    pub fn write_u16(&mut self, val: u16) {
+        self.write_u8(val as u8);
+        self.write_u8((val >> 8) as u8);
+    }
+
+    pub fn write_u32(&mut self, val: u32) {
+        self.write_u16(val as u16);
+        self.write_u16((val >> 16) as u16);
+    }
+
+    pub fn write_u64(&mut self, val: u64) {
+        self.write_u32(val as u32);
+        self.write_u32((val >> 32) as u32);
+    }
+
+    /// Writes `bytes` with their length in front.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_u32(bytes.len() as u32);
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Reads back what a `StateWriter` wrote.
+pub struct StateReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> StateReader<'a> {
+        StateReader { bytes: bytes }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if len > self.bytes.len() {
+            return Err(StateError::Truncated);
+        }
+
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, StateError> {
+        self.take(1).map(|b| b[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, StateError> {
+        self.read_u8().map(|b| b != 0)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, StateError> {
+        Ok(self.read_u8()? as u16 | (self.read_u8()? as u16) << 8)
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, StateError> {
+        Ok(self.read_u16()? as u32 | (self.read_u16()? as u32) << 16)
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, StateError> {
+        Ok(self.read_u32()? as u64 | (self.read_u32()? as u64) << 32)
+    }
+
+    /// Reads bytes written by `StateWriter::write_bytes`.
+    pub fn read_bytes(&mut self) -> Result<&'a [u8], StateError> {
+        let len = self.read_u32()? as usize;
+        self.take(len)
+    }
+
+    /// Reads bytes written by `StateWriter::write_bytes` into `dest`,
+    /// which must be the size they were saved at.
+    pub fn read_bytes_into(&mut self, dest: &mut [u8]) -> Result<(), StateError> {
+        let bytes = self.read_bytes()?;
+        if bytes.len() != dest.len() {
+            return Err(StateError::Mismatch("Memory is a different size to the saved state"));
+        }
+
+        dest.copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+/// A part of the machine whose state can be saved and restored.
+pub trait SaveState {
+    fn save(&self, state: &mut StateWriter);
+
+    /// Restores what `save` wrote. Anything that isn't part of the state,
+    /// such as ROM images and inserted discs, is left as it is.
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError>;
+}
+
+#[cfg(test)]
+mod state_should {
+    use super::*;
+
+    #[test]
+    fn read_back_what_was_written() {
+        let mut w = StateWriter::new();
+        w.write_u8(0x12);
+        w.write_u16(0x3456);
+        w.write_u64(0x0123456789abcdef);
+        w.write_bytes(&[1, 2, 3]);
+        let bytes = w.into_bytes();
+
+        let mut r = StateReader::new(&bytes);
+        assert_eq!(Ok(0x12), r.read_u8());
+        assert_eq!(Ok(0x3456), r.read_u16());
+        assert_eq!(Ok(0x0123456789abcdef), r.read_u64());
+        assert_eq!(Ok(&[1, 2, 3][..]), r.read_bytes());
+        assert_eq!(Err(StateError::Truncated), r.read_u8());
+    }
+}
@@ -0,0 +1,404 @@
+use cpu::{Registers, StatusFlags};
+use via::ViaSnapshot;
+use state::{StateReader, StateWriter, StateError};
+
+/// Every UEF file starts with this, followed by the minor and major
+/// version of the format.
+const UEF_MAGIC: &'static [u8; 10] = b"UEF File!\0";
+const UEF_MINOR_VERSION: u8 = 12;
+const UEF_MAJOR_VERSION: u8 = 0;
+
+/// The gzip header, which UEF files are often wrapped in.
+const GZIP_MAGIC: &'static [u8; 2] = &[0x1f, 0x8b];
+
+/// The start of a B-Em snapshot, which is a different format altogether.
+const BEM_MAGIC: &'static [u8; 7] = b"BEMSNAP";
+
+// The chunks BeebEm writes in its `.uefstate` snapshots.
+const CPU_CHUNK: u16 = 0x0460;
+const PAGING_CHUNK: u16 = 0x0461;
+const MAIN_RAM_CHUNK: u16 = 0x0462;
+const SHADOW_RAM_CHUNK: u16 = 0x0463;
+const PRIVATE_RAM_CHUNK: u16 = 0x0464;
+const FILING_SYSTEM_RAM_CHUNK: u16 = 0x0465;
+const SIDEWAYS_RAM_CHUNK: u16 = 0x0466;
+const VIA_CHUNK: u16 = 0x0467;
+const VIDEO_CHUNK: u16 = 0x0468;
+const MACHINE_CHUNK: u16 = 0x046a;
+
+/// Both VIAs are saved in a `VIA_CHUNK`, which starts with a byte saying
+/// which one it is.
+const SYSTEM_VIA: u8 = 0;
+
+/// The bytes of BeebEm's position in the frame, which follow the palette
+/// in the video chunk. bbc_em works it out again from the CRTC registers.
+const VIDEO_TIMING_LEN: usize = 12;
+
+/// BeebEm's numbers for the machines in the machine chunk.
+pub const MODEL_B: u8 = 0;
+pub const B_PLUS: u8 = 2;
+pub const MASTER_128: u8 = 3;
+
+/// A snapshot in the format BeebEm saves as `.uefstate`: the UEF header
+/// followed by a chunk for each part of the machine. Chunks for hardware
+/// bbc_em doesn't emulate, such as the user VIA, sound chip and Tube, are
+/// skipped when reading.
+#[derive(Debug, PartialEq, Clone)]
+pub struct UefState {
+    /// The machine, as one of BeebEm's machine numbers.
+    pub machine: u8,
+    pub registers: Registers,
+    /// The 2MHz cycles since the machine started. BeebEm keeps a 32 bit
+    /// count, so this wraps around roughly every 36 minutes.
+    pub cycles: u32,
+    pub romsel: u8,
+    pub acccon: u8,
+    pub main_ram: Vec<u8>,
+    /// Shadow, private and filing system RAM, which are empty on
+    /// machines that don't have them.
+    pub shadow_ram: Vec<u8>,
+    pub private_ram: Vec<u8>,
+    pub filing_system_ram: Vec<u8>,
+    /// The contents of each sideways RAM slot, by slot number.
+    pub sideways_ram: Vec<(u8, Vec<u8>)>,
+    pub system_via: ViaSnapshot,
+    pub crtc_registers: [u8; 18],
+    pub video_ula_control: u8,
+    /// The physical colour of each logical colour. BeebEm saves the values
+    /// written to the ULA, which have the colour bits inverted.
+    pub video_ula_palette: [u8; 16],
+}
+
+fn read_via(r: &mut StateReader) -> Result<ViaSnapshot, StateError> {
+    Ok(ViaSnapshot {
+        orb: r.read_u8()?,
+        irb: r.read_u8()?,
+        ora: r.read_u8()?,
+        ira: r.read_u8()?,
+        ddrb: r.read_u8()?,
+        ddra: r.read_u8()?,
+        timer1_counter: r.read_u16()?,
+        timer1_latch: r.read_u16()?,
+        timer2_counter: r.read_u16()?,
+        timer2_latch: r.read_u16()?,
+        acr: r.read_u8()?,
+        pcr: r.read_u8()?,
+        ifr: r.read_u8()?,
+        ier: r.read_u8()?,
+        timer1_has_shot: r.read_bool()?,
+        timer2_has_shot: r.read_bool()?,
+        ic32: r.read_u8()?,
+    })
+}
+
+fn write_via(w: &mut StateWriter, via: &ViaSnapshot) {
+    for &b in &[via.orb, via.irb, via.ora, via.ira, via.ddrb, via.ddra] {
+        w.write_u8(b);
+    }
+    for &n in &[via.timer1_counter, via.timer1_latch, via.timer2_counter, via.timer2_latch] {
+        w.write_u16(n);
+    }
+    for &b in &[via.acr, via.pcr, via.ifr, via.ier] {
+        w.write_u8(b);
+    }
+    w.write_bool(via.timer1_has_shot);
+    w.write_bool(via.timer2_has_shot);
+    w.write_u8(via.ic32);
+}
+
+/// Writes a chunk, built by `body`, with its ID and length in front.
+fn write_chunk<F: FnOnce(&mut StateWriter)>(w: &mut StateWriter, id: u16, body: F) {
+    let mut chunk = StateWriter::new();
+    body(&mut chunk);
+    w.write_u16(id);
+    w.write_bytes(&chunk.into_bytes());
+}
+
+impl UefState {
+    pub fn parse(bytes: &[u8]) -> Result<UefState, StateError> {
+        if bytes.starts_with(GZIP_MAGIC) {
+            return Err(StateError::Compressed);
+        }
+        if bytes.starts_with(BEM_MAGIC) {
+            return Err(StateError::BemSnapshot);
+        }
+        if !bytes.starts_with(UEF_MAGIC) {
+            return Err(StateError::NotASaveState);
+        }
+
+        let mut state = UefState {
+            machine: MODEL_B,
+            registers: Registers::new(),
+            cycles: 0,
+            romsel: 0,
+            acccon: 0,
+            main_ram: vec![],
+            shadow_ram: vec![],
+            private_ram: vec![],
+            filing_system_ram: vec![],
+            sideways_ram: vec![],
+            system_via: ViaSnapshot::default(),
+            crtc_registers: [0; 18],
+            video_ula_control: 0,
+            video_ula_palette: [0; 16],
+        };
+        let (mut has_cpu, mut has_ram) = (false, false);
+
+        // Skip the version. Chunks are the same in every version BeebEm
+        // has written.
+        let chunks = bytes.get(UEF_MAGIC.len() + 2..).ok_or(StateError::Truncated)?;
+        let mut r = StateReader::new(chunks);
+        while let Ok(id) = r.read_u16() {
+            let body = r.read_bytes()?;
+            let mut c = StateReader::new(body);
+            match id {
+                MACHINE_CHUNK => state.machine = c.read_u8()?,
+                CPU_CHUNK => {
+                    state.registers.pc = c.read_u16()?;
+                    state.registers.acc = c.read_u8()?;
+                    state.registers.x = c.read_u8()?;
+                    state.registers.y = c.read_u8()?;
+                    state.registers.sp = c.read_u8()?;
+                    state.registers.status = StatusFlags::from(c.read_u8()?);
+                    state.cycles = c.read_u32()?;
+                    has_cpu = true;
+                },
+                PAGING_CHUNK => {
+                    state.romsel = c.read_u8()?;
+                    state.acccon = c.read_u8()?;
+                },
+                MAIN_RAM_CHUNK => {
+                    state.main_ram = body.to_vec();
+                    has_ram = true;
+                },
+                SHADOW_RAM_CHUNK => state.shadow_ram = body.to_vec(),
+                PRIVATE_RAM_CHUNK => state.private_ram = body.to_vec(),
+                FILING_SYSTEM_RAM_CHUNK => state.filing_system_ram = body.to_vec(),
+                SIDEWAYS_RAM_CHUNK if !body.is_empty() =>
+                    state.sideways_ram.push((body[0], body[1..].to_vec())),
+                VIA_CHUNK => {
+                    if c.read_u8()? & 0x01 == SYSTEM_VIA {
+                        state.system_via = read_via(&mut c)?;
+                    }
+                },
+                VIDEO_CHUNK => {
+                    for reg in state.crtc_registers.iter_mut() {
+                        *reg = c.read_u8()?;
+                    }
+                    state.video_ula_control = c.read_u8()?;
+                    for colour in state.video_ula_palette.iter_mut() {
+                        *colour = c.read_u8()? ^ 0x07;
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        if !has_cpu {
+            return Err(StateError::MissingChunk(CPU_CHUNK));
+        }
+        if !has_ram {
+            return Err(StateError::MissingChunk(MAIN_RAM_CHUNK));
+        }
+
+        Ok(state)
+    }
+
+    /// Writes the snapshot as BeebEm would, leaving out the chunks for
+    /// memory the machine doesn't have.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        for &b in UEF_MAGIC.iter().chain(&[UEF_MINOR_VERSION, UEF_MAJOR_VERSION]) {
+            w.write_u8(b);
+        }
+
+        write_chunk(&mut w, MACHINE_CHUNK, |c| {
+            c.write_u8(self.machine);
+            for _ in 1..16 {
+                c.write_u8(0);
+            }
+        });
+        write_chunk(&mut w, CPU_CHUNK, |c| {
+            let r = &self.registers;
+            c.write_u16(r.pc);
+            for &b in &[r.acc, r.x, r.y, r.sp, u8::from(&r.status)] {
+                c.write_u8(b);
+            }
+            c.write_u32(self.cycles);
+            // The CPU's interrupt and NMI state, which are recalculated
+            // from the devices
+            for _ in 0..5 {
+                c.write_u8(0);
+            }
+        });
+        write_chunk(&mut w, PAGING_CHUNK, |c| {
+            c.write_u8(self.romsel);
+            c.write_u8(self.acccon);
+        });
+
+        let memories = [
+            (MAIN_RAM_CHUNK, &self.main_ram),
+            (SHADOW_RAM_CHUNK, &self.shadow_ram),
+            (PRIVATE_RAM_CHUNK, &self.private_ram),
+            (FILING_SYSTEM_RAM_CHUNK, &self.filing_system_ram),
+        ];
+        for &(id, ram) in memories.iter().filter(|&&(_, ram)| !ram.is_empty()) {
+            write_chunk(&mut w, id, |c| {
+                for &b in ram.iter() {
+                    c.write_u8(b);
+                }
+            });
+        }
+        for &(slot, ref ram) in &self.sideways_ram {
+            write_chunk(&mut w, SIDEWAYS_RAM_CHUNK, |c| {
+                c.write_u8(slot);
+                for &b in ram.iter() {
+                    c.write_u8(b);
+                }
+            });
+        }
+
+        write_chunk(&mut w, VIA_CHUNK, |c| {
+            c.write_u8(SYSTEM_VIA);
+            write_via(c, &self.system_via);
+        });
+        write_chunk(&mut w, VIDEO_CHUNK, |c| {
+            for &b in self.crtc_registers.iter() {
+                c.write_u8(b);
+            }
+            c.write_u8(self.video_ula_control);
+            for &b in self.video_ula_palette.iter() {
+                c.write_u8(b ^ 0x07);
+            }
+            for _ in 0..VIDEO_TIMING_LEN {
+                c.write_u8(0);
+            }
+        });
+
+        w.into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod uef_state_should {
+    use super::*;
+
+    #[test]
+    fn skip_chunks_it_does_not_know_about() {
+        let mut bytes = b"UEF File!\0\x0c\x00".to_vec();
+        // The sound chip, then the CPU and main RAM
+        bytes.extend_from_slice(&[0x6b, 0x04, 0x02, 0x00, 0x00, 0x00, 0xaa, 0xbb]);
+        bytes.extend_from_slice(&[0x60, 0x04, 0x10, 0x00, 0x00, 0x00,
+                                  0x34, 0x12, 0x01, 0x02, 0x03, 0xfd, 0x24,
+                                  0x00, 0x00, 0x00, 0x00,
+                                  0x00, 0x00, 0x00, 0x00, 0x00]);
+        bytes.extend_from_slice(&[0x62, 0x04, 0x01, 0x00, 0x00, 0x00, 0x42]);
+
+        let state = UefState::parse(&bytes).unwrap();
+        assert_eq!(0x1234, state.registers.pc);
+        assert_eq!(0xfd, state.registers.sp);
+        assert!(state.registers.status.interrupt);
+        assert_eq!(vec![0x42], state.main_ram);
+    }
+
+    fn chunk(id: u16, body: &[u8]) -> Vec<u8> {
+        let len = body.len() as u32;
+        let mut bytes = vec![id as u8, (id >> 8) as u8,
+                             len as u8, (len >> 8) as u8, (len >> 16) as u8, (len >> 24) as u8];
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    /// Laid out field by field as BeebEm's save code writes a Model B,
+    /// with the user VIA's chunk after the system VIA's.
+    #[test]
+    fn read_the_chunks_as_beebem_lays_them_out() {
+        let mut bytes = b"UEF File!\0\x0c\x00".to_vec();
+        bytes.extend(chunk(0x046a, &[0x00; 16]));
+        // PC, A, X, Y, S, P, cycles, then the interrupt state
+        bytes.extend(chunk(0x0460, &[0xcd, 0xd9, 0x01, 0x02, 0x03, 0xf5, 0x24,
+                                     0x40, 0xe2, 0x01, 0x00,
+                                     0x00, 0x00, 0x00, 0x00, 0x00]));
+        bytes.extend(chunk(0x0461, &[0x0f, 0x00]));
+        bytes.extend(chunk(0x0462, &[0x55; 0x8000]));
+        // Which VIA, ORB, IRB, ORA, IRA, DDRB, DDRA, T1C / 2, T1L, T2C / 2,
+        // T2L, ACR, PCR, IFR, IER, whether each timer has shot and IC32
+        let system_via = chunk(0x0467, &[0x00, 0x0f, 0xff, 0x7f, 0xff, 0x0f, 0x7f,
+                                         0x0e, 0x27, 0x0e, 0x27, 0x34, 0x12, 0xff, 0xff,
+                                         0x60, 0x04, 0xa0, 0xf2, 0x01, 0x00, 0x7c]);
+        bytes.extend_from_slice(&system_via);
+        bytes.extend(chunk(0x0467, &[0x01, 0x00, 0x00, 0x00, 0x00, 0xff, 0x00,
+                                     0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                                     0x00, 0x00, 0x00, 0x80, 0x00, 0x00, 0x00]));
+        // 18 CRTC registers, the ULA's control register and palette, then
+        // the position in the frame
+        let mut video = vec![0x3f, 0x28, 0x31, 0x24, 0x26, 0x00, 0x20, 0x23,
+                             0x01, 0x07, 0x67, 0x08, 0x06, 0x00, 0x07, 0x00,
+                             0x00, 0x00, 0x9c];
+        video.extend((0..16).map(|c| c ^ 0x07));
+        video.extend_from_slice(&[0x00; 12]);
+        let video = chunk(0x0468, &video);
+        bytes.extend_from_slice(&video);
+        bytes.extend(chunk(0x046b, &[0x00; 55]));
+
+        let state = UefState::parse(&bytes).unwrap();
+        assert_eq!(MODEL_B, state.machine);
+        assert_eq!(0xd9cd, state.registers.pc);
+        assert_eq!((0x01, 0x02, 0x03, 0xf5), (state.registers.acc, state.registers.x,
+                                              state.registers.y, state.registers.sp));
+        assert_eq!(0x0001e240, state.cycles);
+        assert_eq!(0x0f, state.romsel);
+        assert_eq!(ViaSnapshot {
+            orb: 0x0f, irb: 0xff, ora: 0x7f, ira: 0xff, ddrb: 0x0f, ddra: 0x7f,
+            timer1_counter: 0x270e, timer1_latch: 0x270e,
+            timer2_counter: 0x1234, timer2_latch: 0xffff,
+            acr: 0x60, pcr: 0x04, ifr: 0xa0, ier: 0xf2,
+            timer1_has_shot: true, timer2_has_shot: false,
+            ic32: 0x7c,
+        }, state.system_via);
+        assert_eq!(0x3f, state.crtc_registers[0]);
+        assert_eq!(0x06, state.crtc_registers[12]);
+        assert_eq!(0x9c, state.video_ula_control);
+        assert_eq!([0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+                   state.video_ula_palette);
+
+        let written = state.to_bytes();
+        for expected in &[system_via, video] {
+            assert!(written.windows(expected.len()).any(|w| w == &expected[..]));
+        }
+    }
+
+    #[test]
+    fn read_back_what_it_writes() {
+        let mut registers = Registers::new();
+        registers.pc = 0xd940;
+        registers.acc = 0x7f;
+        let state = UefState {
+            machine: MASTER_128,
+            registers: registers,
+            cycles: 123456,
+            romsel: 0x8f,
+            acccon: 0x04,
+            main_ram: vec![0x11; 0x8000],
+            shadow_ram: vec![0x22; 0x5000],
+            private_ram: vec![0x33; 0x1000],
+            filing_system_ram: vec![0x44; 0x2000],
+            sideways_ram: vec![(4, vec![0x55; 0x4000])],
+            system_via: ViaSnapshot { ier: 0xf2, ic32: 0x0f, ..ViaSnapshot::default() },
+            crtc_registers: [0x3f; 18],
+            video_ula_control: 0x9c,
+            video_ula_palette: [0x07; 16],
+        };
+
+        assert_eq!(Ok(state.clone()), UefState::parse(&state.to_bytes()));
+    }
+
+    #[test]
+    fn refuse_compressed_files_and_other_data() {
+        assert_eq!(Err(StateError::Compressed), UefState::parse(&[0x1f, 0x8b, 0x08]));
+        assert_eq!(Err(StateError::BemSnapshot), UefState::parse(b"BEMSNAP3"));
+        assert_eq!(Err(StateError::NotASaveState), UefState::parse(b"BBCS"));
+        assert_eq!(Err(StateError::MissingChunk(CPU_CHUNK)),
+                   UefState::parse(b"UEF File!\0\x0c\x00"));
+    }
+}
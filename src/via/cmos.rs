@@ -0,0 +1,278 @@
+use state::{SaveState, StateReader, StateWriter, StateError};
+
+const SECONDS: usize = 0;
+const MINUTES: usize = 2;
+const HOURS: usize = 4;
+const DAY_OF_WEEK: usize = 6;
+const DATE: usize = 7;
+const MONTH: usize = 8;
+const YEAR: usize = 9;
+const REGISTER_A: usize = 10;
+const REGISTER_B: usize = 11;
+const REGISTER_C: usize = 12;
+const REGISTER_D: usize = 13;
+
+/// Register B: stops the clock while it's being set.
+const SET: u8 = 7;
+/// Register B: binary rather than BCD.
+const BINARY: u8 = 2;
+/// Register D: the battery is good.
+const VALID_RAM: u8 = 7;
+
+const CYCLES_PER_SECOND: u64 = 2_000_000;
+
+/// The state of the chip's control pins. On the Master, chip enable is
+/// system VIA PB6 and address strobe PB7, while data strobe and
+/// read/not write come from bits 1 and 2 of the addressable latch.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub struct CmosPins {
+    pub chip_enable: bool,
+    pub address_strobe: bool,
+    pub data_strobe: bool,
+    pub read: bool,
+}
+
+/// The MC146818 real time clock and its 50 bytes of battery backed RAM,
+/// which the Master keeps its configuration in. Its address and data go
+/// over the slow data bus, system VIA port A.
+///
+/// The clock only runs in 24 hour mode.
+pub struct Cmos {
+    ram: [u8; 64],
+    address: u8,
+    pins: CmosPins,
+    cycles: u64,
+}
+
+fn days_in_month(month: u8, year: u8) -> u8 {
+    match month {
+        2 if year % 4 == 0 => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+fn from_bcd(val: u8) -> u8 {
+    (val >> 4) * 10 + (val & 0x0f)
+}
+
+fn to_bcd(val: u8) -> u8 {
+    ((val / 10) << 4) | (val % 10)
+}
+
+impl Cmos {
+    /// Starts the clock at midnight on Saturday 1st January 2000, with the
+    /// RAM cleared.
+    pub fn new() -> Cmos {
+        let mut ram = [0x00; 64];
+        ram[DAY_OF_WEEK] = 7;
+        ram[DATE] = 0x01;
+        ram[MONTH] = 0x01;
+        ram[REGISTER_B] = 0x02;
+        ram[REGISTER_D] = 0x01 << VALID_RAM;
+
+        Cmos {
+            ram: ram,
+            address: 0,
+            pins: CmosPins::default(),
+            cycles: 0,
+        }
+    }
+
+    /// The clock registers followed by the RAM.
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    /// Reads register `address`, with its side effects.
+    fn read(&mut self, address: u8) -> u8 {
+        let val = self.ram[address as usize];
+        if address as usize == REGISTER_C {
+            self.ram[REGISTER_C] = 0x00;
+        }
+        val
+    }
+
+    fn write(&mut self, address: u8, val: u8) {
+        match address as usize {
+            REGISTER_C | REGISTER_D => {},
+            // Update in progress is read only
+            REGISTER_A => self.ram[REGISTER_A] = val & 0x7f,
+            address => self.ram[address] = val,
+        }
+    }
+
+    /// Changes the pins to `pins`, with `bus` on the data bus. Returns the
+    /// value the chip drives onto the bus, if it's being read.
+    pub fn update(&mut self, pins: CmosPins, bus: u8) -> Option<u8> {
+        let previous = self.pins;
+        self.pins = pins;
+
+        if !pins.chip_enable {
+            return None;
+        }
+
+        // The address is latched as the strobe falls
+        if previous.address_strobe && !pins.address_strobe {
+            self.address = bus & 0x3f;
+            log_via!("CMOS address set to {:02x}", self.address);
+        }
+
+        match (pins.data_strobe, pins.read) {
+            (true, true) => {
+                let address = self.address;
+                Some(self.read(address))
+            },
+            (true, false) => {
+                let address = self.address;
+                log_via!("CMOS {:02x} set to {:02x}", address, bus);
+                self.write(address, bus);
+                None
+            },
+            _ => None,
+        }
+    }
+
+    /// Advances the clock by `cycles` 2MHz cycles.
+    pub fn step(&mut self, cycles: usize) {
+        self.cycles += cycles as u64;
+        while self.cycles >= CYCLES_PER_SECOND {
+            self.cycles -= CYCLES_PER_SECOND;
+            if !bit_is_set!(self.ram[REGISTER_B], SET) {
+                self.tick();
+            }
+        }
+    }
+
+    /// Counts off one second, carrying into the minutes, hours and date.
+    fn tick(&mut self) {
+        let binary = bit_is_set!(self.ram[REGISTER_B], BINARY);
+        let decode = |val: u8| if binary { val } else { from_bcd(val) };
+
+        let mut clock = [SECONDS, MINUTES, HOURS, DAY_OF_WEEK, DATE, MONTH, YEAR]
+            .iter()
+            .map(|&r| decode(self.ram[r]))
+            .collect::<Vec<_>>();
+
+        clock[0] += 1;
+        if clock[0] == 60 {
+            clock[0] = 0;
+            clock[1] += 1;
+        }
+        if clock[1] == 60 {
+            clock[1] = 0;
+            clock[2] += 1;
+        }
+        if clock[2] == 24 {
+            clock[2] = 0;
+            clock[3] = clock[3] % 7 + 1;
+            clock[4] += 1;
+        }
+        if clock[4] > days_in_month(clock[5], clock[6]) {
+            clock[4] = 1;
+            clock[5] += 1;
+        }
+        if clock[5] > 12 {
+            clock[5] = 1;
+            clock[6] = (clock[6] + 1) % 100;
+        }
+
+        for (&r, &val) in [SECONDS, MINUTES, HOURS, DAY_OF_WEEK, DATE, MONTH, YEAR].iter().zip(&clock) {
+            self.ram[r] = if binary { val } else { to_bcd(val) };
+        }
+    }
+}
+
+impl SaveState for Cmos {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.ram);
+        state.write_u8(self.address);
+        state.write_bool(self.pins.chip_enable);
+        state.write_bool(self.pins.address_strobe);
+        state.write_bool(self.pins.data_strobe);
+        state.write_bool(self.pins.read);
+        state.write_u64(self.cycles);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes_into(&mut self.ram)?;
+        self.address = state.read_u8()?;
+        self.pins = CmosPins {
+            chip_enable: state.read_bool()?,
+            address_strobe: state.read_bool()?,
+            data_strobe: state.read_bool()?,
+            read: state.read_bool()?,
+        };
+        self.cycles = state.read_u64()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod cmos_should {
+    use super::*;
+
+    fn pins(address_strobe: bool, data_strobe: bool, read: bool) -> CmosPins {
+        CmosPins {
+            chip_enable: true,
+            address_strobe: address_strobe,
+            data_strobe: data_strobe,
+            read: read,
+        }
+    }
+
+    fn access(cmos: &mut Cmos, address: u8, data: Option<u8>) -> Option<u8> {
+        cmos.update(pins(true, false, false), address);
+        cmos.update(pins(false, false, false), address);
+        match data {
+            Some(val) => {
+                cmos.update(pins(false, true, false), val);
+                cmos.update(pins(false, false, false), val)
+            },
+            None => cmos.update(pins(false, true, true), 0xff),
+        }
+    }
+
+    #[test]
+    fn store_values_at_the_latched_address() {
+        let mut cmos = Cmos::new();
+        access(&mut cmos, 0x1e, Some(0xa5));
+        access(&mut cmos, 0x1f, Some(0x5a));
+
+        assert_eq!(Some(0xa5), access(&mut cmos, 0x1e, None));
+        assert_eq!(Some(0x5a), access(&mut cmos, 0x1f, None));
+    }
+
+    #[test]
+    fn ignore_the_bus_unless_enabled() {
+        let mut cmos = Cmos::new();
+        access(&mut cmos, 0x20, Some(0x01));
+        cmos.update(CmosPins { chip_enable: false, ..pins(false, true, false) }, 0x02);
+        assert_eq!(None, cmos.update(CmosPins { chip_enable: false, ..pins(false, true, true) }, 0xff));
+        assert_eq!(Some(0x01), access(&mut cmos, 0x20, None));
+    }
+
+    #[test]
+    fn carry_the_clock_into_the_next_day() {
+        let mut cmos = Cmos::new();
+        cmos.ram_mut()[SECONDS] = 0x59;
+        cmos.ram_mut()[MINUTES] = 0x59;
+        cmos.ram_mut()[HOURS] = 0x23;
+        cmos.ram_mut()[DATE] = 0x31;
+        cmos.ram_mut()[MONTH] = 0x12;
+        cmos.ram_mut()[YEAR] = 0x99;
+
+        cmos.step(1_999_999);
+        assert_eq!(0x59, cmos.ram()[SECONDS]);
+        cmos.step(1);
+        assert_eq!(&[0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x01, 0x01, 0x00],
+                   &[cmos.ram()[SECONDS], cmos.ram()[1], cmos.ram()[MINUTES], cmos.ram()[3],
+                     cmos.ram()[HOURS], cmos.ram()[DAY_OF_WEEK], cmos.ram()[DATE],
+                     cmos.ram()[MONTH], cmos.ram()[YEAR]]);
+    }
+}
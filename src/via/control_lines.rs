@@ -0,0 +1,344 @@
+use via::interrupts::{Interrupts, InterruptType};
+use state::{SaveState, StateReader, StateWriter, StateError};
+
+const ORB: u8 = 0x00;
+const ORA: u8 = 0x01;
+
+const CA2: InterruptType = InterruptType::Ca2;
+const CA1: InterruptType = InterruptType::Ca1;
+const CB2: InterruptType = InterruptType::Cb2;
+const CB1: InterruptType = InterruptType::Cb1;
+
+/// What the PCR sets CA2 or CB2 to do.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Control2Mode {
+    /// Interrupts on the active edge, and the flag is cleared by accessing
+    /// the port.
+    Input { positive_edge: bool },
+    /// As `Input`, but the flag is only cleared through the IFR.
+    IndependentInput { positive_edge: bool },
+    /// Goes low when the port is accessed, and high again on the active
+    /// edge of CA1 or CB1.
+    Handshake,
+    /// Goes low for one tick when the port is accessed.
+    Pulse,
+    Manual(bool),
+}
+
+impl Control2Mode {
+    fn from_pcr_bits(bits: u8) -> Control2Mode {
+        match bits & 0x07 {
+            0 => Control2Mode::Input { positive_edge: false },
+            1 => Control2Mode::IndependentInput { positive_edge: false },
+            2 => Control2Mode::Input { positive_edge: true },
+            3 => Control2Mode::IndependentInput { positive_edge: true },
+            4 => Control2Mode::Handshake,
+            5 => Control2Mode::Pulse,
+            6 => Control2Mode::Manual(false),
+            _ => Control2Mode::Manual(true),
+        }
+    }
+
+    pub fn ca2(pcr: u8) -> Control2Mode {
+        Control2Mode::from_pcr_bits(pcr >> 1)
+    }
+
+    pub fn cb2(pcr: u8) -> Control2Mode {
+        Control2Mode::from_pcr_bits(pcr >> 5)
+    }
+
+    fn active_edge(&self) -> Option<bool> {
+        match *self {
+            Control2Mode::Input { positive_edge } |
+            Control2Mode::IndependentInput { positive_edge } => Some(positive_edge),
+            _ => None,
+        }
+    }
+
+    fn is_independent(&self) -> bool {
+        match *self {
+            Control2Mode::IndependentInput { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+/// One pair of control lines, CA1 and CA2 or CB1 and CB2.
+struct Pair {
+    c1: bool,
+    c2: bool,
+    /// What the VIA drives C2 to in the handshake and pulse modes
+    c2_out: bool,
+    /// Set while a pulse output is low
+    pulsing: bool,
+}
+
+impl Pair {
+    fn new() -> Pair {
+        Pair {
+            c1: false,
+            c2: false,
+            c2_out: true,
+            pulsing: false,
+        }
+    }
+
+    /// Sets the level on C1. Returns whether it was the active edge.
+    fn set_c1(&mut self, level: bool, positive_edge: bool, mode: Control2Mode) -> bool {
+        let active = self.c1 != level && level == positive_edge;
+        self.c1 = level;
+        if active && mode == Control2Mode::Handshake {
+            self.c2_out = true;
+        }
+        active
+    }
+
+    /// Sets the level on C2. Returns whether it was the active edge of an
+    /// input mode.
+    fn set_c2(&mut self, level: bool, mode: Control2Mode) -> bool {
+        let changed = self.c2 != level;
+        self.c2 = level;
+        changed && mode.active_edge() == Some(level)
+    }
+
+    fn c2(&self, mode: Control2Mode) -> bool {
+        match mode {
+            Control2Mode::Handshake | Control2Mode::Pulse => self.c2_out,
+            Control2Mode::Manual(level) => level,
+            _ => self.c2,
+        }
+    }
+
+    /// The port was accessed with handshaking. Clears the flags and starts
+    /// a handshake or pulse on C2.
+    fn access(&mut self, mode: Control2Mode, irqs: [InterruptType; 2], interrupts: &mut Interrupts) {
+        interrupts.clear(&irqs[..if mode.is_independent() { 1 } else { 2 }]);
+        match mode {
+            Control2Mode::Handshake => self.c2_out = false,
+            Control2Mode::Pulse => {
+                self.c2_out = false;
+                self.pulsing = true;
+            },
+            _ => {},
+        }
+    }
+
+    fn tick(&mut self) {
+        if self.pulsing {
+            self.c2_out = true;
+            self.pulsing = false;
+        }
+    }
+}
+
+/// The 6522's control lines, CA1, CA2, CB1 and CB2, working as the PCR
+/// says. Reading or writing ORA clears the CA1 flag, and the CA2 flag
+/// unless CA2 is an independent interrupt input, and starts a handshake
+/// or pulse on CA2. ORB does the same for CB1 and CB2, but only writes
+/// start a handshake on CB2. The port A register at offset 0x0f has no
+/// handshake, so accessing it does neither.
+pub struct ControlLines {
+    a: Pair,
+    b: Pair,
+}
+
+impl ControlLines {
+    pub fn new() -> ControlLines {
+        ControlLines {
+            a: Pair::new(),
+            b: Pair::new(),
+        }
+    }
+
+    pub fn set_ca1(&mut self, level: bool, pcr: u8, interrupts: &mut Interrupts) {
+        if self.a.set_c1(level, bit_is_set!(pcr, 0), Control2Mode::ca2(pcr)) {
+            interrupts.signal_one(CA1);
+        }
+    }
+
+    pub fn set_ca2(&mut self, level: bool, pcr: u8, interrupts: &mut Interrupts) {
+        if self.a.set_c2(level, Control2Mode::ca2(pcr)) {
+            interrupts.signal_one(CA2);
+        }
+    }
+
+    pub fn set_cb1(&mut self, level: bool, pcr: u8, interrupts: &mut Interrupts) {
+        if self.b.set_c1(level, bit_is_set!(pcr, 4), Control2Mode::cb2(pcr)) {
+            interrupts.signal_one(CB1);
+        }
+    }
+
+    pub fn set_cb2(&mut self, level: bool, pcr: u8, interrupts: &mut Interrupts) {
+        if self.b.set_c2(level, Control2Mode::cb2(pcr)) {
+            interrupts.signal_one(CB2);
+        }
+    }
+
+    /// The level on CA2, whether it's driven by the VIA or by something
+    /// else.
+    pub fn ca2(&self, pcr: u8) -> bool {
+        self.a.c2(Control2Mode::ca2(pcr))
+    }
+
+    pub fn cb2(&self, pcr: u8) -> bool {
+        self.b.c2(Control2Mode::cb2(pcr))
+    }
+
+    pub fn read(&mut self, reg: u8, pcr: u8, interrupts: &mut Interrupts) {
+        match reg {
+            ORA => self.a.access(Control2Mode::ca2(pcr), [CA1, CA2], interrupts),
+            // Reading ORB doesn't start a handshake on CB2
+            ORB => interrupts.clear(
+                &[CB1, CB2][..if Control2Mode::cb2(pcr).is_independent() { 1 } else { 2 }]),
+            _ => {},
+        }
+    }
+
+    pub fn write(&mut self, reg: u8, pcr: u8, interrupts: &mut Interrupts) {
+        match reg {
+            ORA => self.a.access(Control2Mode::ca2(pcr), [CA1, CA2], interrupts),
+            ORB => self.b.access(Control2Mode::cb2(pcr), [CB1, CB2], interrupts),
+            _ => {},
+        }
+    }
+
+    /// Ends any pulse on CA2 or CB2 after a tick.
+    pub fn step(&mut self, ticks: u64) {
+        if ticks > 0 {
+            self.a.tick();
+            self.b.tick();
+        }
+    }
+}
+
+impl SaveState for Pair {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_bool(self.c1);
+        state.write_bool(self.c2);
+        state.write_bool(self.c2_out);
+        state.write_bool(self.pulsing);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.c1 = state.read_bool()?;
+        self.c2 = state.read_bool()?;
+        self.c2_out = state.read_bool()?;
+        self.pulsing = state.read_bool()?;
+        Ok(())
+    }
+}
+
+impl SaveState for ControlLines {
+    fn save(&self, state: &mut StateWriter) {
+        self.a.save(state);
+        self.b.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.a.load(state)?;
+        self.b.load(state)
+    }
+}
+
+#[cfg(test)]
+mod control_lines_should {
+    use super::*;
+    use via::interrupts::Flags;
+
+    const CA1_IFR: u8 = 0x02;
+    const CA2_IFR: u8 = 0x01;
+    const CB1_IFR: u8 = 0x10;
+    const CB2_IFR: u8 = 0x08;
+
+    fn flags(interrupts: &Interrupts) -> u8 {
+        u8::from(interrupts.flags()) & 0x7f
+    }
+
+    #[test]
+    fn interrupt_on_the_edge_the_pcr_selects() {
+        let mut lines = ControlLines::new();
+        let mut interrupts = Interrupts::default();
+
+        lines.set_ca1(true, 0x00, &mut interrupts);
+        assert_eq!(0, flags(&interrupts));
+        lines.set_ca1(false, 0x00, &mut interrupts);
+        assert_eq!(CA1_IFR, flags(&interrupts));
+        interrupts.clear_flags(Flags(0x7f));
+
+        lines.set_ca1(true, 0x00, &mut interrupts);
+        lines.set_cb1(false, 0x10, &mut interrupts);
+        assert_eq!(0, flags(&interrupts));
+        lines.set_cb1(true, 0x10, &mut interrupts);
+        assert_eq!(CB1_IFR, flags(&interrupts));
+    }
+
+    #[test]
+    fn leave_independent_interrupts_set_when_the_port_is_accessed() {
+        let mut lines = ControlLines::new();
+        let mut interrupts = Interrupts::default();
+        // CA2 independent, CB2 not, both on negative edges
+        let pcr = 0x02;
+        for &level in &[true, false] {
+            lines.set_ca2(level, pcr, &mut interrupts);
+            lines.set_cb2(level, pcr, &mut interrupts);
+        }
+        assert_eq!(CA2_IFR | CB2_IFR, flags(&interrupts));
+
+        lines.read(ORA, pcr, &mut interrupts);
+        lines.read(ORB, pcr, &mut interrupts);
+        assert_eq!(CA2_IFR, flags(&interrupts));
+    }
+
+    #[test]
+    fn ignore_the_port_a_register_without_handshake() {
+        let mut lines = ControlLines::new();
+        let mut interrupts = Interrupts::default();
+        lines.set_ca1(true, 0x00, &mut interrupts);
+        lines.set_ca1(false, 0x00, &mut interrupts);
+
+        lines.read(0x0f, 0x00, &mut interrupts);
+        lines.write(0x0f, 0x00, &mut interrupts);
+        assert_eq!(CA1_IFR, flags(&interrupts));
+    }
+
+    #[test]
+    fn handshake_on_ca2_until_ca1_goes_active() {
+        let mut lines = ControlLines::new();
+        let mut interrupts = Interrupts::default();
+        let pcr = 0x08;
+
+        lines.read(ORA, pcr, &mut interrupts);
+        assert!(!lines.ca2(pcr));
+        lines.step(10);
+        lines.set_ca1(true, pcr, &mut interrupts);
+        assert!(!lines.ca2(pcr));
+
+        lines.set_ca1(false, pcr, &mut interrupts);
+        assert!(lines.ca2(pcr));
+    }
+
+    #[test]
+    fn pulse_cb2_for_a_tick_only_when_orb_is_written() {
+        let mut lines = ControlLines::new();
+        let mut interrupts = Interrupts::default();
+        let pcr = 0xa0;
+
+        lines.read(ORB, pcr, &mut interrupts);
+        assert!(lines.cb2(pcr));
+
+        lines.write(ORB, pcr, &mut interrupts);
+        assert!(!lines.cb2(pcr));
+        lines.step(1);
+        assert!(lines.cb2(pcr));
+    }
+
+    #[test]
+    fn drive_the_manual_output_levels() {
+        let lines = ControlLines::new();
+
+        assert!(!lines.ca2(0x0c));
+        assert!(lines.ca2(0x0e));
+        assert!(!lines.cb2(0xc0));
+        assert!(lines.cb2(0xe0));
+    }
+}
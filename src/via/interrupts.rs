@@ -1,5 +1,7 @@
 use std::fmt::{self, Formatter, Display};
 
+use state::{SaveState, StateReader, StateWriter, StateError};
+
 pub struct Interrupts {
     flags: u8,
     enabled: u8,
@@ -8,8 +10,11 @@ pub struct Interrupts {
 
 #[derive(Clone, Copy)]
 pub enum InterruptType {
-    Keyboard = 0,
-    VerticalSync = 1,
+    Ca2 = 0,
+    Ca1 = 1,
+    ShiftRegister = 2,
+    Cb2 = 3,
+    Cb1 = 4,
     Timer2 = 5,
     Timer1 = 6
 }
@@ -17,8 +22,11 @@ pub enum InterruptType {
 impl Display for InterruptType {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         let s = match *self {
-            InterruptType::Keyboard => "keyboard",
-            InterruptType::VerticalSync => "v-sync",
+            InterruptType::Ca2 => "CA2",
+            InterruptType::Ca1 => "CA1",
+            InterruptType::ShiftRegister => "shift register",
+            InterruptType::Cb2 => "CB2",
+            InterruptType::Cb1 => "CB1",
             InterruptType::Timer2 => "timer2",
             InterruptType::Timer1 => "timer1",
         };
@@ -67,6 +75,21 @@ impl Default for Interrupts {
     }
 }
 
+impl SaveState for Interrupts {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_u8(self.flags);
+        state.write_u8(self.enabled);
+        state.write_u8(self.signalled);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.flags = state.read_u8()?;
+        self.enabled = state.read_u8()?;
+        self.signalled = state.read_u8()?;
+        Ok(())
+    }
+}
+
 impl Interrupts {
     pub fn new(flags: Flags, enabled: Enabled) -> Interrupts {
         Interrupts {
@@ -95,6 +118,12 @@ impl Interrupts {
         Flags(u8::from(self.flags()) & u8::from(self.enabled()))
     }
 
+    /// The level of the VIA's IRQ output, which is held low for as long as
+    /// any enabled interrupt is flagged.
+    pub fn irq(&self) -> bool {
+        (self.flags & self.enabled & 0x7f) != 0
+    }
+
     pub fn drain_signalled(&mut self) -> Flags {
         let s = Flags(u8::from(self.signalled) & u8::from(self.enabled()));
         self.signalled = 0;
@@ -172,11 +201,11 @@ impl Iterator for InterruptIterator {
 
             if bit_is_set!(inner, 0) {
                 match (self.1 - 1) {
-                    0 => return Some(InterruptType::Keyboard),
-                    1 => return Some(InterruptType::VerticalSync),
-                    2 => continue,        
-                    3 => continue,        
-                    4 => continue,        
+                    0 => return Some(InterruptType::Ca2),
+                    1 => return Some(InterruptType::Ca1),
+                    2 => return Some(InterruptType::ShiftRegister),
+                    3 => return Some(InterruptType::Cb2),
+                    4 => return Some(InterruptType::Cb1),
                     5 => return Some(InterruptType::Timer2),        
                     6 => return Some(InterruptType::Timer1),        
                     _ => unreachable!(),
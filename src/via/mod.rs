@@ -1,7 +1,11 @@
 pub mod system;
+pub mod cmos;
+mod control_lines;
 mod interrupts;
 mod peripheral_port;
 mod registers;
+mod shift_register;
+mod timers;
 
-pub use self::system::System;
+pub use self::system::{System, ViaSnapshot};
 
@@ -1,5 +1,7 @@
 use std::u8;
 
+use state::{SaveState, StateReader, StateWriter, StateError};
+
 #[derive(Default, Clone, Copy)]
 pub struct Io(pub(crate) u8);
 #[derive(Default, Clone, Copy)]
@@ -56,6 +58,19 @@ impl PeripheralPort {
     }
 }
 
+impl SaveState for PeripheralPort {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_u8(self.io.0);
+        state.write_u8(self.ddr.0);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.io = Io(state.read_u8()?);
+        self.ddr = DataDirection(state.read_u8()?);
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod port_should {
     use super::*;
@@ -1,5 +1,7 @@
 use via::interrupts::*;
 use via::peripheral_port::*;
+use via::system::ViaSnapshot;
+use state::{SaveState, StateReader, StateWriter, StateError};
 
 macro_rules! create_key_map {
     ( $( $platform_num:expr => [$row:expr, $col:expr] ),+, ) => {
@@ -95,6 +97,9 @@ pub struct Registers {
     pa2: PeripheralPort,
     keyboard_buffer: KeyboardBuffer,
     latches: [bool; 8],
+    /// Set when the keyboard pulses its interrupt line, which is wired to
+    /// CA2, until the VIA takes it.
+    keyboard_interrupt: bool,
 }
 
 fn check_len(mem: &[u8]) {
@@ -121,7 +126,7 @@ impl Registers {
     pub fn write_port_a2_io(&mut self, val: u8) {
         self.pa2.write(val);
         if self.keyboard_buffer.len() > 0 {
-            self.interrupts.signal_one(InterruptType::Keyboard);
+            self.keyboard_interrupt = true;
         }
 
         if !self.is_keyboard_write_enabled() && 
@@ -133,7 +138,7 @@ impl Registers {
 
     pub fn write_port_b_io(&mut self, val: u8) {
         self.pb.write(val);
-        match (val & 0x03, bit_is_set!(val, 3)) {
+        match (val & 0x07, bit_is_set!(val, 3)) {
             (0, f) => {
                 log_via!("Sound write enable latch set to {}", f);
                 self.latches[SOUND_IC_LATCH] = f;
@@ -154,10 +159,35 @@ impl Registers {
                 //  Do we have to rescan the keyboard each time we latch the 
                 //  KB IC? Do we have to rescan when we latch *any* IC?
             },
-            _ => panic!("Invalid IC ({}) latched!", val)
+            (4, f) => self.latches[HW_SCROLL_LOW_LATCH] = f,
+            (5, f) => self.latches[HW_SCROLL_HIGH_LATCH] = f,
+            (6, f) => self.latches[CAPS_LATCH] = f,
+            (_, f) => self.latches[SHIFT_LATCH] = f,
         }
     }
 
+    /// The output of the addressable latch, IC32.
+    pub fn latch(&self, n: usize) -> bool {
+        self.latches[n]
+    }
+
+    /// What port B is driving onto its pins. Inputs read as `1`.
+    pub fn port_b_output(&self) -> u8 {
+        self.pb.io().into()
+    }
+
+    /// What port A is driving onto the slow data bus. Inputs read as `1`.
+    pub fn port_a_output(&self) -> u8 {
+        self.pa2.io().into()
+    }
+
+    /// Puts `val` on the slow data bus from another device, so it's read
+    /// from the port A input pins.
+    pub fn drive_port_a(&mut self, val: u8) {
+        self.pa1.set_io(Io(val));
+        self.pa2.set_io(Io(val));
+    }
+
     pub fn set_port_a_ddr(&mut self, val: u8) {
         self.pa1.set_data_direction(val);
         self.pa2.set_data_direction(val);
@@ -179,10 +209,47 @@ impl Registers {
         mem[15] = self.pa2.read();
     }
 
+    /// Sets the ports, interrupt registers and addressable latch to those
+    /// in `snapshot`.
+    pub fn restore(&mut self, snapshot: &ViaSnapshot) {
+        self.pb.set_data_direction(snapshot.ddrb);
+        self.pb.write(snapshot.orb);
+        self.set_port_a_ddr(snapshot.ddra);
+        self.pa1.write(snapshot.ora);
+        self.pa2.write(snapshot.ora);
+        self.interrupts = Interrupts::new(Flags(snapshot.ifr & 0x7f), Enabled(snapshot.ier & 0x7f));
+        for (n, latch) in self.latches.iter_mut().enumerate() {
+            *latch = bit_is_set!(snapshot.ic32, n);
+        }
+    }
+
+    /// Fills in the parts of `snapshot` that `restore` sets.
+    pub fn snapshot(&self, snapshot: &mut ViaSnapshot) {
+        snapshot.orb = self.pb.io().into();
+        snapshot.irb = self.pb.read();
+        snapshot.ora = self.pa2.io().into();
+        snapshot.ira = self.pa2.read();
+        snapshot.ddrb = self.pb.data_direction().into();
+        snapshot.ddra = self.pa2.data_direction().into();
+        snapshot.ifr = self.interrupts.flags().into();
+        snapshot.ier = self.interrupts.enabled().into();
+        snapshot.ic32 = self.latches.iter()
+                                   .enumerate()
+                                   .fold(0x00, |ic32, (n, &l)| ic32 | (l as u8) << n);
+    }
+
     pub fn key_down(&mut self, keynum: u32) {
         log_via!("Keyboard interrupt signalled: {}", keynum);
         self.keyboard_buffer.key_down(keynum);
-        self.interrupts.signal_one(InterruptType::Keyboard);
+        self.keyboard_interrupt = true;
+    }
+
+    /// Whether the keyboard has pulsed its interrupt line since this was
+    /// last called.
+    pub fn take_keyboard_interrupt(&mut self) -> bool {
+        let pulsed = self.keyboard_interrupt;
+        self.keyboard_interrupt = false;
+        pulsed
     }
 
     pub fn clear_keyboard_buffer(&mut self) {
@@ -190,6 +257,50 @@ impl Registers {
     }
 }
 
+impl SaveState for KeyboardBuffer {
+    fn save(&self, state: &mut StateWriter) {
+        for &key in self.buffer.iter() {
+            state.write_u32(key);
+        }
+        state.write_u8(self.next as u8);
+        state.write_bool(self.write_enabled);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        for key in self.buffer.iter_mut() {
+            *key = state.read_u32()?;
+        }
+        self.next = (state.read_u8()? & 0x0f) as usize;
+        self.write_enabled = state.read_bool()?;
+        Ok(())
+    }
+}
+
+impl SaveState for Registers {
+    fn save(&self, state: &mut StateWriter) {
+        self.pa1.save(state);
+        self.pb.save(state);
+        self.interrupts.save(state);
+        self.pa2.save(state);
+        self.keyboard_buffer.save(state);
+        for &latch in self.latches.iter() {
+            state.write_bool(latch);
+        }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.pa1.load(state)?;
+        self.pb.load(state)?;
+        self.interrupts.load(state)?;
+        self.pa2.load(state)?;
+        self.keyboard_buffer.load(state)?;
+        for latch in self.latches.iter_mut() {
+            *latch = state.read_bool()?;
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod keyboard_should {
     use super::*;
@@ -0,0 +1,300 @@
+use via::interrupts::{Interrupts, InterruptType};
+use state::{SaveState, StateReader, StateWriter, StateError};
+
+const SR: u8 = 0x0a;
+
+/// The shift register's mode, from ACR bits 2 to 4.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ShiftMode {
+    Disabled,
+    InUnderTimer2,
+    InUnderPhi2,
+    InUnderCb1,
+    OutFreeRunning,
+    OutUnderTimer2,
+    OutUnderPhi2,
+    OutUnderCb1,
+}
+
+impl ShiftMode {
+    pub fn from_acr(acr: u8) -> ShiftMode {
+        match (acr >> 2) & 0x07 {
+            0 => ShiftMode::Disabled,
+            1 => ShiftMode::InUnderTimer2,
+            2 => ShiftMode::InUnderPhi2,
+            3 => ShiftMode::InUnderCb1,
+            4 => ShiftMode::OutFreeRunning,
+            5 => ShiftMode::OutUnderTimer2,
+            6 => ShiftMode::OutUnderPhi2,
+            _ => ShiftMode::OutUnderCb1,
+        }
+    }
+
+    pub fn is_output(&self) -> bool {
+        match *self {
+            ShiftMode::OutFreeRunning |
+            ShiftMode::OutUnderTimer2 |
+            ShiftMode::OutUnderPhi2 |
+            ShiftMode::OutUnderCb1 => true,
+            _ => false,
+        }
+    }
+
+    fn is_clocked_by_timer2(&self) -> bool {
+        match *self {
+            ShiftMode::InUnderTimer2 |
+            ShiftMode::OutFreeRunning |
+            ShiftMode::OutUnderTimer2 => true,
+            _ => false,
+        }
+    }
+
+    fn is_clocked_by_cb1(&self) -> bool {
+        *self == ShiftMode::InUnderCb1 || *self == ShiftMode::OutUnderCb1
+    }
+}
+
+/// The 6522's shift register. Reading or writing it starts a shift of 8
+/// bits, clocked by the low byte of timer 2 (a bit every 2 * (N + 2)
+/// ticks), by the 1MHz clock or by rising edges on CB1. Bits are shifted
+/// in from CB2, or rotated out onto it MSB first. Internally clocked
+/// shifts drive CB1 as the shift clock and stop after 8 bits, raising the
+/// SR interrupt; the free running mode carries on without interrupting,
+/// and the CB1 clocked modes interrupt every 8 bits without stopping.
+pub struct ShiftRegister {
+    value: u8,
+    /// Bits shifted since the register was last read or written
+    count: u8,
+    running: bool,
+    /// Ticks until the timer 2 shift clock next changes level
+    divider: u16,
+    cb1: bool,
+    cb2: bool,
+}
+
+impl ShiftRegister {
+    pub fn new() -> ShiftRegister {
+        ShiftRegister {
+            value: 0x00,
+            count: 0,
+            running: false,
+            divider: 0,
+            cb1: true,
+            cb2: true,
+        }
+    }
+
+    /// The level on CB1, which is the shift clock in the internally
+    /// clocked modes.
+    pub fn cb1(&self) -> bool {
+        self.cb1
+    }
+
+    /// The level on CB2, which is the last bit shifted out in the output
+    /// modes.
+    pub fn cb2(&self) -> bool {
+        self.cb2
+    }
+
+    /// What reading register `reg` returns, if it's the shift register.
+    pub fn peek(&self, reg: u8) -> Option<u8> {
+        match reg {
+            SR => Some(self.value),
+            _ => None,
+        }
+    }
+
+    pub fn read(&mut self, reg: u8, interrupts: &mut Interrupts) {
+        if reg == SR {
+            self.start(interrupts);
+        }
+    }
+
+    pub fn write(&mut self, reg: u8, val: u8, interrupts: &mut Interrupts) {
+        if reg == SR {
+            self.value = val;
+            self.start(interrupts);
+        }
+    }
+
+    fn start(&mut self, interrupts: &mut Interrupts) {
+        self.count = 0;
+        self.running = true;
+        self.divider = 0;
+        interrupts.clear(&[InterruptType::ShiftRegister]);
+    }
+
+    /// Runs the shift register for `ticks` 1MHz ticks, with timer 2's
+    /// latch as `t2_latch`.
+    pub fn step(&mut self, ticks: u64, acr: u8, t2_latch: u8, interrupts: &mut Interrupts) {
+        let mode = ShiftMode::from_acr(acr);
+        for _ in 0..ticks {
+            if !self.running {
+                return;
+            }
+
+            let shifted = match mode {
+                ShiftMode::InUnderPhi2 | ShiftMode::OutUnderPhi2 => self.shift(mode),
+                _ if mode.is_clocked_by_timer2() => {
+                    if self.divider == 0 {
+                        self.divider = t2_latch as u16 + 2;
+                    }
+                    self.divider -= 1;
+                    if self.divider == 0 {
+                        self.cb1 = !self.cb1;
+                        self.cb1 && self.shift(mode)
+                    }
+                    else {
+                        false
+                    }
+                },
+                _ => false,
+            };
+
+            if shifted {
+                interrupts.signal_one(InterruptType::ShiftRegister);
+            }
+        }
+    }
+
+    /// Sets the level on CB1. In the CB1 clocked modes, a rising edge
+    /// shifts a bit.
+    pub fn set_cb1(&mut self, level: bool, acr: u8, interrupts: &mut Interrupts) {
+        let rising = !self.cb1 && level;
+        self.cb1 = level;
+
+        let mode = ShiftMode::from_acr(acr);
+        if rising && mode.is_clocked_by_cb1() && self.shift(mode) {
+            interrupts.signal_one(InterruptType::ShiftRegister);
+        }
+    }
+
+    /// Sets the level on CB2, which the input modes shift in.
+    pub fn set_cb2(&mut self, level: bool, acr: u8) {
+        if !ShiftMode::from_acr(acr).is_output() {
+            self.cb2 = level;
+        }
+    }
+
+    /// Shifts a bit, returning whether that completed 8 and should raise
+    /// the interrupt.
+    fn shift(&mut self, mode: ShiftMode) -> bool {
+        if mode.is_output() {
+            self.cb2 = bit_is_set!(self.value, 7);
+            self.value = self.value.rotate_left(1);
+        }
+        else {
+            self.value = (self.value << 1) | self.cb2 as u8;
+        }
+
+        self.count += 1;
+        if self.count < 8 {
+            return false;
+        }
+
+        self.count = 0;
+        match mode {
+            ShiftMode::OutFreeRunning => false,
+            ShiftMode::InUnderCb1 | ShiftMode::OutUnderCb1 => true,
+            _ => {
+                self.running = false;
+                true
+            },
+        }
+    }
+}
+
+impl SaveState for ShiftRegister {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_u8(self.value);
+        state.write_u8(self.count);
+        state.write_bool(self.running);
+        state.write_u16(self.divider);
+        state.write_bool(self.cb1);
+        state.write_bool(self.cb2);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.value = state.read_u8()?;
+        self.count = state.read_u8()?;
+        self.running = state.read_bool()?;
+        self.divider = state.read_u16()?;
+        self.cb1 = state.read_bool()?;
+        self.cb2 = state.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod shift_register_should {
+    use super::*;
+
+    const SR_IFR: u8 = 0x04;
+
+    fn mode_acr(mode: u8) -> u8 {
+        mode << 2
+    }
+
+    #[test]
+    fn shift_out_msb_first_under_phi2_then_stop() {
+        let mut sr = ShiftRegister::new();
+        let mut interrupts = Interrupts::default();
+        let acr = mode_acr(6);
+        sr.write(SR, 0x81, &mut interrupts);
+
+        sr.step(1, acr, 0, &mut interrupts);
+        assert!(sr.cb2());
+        sr.step(6, acr, 0, &mut interrupts);
+        assert_eq!(0, u8::from(interrupts.flags()) & SR_IFR);
+        sr.step(1, acr, 0, &mut interrupts);
+        assert_eq!(SR_IFR, u8::from(interrupts.flags()) & SR_IFR);
+        assert_eq!(Some(0x81), sr.peek(SR));
+
+        sr.step(4, acr, 0, &mut interrupts);
+        assert_eq!(Some(0x81), sr.peek(SR));
+    }
+
+    #[test]
+    fn shift_in_from_cb2_on_rising_cb1_edges() {
+        let mut sr = ShiftRegister::new();
+        let mut interrupts = Interrupts::default();
+        let acr = mode_acr(3);
+        sr.read(SR, &mut interrupts);
+
+        for &bit in &[true, false, true, true, false, false, true, false] {
+            sr.set_cb2(bit, acr);
+            sr.set_cb1(false, acr, &mut interrupts);
+            sr.set_cb1(true, acr, &mut interrupts);
+        }
+
+        assert_eq!(Some(0xb2), sr.peek(SR));
+        assert_eq!(SR_IFR, u8::from(interrupts.flags()) & SR_IFR);
+    }
+
+    #[test]
+    fn shift_a_bit_every_two_timer_2_periods() {
+        let mut sr = ShiftRegister::new();
+        let mut interrupts = Interrupts::default();
+        let acr = mode_acr(5);
+        sr.write(SR, 0x80, &mut interrupts);
+
+        sr.step(2 * 12 - 1, acr, 10, &mut interrupts);
+        assert_eq!(Some(0x80), sr.peek(SR));
+        sr.step(1, acr, 10, &mut interrupts);
+        assert_eq!(Some(0x01), sr.peek(SR));
+
+        sr.step(7 * 2 * 12, acr, 10, &mut interrupts);
+        assert_eq!(SR_IFR, u8::from(interrupts.flags()) & SR_IFR);
+    }
+
+    #[test]
+    fn keep_rotating_without_interrupting_when_free_running() {
+        let mut sr = ShiftRegister::new();
+        let mut interrupts = Interrupts::default();
+        sr.write(SR, 0x01, &mut interrupts);
+
+        sr.step(20 * 2 * 2, mode_acr(4), 0, &mut interrupts);
+        assert_eq!(Some(0x10), sr.peek(SR));
+        assert_eq!(0, u8::from(interrupts.flags()) & SR_IFR);
+    }
+}
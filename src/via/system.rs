@@ -1,22 +1,55 @@
-use cpu::Cpu;
-use memory::{MemoryMap, AsMemoryRegionMut};
+use memory::BusDevice;
 use via::registers::{Registers};
-use via::interrupts::{Flags, Enabled, InterruptType};
-use std::ops::Range;
+use via::interrupts::{Flags, Enabled};
+use via::cmos::{Cmos, CmosPins};
+use via::timers::{Timers, ACR_T1_PB7_OUTPUT};
+use via::shift_register::{ShiftRegister, ShiftMode};
+use via::control_lines::ControlLines;
+use state::{SaveState, StateReader, StateWriter, StateError};
 
 const MHZ: usize = 2_000_000;
 const CYCLES_PER_MS: usize = MHZ / 1_000;
-const TIMER_FREQ: u64 = 1_000 / 100 * CYCLES_PER_MS as u64;
 const VSYNC_FREQ: u64 = 1_000 / 50 * CYCLES_PER_MS as u64;
 
+/// The registers of the system VIA as other emulators record them in
+/// their snapshots. Timer counters are in 1MHz ticks.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub struct ViaSnapshot {
+    pub orb: u8,
+    pub irb: u8,
+    pub ora: u8,
+    pub ira: u8,
+    pub ddrb: u8,
+    pub ddra: u8,
+    pub timer1_counter: u16,
+    pub timer1_latch: u16,
+    pub timer2_counter: u16,
+    pub timer2_latch: u16,
+    pub acr: u8,
+    pub pcr: u8,
+    pub ifr: u8,
+    pub ier: u8,
+    pub timer1_has_shot: bool,
+    pub timer2_has_shot: bool,
+    /// The addressable latch, one bit for each output.
+    pub ic32: u8,
+}
+
 pub struct System {
     cycles_elapsed: u64,
-    timer_count: u64,
     vsync_count: u64,
-    registers: Registers
+    registers: Registers,
+    timers: Timers,
+    shift_register: ShiftRegister,
+    control_lines: ControlLines,
+    acr: u8,
+    pcr: u8,
+    /// The last value written to each register, which is what registers
+    /// that aren't modelled yet read back as.
+    written: [u8; 16],
+    cmos: Option<Cmos>,
 }
 
-const SYSTEM_VIA_REG_RANGE: Range<usize> = 0xfe40..0xfe50;
 const SYSTEM_VIA_REG_START: u16 = 0xfe40;
 const IFR_REGISTER: u16 = SYSTEM_VIA_REG_START | 0x0d;
 const IER_REGISTER: u16 = SYSTEM_VIA_REG_START | 0x0e;
@@ -25,23 +58,72 @@ const PB_DDR_REG: u16 = SYSTEM_VIA_REG_START | 0x02;
 const PA1_IO_REG: u16 = SYSTEM_VIA_REG_START | 0x01;
 const PA2_IO_REG: u16 = SYSTEM_VIA_REG_START | 0x0f;
 const PB_IO_REG: u16 = SYSTEM_VIA_REG_START | 0x00;
+const ACR_REG: u16 = SYSTEM_VIA_REG_START | 0x0b;
+const PCR_REG: u16 = SYSTEM_VIA_REG_START | 0x0c;
 
 impl System {
     pub fn new() -> System {
         System {
             cycles_elapsed: 0,
-            timer_count: 0,
             vsync_count: 0,
             registers: Registers::new(),
+            timers: Timers::new(),
+            shift_register: ShiftRegister::new(),
+            control_lines: ControlLines::new(),
+            acr: 0,
+            pcr: 0,
+            written: [0x00; 16],
+            cmos: None,
+        }
+    }
+
+    /// Fits the Master's CMOS RAM and real time clock.
+    pub fn with_cmos(mut self) -> System {
+        self.cmos = Some(Cmos::new());
+        self
+    }
+
+    pub fn cmos(&self) -> Option<&Cmos> {
+        self.cmos.as_ref()
+    }
+
+    pub fn cmos_mut(&mut self) -> Option<&mut Cmos> {
+        self.cmos.as_mut()
+    }
+
+    /// Lets the CMOS see the latest state of the slow data bus and its
+    /// control lines.
+    fn update_cmos(&mut self) {
+        let registers = &mut self.registers;
+        if let Some(ref mut cmos) = self.cmos {
+            let pb = registers.port_b_output();
+            let pins = CmosPins {
+                chip_enable: bit_is_set!(pb, 6),
+                address_strobe: bit_is_set!(pb, 7),
+                data_strobe: registers.latch(1),
+                read: registers.latch(2),
+            };
+
+            if let Some(val) = cmos.update(pins, registers.port_a_output()) {
+                registers.drive_port_a(val);
+            }
         }
     }
 
-    fn process_reads_and_writes<K>(&mut self, 
-                                   read: Option<u16>, 
-                                   write: Option<(u16, u8)>,
-                                   key_eval: K)
-        where K: Fn(u8) -> bool
-    {
+    fn process_reads_and_writes(&mut self, read: Option<u16>, write: Option<(u16, u8)>) {
+        if let Some(addr) = read {
+            let reg = (addr & 0x0f) as u8;
+            self.timers.read(reg, &mut self.registers.interrupts);
+            self.shift_register.read(reg, &mut self.registers.interrupts);
+            self.control_lines.read(reg, self.pcr, &mut self.registers.interrupts);
+        }
+        if let Some((addr, val)) = write {
+            let reg = (addr & 0x0f) as u8;
+            self.timers.write(reg, val, &mut self.registers.interrupts);
+            self.shift_register.write(reg, val, &mut self.registers.interrupts);
+            self.control_lines.write(reg, self.pcr, &mut self.registers.interrupts);
+        }
+
         match write {
             Some((PB_IO_REG, val)) => {
                 match val & 0x07 {
@@ -59,21 +141,10 @@ impl System {
                     _ => {},
                 }
 
-                self.registers.interrupts.clear_flags(Flags(0x18));
                 self.registers.write_port_b_io(val);
             },
-            Some((PA1_IO_REG, val)) => {
-                self.registers.interrupts.clear(&[
-                    InterruptType::Keyboard, 
-                    InterruptType::VerticalSync]);
-                self.registers.write_port_a1_io(val);
-            },
-            Some((PA2_IO_REG, val)) => {
-                self.registers.interrupts.clear(&[
-                    InterruptType::Keyboard, 
-                    InterruptType::VerticalSync]);
-                self.registers.write_port_a2_io(val);
-            },
+            Some((PA1_IO_REG, val)) => self.registers.write_port_a1_io(val),
+            Some((PA2_IO_REG, val)) => self.registers.write_port_a2_io(val),
             Some((PB_DDR_REG, val)) => {
                 self.registers.set_port_b_ddr(val);
                 log_via!("Port B Data direction register set to {:02x}", val);
@@ -82,6 +153,14 @@ impl System {
                 self.registers.set_port_a_ddr(val);
                 log_via!("Port A Data direction register set to {:02x}", val);
             },
+            Some((ACR_REG, val)) => {
+                self.acr = val;
+                log_via!("Auxiliary control register set to {:02x}", val);
+            },
+            Some((PCR_REG, val)) => {
+                self.pcr = val;
+                log_via!("Peripheral control register set to {:02x}", val);
+            },
             Some((IFR_REGISTER, val)) => {
                 self.registers.interrupts.clear_flags(Flags(val));
                 log_via!(
@@ -98,33 +177,50 @@ impl System {
             },
             _ => {}
         }
+
+        if write.is_some() {
+            self.update_cmos();
+        }
+        self.pulse_keyboard_interrupt();
+    }
+
+    /// The keyboard's interrupt line is wired to CA2 and the CRTC's
+    /// vertical sync to CA1. Each pulses its line, and the PCR decides
+    /// which edge interrupts.
+    fn pulse_keyboard_interrupt(&mut self) {
+        if self.registers.take_keyboard_interrupt() {
+            for &level in &[true, false] {
+                self.control_lines.set_ca2(level, self.pcr, &mut self.registers.interrupts);
+            }
+        }
+    }
+
+    fn pulse_vertical_sync(&mut self) {
+        for &level in &[true, false] {
+            self.control_lines.set_ca1(level, self.pcr, &mut self.registers.interrupts);
+        }
     }
 
-    pub fn step<M, F, K>(&mut self, 
-                         cycles: usize, 
-                         mut mem: M, 
-                         mut interrup_request: F,
-                         key_eval: K)
-        where M: MemoryMap + AsMemoryRegionMut,
-              F: FnMut(),
-              K: Fn(u8) -> bool
-    {
+    pub fn step(&mut self, cycles: usize) {
+        // The VIA is clocked at 1MHz, on every other 2MHz cycle
+        let ticks = (self.cycles_elapsed + cycles as u64) / 2 - self.cycles_elapsed / 2;
         self.cycles_elapsed = self.cycles_elapsed.wrapping_add(cycles as _);
-        self.timer_count += cycles as u64;
         self.vsync_count += cycles as u64;
 
-        self.process_reads_and_writes(
-            mem.last_hw_read(), 
-            mem.last_hw_write(),
-            key_eval);
-
-        if self.timer_count >= TIMER_FREQ {
-            self.registers.interrupts.signal_one(InterruptType::Timer1);
-            self.timer_count -= TIMER_FREQ;
+        if let Some(ref mut cmos) = self.cmos {
+            cmos.step(cycles);
         }
 
+        self.timers.step(ticks, self.acr, &mut self.registers.interrupts);
+        self.shift_register.step(
+            ticks,
+            self.acr,
+            self.timers.timer2.latch(),
+            &mut self.registers.interrupts);
+        self.control_lines.step(ticks);
+
         if self.vsync_count >= VSYNC_FREQ {
-            self.registers.interrupts.signal_one(InterruptType::VerticalSync);
+            self.pulse_vertical_sync();
             self.vsync_count -= VSYNC_FREQ;
         }
 
@@ -142,21 +238,97 @@ impl System {
                          .as_slice()
                          .join(", ")
             );
-            interrup_request();
         }
 
-        self.registers.write_to(
-            &mut mem.region_mut(SYSTEM_VIA_REG_RANGE)
-                    .unwrap_or_else(|e| e.0));
+    }
+
+    /// Puts the VIA back into the state the reset line leaves it in. This
+    /// clears IER, which is how the MOS tells a power-on from BREAK. The
+    /// CMOS is battery backed, so it keeps its contents.
+    pub fn reset(&mut self) {
+        let cmos = self.cmos.take();
+        *self = System::new();
+        self.cmos = cmos;
+    }
+
+    /// Takes on the state of a VIA from another emulator's snapshot.
+    pub fn restore(&mut self, snapshot: &ViaSnapshot) {
+        self.registers.restore(snapshot);
+        self.timers.timer1.restore(
+            snapshot.timer1_counter,
+            snapshot.timer1_latch,
+            snapshot.timer1_has_shot);
+        self.timers.timer2.restore(
+            snapshot.timer2_counter,
+            snapshot.timer2_latch as u8,
+            snapshot.timer2_has_shot);
+        self.acr = snapshot.acr;
+        self.pcr = snapshot.pcr;
+        self.update_cmos();
+    }
+
+    pub fn snapshot(&self) -> ViaSnapshot {
+        let mut snapshot = ViaSnapshot {
+            timer1_counter: self.timers.timer1.counter(),
+            timer1_latch: self.timers.timer1.latch(),
+            timer1_has_shot: self.timers.timer1.has_shot(),
+            timer2_counter: self.timers.timer2.counter(),
+            timer2_latch: self.timers.timer2.latch() as u16,
+            timer2_has_shot: self.timers.timer2.has_shot(),
+            acr: self.acr,
+            pcr: self.pcr,
+            ..ViaSnapshot::default()
+        };
+        self.registers.snapshot(&mut snapshot);
+        snapshot
+    }
 
-        assert_eq!(
-            mem.region(SYSTEM_VIA_REG_RANGE).unwrap_or_else(|e| e.0)[IFR_REGISTER as usize - SYSTEM_VIA_REG_START as usize],
-            u8::from(self.registers.interrupts.flags()));
+    /// Sets the level on PB6, whose falling edges timer 2 can count. On
+    /// the BBC it's an input from the speech processor.
+    pub fn set_pb6(&mut self, level: bool) {
+        self.timers.set_pb6(level, self.acr, &mut self.registers.interrupts);
+    }
+
+    /// Sets the level on CB1, which clocks the shift register in its
+    /// external clock modes. On the BBC it's the ADC's end of conversion.
+    pub fn set_cb1(&mut self, level: bool) {
+        self.shift_register.set_cb1(level, self.acr, &mut self.registers.interrupts);
+        self.control_lines.set_cb1(level, self.pcr, &mut self.registers.interrupts);
+    }
 
+    /// Sets the level on CB2, which the shift register shifts in from. On
+    /// the BBC it's the light pen strobe.
+    pub fn set_cb2(&mut self, level: bool) {
+        self.shift_register.set_cb2(level, self.acr);
+        self.control_lines.set_cb2(level, self.pcr, &mut self.registers.interrupts);
+    }
+
+    /// The level on CA2, which the PCR can make an output.
+    pub fn ca2_output(&self) -> bool {
+        self.control_lines.ca2(self.pcr)
+    }
+
+    /// The levels on CB1 and CB2. The shift register drives CB1 in its
+    /// internally clocked modes and CB2 in its output modes; otherwise
+    /// the PCR decides what CB2 does.
+    pub fn cb_outputs(&self) -> (bool, bool) {
+        let cb2 = if ShiftMode::from_acr(self.acr).is_output() {
+            self.shift_register.cb2()
+        }
+        else {
+            self.control_lines.cb2(self.pcr)
+        };
+        (self.shift_register.cb1(), cb2)
+    }
+
+    /// Whether the VIA is asserting the CPU's IRQ line.
+    pub fn irq(&self) -> bool {
+        self.registers.interrupts.irq()
     }
 
     pub fn keydown(&mut self, keynum: u32) {
         self.registers.key_down(keynum);
+        self.pulse_keyboard_interrupt();
     }
 
     pub fn clear_keyboard_buffer(&mut self) {
@@ -193,6 +365,172 @@ impl System {
 //    }
 }
 
+/// The timers, registers and keyboard. The CMOS RAM is included on
+/// machines that have it.
+impl SaveState for System {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_u64(self.cycles_elapsed);
+        state.write_u64(self.vsync_count);
+        self.registers.save(state);
+        self.timers.save(state);
+        self.shift_register.save(state);
+        self.control_lines.save(state);
+        state.write_u8(self.acr);
+        state.write_u8(self.pcr);
+        state.write_bytes(&self.written);
+        state.write_bool(self.cmos.is_some());
+        if let Some(ref cmos) = self.cmos {
+            cmos.save(state);
+        }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.cycles_elapsed = state.read_u64()?;
+        self.vsync_count = state.read_u64()?;
+        self.registers.load(state)?;
+        self.timers.load(state)?;
+        self.shift_register.load(state)?;
+        self.control_lines.load(state)?;
+        self.acr = state.read_u8()?;
+        self.pcr = state.read_u8()?;
+        state.read_bytes_into(&mut self.written)?;
+        if state.read_bool()? != self.cmos.is_some() {
+            return Err(StateError::Mismatch("Saved from a machine with different CMOS RAM"));
+        }
+        if let Some(ref mut cmos) = self.cmos {
+            cmos.load(state)?;
+        }
+        Ok(())
+    }
+}
+
+impl BusDevice for System {
+    fn read(&mut self, addr: u16) -> u8 {
+        let val = self.peek(addr);
+        self.process_reads_and_writes(Some(SYSTEM_VIA_REG_START | (addr & 0x0f)), None);
+        val
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        self.written[(addr & 0x0f) as usize] = val;
+        self.process_reads_and_writes(None, Some((SYSTEM_VIA_REG_START | (addr & 0x0f), val)));
+    }
+
+    /// The registers repeat every 16 bytes.
+    fn peek(&self, addr: u16) -> u8 {
+        let mut regs = self.written;
+        self.registers.write_to(&mut regs);
+        for (reg, val) in regs.iter_mut().enumerate() {
+            if let Some(timer) = self.timers.peek(reg as u8) {
+                *val = timer;
+            }
+            if let Some(sr) = self.shift_register.peek(reg as u8) {
+                *val = sr;
+            }
+        }
+        regs[0x0b] = self.acr;
+        regs[0x0c] = self.pcr;
+        if self.acr & ACR_T1_PB7_OUTPUT != 0 {
+            regs[0x00] = (regs[0x00] & 0x7f) | (self.timers.timer1.pb7() as u8) << 7;
+        }
+        regs[(addr & 0x0f) as usize]
+    }
+}
+
+#[cfg(test)]
+mod timer1_registers_should {
+    use super::*;
+
+    fn started(acr: u8) -> System {
+        let mut via = System::new();
+        via.write(0xfe4e, 0xc0);
+        via.write(0xfe4b, acr);
+        via.write(0xfe44, 0x0e);
+        via.write(0xfe45, 0x27);
+        via
+    }
+
+    #[test]
+    fn interrupt_when_the_programmed_interval_runs_out() {
+        let mut via = started(0x40);
+        via.step(2 * 9998);
+        assert!(!via.irq());
+        via.step(2);
+        assert!(via.irq());
+        assert_eq!(0xff, via.read(0xfe45));
+    }
+
+    #[test]
+    fn interrupt_every_latch_plus_two_ticks_when_free_running() {
+        let mut via = started(0x40);
+        via.step(2 * 9999);
+        via.read(0xfe44);
+
+        via.step(2 * 10000 - 2);
+        assert!(!via.irq());
+        via.step(2);
+        assert!(via.irq());
+    }
+
+    #[test]
+    fn acknowledge_the_interrupt_when_the_low_counter_is_read() {
+        let mut via = started(0x40);
+        via.step(2 * 10000);
+        assert!(via.irq());
+
+        via.read(0xfe44);
+        assert!(!via.irq());
+    }
+
+    #[test]
+    fn acknowledge_the_interrupt_when_the_high_counter_is_written() {
+        let mut via = started(0x00);
+        via.step(2 * 10000);
+        assert!(via.irq());
+
+        via.write(0xfe45, 0x27);
+        assert!(!via.irq());
+        assert_eq!(0x270e, via.snapshot().timer1_counter);
+    }
+
+    #[test]
+    fn drive_pb7_when_the_acr_asks() {
+        let mut via = started(0xc0);
+        via.write(0xfe42, 0x0f);
+        assert_eq!(0x00, via.read(0xfe40) & 0x80);
+
+        via.step(2 * 10000);
+        assert_eq!(0x80, via.read(0xfe40) & 0x80);
+    }
+}
+
+#[cfg(test)]
+mod control_lines_should {
+    use super::*;
+
+    #[test]
+    fn raise_the_keyboard_interrupt_on_ca2_as_the_pcr_says() {
+        let mut via = System::new();
+        via.write(0xfe4e, 0x81);
+        via.write(0xfe4c, 0x04);
+        via.keydown(10);
+        assert!(via.irq());
+
+        via.read(0xfe4f);
+        assert!(via.irq());
+        via.read(0xfe41);
+        assert!(!via.irq());
+
+        // As an independent interrupt, only the IFR clears it
+        via.write(0xfe4c, 0x06);
+        via.keydown(10);
+        via.read(0xfe41);
+        assert!(via.irq());
+        via.write(0xfe4d, 0x01);
+        assert!(!via.irq());
+    }
+}
+
 //#[cfg(test)]
 //mod system_via_should {
 //    use super::*;
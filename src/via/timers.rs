@@ -0,0 +1,443 @@
+use via::interrupts::{Interrupts, InterruptType};
+use state::{SaveState, StateReader, StateWriter, StateError};
+
+const T1C_L: u8 = 0x04;
+const T1C_H: u8 = 0x05;
+const T1L_L: u8 = 0x06;
+const T1L_H: u8 = 0x07;
+const T2C_L: u8 = 0x08;
+const T2C_H: u8 = 0x09;
+
+/// ACR bit 5. Timer 2 counts falling edges on PB6 instead of 1MHz ticks.
+pub const ACR_T2_PULSE_COUNT: u8 = 0x20;
+/// ACR bit 6. Timer 1 keeps interrupting each time it reloads, rather than
+/// only once after its counter is written.
+pub const ACR_T1_FREE_RUN: u8 = 0x40;
+/// ACR bit 7. Timer 1 drives PB7, overriding ORB.
+pub const ACR_T1_PB7_OUTPUT: u8 = 0x80;
+
+/// The 6522's Timer 1. The counter counts down once every 1MHz tick and
+/// reloads from the latches the tick after it passes zero, so it reads
+/// N, N-1 ... 0, 0xffff, N, and an interval lasts N + 2 ticks. The real
+/// chip raises the interrupt half a tick earlier than that, hence the
+/// "N + 1.5" in the datasheet, but the timers only run in whole 1MHz ticks
+/// so the interrupt is one 2MHz cycle late.
+///
+/// In one-shot mode the counter carries on reloading, but only the first
+/// time out after the counter is written raises an interrupt.
+pub struct Timer1 {
+    counter: u16,
+    latch: u16,
+    /// Set on the tick the counter passes zero; it's reloaded on the next
+    reload: bool,
+    /// Whether the next time out interrupts
+    armed: bool,
+    pb7: bool,
+}
+
+impl Timer1 {
+    pub fn new() -> Timer1 {
+        Timer1 {
+            counter: 0xffff,
+            latch: 0xffff,
+            reload: false,
+            armed: false,
+            pb7: true,
+        }
+    }
+
+    pub fn counter(&self) -> u16 {
+        self.counter
+    }
+
+    pub fn latch(&self) -> u16 {
+        self.latch
+    }
+
+    /// Whether the timer has timed out since the counter was written, and
+    /// so won't interrupt again in one-shot mode.
+    pub fn has_shot(&self) -> bool {
+        !self.armed
+    }
+
+    /// The level the timer drives PB7 to, when ACR bit 7 lets it.
+    pub fn pb7(&self) -> bool {
+        self.pb7
+    }
+
+    pub fn write_latch_low(&mut self, val: u8) {
+        self.latch = (self.latch & 0xff00) | val as u16;
+    }
+
+    pub fn write_latch_high(&mut self, val: u8) {
+        self.latch = (self.latch & 0x00ff) | (val as u16) << 8;
+    }
+
+    /// Writing T1C-H loads the counter from the latches and starts a new
+    /// interval, taking PB7 low.
+    pub fn write_counter_high(&mut self, val: u8) {
+        self.write_latch_high(val);
+        self.counter = self.latch;
+        self.reload = false;
+        self.armed = true;
+        self.pb7 = false;
+    }
+
+    /// Takes on a counter and latches from another emulator's snapshot.
+    pub fn restore(&mut self, counter: u16, latch: u16, has_shot: bool) {
+        self.counter = counter;
+        self.latch = latch;
+        self.reload = false;
+        self.armed = !has_shot;
+    }
+
+    /// Advances the timer by a 1MHz tick. Returns whether it timed out
+    /// and should raise its interrupt.
+    pub fn tick(&mut self, acr: u8) -> bool {
+        if self.reload {
+            self.counter = self.latch;
+            self.reload = false;
+            return false;
+        }
+
+        self.counter = self.counter.wrapping_sub(1);
+        if self.counter != 0xffff {
+            return false;
+        }
+
+        self.reload = true;
+        let fired = self.armed;
+        if fired {
+            self.pb7 = !self.pb7;
+        }
+        self.armed = acr & ACR_T1_FREE_RUN != 0;
+        fired
+    }
+}
+
+/// The 6522's Timer 2. It only has a low latch; writing T2C-H loads the
+/// counter from it and the byte written. It always runs one-shot,
+/// interrupting the first time it counts past zero and then carrying on
+/// down from 0xffff. In pulse counting mode it counts falling edges on PB6
+/// instead, and interrupts when it reaches zero.
+pub struct Timer2 {
+    counter: u16,
+    latch: u8,
+    /// Whether the next time out interrupts
+    armed: bool,
+    /// The last level seen on PB6
+    pb6: bool,
+}
+
+impl Timer2 {
+    pub fn new() -> Timer2 {
+        Timer2 {
+            counter: 0xffff,
+            latch: 0xff,
+            armed: false,
+            pb6: true,
+        }
+    }
+
+    pub fn counter(&self) -> u16 {
+        self.counter
+    }
+
+    pub fn latch(&self) -> u8 {
+        self.latch
+    }
+
+    pub fn has_shot(&self) -> bool {
+        !self.armed
+    }
+
+    pub fn write_latch_low(&mut self, val: u8) {
+        self.latch = val;
+    }
+
+    pub fn write_counter_high(&mut self, val: u8) {
+        self.counter = (val as u16) << 8 | self.latch as u16;
+        self.armed = true;
+    }
+
+    /// Takes on a counter and latch from another emulator's snapshot.
+    pub fn restore(&mut self, counter: u16, latch: u8, has_shot: bool) {
+        self.counter = counter;
+        self.latch = latch;
+        self.armed = !has_shot;
+    }
+
+    /// Advances the timer by a 1MHz tick. Returns whether it timed out
+    /// and should raise its interrupt.
+    pub fn tick(&mut self, acr: u8) -> bool {
+        if acr & ACR_T2_PULSE_COUNT != 0 {
+            return false;
+        }
+
+        self.counter = self.counter.wrapping_sub(1);
+        self.time_out_at(0xffff)
+    }
+
+    /// Sets the level on PB6. Returns whether a pulse brought the count to
+    /// zero and the timer should raise its interrupt.
+    pub fn set_pb6(&mut self, level: bool, acr: u8) -> bool {
+        let falling = self.pb6 && !level;
+        self.pb6 = level;
+        if !falling || acr & ACR_T2_PULSE_COUNT == 0 {
+            return false;
+        }
+
+        self.counter = self.counter.wrapping_sub(1);
+        self.time_out_at(0x0000)
+    }
+
+    fn time_out_at(&mut self, count: u16) -> bool {
+        let fired = self.armed && self.counter == count;
+        if fired {
+            self.armed = false;
+        }
+        fired
+    }
+}
+
+/// Both timers, as they're seen through the VIA's registers 4 to 9. Reading
+/// the low byte of a counter or writing the high byte acknowledges that
+/// timer's interrupt.
+pub struct Timers {
+    pub timer1: Timer1,
+    pub timer2: Timer2,
+}
+
+impl Timers {
+    pub fn new() -> Timers {
+        Timers {
+            timer1: Timer1::new(),
+            timer2: Timer2::new(),
+        }
+    }
+
+    /// What reading register `reg` returns, if it's one of the timers'.
+    pub fn peek(&self, reg: u8) -> Option<u8> {
+        match reg {
+            T1C_L => Some(self.timer1.counter() as u8),
+            T1C_H => Some((self.timer1.counter() >> 8) as u8),
+            T1L_L => Some(self.timer1.latch() as u8),
+            T1L_H => Some((self.timer1.latch() >> 8) as u8),
+            T2C_L => Some(self.timer2.counter() as u8),
+            T2C_H => Some((self.timer2.counter() >> 8) as u8),
+            _ => None,
+        }
+    }
+
+    pub fn read(&mut self, reg: u8, interrupts: &mut Interrupts) {
+        match reg {
+            T1C_L => interrupts.clear(&[InterruptType::Timer1]),
+            T2C_L => interrupts.clear(&[InterruptType::Timer2]),
+            _ => {},
+        }
+    }
+
+    pub fn write(&mut self, reg: u8, val: u8, interrupts: &mut Interrupts) {
+        match reg {
+            T1C_L | T1L_L => self.timer1.write_latch_low(val),
+            T1C_H => {
+                self.timer1.write_counter_high(val);
+                interrupts.clear(&[InterruptType::Timer1]);
+            },
+            // As on the NMOS 6522, writing the high latch also
+            // acknowledges the interrupt
+            T1L_H => {
+                self.timer1.write_latch_high(val);
+                interrupts.clear(&[InterruptType::Timer1]);
+            },
+            T2C_L => self.timer2.write_latch_low(val),
+            T2C_H => {
+                self.timer2.write_counter_high(val);
+                interrupts.clear(&[InterruptType::Timer2]);
+            },
+            _ => {},
+        }
+    }
+
+    /// Runs both timers for `ticks` 1MHz ticks.
+    pub fn step(&mut self, ticks: u64, acr: u8, interrupts: &mut Interrupts) {
+        for _ in 0..ticks {
+            if self.timer1.tick(acr) {
+                interrupts.signal_one(InterruptType::Timer1);
+            }
+            if self.timer2.tick(acr) {
+                interrupts.signal_one(InterruptType::Timer2);
+            }
+        }
+    }
+
+    pub fn set_pb6(&mut self, level: bool, acr: u8, interrupts: &mut Interrupts) {
+        if self.timer2.set_pb6(level, acr) {
+            interrupts.signal_one(InterruptType::Timer2);
+        }
+    }
+}
+
+impl SaveState for Timer1 {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_u16(self.counter);
+        state.write_u16(self.latch);
+        state.write_bool(self.reload);
+        state.write_bool(self.armed);
+        state.write_bool(self.pb7);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.counter = state.read_u16()?;
+        self.latch = state.read_u16()?;
+        self.reload = state.read_bool()?;
+        self.armed = state.read_bool()?;
+        self.pb7 = state.read_bool()?;
+        Ok(())
+    }
+}
+
+impl SaveState for Timer2 {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_u16(self.counter);
+        state.write_u8(self.latch);
+        state.write_bool(self.armed);
+        state.write_bool(self.pb6);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.counter = state.read_u16()?;
+        self.latch = state.read_u8()?;
+        self.armed = state.read_bool()?;
+        self.pb6 = state.read_bool()?;
+        Ok(())
+    }
+}
+
+impl SaveState for Timers {
+    fn save(&self, state: &mut StateWriter) {
+        self.timer1.save(state);
+        self.timer2.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.timer1.load(state)?;
+        self.timer2.load(state)
+    }
+}
+
+#[cfg(test)]
+mod timer1_should {
+    use super::*;
+
+    fn started(latch: u16) -> Timer1 {
+        let mut timer = Timer1::new();
+        timer.write_latch_low(latch as u8);
+        timer.write_counter_high((latch >> 8) as u8);
+        timer
+    }
+
+    /// Ticks until the timer fires, returning how many ticks it took.
+    fn ticks_to_fire(timer: &mut Timer1, acr: u8) -> Option<usize> {
+        (1..0x20000).find(|_| timer.tick(acr))
+    }
+
+    #[test]
+    fn load_the_counter_only_when_the_high_byte_is_written() {
+        let mut timer = Timer1::new();
+        timer.write_latch_low(0x34);
+        timer.write_latch_high(0x12);
+        assert_eq!(0x1234, timer.latch());
+        assert_eq!(0xffff, timer.counter());
+
+        timer.write_counter_high(0x56);
+        assert_eq!(0x5634, timer.latch());
+        assert_eq!(0x5634, timer.counter());
+    }
+
+    #[test]
+    fn pass_through_ffff_before_reloading() {
+        let mut timer = started(0x0002);
+        let counts: Vec<u16> = (0..6).map(|_| { timer.tick(0); timer.counter() }).collect();
+
+        assert_eq!(vec![0x0001, 0x0000, 0xffff, 0x0002, 0x0001, 0x0000], counts);
+    }
+
+    #[test]
+    fn interrupt_once_in_one_shot_mode() {
+        let mut timer = started(100);
+
+        assert_eq!(Some(101), ticks_to_fire(&mut timer, 0));
+        assert!(timer.has_shot());
+        assert_eq!(None, ticks_to_fire(&mut timer, 0));
+    }
+
+    #[test]
+    fn interrupt_every_latch_plus_two_ticks_when_free_running() {
+        let mut timer = started(100);
+
+        assert_eq!(Some(101), ticks_to_fire(&mut timer, ACR_T1_FREE_RUN));
+        assert_eq!(Some(102), ticks_to_fire(&mut timer, ACR_T1_FREE_RUN));
+        assert_eq!(Some(102), ticks_to_fire(&mut timer, ACR_T1_FREE_RUN));
+    }
+
+    #[test]
+    fn drive_pb7_low_until_it_times_out() {
+        let mut timer = started(10);
+        assert!(!timer.pb7());
+
+        ticks_to_fire(&mut timer, ACR_T1_FREE_RUN | ACR_T1_PB7_OUTPUT);
+        assert!(timer.pb7());
+        ticks_to_fire(&mut timer, ACR_T1_FREE_RUN | ACR_T1_PB7_OUTPUT);
+        assert!(!timer.pb7());
+    }
+}
+
+#[cfg(test)]
+mod timer2_should {
+    use super::*;
+
+    fn started(count: u16) -> Timer2 {
+        let mut timer = Timer2::new();
+        timer.write_latch_low(count as u8);
+        timer.write_counter_high((count >> 8) as u8);
+        timer
+    }
+
+    #[test]
+    fn interrupt_once_then_carry_on_counting_down() {
+        let mut timer = started(100);
+
+        assert_eq!(Some(101), (1..0x20000).find(|_| timer.tick(0)));
+        assert_eq!(0xffff, timer.counter());
+        assert!(timer.has_shot());
+        assert_eq!(None, (1..0x20000).find(|_| timer.tick(0)));
+    }
+
+    #[test]
+    fn count_falling_edges_on_pb6_in_pulse_counting_mode() {
+        let mut timer = started(3);
+        let mut fired = vec![];
+        for _ in 0..4 {
+            assert!(!timer.tick(ACR_T2_PULSE_COUNT));
+            assert!(!timer.set_pb6(true, ACR_T2_PULSE_COUNT));
+            fired.push(timer.set_pb6(false, ACR_T2_PULSE_COUNT));
+        }
+
+        assert_eq!(vec![false, false, true, false], fired);
+        assert_eq!(0xffff, timer.counter());
+    }
+
+    #[test]
+    fn acknowledge_its_interrupt_through_the_registers() {
+        let mut timers = Timers::new();
+        let mut interrupts = Interrupts::default();
+        timers.write(T2C_L, 0x05, &mut interrupts);
+        timers.write(T2C_H, 0x00, &mut interrupts);
+        timers.step(6, 0, &mut interrupts);
+        assert_eq!(0x20, u8::from(interrupts.flags()) & 0x20);
+
+        timers.read(T2C_L, &mut interrupts);
+        assert_eq!(0x00, u8::from(interrupts.flags()) & 0x20);
+    }
+}
@@ -5,7 +5,8 @@ use std::cmp;
 pub mod framebuffer;
 pub use self::framebuffer::FrameBuffer;
 
-use memory::{MemoryMap, AsMemoryRegion};
+use memory::{MemoryMap, AsMemoryRegion, BusDevice};
+use state::{SaveState, StateReader, StateWriter, StateError};
 
 pub struct Crtc6845 {
     registers: [u8; 18],
@@ -18,6 +19,9 @@ pub struct Crtc6845 {
     video_line_addr: usize,
     state: VideoState,
     video_control_reg: u8,
+    /// The video ULA's physical colour for each logical colour. The
+    /// palette is latched but not yet applied when drawing.
+    palette: [u8; 16],
 }
 
 enum VideoState {
@@ -40,6 +44,7 @@ impl Crtc6845 {
             video_line_addr: 0,
             state: VideoState::NotInitialized,
             video_control_reg: 0,
+            palette: [0x00; 16],
         }
     }
 
@@ -71,6 +76,31 @@ impl Crtc6845 {
         }
     }
 
+    pub fn registers(&self) -> &[u8] {
+        &self.registers
+    }
+
+    /// The video ULA's control register, at $FE20.
+    pub fn video_control(&self) -> u8 {
+        self.video_control_reg
+    }
+
+    /// The video ULA's palette, at $FE21, as the physical colour for each
+    /// logical colour.
+    pub fn palette(&self) -> &[u8; 16] {
+        &self.palette
+    }
+
+    /// Takes on the registers from another emulator's snapshot, starting
+    /// a new frame with them.
+    pub fn restore(&mut self, registers: &[u8], video_control: u8, palette: &[u8; 16]) {
+        let len = cmp::min(registers.len(), self.registers.len());
+        self.registers[..len].copy_from_slice(&registers[..len]);
+        self.video_control_reg = video_control;
+        self.palette = *palette;
+        self.state = VideoState::NotInitialized;
+    }
+
     fn render_char(&self, byte: u8, fb: &mut FrameBuffer, scanline: usize, x: usize, y: usize) {
         const SCANLINES_PER_CHAR: usize = 9;
 
@@ -99,42 +129,6 @@ impl Crtc6845 {
         const SCREEN_START_HI: usize = 12;
         const SCREEN_START_LO: usize = 13;
 
-        match mem.last_hw_write() {
-            Some((addr, val)) if addr == 0xfe00 => {
-                self.selected_reg = Some(val);
-            },
-            Some((addr, val)) if addr == 0xfe01 => {
-                self.registers[self.selected_reg.unwrap() as usize] = val;
-                #[cfg(feature="video-logging")]
-                match self.selected_reg {
-                    Some(0) => log_video!("Horiz. total register set to {:02x}", val),
-                    Some(1) => log_video!("Horiz. display register set to {:02x}", val),
-                    Some(2) => log_video!("Horiz. sync position register set to {:02x}", val),
-                    Some(3) => log_video!("Sync width register set to {:02x}", val),
-                    Some(4) => log_video!("Vert. total register set to {:02x}", val),
-                    Some(5) => log_video!("Vert. total adjust register set to {:02x}", val),
-                    Some(6) => log_video!("Vert. display register set to {:02x}", val),
-                    Some(7) => log_video!("Vert. sync position set to {:02x}", val),
-                    Some(8) => log_video!("Interlace and delay register set to {:02x} ({:08b})", val, val),
-                    Some(9) => log_video!("Scanlines per char register set to {:02x} ({:08b})", val, val),
-                    Some(10) => log_video!("Cursor start register set to {:02x} ({:08b})", val, val),
-                    Some(11) => log_video!("Cursor end register set to {:02x} ({:08b})", val, val),
-                    Some(12) => log_video!("Screen start address high set to {:02x}", val),
-                    Some(13) => log_video!("Screen start address low set to {:02x}", val),
-                    Some(14) => log_video!("Cursor position high set to {:02x}", val),
-                    Some(15) => log_video!("Cursor position low set to {:02x}", val),
-
-                    _ => {},
-                }
-            },
-            Some((addr, val)) if addr == 0xfe20 => {
-                self.video_control_reg = val;
-                log_video!("ULA: Video control register set to {:02x} ({:08b})", val, val);
-            },
-            Some((addr, val)) if addr == 0xfe21 => log_video!("ULA: Palette register set to {:02x} ({:08b})", val, val),
-            _ => {}
-        }
-
         fn calc_start_addr(hi: u8, lo: u8, teletext: bool) -> u16 {
             if teletext {
                 let hi = (hi ^ 0x20) + 0x74;
@@ -197,7 +191,7 @@ impl Crtc6845 {
                         self.state = VideoState::DisplayingLine(line_addr, l, c + 1, sl);
 
                         if line_addr < 0x8000 {
-                            let video_mem = &*mem.region((line_addr as usize)..0x8000)
+                            let video_mem = &*mem.display_region((line_addr as usize)..0x8000)
                                                  .unwrap_or_else(|e| e.0);
 
                             if self.is_teletext() {
@@ -240,3 +234,126 @@ impl Crtc6845 {
         }
     }
 }
+
+impl BusDevice for Crtc6845 {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.peek(addr)
+    }
+
+    /// The CRTC's two registers repeat across $FE00-$FE07, and the video
+    /// ULA's across $FE20-$FE2F.
+    fn write(&mut self, addr: u16, val: u8) {
+        match (addr & 0xfff0, addr & 0x01) {
+            (0xfe00, 0) => {
+                self.selected_reg = Some(val);
+            },
+            (0xfe00, _) => {
+                match self.selected_reg {
+                    Some(reg) if (reg as usize) < self.registers.len() => {
+                        self.registers[reg as usize] = val;
+                    },
+                    _ => return,
+                }
+                #[cfg(feature="video-logging")]
+                match self.selected_reg {
+                    Some(0) => log_video!("Horiz. total register set to {:02x}", val),
+                    Some(1) => log_video!("Horiz. display register set to {:02x}", val),
+                    Some(2) => log_video!("Horiz. sync position register set to {:02x}", val),
+                    Some(3) => log_video!("Sync width register set to {:02x}", val),
+                    Some(4) => log_video!("Vert. total register set to {:02x}", val),
+                    Some(5) => log_video!("Vert. total adjust register set to {:02x}", val),
+                    Some(6) => log_video!("Vert. display register set to {:02x}", val),
+                    Some(7) => log_video!("Vert. sync position set to {:02x}", val),
+                    Some(8) => log_video!("Interlace and delay register set to {:02x} ({:08b})", val, val),
+                    Some(9) => log_video!("Scanlines per char register set to {:02x} ({:08b})", val, val),
+                    Some(10) => log_video!("Cursor start register set to {:02x} ({:08b})", val, val),
+                    Some(11) => log_video!("Cursor end register set to {:02x} ({:08b})", val, val),
+                    Some(12) => log_video!("Screen start address high set to {:02x}", val),
+                    Some(13) => log_video!("Screen start address low set to {:02x}", val),
+                    Some(14) => log_video!("Cursor position high set to {:02x}", val),
+                    Some(15) => log_video!("Cursor position low set to {:02x}", val),
+
+                    _ => {},
+                }
+            },
+            (0xfe20, 0) => {
+                self.video_control_reg = val;
+                log_video!("ULA: Video control register set to {:02x} ({:08b})", val, val);
+            },
+            (0xfe20, _) => {
+                // The logical colour is in the top nibble, and the physical
+                // colour is written with its bits inverted
+                self.palette[(val >> 4) as usize] = (val & 0x0f) ^ 0x07;
+                log_video!("ULA: Palette register set to {:02x} ({:08b})", val, val);
+            },
+            _ => {}
+        }
+    }
+
+    /// Only the cursor and light pen registers, 14 to 17, can be read
+    /// back. Everything else, including the video ULA, reads as zero.
+    fn peek(&self, addr: u16) -> u8 {
+        match (addr & 0xfff0, addr & 0x01, self.selected_reg) {
+            (0xfe00, 1, Some(reg @ 14..=17)) => self.registers[reg as usize],
+            _ => 0x00,
+        }
+    }
+}
+
+/// The registers and where the beam is. What's already been drawn to the
+/// frame buffer isn't saved.
+impl SaveState for Crtc6845 {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.registers);
+        state.write_bool(self.selected_reg.is_some());
+        state.write_u8(self.selected_reg.unwrap_or(0x00));
+        state.write_u64(self.horizontal_count as u64);
+        state.write_u64(self.vertical_count as u64);
+        state.write_u64(self.scanline_count as u64);
+        state.write_u64(self.fb_offset as u64);
+        state.write_u64(self.video_addr as u64);
+        state.write_u64(self.video_line_addr as u64);
+        state.write_u8(self.video_control_reg);
+        state.write_bytes(&self.palette);
+
+        let (kind, addr, counts) = match self.state {
+            VideoState::NotInitialized => (0, 0, [0, 0, 0]),
+            VideoState::NewFrame(addr) => (1, addr, [0, 0, 0]),
+            VideoState::DisplayingLine(addr, l, c, sl) => (2, addr, [l, c, sl]),
+            VideoState::EndOfLine(addr, l) => (3, addr, [l, 0, 0]),
+        };
+        state.write_u8(kind);
+        state.write_u16(addr);
+        for &n in counts.iter() {
+            state.write_u64(n as u64);
+        }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes_into(&mut self.registers)?;
+        let is_selected = state.read_bool()?;
+        let selected = state.read_u8()?;
+        self.selected_reg = if is_selected { Some(selected) } else { None };
+        self.horizontal_count = state.read_u64()? as usize;
+        self.vertical_count = state.read_u64()? as usize;
+        self.scanline_count = state.read_u64()? as usize;
+        self.fb_offset = state.read_u64()? as usize;
+        self.video_addr = state.read_u64()? as usize;
+        self.video_line_addr = state.read_u64()? as usize;
+        self.video_control_reg = state.read_u8()?;
+        state.read_bytes_into(&mut self.palette)?;
+
+        let kind = state.read_u8()?;
+        let addr = state.read_u16()?;
+        let (l, c, sl) = (state.read_u64()? as usize,
+                          state.read_u64()? as usize,
+                          state.read_u64()? as usize);
+        self.state = match kind {
+            0 => VideoState::NotInitialized,
+            1 => VideoState::NewFrame(addr),
+            2 => VideoState::DisplayingLine(addr, l, c, sl),
+            _ => VideoState::EndOfLine(addr, l),
+        };
+        Ok(())
+    }
+}
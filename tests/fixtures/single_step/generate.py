@@ -0,0 +1,345 @@
+#!/usr/bin/env python3
+"""Generates the sample single step vectors in this directory.
+
+The vectors are in the SingleStepTests format (see ../README.md) and come
+from the small NMOS 6502 model below, which is written from the datasheet
+and Bruce Clark's decimal mode notes rather than from bbc_em's CPU core.
+Each opcode gets a batch of vectors with random registers, operands and
+memory, plus a few fixed ones for edge cases such as the stack pointer
+wrapping.
+
+    python3 generate.py
+
+rewrites every xx.json file that it knows about. The seed is fixed, so the
+output only changes when the model does.
+"""
+
+import json
+import os
+import random
+
+VECTORS_PER_OPCODE = 25
+SEED = 6502
+
+C, Z, I, D, B, U, V, N = (1 << n for n in range(8))
+
+
+class Bus:
+    """Memory with every access recorded, and the initial value of every
+    location touched remembered."""
+
+    def __init__(self, rng):
+        self.rng = rng
+        self.mem = {}
+        self.initial = {}
+        self.cycles = []
+
+    def peek(self, addr):
+        addr &= 0xffff
+        if addr not in self.mem:
+            self.mem[addr] = self.rng.randrange(256)
+            self.initial[addr] = self.mem[addr]
+        return self.mem[addr]
+
+    def poke(self, addr, val):
+        addr &= 0xffff
+        self.peek(addr)
+        self.mem[addr] = val & 0xff
+
+    def read(self, addr):
+        val = self.peek(addr)
+        self.cycles.append([addr & 0xffff, val, "read"])
+        return val
+
+    def write(self, addr, val):
+        self.poke(addr, val)
+        self.cycles.append([addr & 0xffff, val & 0xff, "write"])
+
+
+class Cpu:
+    def __init__(self, bus, pc, s, a, x, y, p):
+        self.bus, self.pc, self.s, self.a, self.x, self.y, self.p = bus, pc, s, a, x, y, p
+
+    def state(self):
+        return {"pc": self.pc, "s": self.s, "a": self.a, "x": self.x,
+                "y": self.y, "p": self.p}
+
+    def flag(self, f, on):
+        self.p = (self.p | f) if on else (self.p & ~f & 0xff)
+
+    def nz(self, val):
+        self.flag(Z, val & 0xff == 0)
+        self.flag(N, val & 0x80)
+        return val & 0xff
+
+    def fetch(self):
+        val = self.bus.read(self.pc)
+        self.pc = (self.pc + 1) & 0xffff
+        return val
+
+    def dummy(self):
+        self.bus.read(self.pc)
+
+    def push(self, val):
+        self.bus.write(0x100 | self.s, val)
+        self.s = (self.s - 1) & 0xff
+
+    def pull(self):
+        self.s = (self.s + 1) & 0xff
+        return self.bus.read(0x100 | self.s)
+
+    # Addressing. Each returns the effective address after making the bus
+    # accesses that come before the operand itself.
+
+    def zero_page(self):
+        return self.fetch()
+
+    def zero_page_x(self):
+        base = self.fetch()
+        self.bus.read(base)
+        return (base + self.x) & 0xff
+
+    def absolute(self):
+        lo = self.fetch()
+        return lo | self.fetch() << 8
+
+    def indexed(self, base, index, write):
+        addr = (base + index) & 0xffff
+        if write or addr & 0xff00 != base & 0xff00:
+            self.bus.read((base & 0xff00) | (addr & 0xff))
+        return addr
+
+    def absolute_x(self, write=False):
+        return self.indexed(self.absolute(), self.x, write)
+
+    def indexed_indirect(self):
+        ptr = self.zero_page_x()
+        return self.bus.read(ptr) | self.bus.read((ptr + 1) & 0xff) << 8
+
+    def indirect_indexed(self, write=False):
+        ptr = self.fetch()
+        base = self.bus.read(ptr) | self.bus.read((ptr + 1) & 0xff) << 8
+        return self.indexed(base, self.y, write)
+
+    # Operations
+
+    def adc(self, m):
+        carry = self.p & C
+        if self.p & D:
+            lo = (self.a & 0x0f) + (m & 0x0f) + carry
+            if lo >= 0x0a:
+                lo = ((lo + 0x06) & 0x0f) + 0x10
+            total = (self.a & 0xf0) + (m & 0xf0) + lo
+            signed = sign(self.a & 0xf0) + sign(m & 0xf0) + lo
+            self.flag(Z, (self.a + m + carry) & 0xff == 0)
+            self.flag(N, signed & 0x80)
+            self.flag(V, signed < -128 or signed > 127)
+            if total >= 0xa0:
+                total += 0x60
+            self.flag(C, total >= 0x100)
+            self.a = total & 0xff
+        else:
+            total = self.a + m + carry
+            self.flag(V, ~(self.a ^ m) & (self.a ^ total) & 0x80)
+            self.flag(C, total > 0xff)
+            self.a = self.nz(total)
+
+    def sbc(self, m):
+        borrow = 1 - (self.p & C)
+        total = self.a - m - borrow
+        result = total & 0xff
+        self.flag(V, (self.a ^ m) & (self.a ^ result) & 0x80)
+        self.flag(C, total >= 0)
+        self.nz(result)
+        if self.p & D:
+            lo = (self.a & 0x0f) - (m & 0x0f) - borrow
+            if lo < 0:
+                lo = ((lo - 0x06) & 0x0f) - 0x10
+            total = (self.a & 0xf0) - (m & 0xf0) + lo
+            if total < 0:
+                total -= 0x60
+            result = total & 0xff
+        self.a = result
+
+    def compare(self, reg, m):
+        self.flag(C, reg >= m)
+        self.nz(reg - m)
+
+    def branch(self, taken):
+        offset = self.fetch()
+        if not taken:
+            return
+        self.dummy()
+        target = (self.pc + offset - (0x100 if offset & 0x80 else 0)) & 0xffff
+        if target & 0xff00 != self.pc & 0xff00:
+            self.bus.read((self.pc & 0xff00) | (target & 0xff))
+        self.pc = target
+
+    def read_modify_write(self, addr, op):
+        val = self.bus.read(addr)
+        self.bus.write(addr, val)
+        self.bus.write(addr, op(val))
+
+    def asl(self, val):
+        self.flag(C, val & 0x80)
+        return self.nz(val << 1)
+
+    def execute(self, opcode):
+        bus = self.bus
+        if opcode == 0xa9: self.a = self.nz(self.fetch())
+        elif opcode == 0xa5: self.a = self.nz(bus.read(self.zero_page()))
+        elif opcode == 0xb5: self.a = self.nz(bus.read(self.zero_page_x()))
+        elif opcode == 0xad: self.a = self.nz(bus.read(self.absolute()))
+        elif opcode == 0xbd: self.a = self.nz(bus.read(self.absolute_x()))
+        elif opcode == 0xa1: self.a = self.nz(bus.read(self.indexed_indirect()))
+        elif opcode == 0xb1: self.a = self.nz(bus.read(self.indirect_indexed()))
+        elif opcode == 0x85: bus.write(self.zero_page(), self.a)
+        elif opcode == 0x95: bus.write(self.zero_page_x(), self.a)
+        elif opcode == 0x8d: bus.write(self.absolute(), self.a)
+        elif opcode == 0x9d: bus.write(self.absolute_x(write=True), self.a)
+        elif opcode == 0x91: bus.write(self.indirect_indexed(write=True), self.a)
+        elif opcode == 0x69: self.adc(self.fetch())
+        elif opcode == 0x6d: self.adc(bus.read(self.absolute()))
+        elif opcode == 0xe9: self.sbc(self.fetch())
+        elif opcode == 0xc9: self.compare(self.a, self.fetch())
+        elif opcode == 0xe0: self.compare(self.x, self.fetch())
+        elif opcode == 0xe8:
+            self.dummy()
+            self.x = self.nz(self.x + 1)
+        elif opcode == 0xca:
+            self.dummy()
+            self.x = self.nz(self.x - 1)
+        elif opcode == 0xc8:
+            self.dummy()
+            self.y = self.nz(self.y + 1)
+        elif opcode == 0xaa:
+            self.dummy()
+            self.x = self.nz(self.a)
+        elif opcode == 0x9a:
+            self.dummy()
+            self.s = self.x
+        elif opcode == 0x0a:
+            self.dummy()
+            self.a = self.asl(self.a)
+        elif opcode == 0x06: self.read_modify_write(self.zero_page(), self.asl)
+        elif opcode == 0xe6: self.read_modify_write(self.zero_page(), lambda v: self.nz(v + 1))
+        elif opcode == 0x18:
+            self.dummy()
+            self.flag(C, False)
+        elif opcode == 0x38:
+            self.dummy()
+            self.flag(C, True)
+        elif opcode == 0xd0: self.branch(not self.p & Z)
+        elif opcode == 0xf0: self.branch(self.p & Z)
+        elif opcode == 0x10: self.branch(not self.p & N)
+        elif opcode == 0x48:
+            self.dummy()
+            self.push(self.a)
+        elif opcode == 0x08:
+            self.dummy()
+            self.push(self.p | B | U)
+        elif opcode == 0x68:
+            self.dummy()
+            bus.read(0x100 | self.s)
+            self.a = self.nz(self.pull())
+        elif opcode == 0x28:
+            self.dummy()
+            bus.read(0x100 | self.s)
+            self.p = self.pull() | U
+        elif opcode == 0x4c: self.pc = self.absolute()
+        elif opcode == 0x6c:
+            ptr = self.absolute()
+            lo = bus.read(ptr)
+            # The high byte comes from the same page as the low byte
+            self.pc = lo | bus.read((ptr & 0xff00) | ((ptr + 1) & 0xff)) << 8
+        elif opcode == 0x20:
+            lo = self.fetch()
+            bus.read(0x100 | self.s)
+            self.push(self.pc >> 8)
+            self.push(self.pc & 0xff)
+            self.pc = lo | bus.read(self.pc) << 8
+        elif opcode == 0x60:
+            self.dummy()
+            bus.read(0x100 | self.s)
+            lo = self.pull()
+            self.pc = lo | self.pull() << 8
+            bus.read(self.pc)
+            self.pc = (self.pc + 1) & 0xffff
+        elif opcode == 0x40:
+            self.dummy()
+            bus.read(0x100 | self.s)
+            self.p = self.pull() | U
+            lo = self.pull()
+            self.pc = lo | self.pull() << 8
+        else:
+            raise ValueError("No model for opcode %02x" % opcode)
+
+
+def sign(byte):
+    return byte - 0x100 if byte & 0x80 else byte
+
+
+OPCODES = [
+    0xa9, 0xa5, 0xb5, 0xad, 0xbd, 0xa1, 0xb1,
+    0x85, 0x95, 0x8d, 0x9d, 0x91,
+    0x69, 0x6d, 0xe9, 0xc9, 0xe0,
+    0xe8, 0xca, 0xc8, 0xaa, 0x9a, 0x0a, 0x06, 0xe6, 0x18, 0x38,
+    0xd0, 0xf0, 0x10,
+    0x48, 0x08, 0x68, 0x28,
+    0x4c, 0x6c, 0x20, 0x60, 0x40,
+]
+
+# Registers that every batch includes, by opcode, on top of the random ones.
+EDGE_CASES = {
+    0x48: [{"s": 0x00}],
+    0x08: [{"s": 0x00}],
+    0x68: [{"s": 0xff}],
+    0x28: [{"s": 0xff}],
+    0x20: [{"s": 0x00}, {"s": 0x01}],
+    0x60: [{"s": 0xfe}, {"s": 0xff}],
+    0x40: [{"s": 0xfd}, {"s": 0xff}],
+    0x69: [{"p": U | D, "a": 0x99}, {"p": U | D | C, "a": 0x58}],
+    0xe9: [{"p": U | D, "a": 0x00}, {"p": U | D | C, "a": 0x46}],
+}
+
+
+def vector(rng, opcode, fixed):
+    bus = Bus(rng)
+    regs = {
+        "pc": rng.randrange(0x0200, 0xfef0),
+        "s": rng.randrange(256),
+        "a": rng.randrange(256),
+        "x": rng.randrange(256),
+        "y": rng.randrange(256),
+        "p": rng.randrange(256) | U,
+    }
+    regs.update(fixed)
+    regs["p"] &= ~B & 0xff
+
+    bus.mem[regs["pc"]] = bus.initial[regs["pc"]] = opcode
+    for n in (1, 2):
+        bus.peek(regs["pc"] + n)
+    cpu = Cpu(bus, **regs)
+    initial = cpu.state()
+    cpu.execute(cpu.fetch())
+
+    name = " ".join("%02x" % bus.initial[(initial["pc"] + n) & 0xffff] for n in range(3))
+    initial["ram"] = sorted([a, v] for a, v in bus.initial.items())
+    final = cpu.state()
+    final["ram"] = sorted([a, bus.mem[a]] for a in bus.initial)
+    return {"name": name, "initial": initial, "final": final, "cycles": bus.cycles}
+
+
+def main():
+    rng = random.Random(SEED)
+    here = os.path.dirname(os.path.abspath(__file__))
+    for opcode in OPCODES:
+        batch = [vector(rng, opcode, f) for f in EDGE_CASES.get(opcode, [])]
+        batch += [vector(rng, opcode, {}) for _ in range(VECTORS_PER_OPCODE)]
+        with open(os.path.join(here, "%02x.json" % opcode), "w") as f:
+            json.dump(batch, f, indent=1)
+            f.write("\n")
+
+
+if __name__ == "__main__":
+    main()
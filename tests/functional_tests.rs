@@ -0,0 +1,120 @@
+//! Runs whole-program 6502 test images against the CPU core.
+//!
+//! The images are flat 64K binaries that report their result by trapping,
+//! i.e. executing a branch or jump to itself. Success or failure is decided
+//! by the address of that trap. See `tests/fixtures/README.md` for how to
+//! build the images. Until they're committed, the tests that need them are
+//! ignored, and fail if run with `--ignored` while an image is missing.
+extern crate bbc_em;
+
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
+use bbc_em::cpu::{Cpu, CpuVariant};
+use bbc_em::memory::{Map, MemoryMap, AsMemoryRegionMut};
+
+const INSTRUCTION_LIMIT: usize = 100_000_000;
+
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Trapped(u16),
+    Halted(u16),
+    LimitReached(u16),
+}
+
+fn load_fixture(name: &str) -> Result<Vec<u8>, String> {
+    let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.push("tests");
+    path.push("fixtures");
+    path.push(name);
+
+    let mut bytes = vec![];
+    File::open(&path)
+        .and_then(|mut f| f.read_to_end(&mut bytes))
+        .map_err(|e| format!("Couldn't read {} ({}); see tests/fixtures/README.md",
+                             path.display(), e))?;
+    Ok(bytes)
+}
+
+/// Copies `image` into an otherwise empty 64K memory map at `load_address`
+/// and points the reset vector at `start`.
+fn build_memory(image: &[u8], load_address: u16, start: u16) -> Map {
+    let mut mem = Map::new();
+    {
+        let load_address = load_address as usize;
+        let mut region = mem.region_mut(load_address..load_address + image.len())
+                            .unwrap();
+        region.copy_from_slice(image);
+    }
+    mem.write(0xfffc, (start & 0x00ff) as u8);
+    mem.write(0xfffd, ((start & 0xff00) >> 8) as u8);
+    mem
+}
+
+/// Steps the CPU until the program counter stops moving, the CPU jams, or
+/// `INSTRUCTION_LIMIT` is reached.
+fn run_until_trap(cpu: &mut Cpu, mem: &mut Map) -> Outcome {
+    cpu.initialize(&mut *mem).unwrap();
+
+    for _ in 0..INSTRUCTION_LIMIT {
+        let pc = cpu.program_counter();
+        cpu.step(&mut *mem).unwrap();
+
+        if cpu.is_halted() {
+            return Outcome::Halted(pc);
+        }
+
+        if cpu.program_counter() == pc {
+            return Outcome::Trapped(pc);
+        }
+    }
+
+    Outcome::LimitReached(cpu.program_counter())
+}
+
+#[test]
+fn detect_trap_in_hand_assembled_program() {
+    let program = [
+        0xa2, 0x05,         // 0400: LDX #$05
+        0xca,               // 0402: DEX
+        0xd0, 0xfd,         // 0403: BNE $0402
+        0x86, 0x10,         // 0405: STX $10
+        0xa9, 0x42,         // 0407: LDA #$42
+        0xf0, 0xfe,         // 0409: BEQ $0409
+        0x4c, 0x0b, 0x04,   // 040b: JMP $040b
+    ];
+    let mut mem = build_memory(&program, 0x0400, 0x0400);
+    mem.write(0x0010, 0xff);
+    let mut cpu = Cpu::new(CpuVariant::Nmos6502);
+
+    assert_eq!(Outcome::Trapped(0x040b), run_until_trap(&mut cpu, &mut mem));
+    assert_eq!(0x00, mem.read(0x0010));
+}
+
+#[test]
+#[ignore = "needs tests/fixtures/6502_functional_test.bin; see tests/fixtures/README.md"]
+fn pass_klaus_dormann_functional_test() -> Result<(), String> {
+    let image = load_fixture("6502_functional_test.bin")?;
+    let mut mem = build_memory(&image, 0x0000, 0x0400);
+    let mut cpu = Cpu::new(CpuVariant::Nmos6502);
+
+    assert_eq!(Outcome::Trapped(0x3469), run_until_trap(&mut cpu, &mut mem));
+    Ok(())
+}
+
+#[test]
+#[ignore = "needs tests/fixtures/6502_decimal_test.bin; see tests/fixtures/README.md"]
+fn pass_klaus_dormann_decimal_test() -> Result<(), String> {
+    const ERROR: u16 = 0x000b;
+
+    let image = load_fixture("6502_decimal_test.bin")?;
+    let mut mem = build_memory(&image, 0x0000, 0x0200);
+    let mut cpu = Cpu::new(CpuVariant::Nmos6502);
+
+    match run_until_trap(&mut cpu, &mut mem) {
+        Outcome::Trapped(_) => assert_eq!(0, mem.read(ERROR)),
+        outcome => panic!("Decimal test didn't finish: {:?}", outcome),
+    }
+    Ok(())
+}
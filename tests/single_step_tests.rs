@@ -0,0 +1,232 @@
+//! Runs per-instruction test vectors in the "SingleStepTests" JSON format
+//! against the CPU core.
+//!
+//! Each opcode has its own file (`a9.json`, etc.) holding an array of
+//! vectors. A vector gives the registers and RAM before a single
+//! `Cpu::step`, along with the expected registers, RAM and per-cycle bus
+//! activity afterwards. The vectors are looked for in the directory named
+//! by `SINGLE_STEP_TESTS_DIR`, or `tests/fixtures/single_step` if that
+//! isn't set. The crate ships a sample of generated vectors for a range of
+//! opcodes there; see `tests/fixtures/README.md` for how they are made and
+//! where to get the full set.
+//!
+//! Run with `--nocapture` to see the per-opcode report.
+extern crate bbc_em;
+extern crate serde_json;
+
+use std::env;
+use std::fs::File;
+use std::path::PathBuf;
+
+use serde_json::Value;
+
+use bbc_em::cpu::{self, Cpu, CpuVariant, Registers, StatusFlags};
+use bbc_em::memory::{Map, MemoryMap, BusOperation};
+
+/// The `JAM` opcodes, plus the undocumented opcodes whose results depend on
+/// analogue effects and so aren't emulated.
+const SKIPPED_OPCODES: &'static [u8] = &[
+    0x02, 0x12, 0x22, 0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xb2, 0xd2, 0xf2,
+    0x8b, 0x93, 0x9b, 0x9c, 0x9e, 0x9f, 0xab, 0xbb,
+];
+
+/// The B flag and bit 5 only exist on the stack, so they're ignored when
+/// comparing the status register.
+const STATUS_MASK: u8 = 0xcf;
+
+struct State {
+    registers: Registers,
+    ram: Vec<(u16, u8)>,
+}
+
+struct Vector {
+    name: String,
+    initial: State,
+    expected: State,
+    cycles: Vec<(u16, u8, BusOperation)>,
+}
+
+#[derive(Default)]
+struct Report {
+    passed: usize,
+    failed: usize,
+    first_failure: Option<String>,
+}
+
+fn vector_dir() -> PathBuf {
+    match env::var_os("SINGLE_STEP_TESTS_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => {
+            let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+            path.push("tests");
+            path.push("fixtures");
+            path.push("single_step");
+            path
+        }
+    }
+}
+
+fn as_u16(v: &Value) -> u16 {
+    v.as_u64().expect("Expected a number") as u16
+}
+
+fn parse_state(v: &Value) -> State {
+    let mut registers = Registers::new();
+    registers.pc = as_u16(&v["pc"]);
+    registers.sp = as_u16(&v["s"]) as u8;
+    registers.acc = as_u16(&v["a"]) as u8;
+    registers.x = as_u16(&v["x"]) as u8;
+    registers.y = as_u16(&v["y"]) as u8;
+    registers.status = StatusFlags::from(as_u16(&v["p"]) as u8);
+
+    let ram = v["ram"].as_array()
+                      .expect("Expected a RAM array")
+                      .iter()
+                      .map(|cell| (as_u16(&cell[0]), as_u16(&cell[1]) as u8))
+                      .collect();
+
+    State {
+        registers: registers,
+        ram: ram,
+    }
+}
+
+fn parse_cycle(v: &Value) -> (u16, u8, BusOperation) {
+    let operation = match v[2].as_str() {
+        Some("write") => BusOperation::Write,
+        _ => BusOperation::Read,
+    };
+
+    (as_u16(&v[0]), as_u16(&v[1]) as u8, operation)
+}
+
+fn parse_vectors(v: Value) -> Vec<Vector> {
+    v.as_array()
+     .expect("Expected an array of test vectors")
+     .iter()
+     .map(|t| Vector {
+         name: t["name"].as_str().unwrap_or("").to_string(),
+         initial: parse_state(&t["initial"]),
+         expected: parse_state(&t["final"]),
+         cycles: t["cycles"].as_array()
+                            .map(|c| c.iter().map(parse_cycle).collect())
+                            .unwrap_or_default(),
+     })
+     .collect()
+}
+
+fn describe_registers(r: &Registers) -> String {
+    format!("PC={:04x} S={:02x} A={:02x} X={:02x} Y={:02x} P={:02x}",
+            r.pc, r.sp, r.acc, r.x, r.y, u8::from(&r.status) & STATUS_MASK)
+}
+
+/// Runs a single vector, returning a description of the first mismatch
+/// found.
+fn run_vector(vector: &Vector, mem: &mut Map) -> Result<(), String> {
+    for &(loc, val) in &vector.initial.ram {
+        mem.write(loc, val);
+    }
+
+    let mut cpu = Cpu::new(CpuVariant::Nmos6502);
+    *cpu.registers_mut() = vector.initial.registers;
+
+    let cycles = cpu.step(&mut *mem)
+                    .map_err(|e| format!("{}: step failed: {:?}", vector.name, e))?;
+
+    let actual = describe_registers(cpu.registers());
+    let expected = describe_registers(&vector.expected.registers);
+    if actual != expected {
+        return Err(format!("{}: expected {}, got {}", vector.name, expected, actual));
+    }
+
+    for &(loc, val) in &vector.expected.ram {
+        let actual = mem.read(loc);
+        if actual != val {
+            return Err(format!("{}: expected {:02x} at {:04x}, got {:02x}",
+                               vector.name, val, loc, actual));
+        }
+    }
+
+    if cycles != vector.cycles.len() {
+        return Err(format!("{}: expected {} cycles, got {}",
+                           vector.name, vector.cycles.len(), cycles));
+    }
+
+    for (n, (expected, actual)) in vector.cycles.iter().zip(cpu.bus_cycles()).enumerate() {
+        if *expected != (actual.address, actual.value, actual.operation) {
+            return Err(format!("{}: expected {:?} on cycle {}, got {:?}",
+                               vector.name, expected, n, actual));
+        }
+    }
+
+    if cpu.bus_cycles().len() != vector.cycles.len() {
+        return Err(format!("{}: expected {} bus accesses, got {}",
+                           vector.name, vector.cycles.len(), cpu.bus_cycles().len()));
+    }
+
+    Ok(())
+}
+
+fn describe_opcode(vector: &Vector) -> String {
+    let mut bytes = [0u8; 3];
+    for &(loc, val) in &vector.initial.ram {
+        let offset = loc.wrapping_sub(vector.initial.registers.pc) as usize;
+        if offset < bytes.len() {
+            bytes[offset] = val;
+        }
+    }
+
+    cpu::decode_instruction(&bytes)
+        .map(|(_, ins)| format!("{}", ins))
+        .unwrap_or_else(|_| "???".to_string())
+}
+
+#[test]
+fn pass_single_step_test_vectors() {
+    let dir = vector_dir();
+    assert!(dir.is_dir(), "{} not found; see tests/fixtures/README.md", dir.display());
+
+    let mut mem = Map::new();
+    let mut failing = vec![];
+    let mut total = Report::default();
+
+    for opcode in 0..256usize {
+        let opcode = opcode as u8;
+        let path = dir.join(format!("{:02x}.json", opcode));
+        if SKIPPED_OPCODES.contains(&opcode) || !path.is_file() {
+            continue;
+        }
+
+        let vectors = parse_vectors(serde_json::from_reader(File::open(&path).unwrap())
+            .expect("Invalid test vector file"));
+        let mut report = Report::default();
+
+        for vector in &vectors {
+            match run_vector(vector, &mut mem) {
+                Ok(_) => report.passed += 1,
+                Err(e) => {
+                    report.failed += 1;
+                    report.first_failure = report.first_failure.or(Some(e));
+                }
+            }
+        }
+
+        println!("{:02x} {:<16} {:>6} passed {:>6} failed",
+                 opcode,
+                 vectors.first().map(describe_opcode).unwrap_or_default(),
+                 report.passed,
+                 report.failed);
+        if let Some(ref e) = report.first_failure {
+            println!("   first failure: {}", e);
+            failing.push(opcode);
+        }
+
+        total.passed += report.passed;
+        total.failed += report.failed;
+    }
+
+    println!("{} passed, {} failed", total.passed, total.failed);
+    assert!(total.passed + total.failed > 0, "No test vectors in {}", dir.display());
+    assert!(failing.is_empty(),
+            "Vectors failed for opcodes {:02x?}", failing);
+}